@@ -0,0 +1,160 @@
+use crate::aws::{self, AwsEc2InstancesClient};
+use crate::clients;
+use crate::config::{self, Action};
+use crate::protection;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::{Filter, InstanceStateName};
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub struct ApplyConfig {
+    pub file: PathBuf,
+    /// Only print the plan
+    pub dry_run: bool,
+}
+
+/// File declaring the desired state of instances
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Spec {
+    instances: Vec<Desired>,
+}
+
+/// Desired state of the instances matching either an id or a tag
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Desired {
+    instance: Option<String>,
+    /// `KEY=VALUE`
+    tag: Option<String>,
+    state: DesiredState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DesiredState {
+    Running,
+    Stopped,
+}
+
+impl Desired {
+    fn filter(&self) -> Result<Filter> {
+        match (&self.instance, &self.tag) {
+            (Some(instance_id), None) => Ok(Filter::builder()
+                .name("instance-id")
+                .values(instance_id)
+                .build()),
+            (None, Some(tag)) => {
+                let (key, value) = config::parse_tag(tag).map_err(|e| eyre!("{}: {}", tag, e))?;
+                Ok(aws::tag_filter(&key, &value))
+            }
+            _ => Err(eyre!("Each entry needs exactly one of instance and tag")),
+        }
+    }
+}
+
+/// Computes the transitions needed to reach the desired states, prints them, then performs them
+pub async fn run(aws_config: &SdkConfig, config: ApplyConfig) -> Result<()> {
+    let contents = std::fs::read_to_string(&config.file)
+        .wrap_err_with(|| format!("Failed to read {}", config.file.display()))?;
+    let spec: Spec = serde_yaml::from_str(&contents)
+        .wrap_err_with(|| format!("Failed to parse {}", config.file.display()))?;
+
+    let client = AwsEc2InstancesClient::new(clients::ec2(aws_config));
+
+    // Sorted, so the plan is stable
+    let mut desired_states = BTreeMap::new();
+    let mut instances = BTreeMap::new();
+    for desired in &spec.instances {
+        for instance in client.describe_instances(vec![desired.filter()?]).await? {
+            let instance_id = instance.instance_id().to_string();
+            match desired_states.insert(instance_id.clone(), desired.state) {
+                Some(previous) if previous != desired.state => {
+                    return Err(eyre!("Conflicting desired states for {}", instance_id))
+                }
+                _ => {}
+            }
+            instances.insert(instance_id, instance);
+        }
+    }
+
+    let mut to_start = vec![];
+    let mut to_stop = vec![];
+    for (instance_id, desired_state) in desired_states {
+        let instance = &instances[&instance_id];
+        match (desired_state, instance.state()) {
+            (DesiredState::Running, InstanceStateName::Running | InstanceStateName::Pending)
+            | (DesiredState::Stopped, InstanceStateName::Stopped | InstanceStateName::Stopping) => {
+            }
+            (DesiredState::Running, InstanceStateName::Stopped) => to_start.push(instance),
+            (DesiredState::Stopped, InstanceStateName::Running) => to_stop.push(instance),
+            (_, state) => println!("{} is {}, skipping", instance_id, state.as_str()),
+        }
+    }
+    let to_start = protection::filter("apply", &Action::Start, to_start);
+    let to_stop = protection::filter("apply", &Action::Stop, to_stop);
+
+    if to_start.is_empty() && to_stop.is_empty() {
+        println!("Nothing to do");
+        return Ok(());
+    }
+
+    println!("Plan:");
+    for instance_id in &to_start {
+        println!("\tstart {}", instance_id);
+    }
+    for instance_id in &to_stop {
+        println!("\tstop {}", instance_id);
+    }
+
+    if config.dry_run {
+        return Ok(());
+    }
+
+    client.change_state(&Action::Start, &to_start).await?;
+    client.change_state(&Action::Stop, &to_stop).await?;
+    println!(
+        "Started {} and stopped {} instances",
+        to_start.len(),
+        to_stop.len()
+    );
+
+    Ok(())
+}
+
+#[cfg(all(test, feature = "http-replay"))]
+mod tests {
+    use super::{run, ApplyConfig};
+    use crate::fixtures;
+    use crate::protection;
+
+    #[tokio::test]
+    async fn leaves_protected_instances_running() {
+        let _exclusive = fixtures::EXCLUSIVE.lock().await;
+        let _ = protection::init(&["Environment=production".to_string()]);
+        let file = std::env::temp_dir().join("aws-start-stop-apply-protected.yaml");
+        let spec = format!(
+            "instances:\n  - instance: {}\n    state: stopped\n",
+            fixtures::INSTANCE_ID
+        );
+        std::fs::write(&file, spec).unwrap();
+        let (client, aws_config) = fixtures::replay("apply-protected");
+
+        // The fixture has no StopInstances to answer with, so trying to stop fails
+        let result = run(
+            &aws_config,
+            ApplyConfig {
+                file,
+                dry_run: false,
+            },
+        )
+        .await;
+        assert!(result.is_ok(), "{:?}", result);
+
+        fixtures::validate(&client).await;
+    }
+}
@@ -0,0 +1,91 @@
+use crate::aws::AwsAutoScalingClient;
+use crate::clients;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_autoscaling::types::LifecycleState;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::time::Duration;
+
+fn client(aws_config: &SdkConfig, instance_id: &str) -> AwsAutoScalingClient {
+    AwsAutoScalingClient {
+        client: clients::autoscaling(aws_config),
+        instance_id: instance_id.to_string(),
+        wait: Duration::from_secs(10),
+    }
+}
+
+fn is_warmed(state: &LifecycleState) -> bool {
+    state.as_str().starts_with("Warmed:")
+}
+
+/// Takes the instance out of service before stopping it
+///
+/// If the warm pool of its group reuses instances on scale in, the group is scaled in by the
+/// instance, which goes back to the warm pool in the pool's state, and `true` is returned as
+/// there is nothing left to do. Otherwise the instance is put in standby, so the group doesn't
+/// replace it once it is stopped.
+pub async fn leave_service(aws_config: &SdkConfig, instance_id: &str) -> Result<bool> {
+    let client = client(aws_config, instance_id);
+    let Some((group, state)) = client.membership().await? else {
+        return Ok(false);
+    };
+    match state {
+        LifecycleState::InService => {}
+        LifecycleState::Standby => return Ok(false),
+        _ if is_warmed(&state) => return Ok(true),
+        _ => {
+            return Err(eyre!(
+                "The instance is {} in Auto Scaling group {}",
+                state.as_str(),
+                group
+            ))
+        }
+    }
+
+    if client.reuses_on_scale_in(&group).await? {
+        log::info(
+            "asg",
+            format!("Returning instance to the warm pool of {}...", group),
+        );
+        client.return_to_warm_pool().await?;
+        client.wait_for_lifecycle(is_warmed).await?;
+        Ok(true)
+    } else {
+        log::info(
+            "asg",
+            format!("Putting instance in standby in {}...", group),
+        );
+        client.enter_standby(&group).await?;
+        client
+            .wait_for_lifecycle(|state| *state == LifecycleState::Standby)
+            .await?;
+        Ok(false)
+    }
+}
+
+/// Returns the group to put the instance back in service in once it is running, if any
+pub async fn standby_group(aws_config: &SdkConfig, instance_id: &str) -> Result<Option<String>> {
+    match client(aws_config, instance_id).membership().await? {
+        Some((group, LifecycleState::Standby)) => Ok(Some(group)),
+        Some((group, state)) if is_warmed(&state) => Err(eyre!(
+            "The instance is in the warm pool of {}, which picks the instances it puts in \
+            service: increase the desired capacity of the group instead",
+            group
+        )),
+        _ => Ok(None),
+    }
+}
+
+/// Puts the started instance back in service
+pub async fn enter_service(aws_config: &SdkConfig, instance_id: &str, group: &str) -> Result<()> {
+    let client = client(aws_config, instance_id);
+    log::info(
+        "asg",
+        format!("Putting instance back in service in {}...", group),
+    );
+    client.exit_standby(group).await?;
+    client
+        .wait_for_lifecycle(|state| *state == LifecycleState::InService)
+        .await
+}
@@ -0,0 +1,54 @@
+use crate::config::Action;
+use crate::credentials;
+use crate::log;
+use aws_config::SdkConfig;
+use color_eyre::Result;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// File the actions are recorded to, and who they are made as
+struct AuditLog {
+    file: Mutex<File>,
+    caller: String,
+}
+
+static AUDIT_LOG: OnceLock<AuditLog> = OnceLock::new();
+
+/// Records the actions to the file from now on, appending to it
+pub async fn init(aws_config: &SdkConfig, path: &Path) -> Result<()> {
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let caller = credentials::caller(aws_config).await?;
+
+    let _ = AUDIT_LOG.set(AuditLog {
+        file: Mutex::new(file),
+        caller,
+    });
+    Ok(())
+}
+
+/// Appends the outcome of an action to the audit log, if there is one
+pub fn record<T>(action: &Action, instance_id: &str, result: &Result<T>, duration: Duration) {
+    let Some(audit_log) = AUDIT_LOG.get() else {
+        return;
+    };
+
+    let mut record = serde_json::json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "caller": audit_log.caller,
+        "action": action.to_string(),
+        "instance_id": instance_id,
+        "outcome": if result.is_ok() { "success" } else { "failure" },
+        "duration_secs": duration.as_secs_f64(),
+    });
+    if let Err(e) = result {
+        record["error"] = e.to_string().into();
+    }
+
+    let mut file = audit_log.file.lock().unwrap();
+    if let Err(e) = writeln!(file, "{}", record) {
+        log::warn("audit", format!("Failed to write to the audit log: {}", e));
+    }
+}
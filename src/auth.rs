@@ -0,0 +1,139 @@
+use crate::settings::{ApiAuthSettings, ApiToken};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+/// What the bearer of a token may do
+#[derive(Debug)]
+pub struct Grant {
+    /// Who the token was given to
+    pub name: String,
+    /// Instances the token may act on, all of them if empty
+    instances: Vec<String>,
+    /// Actions the token may take, all of them if empty
+    actions: Vec<String>,
+}
+
+impl Grant {
+    pub fn allows(&self, instance_id: &str, action: &str) -> bool {
+        (self.instances.is_empty() || self.instances.iter().any(|allowed| allowed == instance_id))
+            && (self.actions.is_empty() || self.actions.iter().any(|allowed| allowed == action))
+    }
+}
+
+#[derive(Deserialize)]
+struct JwtHeader {
+    alg: String,
+}
+
+/// Claims of the JSON Web Tokens, which must expire
+#[derive(Deserialize)]
+struct Claims {
+    sub: Option<String>,
+    exp: i64,
+    #[serde(default)]
+    instances: Vec<String>,
+    #[serde(default)]
+    actions: Vec<String>,
+}
+
+/// Tokens accepted by the APIs of `serve`
+///
+/// Without any, the gRPC API lets anyone do anything, as it did before they could be configured.
+#[derive(Debug, Clone, Default)]
+pub struct ApiAuth {
+    tokens: Vec<ApiToken>,
+    jwt_secret: Option<String>,
+}
+
+impl ApiAuth {
+    pub fn new(settings: Option<&ApiAuthSettings>) -> Self {
+        settings.map_or_else(Self::default, |settings| Self {
+            tokens: settings.tokens.clone(),
+            jwt_secret: settings.jwt_secret.clone(),
+        })
+    }
+
+    /// Also accepts the token, allowing everything
+    pub fn with_token(mut self, name: &str, token: &str) -> Self {
+        self.tokens.push(ApiToken {
+            name: name.to_string(),
+            token: token.to_string(),
+            instances: vec![],
+            actions: vec![],
+        });
+        self
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.tokens.is_empty() || self.jwt_secret.is_some()
+    }
+
+    /// What the token allows, if it is valid
+    pub fn authorize(&self, token: Option<&str>) -> Option<Grant> {
+        if !self.is_enabled() {
+            return Some(Grant {
+                name: "anonymous".to_string(),
+                instances: vec![],
+                actions: vec![],
+            });
+        }
+        let token = token?;
+
+        if let Some(allowed) = self
+            .tokens
+            .iter()
+            .find(|allowed| constant_time_eq(allowed.token.as_bytes(), token.as_bytes()))
+        {
+            return Some(Grant {
+                name: allowed.name.clone(),
+                instances: allowed.instances.clone(),
+                actions: allowed.actions.clone(),
+            });
+        }
+
+        let claims = verify_jwt(self.jwt_secret.as_deref()?, token)?;
+        Some(Grant {
+            name: claims.sub.unwrap_or_else(|| "JWT".to_string()),
+            instances: claims.instances,
+            actions: claims.actions,
+        })
+    }
+}
+
+/// Checks the HS256 signature and expiry of the token, returning its claims
+fn verify_jwt(secret: &str, token: &str) -> Option<Claims> {
+    let mut parts = token.split('.');
+    let (Some(header), Some(payload), Some(signature), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return None;
+    };
+
+    // Other algorithms, "none" above all, must not be accepted
+    let jwt_header: JwtHeader = serde_json::from_slice(&decode_base64url(header)?).ok()?;
+    if jwt_header.alg != "HS256" {
+        return None;
+    }
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).ok()?;
+    mac.update(format!("{}.{}", header, payload).as_bytes());
+    mac.verify_slice(&decode_base64url(signature)?).ok()?;
+
+    let claims: Claims = serde_json::from_slice(&decode_base64url(payload)?).ok()?;
+    (claims.exp > Utc::now().timestamp()).then_some(claims)
+}
+
+/// JSON Web Tokens use the URL alphabet, without padding
+fn decode_base64url(encoded: &str) -> Option<Vec<u8>> {
+    let mut standard = encoded.replace('-', "+").replace('_', "/");
+    while standard.len() % 4 != 0 {
+        standard.push('=');
+    }
+    aws_smithy_types::base64::decode(standard).ok()
+}
+
+/// Compares the tokens in a time which doesn't tell how much of them matched
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
@@ -1,13 +1,100 @@
 use color_eyre::Result;
 
-use aws_sdk_ec2::types::InstanceStateName;
-use aws_sdk_ssm::types::ConnectionStatus;
+use crate::capture;
+use crate::config::Action;
+use crate::error::{AwsError, Hint};
+#[cfg(feature = "test-faults")]
+use crate::faults;
+use crate::launch::LaunchConfig;
+use crate::log;
+use crate::progress;
+use crate::ratelimit;
+use aws_sdk_autoscaling::types::LifecycleState;
+use aws_sdk_cloudtrail::types::{LookupAttribute, LookupAttributeKey};
+use aws_sdk_cloudwatch::types::{
+    ComparisonOperator, Dimension, MetricDatum, StandardUnit, Statistic,
+};
+use aws_sdk_ec2::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_ec2::types::{
+    AttributeValue, CapacityReservationPreference, CapacityReservationSpecification,
+    CapacityReservationTarget, Filter, ImageState, InstanceCreditSpecificationRequest,
+    InstanceStateName, InstanceType, LaunchTemplateSpecification, PlatformValues, ResourceType,
+    SnapshotState, Tag, TagSpecification, VolumeAttachmentState, VolumeState,
+    VolumeStatusInfoStatus,
+};
+use aws_sdk_scheduler::types::{
+    ActionAfterCompletion, FlexibleTimeWindow, FlexibleTimeWindowMode, Target,
+};
+use aws_sdk_ssm::types::{CommandInvocationStatus, ConnectionStatus};
+use chrono::{DateTime, Utc};
 use color_eyre::eyre::eyre;
+use std::collections::{BTreeMap, HashSet};
+use std::future::Future;
+use std::net::IpAddr;
+use std::sync::OnceLock;
+use std::time::SystemTime;
 use tokio::time::Duration;
 
+/// Prefixes of the burstable instance types, those with CPU credits
+const BURSTABLE_FAMILIES: [&str; 4] = ["t2.", "t3.", "t3a.", "t4g."];
+
+/// Connect to instances over IPv6 when they have an address, rather than public IPv4
+static PREFER_IPV6: OnceLock<bool> = OnceLock::new();
+
+pub fn prefer_ipv6(prefer: bool) {
+    let _ = PREFER_IPV6.set(prefer);
+}
+
+/// Host of a URL for the address, as IPv6 ones must be bracketed
+pub fn url_host(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    }
+}
+
 pub struct Instance(aws_sdk_ec2::types::Instance);
 
 impl Instance {
+    pub fn instance_id(&self) -> &str {
+        self.0.instance_id().unwrap_or_default()
+    }
+
+    pub fn tag(&self, key: &str) -> Option<&str> {
+        self.0
+            .tags()
+            .iter()
+            .find(|tag| tag.key() == Some(key))
+            .and_then(|tag| tag.value())
+    }
+
+    pub fn tags(&self) -> BTreeMap<&str, &str> {
+        self.0
+            .tags()
+            .iter()
+            .filter_map(|tag| Some((tag.key()?, tag.value().unwrap_or_default())))
+            .collect()
+    }
+
+    /// Value of the Name tag
+    pub fn name(&self) -> Option<&str> {
+        self.tag("Name")
+    }
+
+    /// When the instance was last started
+    pub fn launch_time(&self) -> Option<SystemTime> {
+        self.0
+            .launch_time()
+            .and_then(|time| SystemTime::try_from(*time).ok())
+    }
+
+    /// Who launched the instance, as EC2 doesn't record it this relies on the usual tags
+    pub fn launched_by(&self) -> Option<&str> {
+        ["CreatedBy", "Owner"]
+            .into_iter()
+            .find_map(|key| self.tag(key))
+    }
+
     pub fn state(&self) -> &InstanceStateName {
         self.0.state.as_ref().unwrap().name.as_ref().unwrap()
     }
@@ -23,8 +110,172 @@ impl Instance {
     pub fn ipv4_address_private(&self) -> Option<&str> {
         self.0.private_ip_address()
     }
+
+    /// Address to connect to the instance on
+    ///
+    /// That is its public IPv4 address, else its IPv6 one, which is reachable from outside the VPC
+    /// unlike the private IPv4 one, tried last. The IPv6 address comes first with `--prefer-ipv6`.
+    pub fn address(&self) -> Option<IpAddr> {
+        let ipv6 = self.ipv6_address().and_then(|ip| ip.parse().ok());
+        let public = self.ipv4_address_public().and_then(|ip| ip.parse().ok());
+        let private = self.ipv4_address_private().and_then(|ip| ip.parse().ok());
+        if PREFER_IPV6.get() == Some(&true) {
+            ipv6.or(public).or(private)
+        } else {
+            public.or(ipv6).or(private)
+        }
+    }
+
+    /// EC2 returns an empty name rather than none when the instance has no public address
+    pub fn public_dns_name(&self) -> Option<&str> {
+        self.0.public_dns_name().filter(|name| !name.is_empty())
+    }
+
+    pub fn private_dns_name(&self) -> Option<&str> {
+        self.0.private_dns_name().filter(|name| !name.is_empty())
+    }
+
+    /// Security groups as `id (name)`
+    pub fn security_groups(&self) -> Vec<String> {
+        self.0
+            .security_groups()
+            .iter()
+            .map(|group| match (group.group_id(), group.group_name()) {
+                (Some(id), Some(name)) => format!("{} ({})", id, name),
+                (id, name) => id.or(name).unwrap_or_default().to_string(),
+            })
+            .collect()
+    }
+
+    pub fn security_group_ids(&self) -> Vec<&str> {
+        self.0
+            .security_groups()
+            .iter()
+            .filter_map(|group| group.group_id())
+            .collect()
+    }
+
+    pub fn key_name(&self) -> Option<&str> {
+        self.0.key_name()
+    }
+
+    pub fn is_windows(&self) -> bool {
+        self.0.platform() == Some(&PlatformValues::Windows)
+    }
+
+    /// Purchasing option: `on-demand`, `spot` or `scheduled`
+    pub fn lifecycle(&self) -> &str {
+        self.0
+            .instance_lifecycle()
+            .map_or("on-demand", |lifecycle| lifecycle.as_str())
+    }
+
+    /// Capacity reservation the instance is running in, if any
+    pub fn capacity_reservation_id(&self) -> Option<&str> {
+        self.0.capacity_reservation_id()
+    }
+
+    /// `default`, `dedicated` or `host`
+    pub fn tenancy(&self) -> &str {
+        self.0
+            .placement()
+            .and_then(|placement| placement.tenancy())
+            .map_or("default", |tenancy| tenancy.as_str())
+    }
+
+    pub fn availability_zone(&self) -> Option<&str> {
+        self.0
+            .placement()
+            .and_then(|placement| placement.availability_zone())
+    }
+
+    /// Device index following those of the network interfaces attached to the instance
+    pub fn next_device_index(&self) -> i32 {
+        self.0
+            .network_interfaces()
+            .iter()
+            .filter_map(|eni| eni.attachment()?.device_index())
+            .max()
+            .map_or(1, |index| index + 1)
+    }
+
+    pub fn instance_type(&self) -> &str {
+        self.0
+            .instance_type()
+            .map(|instance_type| instance_type.as_str())
+            .unwrap_or_default()
+    }
+
+    /// Whether the instance is of a burstable type, earning CPU credits
+    pub fn is_burstable(&self) -> bool {
+        BURSTABLE_FAMILIES
+            .iter()
+            .any(|family| self.instance_type().starts_with(family))
+    }
+
+    pub fn hibernation_configured(&self) -> bool {
+        self.0
+            .hibernation_options()
+            .and_then(|options| options.configured())
+            .unwrap_or(false)
+    }
+
+    /// EBS volume of the root device, if the instance is EBS-backed
+    pub fn root_volume_id(&self) -> Option<&str> {
+        let root_device_name = self.0.root_device_name()?;
+        self.0
+            .block_device_mappings()
+            .iter()
+            .find(|mapping| mapping.device_name() == Some(root_device_name))
+            .and_then(|mapping| mapping.ebs())
+            .and_then(|ebs| ebs.volume_id())
+    }
+
+    /// Attached EBS volumes as `(device name, volume id)`
+    pub fn volumes(&self) -> Vec<(String, String)> {
+        self.0
+            .block_device_mappings()
+            .iter()
+            .filter_map(|mapping| {
+                let volume_id = mapping.ebs()?.volume_id()?;
+                let device_name = mapping.device_name().unwrap_or_default();
+                Some((device_name.to_string(), volume_id.to_string()))
+            })
+            .collect()
+    }
+}
+
+/// Maintenance AWS scheduled for an instance, e.g. rebooting it or retiring its hardware
+pub struct ScheduledEvent {
+    /// e.g. `instance-retirement` or `system-reboot`
+    pub code: String,
+    pub description: String,
+    pub not_before: Option<DateTime<Utc>>,
+}
+
+impl ScheduledEvent {
+    /// Whether the hardware of the instance is to be retired, stopping it unless it is moved
+    pub fn is_retirement(&self) -> bool {
+        self.code == "instance-retirement"
+    }
 }
 
+impl std::fmt::Display for ScheduledEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.code)?;
+        if let Some(not_before) = self.not_before {
+            write!(f, " not before {}", not_before.to_rfc3339())?;
+        }
+        if !self.description.is_empty() {
+            write!(f, ": {}", self.description)?;
+        }
+        Ok(())
+    }
+}
+
+/// How long EC2 may take to reflect an action in the descriptions of the instance
+const CONSISTENCY_DELAY: Duration = Duration::from_secs(30);
+
 pub struct AwsEc2Client {
     client: aws_sdk_ec2::client::Client,
     instance_id: String,
@@ -48,12 +299,16 @@ impl AwsEc2Client {
     }
 
     pub async fn get_instance(&self) -> Result<Instance> {
-        let response = self
-            .client
-            .describe_instances()
-            .instance_ids(&self.instance_id)
-            .send()
-            .await?;
+        #[cfg(feature = "test-faults")]
+        faults::call("ec2:DescribeInstances").await?;
+        let response = ratelimit::call(
+            self.client
+                .describe_instances()
+                .instance_ids(&self.instance_id)
+                .send(),
+        )
+        .await
+        .hint("ec2:DescribeInstances")?;
 
         // Do a sanity check. There should be exactly one instance, no more, no less
         let mut reservations = response.reservations.unwrap_or_default();
@@ -74,17 +329,25 @@ impl AwsEc2Client {
         }
 
         let instance = instance_vec.pop().unwrap();
+        #[cfg(feature = "test-faults")]
+        let instance = faults::described(instance);
 
         Ok(Instance(instance))
     }
 
     pub async fn start_instance(&self) -> Result<InstanceStateName> {
-        let response = self
-            .client
-            .start_instances()
-            .instance_ids(&self.instance_id)
-            .send()
-            .await?;
+        #[cfg(feature = "test-faults")]
+        faults::call("ec2:StartInstances").await?;
+        let response = retry_incorrect_state(self.wait, || {
+            ratelimit::call(
+                self.client
+                    .start_instances()
+                    .instance_ids(&self.instance_id)
+                    .send(),
+            )
+        })
+        .await
+        .hint("ec2:StartInstances")?;
 
         // Sanity check
         let mut state_changes = response.starting_instances.unwrap_or_default();
@@ -110,13 +373,42 @@ impl AwsEc2Client {
         Ok(current_state)
     }
 
-    pub async fn stop_instance(&self) -> Result<InstanceStateName> {
-        let response = self
-            .client
-            .stop_instances()
-            .instance_ids(&self.instance_id)
+    pub async fn set_tag(&self, key: &str, value: &str) -> Result<()> {
+        self.client
+            .create_tags()
+            .resources(&self.instance_id)
+            .tags(Tag::builder().key(key).value(value).build())
+            .send()
+            .await
+            .hint("ec2:CreateTags")?;
+        Ok(())
+    }
+
+    /// Removes the tag, only if it still has this value
+    pub async fn remove_tag(&self, key: &str, value: &str) -> Result<()> {
+        self.client
+            .delete_tags()
+            .resources(&self.instance_id)
+            .tags(Tag::builder().key(key).value(value).build())
             .send()
-            .await?;
+            .await
+            .hint("ec2:DeleteTags")?;
+        Ok(())
+    }
+
+    pub async fn stop_instance(&self) -> Result<InstanceStateName> {
+        #[cfg(feature = "test-faults")]
+        faults::call("ec2:StopInstances").await?;
+        let response = retry_incorrect_state(self.wait, || {
+            ratelimit::call(
+                self.client
+                    .stop_instances()
+                    .instance_ids(&self.instance_id)
+                    .send(),
+            )
+        })
+        .await
+        .hint("ec2:StopInstances")?;
 
         // Sanity check
         let mut state_changes = response.stopping_instances.unwrap_or_default();
@@ -142,16 +434,489 @@ impl AwsEc2Client {
         Ok(current_state)
     }
 
-    pub async fn wait_for_state(&self) -> Result<Instance> {
+    /// Makes the instance use a capacity reservation, given by its id, or any open one
+    ///
+    /// The instance must be stopped.
+    pub async fn set_capacity_reservation(&self, reservation: &str) -> Result<()> {
+        let specification = if reservation == "open" {
+            CapacityReservationSpecification::builder()
+                .capacity_reservation_preference(CapacityReservationPreference::Open)
+                .build()
+        } else {
+            CapacityReservationSpecification::builder()
+                .capacity_reservation_target(
+                    CapacityReservationTarget::builder()
+                        .capacity_reservation_id(reservation)
+                        .build(),
+                )
+                .build()
+        };
+        self.client
+            .modify_instance_capacity_reservation_attributes()
+            .instance_id(&self.instance_id)
+            .capacity_reservation_specification(specification)
+            .send()
+            .await
+            .hint("ec2:ModifyInstanceCapacityReservationAttributes")?;
+        Ok(())
+    }
+
+    /// Credit option of a burstable instance, `standard` or `unlimited`
+    pub async fn credit_specification(&self) -> Result<Option<String>> {
+        let response = self
+            .client
+            .describe_instance_credit_specifications()
+            .instance_ids(&self.instance_id)
+            .send()
+            .await
+            .hint("ec2:DescribeInstanceCreditSpecifications")?;
+
+        Ok(response
+            .instance_credit_specifications()
+            .first()
+            .and_then(|specification| specification.cpu_credits())
+            .map(str::to_string))
+    }
+
+    /// Maintenance AWS scheduled for the instance, leaving out the completed and canceled events
+    pub async fn scheduled_events(&self) -> Result<Vec<ScheduledEvent>> {
+        let response = self
+            .client
+            .describe_instance_status()
+            .instance_ids(&self.instance_id)
+            .include_all_instances(true)
+            .send()
+            .await
+            .hint("ec2:DescribeInstanceStatus")?;
+
+        Ok(response
+            .instance_statuses()
+            .iter()
+            .flat_map(|status| status.events())
+            .filter_map(|event| {
+                let description = event.description().unwrap_or_default();
+                if description.starts_with("[Completed]") || description.starts_with("[Canceled]") {
+                    return None;
+                }
+                Some(ScheduledEvent {
+                    code: event.code()?.as_str().to_string(),
+                    description: description.to_string(),
+                    not_before: event.not_before().map(|time| {
+                        DateTime::from_timestamp(time.secs(), time.subsec_nanos())
+                            .unwrap_or_default()
+                    }),
+                })
+            })
+            .collect())
+    }
+
+    pub async fn set_credit_specification(&self, cpu_credits: &str) -> Result<()> {
+        self.client
+            .modify_instance_credit_specification()
+            .instance_credit_specifications(
+                InstanceCreditSpecificationRequest::builder()
+                    .instance_id(&self.instance_id)
+                    .cpu_credits(cpu_credits)
+                    .build(),
+            )
+            .send()
+            .await
+            .hint("ec2:ModifyInstanceCreditSpecification")?;
+        Ok(())
+    }
+
+    /// Changes the type of the instance, which must be stopped
+    pub async fn set_instance_type(&self, instance_type: &str) -> Result<()> {
+        self.client
+            .modify_instance_attribute()
+            .instance_id(&self.instance_id)
+            .instance_type(AttributeValue::builder().value(instance_type).build())
+            .send()
+            .await
+            .hint("ec2:ModifyInstanceAttribute")?;
+        Ok(())
+    }
+
+    /// Creates an AMI of the instance, returning its id
+    pub async fn create_image(&self, name: &str, no_reboot: bool) -> Result<String> {
+        let response = self
+            .client
+            .create_image()
+            .instance_id(&self.instance_id)
+            .name(name)
+            .no_reboot(no_reboot)
+            .send()
+            .await
+            .hint("ec2:CreateImage")?;
+
+        response
+            .image_id
+            .ok_or_else(|| eyre!("CreateImage returned no image id"))
+    }
+
+    /// Waits for the AMI to be available
+    pub async fn wait_for_image(&self, image_id: &str) -> Result<()> {
+        let mut wait_interval = tokio::time::interval(self.wait);
+        loop {
+            wait_interval.tick().await;
+            let response = self
+                .client
+                .describe_images()
+                .image_ids(image_id)
+                .send()
+                .await
+                .hint("ec2:DescribeImages")?;
+            match response.images().first().and_then(|image| image.state()) {
+                Some(ImageState::Available) => return Ok(()),
+                Some(ImageState::Pending) | None => {}
+                Some(state) => return Err(eyre!("AMI {} is {}", image_id, state.as_str())),
+            }
+        }
+    }
+
+    pub async fn volume_encrypted(&self, volume_id: &str) -> Result<bool> {
+        let response = self
+            .client
+            .describe_volumes()
+            .volume_ids(volume_id)
+            .send()
+            .await
+            .hint("ec2:DescribeVolumes")?;
+
+        response
+            .volumes()
+            .first()
+            .map(|volume| volume.encrypted().unwrap_or(false))
+            .ok_or_else(|| eyre!("Volume {} not found", volume_id))
+    }
+
+    /// Creates a tagged snapshot of a volume of the instance, returning its id
+    pub async fn create_snapshot(&self, device_name: &str, volume_id: &str) -> Result<String> {
+        let tags = TagSpecification::builder()
+            .resource_type(ResourceType::Snapshot)
+            .tags(
+                Tag::builder()
+                    .key("Name")
+                    .value(format!("{} {}", self.instance_id, device_name))
+                    .build(),
+            )
+            .tags(
+                Tag::builder()
+                    .key(format!("{}instance-id", SCHEDULE_PREFIX))
+                    .value(&self.instance_id)
+                    .build(),
+            )
+            .tags(
+                Tag::builder()
+                    .key(format!("{}device-name", SCHEDULE_PREFIX))
+                    .value(device_name)
+                    .build(),
+            )
+            .build();
+        let response = self
+            .client
+            .create_snapshot()
+            .volume_id(volume_id)
+            .description(format!(
+                "{} of {} before stopping",
+                device_name, self.instance_id
+            ))
+            .tag_specifications(tags)
+            .send()
+            .await
+            .hint("ec2:CreateSnapshot")?;
+
+        response
+            .snapshot_id
+            .ok_or_else(|| eyre!("CreateSnapshot returned no snapshot id"))
+    }
+
+    /// Waits for the snapshots to be completed
+    pub async fn wait_for_snapshots(&self, snapshot_ids: &[String]) -> Result<()> {
+        let mut wait_interval = tokio::time::interval(self.wait);
+        loop {
+            wait_interval.tick().await;
+            let response = self
+                .client
+                .describe_snapshots()
+                .set_snapshot_ids(Some(snapshot_ids.to_vec()))
+                .send()
+                .await
+                .hint("ec2:DescribeSnapshots")?;
+            let mut completed = true;
+            for snapshot in response.snapshots() {
+                match snapshot.state() {
+                    Some(SnapshotState::Completed) => {}
+                    Some(SnapshotState::Error) => {
+                        return Err(eyre!(
+                            "Snapshot {} failed: {}",
+                            snapshot.snapshot_id().unwrap_or_default(),
+                            snapshot.state_message().unwrap_or_default()
+                        ))
+                    }
+                    _ => completed = false,
+                }
+            }
+            if completed {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Waits for the volumes to be attached and in use, with an ok status
+    ///
+    /// Volumes whose status checks have insufficient data, as often right after starting, are
+    /// only warned about, the checks possibly not running for minutes. Impaired volumes are
+    /// warned about and waited for, their I/O being disabled until they recover.
+    pub async fn wait_for_volumes(&self, volume_ids: &[String]) -> Result<()> {
+        let mut warned = HashSet::new();
+        let mut wait_interval = tokio::time::interval(self.wait);
+        loop {
+            wait_interval.tick().await;
+            let response = self
+                .client
+                .describe_volumes()
+                .set_volume_ids(Some(volume_ids.to_vec()))
+                .send()
+                .await
+                .hint("ec2:DescribeVolumes")?;
+            let in_use = response
+                .volumes()
+                .iter()
+                .filter(|volume| {
+                    volume.state() == Some(&VolumeState::InUse)
+                        && volume.attachments().iter().any(|attachment| {
+                            attachment.state() == Some(&VolumeAttachmentState::Attached)
+                        })
+                })
+                .count();
+            if in_use < volume_ids.len() {
+                continue;
+            }
+
+            let response = self
+                .client
+                .describe_volume_status()
+                .set_volume_ids(Some(volume_ids.to_vec()))
+                .send()
+                .await
+                .hint("ec2:DescribeVolumeStatus")?;
+            let mut ready = true;
+            for volume in response.volume_statuses() {
+                let volume_id = volume.volume_id().unwrap_or_default();
+                let Some(status) = volume.volume_status().and_then(|info| info.status()) else {
+                    ready = false;
+                    continue;
+                };
+                let first = warned.insert(format!("{} {}", volume_id, status.as_str()));
+                match status {
+                    VolumeStatusInfoStatus::Ok => {}
+                    VolumeStatusInfoStatus::InsufficientData => {
+                        if first {
+                            log::warn(
+                                "volumes",
+                                format!(
+                                    "The status checks of volume {} have insufficient data, \
+                                    not waiting for them",
+                                    volume_id
+                                ),
+                            );
+                        }
+                    }
+                    _ => {
+                        if first {
+                            log::warn(
+                                "volumes",
+                                format!(
+                                    "Volume {} is {}, waiting for it to be ok",
+                                    volume_id,
+                                    status.as_str()
+                                ),
+                            );
+                        }
+                        ready = false;
+                    }
+                }
+            }
+            if ready {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Gets the instance's console output, if any has been captured yet
+    pub async fn get_console_output(&self) -> Result<Option<String>> {
+        let response = self
+            .client
+            .get_console_output()
+            .instance_id(&self.instance_id)
+            .send()
+            .await
+            .hint("ec2:GetConsoleOutput")?;
+
+        match response.output() {
+            None => Ok(None),
+            Some(output) => {
+                let output = aws_smithy_types::base64::decode(output)?;
+                Ok(Some(String::from_utf8_lossy(&output).into_owned()))
+            }
+        }
+    }
+
+    /// Gets a screenshot of the instance's console, as a JPEG image
+    pub async fn get_console_screenshot(&self) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .get_console_screenshot()
+            .instance_id(&self.instance_id)
+            .send()
+            .await
+            .hint("ec2:GetConsoleScreenshot")?;
+
+        let image = response
+            .image_data()
+            .ok_or_else(|| eyre!("No console screenshot available"))?;
+        Ok(aws_smithy_types::base64::decode(image)?)
+    }
+
+    /// Waits for the console output to contain `marker`
+    pub async fn wait_for_console_output(&self, marker: &str) -> Result<String> {
+        let mut wait_interval = tokio::time::interval(self.wait);
+        loop {
+            wait_interval.tick().await;
+            if let Some(output) = self.get_console_output().await? {
+                if output.contains(marker) {
+                    return Ok(output);
+                }
+            }
+        }
+    }
+
+    /// Waits for the encrypted Windows administrator password to be available
+    ///
+    /// It is only generated some time after the instance's first boot.
+    pub async fn wait_for_password_data(&self) -> Result<String> {
         let mut wait_interval = tokio::time::interval(self.wait);
         loop {
             wait_interval.tick().await;
-            let instance = self.get_instance().await?;
-            if check_state(instance.state(), &self.target_state)? {
-                return Ok(instance);
+            let response = self
+                .client
+                .get_password_data()
+                .instance_id(&self.instance_id)
+                .send()
+                .await
+                .hint("ec2:GetPasswordData")?;
+            match response.password_data() {
+                Some(data) if !data.is_empty() => return Ok(data.to_string()),
+                _ => {}
             }
         }
     }
+
+    /// Returns the current state if the instance is already in, or moving to, the target state
+    ///
+    /// In that case there's no need to request the change again, only to wait for it,
+    /// e.g. when running again after being interrupted.
+    pub async fn already_requested(&self) -> Result<Option<InstanceStateName>> {
+        let state = self.get_instance().await?.state().clone();
+        match check_state(&state, &self.target_state) {
+            Ok(_) => Ok(Some(state)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Describing the instance right after acting on it can fail to find it, or return its
+    /// previous state, so failures are retried at first
+    ///
+    /// `initial_state` is the state returned when requesting the change, if any. When it already
+    /// is the target, the instance is described once without waiting, and otherwise the first
+    /// description is put off by an interval, as it could only echo that state.
+    pub async fn wait_for_state(
+        &self,
+        initial_state: Option<&InstanceStateName>,
+    ) -> Result<Instance> {
+        if let Some(state) = initial_state {
+            progress::state_changed(&self.instance_id, state);
+            if *state == self.target_state {
+                return self.get_instance().await;
+            }
+        }
+
+        let mut wait_interval = tokio::time::interval(self.wait);
+        if initial_state.is_some() {
+            wait_interval.tick().await;
+        }
+        let mut last_state = initial_state.cloned();
+        let started = tokio::time::Instant::now();
+        let mut last_heartbeat = started;
+        loop {
+            wait_interval.tick().await;
+            let consistent = started.elapsed() >= CONSISTENCY_DELAY;
+            let instance = match self.get_instance().await {
+                Ok(instance) => instance,
+                Err(_) if !consistent => continue,
+                // Throttling or AWS failing to handle the call doesn't mean the instance won't
+                // reach the state
+                Err(e) if is_retryable(&e) => {
+                    log::info("retry", format!("{}, retrying", e));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+            if last_state.as_ref() != Some(instance.state()) {
+                progress::state_changed(&self.instance_id, instance.state());
+                last_state = Some(instance.state().clone());
+            }
+            if let Some(interval) = progress::heartbeat_interval() {
+                if last_heartbeat.elapsed() >= interval {
+                    progress::heartbeat(instance.state(), started.elapsed());
+                    last_heartbeat = tokio::time::Instant::now();
+                }
+            }
+            match check_state(instance.state(), &self.target_state) {
+                Ok(true) => return Ok(instance),
+                Ok(false) => {}
+                Err(_) if !consistent => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Whether the call failed in a way which may not last, e.g. throttled
+fn is_retryable(error: &color_eyre::Report) -> bool {
+    error
+        .downcast_ref::<AwsError>()
+        .is_some_and(AwsError::is_retryable)
+}
+
+/// Makes the call again while the instance isn't in a state allowing it yet, e.g. still stopping
+/// from a previous action, the timeout of the action being the deadline
+async fn retry_incorrect_state<T, E, R, F, Fut>(
+    wait: Duration,
+    call: F,
+) -> Result<T, SdkError<E, R>>
+where
+    E: ProvideErrorMetadata,
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, SdkError<E, R>>>,
+{
+    loop {
+        match call().await {
+            Err(e) if e.code() == Some("IncorrectInstanceState") => {
+                log::info(
+                    "retry",
+                    format!(
+                        "{}, retrying",
+                        e.message()
+                            .unwrap_or("The instance isn't ready for this yet")
+                    ),
+                );
+                tokio::time::sleep(wait).await;
+            }
+            result => return result,
+        }
+    }
 }
 
 /// Checks whether the current state is "before" or equal to the current state
@@ -194,6 +959,198 @@ fn check_state(
     }
 }
 
+/// Acts on all the instances matching some filters, without waiting for their state to change
+pub struct AwsEc2InstancesClient {
+    client: aws_sdk_ec2::client::Client,
+}
+
+impl AwsEc2InstancesClient {
+    pub fn new(client: aws_sdk_ec2::client::Client) -> Self {
+        Self { client }
+    }
+
+    /// Names of the regions enabled in the account
+    pub async fn enabled_regions(&self) -> Result<Vec<String>> {
+        let response = self
+            .client
+            .describe_regions()
+            .send()
+            .await
+            .hint("ec2:DescribeRegions")?;
+
+        Ok(response
+            .regions()
+            .iter()
+            .filter_map(|region| region.region_name())
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub async fn describe_instances(&self, filters: Vec<Filter>) -> Result<Vec<Instance>> {
+        let mut instances = vec![];
+        let mut next_token = None;
+        loop {
+            let response = ratelimit::call(
+                self.client
+                    .describe_instances()
+                    .set_filters(Some(filters.clone()))
+                    .set_next_token(next_token)
+                    .send(),
+            )
+            .await
+            .hint("ec2:DescribeInstances")?;
+            instances.extend(
+                response
+                    .reservations()
+                    .iter()
+                    .flat_map(|reservation| reservation.instances())
+                    .cloned()
+                    .map(Instance),
+            );
+            next_token = response.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(instances)
+    }
+
+    pub async fn change_state(&self, action: &Action, instance_ids: &[String]) -> Result<()> {
+        if instance_ids.is_empty() {
+            return Ok(());
+        }
+
+        match action {
+            Action::Start => {
+                ratelimit::call(
+                    self.client
+                        .start_instances()
+                        .set_instance_ids(Some(instance_ids.to_vec()))
+                        .send(),
+                )
+                .await
+                .hint("ec2:StartInstances")?;
+            }
+            Action::Stop => {
+                ratelimit::call(
+                    self.client
+                        .stop_instances()
+                        .set_instance_ids(Some(instance_ids.to_vec()))
+                        .send(),
+                )
+                .await
+                .hint("ec2:StopInstances")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Launches an instance from a launch template or an AMI, returning its id
+    pub async fn run_instance(&self, config: &LaunchConfig) -> Result<String> {
+        let launch_template = config.launch_template.as_ref().map(|template| {
+            let builder = LaunchTemplateSpecification::builder();
+            if template.starts_with("lt-") {
+                builder.launch_template_id(template).build()
+            } else {
+                builder.launch_template_name(template).build()
+            }
+        });
+        let tag_specification = (!config.tags.is_empty()).then(|| {
+            TagSpecification::builder()
+                .resource_type(ResourceType::Instance)
+                .set_tags(Some(
+                    config
+                        .tags
+                        .iter()
+                        .map(|(key, value)| Tag::builder().key(key).value(value).build())
+                        .collect(),
+                ))
+                .build()
+        });
+
+        let response = self
+            .client
+            .run_instances()
+            .min_count(1)
+            .max_count(1)
+            .set_launch_template(launch_template)
+            .set_image_id(config.image_id.clone())
+            .set_instance_type(config.instance_type.as_deref().map(InstanceType::from))
+            .set_subnet_id(config.subnet_id.clone())
+            .set_key_name(config.key_name.clone())
+            .set_tag_specifications(tag_specification.map(|spec| vec![spec]))
+            .send()
+            .await
+            .hint("ec2:RunInstances")?;
+
+        response
+            .instances()
+            .first()
+            .and_then(|instance| instance.instance_id())
+            .map(str::to_string)
+            .ok_or_else(|| eyre!("No instance was launched"))
+    }
+}
+
+/// Matches instances having the tag with the given value
+pub fn tag_filter(key: &str, value: &str) -> Filter {
+    Filter::builder()
+        .name(format!("tag:{}", key))
+        .values(value)
+        .build()
+}
+
+/// Matches instances which can be moved to the state of the action
+pub fn actionable_filter(action: &Action) -> Filter {
+    let state = match action {
+        Action::Start => InstanceStateName::Stopped,
+        Action::Stop => InstanceStateName::Running,
+    };
+    Filter::builder()
+        .name("instance-state-name")
+        .values(state.as_str())
+        .build()
+}
+
+pub struct AwsOrganizationsClient {
+    client: aws_sdk_organizations::client::Client,
+}
+
+impl AwsOrganizationsClient {
+    pub fn new(client: aws_sdk_organizations::client::Client) -> Self {
+        Self { client }
+    }
+
+    /// Lists the ids and names of the organization's accounts
+    pub async fn list_accounts(&self) -> Result<Vec<(String, String)>> {
+        let mut accounts = vec![];
+        let mut next_token = None;
+        loop {
+            let response = self
+                .client
+                .list_accounts()
+                .set_next_token(next_token)
+                .send()
+                .await
+                .hint("organizations:ListAccounts")?;
+            accounts.extend(response.accounts().iter().filter_map(|account| {
+                Some((
+                    account.id()?.to_string(),
+                    account.name().unwrap_or_default().to_string(),
+                ))
+            }));
+            next_token = response.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        Ok(accounts)
+    }
+}
+
 pub struct AwsSsmClient {
     pub client: aws_sdk_ssm::client::Client,
     pub instance_id: String,
@@ -201,13 +1158,21 @@ pub struct AwsSsmClient {
 }
 
 impl AwsSsmClient {
-    async fn get_connection_status(&self) -> Result<bool> {
+    pub async fn get_connection_status(&self) -> Result<bool> {
+        #[cfg(feature = "test-faults")]
+        {
+            faults::call("ssm:GetConnectionStatus").await?;
+            if faults::inject(faults::Fault::SsmNeverConnects) {
+                return Ok(false);
+            }
+        }
         let res = self
             .client
             .get_connection_status()
             .target(&self.instance_id)
             .send()
-            .await?;
+            .await
+            .hint("ssm:GetConnectionStatus")?;
 
         match res.status {
             None => Err(eyre!("SSM GetConnectionStatus returned nothing")),
@@ -232,4 +1197,522 @@ impl AwsSsmClient {
             }
         }
     }
+    /// Runs a script on the instance with Run Command and waits for it to finish
+    ///
+    /// `document` is `AWS-RunShellScript` or `AWS-RunPowerShellScript`.
+    pub async fn run_command(&self, document: &str, script: &str) -> Result<CommandResult> {
+        let started = Utc::now();
+        let response = self
+            .client
+            .send_command()
+            .instance_ids(&self.instance_id)
+            .document_name(document)
+            .parameters("commands", vec![script.to_string()])
+            .send()
+            .await
+            .hint("ssm:SendCommand")?;
+        let command_id = response
+            .command()
+            .and_then(|command| command.command_id())
+            .ok_or_else(|| eyre!("SSM SendCommand returned no command id"))?;
+
+        let mut wait_interval = tokio::time::interval(self.wait);
+        loop {
+            wait_interval.tick().await;
+            let result = self
+                .client
+                .get_command_invocation()
+                .command_id(command_id)
+                .instance_id(&self.instance_id)
+                .send()
+                .await;
+
+            // The invocation may not be visible right after sending the command
+            let invocation = match result {
+                Ok(invocation) => invocation,
+                Err(err) => match err.as_service_error() {
+                    Some(err) if err.is_invocation_does_not_exist() => continue,
+                    _ => return Err(AwsError::new(err, "ssm:GetCommandInvocation").into()),
+                },
+            };
+
+            let status = match invocation.status() {
+                None
+                | Some(CommandInvocationStatus::Pending)
+                | Some(CommandInvocationStatus::InProgress)
+                | Some(CommandInvocationStatus::Delayed)
+                | Some(CommandInvocationStatus::Cancelling) => continue,
+                Some(status) => status,
+            };
+
+            let result = CommandResult {
+                success: *status == CommandInvocationStatus::Success,
+                status: status.as_str().to_string(),
+                output: invocation
+                    .standard_output_content()
+                    .unwrap_or_default()
+                    .to_string(),
+                error: invocation
+                    .standard_error_content()
+                    .unwrap_or_default()
+                    .to_string(),
+            };
+            capture::save(
+                &self.instance_id,
+                command_id,
+                document,
+                script,
+                started,
+                &result,
+            );
+            return Ok(result);
+        }
+    }
+}
+
+/// Outcome of a script run with Run Command
+pub struct CommandResult {
+    pub success: bool,
+    pub status: String,
+    pub output: String,
+    pub error: String,
+}
+
+pub struct AwsCloudWatchClient {
+    pub client: aws_sdk_cloudwatch::client::Client,
+    pub instance_id: String,
+}
+
+impl AwsCloudWatchClient {
+    /// Period of the datapoints, matching EC2's basic monitoring
+    pub const PERIOD: Duration = Duration::from_secs(300);
+
+    fn dimension(&self) -> Result<Dimension> {
+        Ok(Dimension::builder()
+            .name("InstanceId")
+            .value(&self.instance_id)
+            .build()?)
+    }
+
+    /// Puts a datapoint of each metric, named with its value and unit, dimensioned by the instance
+    pub async fn put_metrics(
+        &self,
+        namespace: &str,
+        metrics: &[(&str, f64, StandardUnit)],
+    ) -> Result<()> {
+        let mut request = self.client.put_metric_data().namespace(namespace);
+        for (name, value, unit) in metrics {
+            request = request.metric_data(
+                MetricDatum::builder()
+                    .metric_name(*name)
+                    .dimensions(self.dimension()?)
+                    .value(*value)
+                    .unit(unit.clone())
+                    .build(),
+            );
+        }
+        request.send().await.hint("cloudwatch:PutMetricData")?;
+        Ok(())
+    }
+
+    /// Creates or updates an alarm stopping the instance once its CPU utilization has stayed
+    /// below `threshold` for `periods` periods
+    ///
+    /// Returns the name of the alarm.
+    pub async fn put_idle_alarm(
+        &self,
+        region: &str,
+        threshold: f64,
+        periods: i32,
+    ) -> Result<String> {
+        let name = format!("{}idle-{}", SCHEDULE_PREFIX, self.instance_id);
+        self.client
+            .put_metric_alarm()
+            .alarm_name(&name)
+            .alarm_description("Stops the instance when it is idle")
+            .namespace("AWS/EC2")
+            .metric_name("CPUUtilization")
+            .dimensions(self.dimension()?)
+            .statistic(Statistic::Maximum)
+            .period(Self::PERIOD.as_secs() as i32)
+            .evaluation_periods(periods)
+            .threshold(threshold)
+            .comparison_operator(ComparisonOperator::LessThanThreshold)
+            .treat_missing_data("notBreaching")
+            .alarm_actions(format!("arn:aws:automate:{}:ec2:stop", region))
+            .send()
+            .await
+            .hint("cloudwatch:PutMetricAlarm")?;
+
+        Ok(name)
+    }
+
+    /// Gets the given statistic of an EC2 metric of the instance, for each period of the window
+    pub async fn get_statistics(
+        &self,
+        metric: &str,
+        statistic: Statistic,
+        window: Duration,
+    ) -> Result<Vec<f64>> {
+        let end = SystemTime::now();
+        let response = self
+            .client
+            .get_metric_statistics()
+            .namespace("AWS/EC2")
+            .metric_name(metric)
+            .dimensions(self.dimension()?)
+            .start_time((end - window).into())
+            .end_time(end.into())
+            .period(Self::PERIOD.as_secs() as i32)
+            .statistics(statistic.clone())
+            .send()
+            .await
+            .hint("cloudwatch:GetMetricStatistics")?;
+
+        Ok(response
+            .datapoints()
+            .iter()
+            .filter_map(|datapoint| match statistic {
+                Statistic::Sum => datapoint.sum(),
+                Statistic::Minimum => datapoint.minimum(),
+                Statistic::Average => datapoint.average(),
+                _ => datapoint.maximum(),
+            })
+            .collect())
+    }
+}
+
+/// Prefix of the names of the schedules created by this program
+pub const SCHEDULE_PREFIX: &str = "aws-start-stop-";
+
+/// Action on an instance recorded by CloudTrail
+pub struct HistoryEvent {
+    pub time: DateTime<Utc>,
+    pub name: String,
+    pub username: Option<String>,
+}
+
+/// Looks up who acted on an instance in CloudTrail
+pub struct AwsCloudTrailClient {
+    pub client: aws_sdk_cloudtrail::client::Client,
+}
+
+impl AwsCloudTrailClient {
+    /// Returns the events named among `names` about the instance since the given time, oldest first
+    pub async fn instance_events(
+        &self,
+        instance_id: &str,
+        names: &[&str],
+        since: DateTime<Utc>,
+    ) -> Result<Vec<HistoryEvent>> {
+        let attribute = LookupAttribute::builder()
+            .attribute_key(LookupAttributeKey::ResourceName)
+            .attribute_value(instance_id)
+            .build()?;
+
+        let mut events = vec![];
+        let mut next_token = None;
+        loop {
+            let response = self
+                .client
+                .lookup_events()
+                .lookup_attributes(attribute.clone())
+                .start_time(aws_smithy_types::DateTime::from_secs(since.timestamp()))
+                .set_next_token(next_token)
+                .send()
+                .await
+                .hint("cloudtrail:LookupEvents")?;
+            for event in response.events() {
+                let (Some(name), Some(time)) = (event.event_name(), event.event_time()) else {
+                    continue;
+                };
+                if !names.contains(&name) {
+                    continue;
+                }
+                events.push(HistoryEvent {
+                    time: DateTime::from_timestamp(time.secs(), time.subsec_nanos())
+                        .unwrap_or_default(),
+                    name: name.to_string(),
+                    username: event.username().map(str::to_string),
+                });
+            }
+            next_token = response.next_token().map(str::to_string);
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        // CloudTrail returns the most recent events first
+        events.reverse();
+        Ok(events)
+    }
+}
+
+/// Moves an instance of an Auto Scaling group in and out of service
+pub struct AwsAutoScalingClient {
+    pub client: aws_sdk_autoscaling::client::Client,
+    pub instance_id: String,
+    pub wait: Duration,
+}
+
+impl AwsAutoScalingClient {
+    /// Returns the group and lifecycle state of the instance, if it belongs to a group
+    pub async fn membership(&self) -> Result<Option<(String, LifecycleState)>> {
+        let response = self
+            .client
+            .describe_auto_scaling_instances()
+            .instance_ids(&self.instance_id)
+            .send()
+            .await
+            .hint("autoscaling:DescribeAutoScalingInstances")?;
+
+        Ok(response.auto_scaling_instances().first().map(|instance| {
+            (
+                instance.auto_scaling_group_name().to_string(),
+                LifecycleState::from(instance.lifecycle_state()),
+            )
+        }))
+    }
+
+    /// Whether instances scaled in go back to the warm pool of the group rather than terminating
+    pub async fn reuses_on_scale_in(&self, group: &str) -> Result<bool> {
+        let response = self
+            .client
+            .describe_warm_pool()
+            .auto_scaling_group_name(group)
+            .send()
+            .await
+            .hint("autoscaling:DescribeWarmPool")?;
+
+        Ok(response
+            .warm_pool_configuration()
+            .and_then(|warm_pool| warm_pool.instance_reuse_policy())
+            .and_then(|policy| policy.reuse_on_scale_in())
+            .unwrap_or(false))
+    }
+
+    /// Scales the group in by this instance, which then goes back to the warm pool
+    pub async fn return_to_warm_pool(&self) -> Result<()> {
+        self.client
+            .terminate_instance_in_auto_scaling_group()
+            .instance_id(&self.instance_id)
+            .should_decrement_desired_capacity(true)
+            .send()
+            .await
+            .hint("autoscaling:TerminateInstanceInAutoScalingGroup")?;
+        Ok(())
+    }
+
+    pub async fn enter_standby(&self, group: &str) -> Result<()> {
+        self.client
+            .enter_standby()
+            .auto_scaling_group_name(group)
+            .instance_ids(&self.instance_id)
+            .should_decrement_desired_capacity(true)
+            .send()
+            .await
+            .hint("autoscaling:EnterStandby")?;
+        Ok(())
+    }
+
+    pub async fn exit_standby(&self, group: &str) -> Result<()> {
+        self.client
+            .exit_standby()
+            .auto_scaling_group_name(group)
+            .instance_ids(&self.instance_id)
+            .send()
+            .await
+            .hint("autoscaling:ExitStandby")?;
+        Ok(())
+    }
+
+    /// Waits for the lifecycle state of the instance to match
+    pub async fn wait_for_lifecycle(&self, done: impl Fn(&LifecycleState) -> bool) -> Result<()> {
+        let mut wait_interval = tokio::time::interval(self.wait);
+        loop {
+            wait_interval.tick().await;
+            match self.membership().await? {
+                Some((_, state)) if done(&state) => return Ok(()),
+                Some(_) => {}
+                None => return Err(eyre!("The instance left its Auto Scaling group")),
+            }
+        }
+    }
+}
+
+pub struct Schedule {
+    pub name: String,
+    pub state: String,
+    pub expression: String,
+    pub timezone: String,
+}
+
+pub struct AwsSchedulerClient {
+    client: aws_sdk_scheduler::client::Client,
+}
+
+impl AwsSchedulerClient {
+    pub fn new(client: aws_sdk_scheduler::client::Client) -> Self {
+        Self { client }
+    }
+
+    pub async fn create_schedule(
+        &self,
+        name: &str,
+        expression: &str,
+        timezone: &str,
+        target: Target,
+        action_after_completion: ActionAfterCompletion,
+    ) -> Result<()> {
+        let time_window = FlexibleTimeWindow::builder()
+            .mode(FlexibleTimeWindowMode::Off)
+            .build()?;
+
+        self.client
+            .create_schedule()
+            .name(name)
+            .schedule_expression(expression)
+            .schedule_expression_timezone(timezone)
+            .flexible_time_window(time_window)
+            .target(target)
+            .action_after_completion(action_after_completion)
+            .send()
+            .await
+            .hint("scheduler:CreateSchedule")?;
+
+        Ok(())
+    }
+
+    /// Creates a one-time schedule which stops the instance at the given time
+    ///
+    /// The schedule is deleted once it has run.
+    /// Returns the name of the created schedule.
+    pub async fn schedule_once(
+        &self,
+        action: &Action,
+        instance_id: &str,
+        at: DateTime<Utc>,
+        role_arn: &str,
+    ) -> Result<String> {
+        let name = format!(
+            "{}{}-{}-{}",
+            SCHEDULE_PREFIX,
+            action,
+            instance_id,
+            at.timestamp()
+        );
+        let expression = format!("at({})", at.format("%Y-%m-%dT%H:%M:%S"));
+        let target = ec2_target(action, instance_id, role_arn)?;
+
+        self.create_schedule(
+            &name,
+            &expression,
+            "UTC",
+            target,
+            ActionAfterCompletion::Delete,
+        )
+        .await?;
+
+        Ok(name)
+    }
+
+    /// Lists the schedules whose name starts with `SCHEDULE_PREFIX`
+    pub async fn list_schedules(&self) -> Result<Vec<Schedule>> {
+        let mut names = vec![];
+        let mut next_token = None;
+        loop {
+            let response = self
+                .client
+                .list_schedules()
+                .name_prefix(SCHEDULE_PREFIX)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .hint("scheduler:ListSchedules")?;
+            names.extend(
+                response
+                    .schedules()
+                    .iter()
+                    .filter_map(|summary| summary.name().map(str::to_string)),
+            );
+            next_token = response.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+
+        // The summaries don't include the expressions, so get every schedule
+        let mut schedules = Vec::with_capacity(names.len());
+        for name in names {
+            let response = self
+                .client
+                .get_schedule()
+                .name(&name)
+                .send()
+                .await
+                .hint("scheduler:GetSchedule")?;
+            schedules.push(Schedule {
+                state: response
+                    .state()
+                    .map(|state| state.as_str().to_string())
+                    .unwrap_or_default(),
+                expression: response
+                    .schedule_expression()
+                    .unwrap_or_default()
+                    .to_string(),
+                timezone: response
+                    .schedule_expression_timezone()
+                    .unwrap_or("UTC")
+                    .to_string(),
+                name,
+            });
+        }
+
+        Ok(schedules)
+    }
+
+    pub async fn delete_schedule(&self, name: &str) -> Result<()> {
+        self.client
+            .delete_schedule()
+            .name(name)
+            .send()
+            .await
+            .hint("scheduler:DeleteSchedule")?;
+        Ok(())
+    }
+}
+
+/// Builds a target calling StartInstances or StopInstances through the scheduler's universal target
+pub fn ec2_target(action: &Action, instance_id: &str, role_arn: &str) -> Result<Target> {
+    let api = match action {
+        Action::Start => "startInstances",
+        Action::Stop => "stopInstances",
+    };
+    let input = serde_json::json!({ "InstanceIds": [instance_id] }).to_string();
+
+    Ok(Target::builder()
+        .arn(format!("arn:aws:scheduler:::aws-sdk:ec2:{}", api))
+        .role_arn(role_arn)
+        .input(input)
+        .build()?)
+}
+
+/// Builds a target invoking this program deployed as a Lambda function
+pub fn lambda_target(
+    function_arn: &str,
+    action: &Action,
+    instance_id: &str,
+    role_arn: &str,
+) -> Result<Target> {
+    let input = serde_json::json!({
+        "action": action.to_string(),
+        "instance_id": instance_id,
+    })
+    .to_string();
+
+    Ok(Target::builder()
+        .arn(function_arn)
+        .role_arn(role_arn)
+        .input(input)
+        .build()?)
 }
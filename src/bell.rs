@@ -0,0 +1,73 @@
+use crate::log;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::future::Future;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// What to do once the actions are done
+struct Bell {
+    /// Sound file played on top of the terminal bell
+    sound: Option<PathBuf>,
+}
+
+static BELL: OnceLock<Bell> = OnceLock::new();
+
+/// Rings the terminal bell when the actions are done from now on, and plays the sound if any
+pub fn init(enabled: bool, sound: Option<PathBuf>) {
+    if enabled {
+        let _ = BELL.set(Bell { sound });
+    }
+}
+
+/// Rings once when the actions succeeded and twice when they failed, to tell them apart
+///
+/// The bell goes to stderr, as stdout may be reserved for the result.
+pub async fn ring(succeeded: bool) {
+    let Some(bell) = BELL.get() else {
+        return;
+    };
+    let rings = if succeeded { "\x07" } else { "\x07\x07" };
+    let mut stderr = std::io::stderr();
+    let _ = stderr
+        .write_all(rings.as_bytes())
+        .and_then(|_| stderr.flush());
+    if let Some(sound) = &bell.sound {
+        if let Err(e) = play(sound).await {
+            log::warn("bell", format!("Failed to play {}: {}", sound.display(), e));
+        }
+    }
+}
+
+/// Waits for the actions and rings once they are done
+pub async fn after<T>(actions: impl Future<Output = Result<T>>) -> Result<T> {
+    let result = actions.await;
+    ring(result.is_ok()).await;
+    result
+}
+
+/// Plays the file with the player that comes with the system
+async fn play(sound: &Path) -> Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut command = tokio::process::Command::new("afplay");
+        command.arg(sound);
+        command
+    } else if cfg!(windows) {
+        let mut command = tokio::process::Command::new("powershell");
+        command.arg("-NoProfile").arg("-Command").arg(format!(
+            "(New-Object Media.SoundPlayer '{}').PlaySync()",
+            sound.display().to_string().replace('\'', "''")
+        ));
+        command
+    } else {
+        let mut command = tokio::process::Command::new("paplay");
+        command.arg(sound);
+        command
+    };
+    let status = command.status().await?;
+    if !status.success() {
+        return Err(eyre!("Player failed with {}", status));
+    }
+    Ok(())
+}
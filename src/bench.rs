@@ -0,0 +1,220 @@
+use crate::aws::{self, AwsEc2Client, AwsSsmClient};
+use crate::clients;
+use crate::config::Action;
+use crate::log::{self, Level};
+use crate::protection;
+use crate::readiness::{HttpProbe, ReadinessCheck, TcpProbe};
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::future::Future;
+use std::net::SocketAddr;
+use tokio::time::{timeout, Duration, Instant};
+
+/// How often the instance and its SSM agent are polled, finer than when acting on it, as the
+/// durations can't be more precise
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+pub struct BenchConfig {
+    pub instance_id: String,
+    /// Number of stop and start cycles measured
+    pub iterations: u32,
+    /// How long stopping, and starting until ready, may take in each cycle
+    pub timeout: u64,
+    /// Measure how long the SSM agent takes to connect
+    pub ssm: bool,
+    pub wait_for_port: Option<u16>,
+    /// URL with `{ip}` replaced by the instance's address
+    pub wait_for_http: Option<String>,
+}
+
+/// How long after starting to be requested the instance reached each point of a cycle
+struct Cycle {
+    running: Duration,
+    ssm: Option<Duration>,
+    probe: Option<Duration>,
+}
+
+/// Stops and starts the instance the given number of times, then prints the statistics of how
+/// long it took to be running, for its SSM agent to connect and for the probe to pass
+pub async fn run(aws_config: &SdkConfig, config: BenchConfig) -> Result<()> {
+    let stopping = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        &config.instance_id,
+        InstanceStateName::Stopped,
+        POLL_INTERVAL,
+    );
+    let starting = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        &config.instance_id,
+        InstanceStateName::Running,
+        POLL_INTERVAL,
+    );
+    protection::check_action(&Action::Stop, &stopping.get_instance().await?)?;
+
+    let mut cycles = vec![];
+    for iteration in 1..=config.iterations {
+        log::info(
+            "bench",
+            format!("Cycle {} of {}: stopping...", iteration, config.iterations),
+        );
+        within(config.timeout, "stop", async {
+            let state = stopping.stop_instance().await?;
+            stopping.wait_for_state(Some(&state)).await
+        })
+        .await?;
+
+        log::info(
+            "bench",
+            format!("Cycle {} of {}: starting...", iteration, config.iterations),
+        );
+        let cycle = within(
+            config.timeout,
+            "start",
+            cycle(aws_config, &config, &starting),
+        )
+        .await?;
+        log::info(
+            "bench",
+            format!(
+                "Cycle {} of {}: running after {}",
+                iteration,
+                config.iterations,
+                seconds(cycle.running)
+            ),
+        );
+        cycles.push(cycle);
+    }
+
+    print(&cycles);
+    Ok(())
+}
+
+async fn within<T>(
+    timeout_secs: u64,
+    phase: &str,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    timeout(Duration::from_secs(timeout_secs), future)
+        .await
+        .map_err(|_| eyre!("Failed to {}: timed out after {}s", phase, timeout_secs))?
+}
+
+/// Starts the instance, then waits for its SSM agent and the probe at the same time, each
+/// measured on its own
+async fn cycle(
+    aws_config: &SdkConfig,
+    config: &BenchConfig,
+    client: &AwsEc2Client,
+) -> Result<Cycle> {
+    let started = Instant::now();
+    let state = client.start_instance().await?;
+    let instance = client.wait_for_state(Some(&state)).await?;
+    let running = started.elapsed();
+
+    let ssm = async {
+        if !config.ssm {
+            return Ok(None);
+        }
+        let aws_ssm_client = AwsSsmClient {
+            client: clients::ssm(aws_config),
+            instance_id: config.instance_id.clone(),
+            wait: POLL_INTERVAL,
+        };
+        aws_ssm_client.wait_for_connection().await?;
+        Ok::<_, color_eyre::Report>(Some(started.elapsed()))
+    };
+    let probe = async {
+        let ip = || {
+            instance
+                .address()
+                .ok_or_else(|| eyre!("The instance has no IP address to probe"))
+        };
+        let probe: Box<dyn ReadinessCheck> = match (config.wait_for_port, &config.wait_for_http) {
+            (Some(port), _) => Box::new(TcpProbe {
+                address: SocketAddr::new(ip()?, port),
+            }),
+            (None, Some(url)) => Box::new(HttpProbe {
+                url: url.replace("{ip}", &aws::url_host(&ip()?)),
+            }),
+            (None, None) => return Ok(None),
+        };
+        probe.wait(&config.instance_id).await?;
+        Ok::<_, color_eyre::Report>(Some(started.elapsed()))
+    };
+    let (ssm, probe) = tokio::try_join!(ssm, probe)?;
+
+    Ok(Cycle {
+        running,
+        ssm,
+        probe,
+    })
+}
+
+/// Minimum, median and 95th percentile, by nearest rank, of the durations
+fn statistics(mut durations: Vec<Duration>) -> Option<[Duration; 3]> {
+    durations.sort();
+    let rank = |percentile: usize| ((durations.len() * percentile + 99) / 100).max(1) - 1;
+    Some([
+        *durations.first()?,
+        durations[rank(50)],
+        durations[rank(95)],
+    ])
+}
+
+fn seconds(duration: Duration) -> String {
+    format!("{:.1}s", duration.as_secs_f64())
+}
+
+fn print(cycles: &[Cycle]) {
+    let metrics: [(&str, Option<Vec<Duration>>); 3] = [
+        (
+            "running",
+            cycles.iter().map(|cycle| Some(cycle.running)).collect(),
+        ),
+        ("ssm", cycles.iter().map(|cycle| cycle.ssm).collect()),
+        ("probe", cycles.iter().map(|cycle| cycle.probe).collect()),
+    ];
+    // Metrics which weren't measured have no durations
+    let rows: Vec<(&str, [Duration; 3])> = metrics
+        .into_iter()
+        .filter_map(|(metric, durations)| Some((metric, statistics(durations?)?)))
+        .collect();
+
+    if log::is_json() {
+        let fields: serde_json::Map<String, serde_json::Value> = rows
+            .iter()
+            .map(|(metric, [min, median, p95])| {
+                (
+                    format!("{}_seconds", metric),
+                    serde_json::json!({
+                        "min": min.as_secs_f64(),
+                        "median": median.as_secs_f64(),
+                        "p95": p95.as_secs_f64(),
+                    }),
+                )
+            })
+            .collect();
+        log::event(
+            Level::Info,
+            "bench",
+            format!("{} cycles", cycles.len()),
+            serde_json::json!({ "cycles": cycles.len(), "statistics": fields }),
+        );
+        return;
+    }
+
+    println!();
+    println!("{:<10}{:>10}{:>10}{:>10}", "UNTIL", "MIN", "MEDIAN", "P95");
+    for (metric, [min, median, p95]) in rows {
+        println!(
+            "{:<10}{:>10}{:>10}{:>10}",
+            metric,
+            seconds(min),
+            seconds(median),
+            seconds(p95)
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use crate::aws::{AwsEc2Client, Instance};
+use crate::clients;
+use crate::config::{Action, Config};
+use crate::log;
+use crate::work;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::time::{timeout, Duration};
+
+/// Instance managed by a bot, optionally known by a shorter name in commands
+#[derive(Debug, Clone)]
+pub struct BotInstance {
+    pub name: Option<String>,
+    pub instance_id: String,
+}
+
+impl BotInstance {
+    /// Name commands refer to the instance by
+    pub fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.instance_id)
+    }
+}
+
+/// Command sent to a chat bot
+#[derive(Debug, Clone, Copy)]
+pub enum BotCommand {
+    Start,
+    Stop,
+    Status,
+}
+
+/// Runs the command on the instance, returning the reply describing it
+pub async fn execute(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    command: BotCommand,
+    timeout_secs: u64,
+) -> Result<String> {
+    let action = match command {
+        BotCommand::Start => Action::Start,
+        BotCommand::Stop => Action::Stop,
+        BotCommand::Status => {
+            // The target state is only used when waiting
+            let aws_ec2_client = AwsEc2Client::new(
+                clients::ec2(aws_config),
+                instance_id,
+                InstanceStateName::Running,
+                Duration::from_secs(10),
+            );
+            return Ok(describe(&aws_ec2_client.get_instance().await?));
+        }
+    };
+
+    let mut config = Config::new(action.clone(), instance_id.to_string());
+    config.timeout = timeout_secs;
+    let future = timeout(
+        Duration::from_secs(timeout_secs),
+        work(aws_config, &config, &action),
+    );
+    match log::with_instance(instance_id, future).await {
+        Ok(instance) => Ok(describe(&instance?)),
+        Err(_) => Err(eyre!("Timed out waiting for {} to {}", instance_id, action)),
+    }
+}
+
+/// Picks the instance a command is about, which may be omitted when there is only one
+pub fn select_instance(requested: Option<&str>, instances: &[BotInstance]) -> Result<String> {
+    match (requested, instances) {
+        (Some(requested), instances) => instances
+            .iter()
+            .find(|instance| instance.name() == requested || instance.instance_id == requested)
+            .map(|instance| instance.instance_id.clone())
+            .ok_or_else(|| eyre!("{} isn't managed by this bot", requested)),
+        (None, [instance]) => Ok(instance.instance_id.clone()),
+        (None, instances) => {
+            let names: Vec<&str> = instances.iter().map(BotInstance::name).collect();
+            Err(eyre!("Which instance? {}", names.join(", ")))
+        }
+    }
+}
+
+fn describe(instance: &Instance) -> String {
+    let mut reply = format!(
+        "{} is {}",
+        instance.instance_id(),
+        instance.state().as_str()
+    );
+    if let Some(ip) = instance.ipv4_address_public() {
+        reply.push_str(&format!("\npublic IPv4: {}", ip));
+    }
+    if let Some(ip) = instance.ipv4_address_private() {
+        reply.push_str(&format!("\nprivate IPv4: {}", ip));
+    }
+    if let Some(ip) = instance.ipv6_address() {
+        reply.push_str(&format!("\nIPv6: {}", ip));
+    }
+    reply
+}
@@ -0,0 +1,62 @@
+use crate::clients;
+use crate::credentials;
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+/// Fails when the actual spend of the budget this period is above this share, in percent, of its
+/// limit, or only warns when forced
+pub async fn guard(
+    aws_config: &SdkConfig,
+    budget_name: &str,
+    max_percent: f64,
+    force: bool,
+) -> Result<()> {
+    let account_id = credentials::account_id(aws_config).await?;
+    let response = clients::budgets(aws_config)
+        .describe_budget()
+        .account_id(account_id)
+        .budget_name(budget_name)
+        .send()
+        .await
+        .hint("budgets:ViewBudget")?;
+    let budget = response
+        .budget()
+        .ok_or_else(|| eyre!("Budget {} not found", budget_name))?;
+    let limit = budget
+        .budget_limit()
+        .ok_or_else(|| eyre!("Budget {} has no fixed limit", budget_name))?;
+    let actual = budget
+        .calculated_spend()
+        .and_then(|spend| spend.actual_spend())
+        .map_or(Ok(0.0), |spend| spend.amount().parse::<f64>())?;
+    let limit_amount: f64 = limit.amount().parse()?;
+    if limit_amount <= 0.0 {
+        return Err(eyre!("Budget {} has no limit", budget_name));
+    }
+
+    let percent = actual / limit_amount * 100.0;
+    let message = format!(
+        "Budget {} is at {:.0}% ({:.2} of {:.2} {})",
+        budget_name,
+        percent,
+        actual,
+        limit_amount,
+        limit.unit()
+    );
+    if percent <= max_percent {
+        log::info("budget", message);
+        return Ok(());
+    }
+    if force {
+        log::warn("budget", format!("{}, starting anyway", message));
+        return Ok(());
+    }
+    Err(eyre!(
+        "{}, above {}%, use --force-budget to start anyway",
+        message,
+        max_percent
+    ))
+}
@@ -0,0 +1,65 @@
+use crate::aws::CommandResult;
+use crate::log;
+use chrono::{DateTime, SecondsFormat, Utc};
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+static DIRECTORY: OnceLock<PathBuf> = OnceLock::new();
+
+/// Saves the output of the commands run on the instances through SSM to files of the directory
+/// from now on, creating it if needed
+pub fn init(directory: Option<&Path>) -> Result<()> {
+    if let Some(directory) = directory {
+        std::fs::create_dir_all(directory)
+            .wrap_err_with(|| format!("Failed to create {}", directory.display()))?;
+        let _ = DIRECTORY.set(directory.to_path_buf());
+    }
+    Ok(())
+}
+
+/// Writes the command and its output to a file named after the time, instance and command, only
+/// reporting a failure to do so
+///
+/// SSM only keeps the first 24,000 characters of each output, which is all that can be saved.
+pub fn save(
+    instance_id: &str,
+    command_id: &str,
+    document: &str,
+    script: &str,
+    started: DateTime<Utc>,
+    result: &CommandResult,
+) {
+    let Some(directory) = DIRECTORY.get() else {
+        return;
+    };
+
+    let finished = Utc::now();
+    let path = directory.join(format!(
+        "{}-{}-{}.log",
+        started.format("%Y%m%dT%H%M%SZ"),
+        instance_id,
+        command_id
+    ));
+    let contents = format!(
+        "instance: {}\ncommand id: {}\ndocument: {}\nstarted: {}\nfinished: {}\nstatus: {}\n\
+        \n--- command\n{}\n--- stdout\n{}\n--- stderr\n{}\n",
+        instance_id,
+        command_id,
+        document,
+        started.to_rfc3339_opts(SecondsFormat::Secs, true),
+        finished.to_rfc3339_opts(SecondsFormat::Secs, true),
+        result.status,
+        script.trim_end(),
+        result.output.trim_end(),
+        result.error.trim_end()
+    );
+    match std::fs::write(&path, contents) {
+        Ok(()) => log::info("capture", format!("Saved the output to {}", path.display())),
+        Err(e) => log::warn(
+            "capture",
+            format!("Failed to write {}: {}", path.display(), e),
+        ),
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{health, report};
+use aws_config::SdkConfig;
+use aws_credential_types::provider::ProvideCredentials;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Service, region and credentials provider a client was built for
+type Key = (TypeId, Option<String>, usize);
+
+/// Clients already built, shared by all the instances and tasks using the same configuration
+///
+/// Building a client sets up its runtime components, and each one opens its own connections, so
+/// they're only built once per service for a configuration, when first used. The configuration
+/// may change, e.g. per region or assumed role, hence it being part of the key.
+static CLIENTS: OnceLock<Mutex<HashMap<Key, Box<dyn Any + Send>>>> = OnceLock::new();
+
+fn get<C: Clone + Send + 'static>(aws_config: &SdkConfig, new: fn(&SdkConfig) -> C) -> C {
+    // The provider is kept alive by the cached client, so its address can't be reused
+    let provider = aws_config.credentials_provider().map_or(0, |provider| {
+        provider.as_ref() as *const _ as *const () as usize
+    });
+    let key = (
+        TypeId::of::<C>(),
+        aws_config.region().map(ToString::to_string),
+        provider,
+    );
+    let mut clients = CLIENTS.get_or_init(Default::default).lock().unwrap();
+    clients
+        .entry(key)
+        .or_insert_with(|| Box::new(new(aws_config)))
+        .downcast_ref::<C>()
+        .unwrap()
+        .clone()
+}
+
+pub fn autoscaling(aws_config: &SdkConfig) -> aws_sdk_autoscaling::client::Client {
+    get(aws_config, aws_sdk_autoscaling::client::Client::new)
+}
+
+pub fn budgets(aws_config: &SdkConfig) -> aws_sdk_budgets::client::Client {
+    get(aws_config, aws_sdk_budgets::client::Client::new)
+}
+
+pub fn cloudformation(aws_config: &SdkConfig) -> aws_sdk_cloudformation::client::Client {
+    get(aws_config, aws_sdk_cloudformation::client::Client::new)
+}
+
+pub fn cloudtrail(aws_config: &SdkConfig) -> aws_sdk_cloudtrail::client::Client {
+    get(aws_config, aws_sdk_cloudtrail::client::Client::new)
+}
+
+pub fn cloudwatch(aws_config: &SdkConfig) -> aws_sdk_cloudwatch::client::Client {
+    get(aws_config, aws_sdk_cloudwatch::client::Client::new)
+}
+
+pub fn cloudwatchlogs(aws_config: &SdkConfig) -> aws_sdk_cloudwatchlogs::client::Client {
+    get(aws_config, aws_sdk_cloudwatchlogs::client::Client::new)
+}
+
+pub fn ec2(aws_config: &SdkConfig) -> aws_sdk_ec2::client::Client {
+    get(aws_config, |aws_config| {
+        aws_sdk_ec2::client::Client::from_conf(
+            aws_sdk_ec2::config::Builder::from(aws_config)
+                .interceptor(report::RequestIds)
+                .interceptor(health::Calls)
+                .build(),
+        )
+    })
+}
+
+pub fn ec2instanceconnect(aws_config: &SdkConfig) -> aws_sdk_ec2instanceconnect::client::Client {
+    get(aws_config, aws_sdk_ec2instanceconnect::client::Client::new)
+}
+
+pub fn ecs(aws_config: &SdkConfig) -> aws_sdk_ecs::client::Client {
+    get(aws_config, aws_sdk_ecs::client::Client::new)
+}
+
+pub fn elb(aws_config: &SdkConfig) -> aws_sdk_elasticloadbalancingv2::client::Client {
+    get(
+        aws_config,
+        aws_sdk_elasticloadbalancingv2::client::Client::new,
+    )
+}
+
+pub fn iam(aws_config: &SdkConfig) -> aws_sdk_iam::client::Client {
+    get(aws_config, aws_sdk_iam::client::Client::new)
+}
+
+pub fn organizations(aws_config: &SdkConfig) -> aws_sdk_organizations::client::Client {
+    get(aws_config, aws_sdk_organizations::client::Client::new)
+}
+
+pub fn pricing(aws_config: &SdkConfig) -> aws_sdk_pricing::client::Client {
+    get(aws_config, aws_sdk_pricing::client::Client::new)
+}
+
+pub fn route53(aws_config: &SdkConfig) -> aws_sdk_route53::client::Client {
+    get(aws_config, aws_sdk_route53::client::Client::new)
+}
+
+pub fn s3(aws_config: &SdkConfig) -> aws_sdk_s3::client::Client {
+    get(aws_config, aws_sdk_s3::client::Client::new)
+}
+
+pub fn scheduler(aws_config: &SdkConfig) -> aws_sdk_scheduler::client::Client {
+    get(aws_config, aws_sdk_scheduler::client::Client::new)
+}
+
+pub fn sesv2(aws_config: &SdkConfig) -> aws_sdk_sesv2::client::Client {
+    get(aws_config, aws_sdk_sesv2::client::Client::new)
+}
+
+pub fn sns(aws_config: &SdkConfig) -> aws_sdk_sns::client::Client {
+    get(aws_config, aws_sdk_sns::client::Client::new)
+}
+
+pub fn ssm(aws_config: &SdkConfig) -> aws_sdk_ssm::client::Client {
+    get(aws_config, |aws_config| {
+        aws_sdk_ssm::client::Client::from_conf(
+            aws_sdk_ssm::config::Builder::from(aws_config)
+                .interceptor(report::RequestIds)
+                .interceptor(health::Calls)
+                .build(),
+        )
+    })
+}
+
+pub fn sts(aws_config: &SdkConfig) -> aws_sdk_sts::client::Client {
+    get(aws_config, aws_sdk_sts::client::Client::new)
+}
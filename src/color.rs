@@ -0,0 +1,34 @@
+use std::io::IsTerminal;
+use std::sync::OnceLock;
+
+static ENABLED: OnceLock<bool> = OnceLock::new();
+
+/// Colors the human output, unless told not to or it isn't going to a terminal
+pub fn init(no_color: bool) {
+    // https://no-color.org: any non-empty value disables colors
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    let _ = ENABLED.set(!no_color && !no_color_env && std::io::stdout().is_terminal());
+}
+
+fn paint(code: &str, text: &str) -> String {
+    if ENABLED.get().copied().unwrap_or(false) {
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Name of an instance state, green when running, red when stopped and yellow in between
+pub fn state(state: &str) -> String {
+    match state {
+        "running" => paint("32", state),
+        "stopped" | "terminated" => paint("31", state),
+        "pending" | "stopping" | "shutting-down" => paint("33", state),
+        _ => state.to_string(),
+    }
+}
+
+/// IP address, in bold cyan to stand out
+pub fn ip(ip: &str) -> String {
+    paint("1;36", ip)
+}
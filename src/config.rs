@@ -1,7 +1,69 @@
-use clap::{command, AppSettings, Arg, PossibleValue};
+use crate::apply::ApplyConfig;
+use crate::bench::BenchConfig;
+#[cfg(any(feature = "discord", feature = "slack", feature = "telegram"))]
+use crate::bot::BotInstance;
+use crate::ctl::CtlConfig;
+use crate::describe::DescribeConfig;
+#[cfg(feature = "discord")]
+use crate::discord::DiscordConfig;
+#[cfg(feature = "grpc")]
+use crate::grpc::ServeConfig;
+use crate::history::HistoryConfig;
+use crate::iam::PolicyConfig;
+use crate::idle::{IdleAlarm, IdleConfig};
+use crate::imds::SelfStopConfig;
+use crate::inventory::InventoryConfig;
+use crate::launch::LaunchConfig;
+use crate::layers::{self, Resolved};
+use crate::list::ListConfig;
+use crate::log::{LogFileConfig, LogFormat, LogTarget};
+use crate::metrics;
+#[cfg(feature = "mqtt")]
+use crate::mqtt::MqttConfig;
+use crate::org::OrgConfig;
+use crate::parameter;
+use crate::porcelain;
+use crate::proxy::ProxyConfig;
+use crate::proxy_command::ProxyCommandConfig;
+use crate::query;
+use crate::regions::{self, Regions};
+use crate::scheduler_tags::SchedulerTagsConfig;
+use crate::serial::SerialConsole;
+#[cfg(windows)]
+use crate::service::{self, ServiceCommand};
+use crate::settings::Settings;
+#[cfg(feature = "slack")]
+use crate::slack::SlackConfig;
+use crate::ssh::SshConfig;
+use crate::stack;
+use crate::stop_all::StopAllConfig;
+use crate::sweep::SweepConfig;
+#[cfg(feature = "telegram")]
+use crate::telegram::TelegramConfig;
+use crate::template::{self, Template};
+use crate::terraform;
+use crate::validate::ValidateConfig;
+use crate::wait::{Until, WaitConfig};
+#[cfg(feature = "web")]
+use crate::web::WebConfig;
+use aws_config::retry::RetryConfig;
+use chrono::{DateTime, Local, NaiveTime, TimeZone, Utc};
+use clap::parser::ValueSource;
+use clap::{command, AppSettings, Arg, ArgMatches, Command, PossibleValue};
 use color_eyre::{eyre::eyre, Result};
+use regex::Regex;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
 
 #[derive(Debug, PartialEq, Clone)]
+#[cfg_attr(
+    feature = "lambda",
+    derive(serde::Deserialize),
+    serde(rename_all = "lowercase")
+)]
 pub enum Action {
     Start,
     Stop,
@@ -20,33 +82,261 @@ impl clap::ValueEnum for Action {
     }
 }
 
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Start => write!(f, "start"),
+            Self::Stop => write!(f, "stop"),
+        }
+    }
+}
+
+/// Command run by `--wait-for-tailscale`, which fails until Tailscale is connected
+const TAILSCALE_CHECK: &str = "tailscale status";
+
+/// How long `--drain-ecs` waits for the tasks to leave when no `--drain-timeout` is given
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Name of the AMI created by `--snapshot-ami` when none is given
+const DEFAULT_AMI_NAME: &str = "aws-start-stop-{instance_id}-{timestamp}";
+
+/// Parsed command line
 #[derive(Debug)]
-pub struct Config {
-    pub action: Action,
-    pub instance_id: String,
-    pub timeout: u64,
-    pub wait_for_ssm: bool,
+pub struct Cli {
+    pub mode: Mode,
+    pub sso_login: bool,
+    /// Command printing the credentials, run again once they have expired
+    pub credentials_cmd: Option<String>,
+    /// Show the identity, account and region the calls are made with
+    pub whoami: bool,
+    /// Account the credentials must be those of
+    pub expect_account: Option<String>,
+    /// Allow stopping the instances protected in the configuration file
+    pub override_protection: bool,
+    /// Tags as `key=value` instances must have to be acted on
+    pub require_tags: Vec<String>,
+    /// Allow stopping instances during the time windows of the configuration file
+    pub force_window_override: bool,
+    /// Allow stopping instances before their minimum runtime
+    pub ignore_min_runtime: bool,
+    /// Configuration file given on the command line
+    pub config_file: Option<PathBuf>,
+    /// Alias of the account to act in, from `--account` or the instance reference
+    pub account: Option<String>,
+    /// Account and region of the instance, when given as an ARN
+    pub account_id: Option<String>,
+    pub region: Option<String>,
+    pub no_color: bool,
+    /// Only show warnings and errors
+    pub quiet: bool,
+    /// How often to show that instances are still being waited for
+    pub heartbeat: Option<Duration>,
+    /// Prefix the progress lines with the time
+    pub timestamps: bool,
+    /// Connect to instances over IPv6 when they have an address
+    pub prefer_ipv6: bool,
+    /// Show a desktop notification once each action is done
+    pub notify_desktop: bool,
+    /// ntfy topic the outcome of the actions is pushed to
+    pub notify_ntfy: Option<String>,
+    /// PagerDuty Events v2 routing key alerted when an action fails
+    pub notify_pagerduty: Option<String>,
+    /// Directory the output of the commands run through SSM is saved to
+    pub capture_output: Option<PathBuf>,
+    /// File a JSON report of the run is written to
+    pub report: Option<PathBuf>,
+    /// CloudWatch namespace the metrics of the actions are put into
+    pub emit_metrics: Option<String>,
+    /// Port `/healthz` and `/readyz` are served on
+    pub health_port: Option<u16>,
+    /// File the exchanges with AWS are recorded to
+    #[cfg(feature = "http-replay")]
+    pub record_http: Option<PathBuf>,
+    /// File of recorded exchanges replayed instead of calling AWS
+    #[cfg(feature = "http-replay")]
+    pub replay_http: Option<PathBuf>,
+    /// Ring the terminal bell once the actions are done
+    pub bell: bool,
+    /// Sound played along with the bell
+    pub bell_sound: Option<PathBuf>,
+    pub log_format: LogFormat,
+    pub log_target: LogTarget,
+    pub log_file: Option<LogFileConfig>,
+    /// File each action is recorded to, as a JSON line
+    pub audit_log: Option<PathBuf>,
+    /// Lock the instances while acting on them
+    pub lock: bool,
+    /// How long to wait for the lock of an instance, rather than failing at once
+    pub lock_wait: Option<Duration>,
+    /// Retry behaviour of the AWS calls, when not left to the SDK's configuration
+    pub retry_config: Option<RetryConfig>,
+    /// Calls per second allowed to describe, start and stop instances
+    pub max_rps: Option<f64>,
 }
 
-impl Config {
+/// What the program was asked to do
+#[derive(Debug)]
+pub enum Mode {
+    /// Start or stop an instance
+    Run(Config),
+    /// Manage recurring EventBridge Scheduler schedules
+    Schedule(ScheduleCommand),
+    /// Print the IAM policy needed for a set of features
+    PrintIamPolicy(PolicyConfig),
+    /// Show the state and details of an instance, writing a screenshot of its console to the file
+    Status(String, Option<PathBuf>),
+    /// Start and stop the instances tagged with the schedules of the configuration file
+    FromSchedulerTags(SchedulerTagsConfig),
+    /// Check the configuration file
+    ValidateConfig(ValidateConfig),
+    /// Print the configuration file, or the value of each argument and where it came from
+    ShowConfig(Option<Vec<Resolved>>),
+    /// Start the instance and bridge stdin and stdout to its SSH port, as an ssh ProxyCommand
+    ProxyCommand(ProxyCommandConfig),
+    /// Print an Ansible dynamic inventory of the instances
+    Inventory(InventoryConfig),
+    /// Remove the security group rules added for this machine from the instance
+    Revoke(String),
+    /// List the instances of the region
+    List(ListConfig),
+    /// Print the instances matching EC2 filters as JSON
+    Describe(DescribeConfig),
+    /// Show who started and stopped an instance, from CloudTrail
+    History(HistoryConfig),
+    /// Stop and start an instance repeatedly, measuring how long it takes to be ready
+    Bench(BenchConfig),
+    /// Wait for an instance to reach a state, without acting on it
+    Wait(WaitConfig),
+    /// Create an instance and wait for it to be running
+    Launch(LaunchConfig),
+    /// Stop the instance this program runs on
+    SelfStop(SelfStopConfig),
+    /// Manage this program as a Windows service
+    #[cfg(windows)]
+    Service(ServiceCommand),
+    /// Serve the gRPC control API
+    #[cfg(feature = "grpc")]
+    Serve(ServeConfig),
+    /// Start or stop an instance when a message is published to an MQTT topic
+    #[cfg(feature = "mqtt")]
+    Mqtt(MqttConfig),
+    /// Answer slash commands sent to a Discord bot
+    #[cfg(feature = "discord")]
+    Discord(DiscordConfig),
+    /// Serve a Slack slash command
+    #[cfg(feature = "slack")]
+    Slack(SlackConfig),
+    /// Answer commands sent to a Telegram bot
+    #[cfg(feature = "telegram")]
+    Telegram(TelegramConfig),
+    /// Start the instance when a connection comes in and forward it
+    Proxy(ProxyConfig),
+    /// Stop the instance once its metrics show it is idle
+    WatchIdle(IdleConfig),
+    /// Start or stop the tagged instances of every account of the organization
+    Org(OrgConfig),
+    /// Stop every running instance with the tags in the regions
+    StopAll(StopAllConfig),
+    /// Stop every running instance of the regions but the protected and excluded ones
+    Sweep(SweepConfig),
+    /// Move instances to the states declared in a file
+    Apply(ApplyConfig),
+    /// Send a command to a running `from-scheduler-tags --every`
+    Ctl(CtlConfig),
+    /// List the plugins found on the `PATH`
+    Plugins,
+}
+
+impl Mode {
+    /// Whether this mode calls AWS, and therefore needs credentials
+    pub fn uses_aws(&self) -> bool {
+        match self {
+            Self::PrintIamPolicy(_) => false,
+            Self::ValidateConfig(config) => config.aws,
+            Self::ShowConfig(_) => false,
+            Self::Ctl(_) => false,
+            Self::Plugins => false,
+            // The service runs another instance of this program, which calls AWS itself
+            #[cfg(windows)]
+            Self::Service(_) => false,
+            _ => true,
+        }
+    }
+}
+
+impl Cli {
     pub fn from_args() -> Result<Self> {
-        let matches = command!()
+        let command = command!()
             .setting(AppSettings::DeriveDisplayOrder)
             .term_width(120)
+            .args_conflicts_with_subcommands(true)
+            .subcommand_negates_reqs(true)
             .args(&[
-                Arg::new("action")
+                action_arg()
+                    .required(false)
+                    .required_unless_present("output"),
+                instance_arg()
+                    .required(false)
+                    .required_unless_present_any(&["instance-name", "output", "stack", "fleet"])
+                    .value_parser(parse_run_instance)
+                    .help(
+                        "Instance ID, optionally prefixed with an account alias \
+                        (e.g. dev/i-0123456789abcdef0), instance ARN, @group, \
+                        ssm:PARAMETER holding the ID or cfn:STACK:OUTPUT",
+                    ),
+                Arg::new("output")
+                    .long("output")
                     .takes_value(true)
-                    .ignore_case(true)
-                    .value_name("ACTION")
-                    .required(true)
-                    .value_parser(clap::builder::EnumValueParser::<Action>::new())
-                    .help("Action"),
-                Arg::new("instance")
+                    .value_name("FORMAT")
+                    .required(false)
+                    .conflicts_with_all(&["format", "query"])
+                    .value_parser(["terraform-external", "env"])
+                    .help(
+                        "terraform-external: read the action and instance_id from the JSON on \
+                        stdin, and print the result as a Terraform external data source; \
+                        env: print the result as KEY=VALUE lines, to source or as a dotenv file",
+                    ),
+                Arg::new("porcelain")
+                    .long("porcelain")
                     .takes_value(true)
-                    .value_name("INSTANCE_ID")
-                    .required(true)
+                    .value_name("VERSION")
+                    .min_values(0)
+                    .require_equals(true)
+                    .default_missing_value("v1")
+                    .required(false)
+                    .conflicts_with_all(&["format", "query", "output"])
+                    .value_parser(["v1"])
+                    .help(
+                        "Print a line per instance in a format kept stable across releases, \
+                        for scripts, the other output being free to change",
+                    ),
+                Arg::new("stack")
+                    .long("stack")
+                    .takes_value(true)
+                    .value_name("STACK")
+                    .required(false)
+                    .conflicts_with_all(&["instance", "instance-name", "then", "output"])
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Act on every instance of this CloudFormation stack at once"),
+                Arg::new("fleet")
+                    .long("fleet")
+                    .takes_value(true)
+                    .value_name("FLEET_ID")
+                    .required(false)
+                    .conflicts_with_all(&["instance", "instance-name", "stack", "then", "output"])
+                    .value_parser(parse_fleet)
+                    .help(
+                        "Act on this EC2 Fleet or Spot Fleet request, setting its target capacity \
+                        to 0 when stopping and restoring it when starting",
+                    ),
+                Arg::new("instance-name")
+                    .long("instance-name")
+                    .takes_value(true)
+                    .value_name("NAME")
+                    .required(false)
+                    .conflicts_with("instance")
                     .value_parser(clap::builder::NonEmptyStringValueParser::new())
-                    .help("Instance ID"),
+                    .help("Act on the instance with this Name tag, rather than by its id"),
                 Arg::new("timeout")
                     .short('t')
                     .long("timeout")
@@ -56,34 +346,3443 @@ impl Config {
                     .multiple_values(false)
                     .value_parser(clap::builder::RangedU64ValueParser::<u64>::new())
                     .default_value("120")
-                    .help("How long to wait for the action to complete"),
+                    .help(
+                        "How long to wait for the action to complete, fitted to the instance type \
+                        and past durations when not given",
+                    ),
+                Arg::new("wait-timeout")
+                    .long("wait-timeout")
+                    .takes_value(true)
+                    .value_name("TIMEOUT")
+                    .required(false)
+                    .value_parser(clap::builder::RangedU64ValueParser::<u64>::new())
+                    .help(
+                        "How long to wait for the instance to reach the state, on its own, \
+                        --timeout then bounding the API calls and other checks",
+                    ),
+                Arg::new("yes")
+                    .short('y')
+                    .long("yes")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Don't ask for confirmation before stopping, or starting above \
+                        --confirm-above, from a terminal",
+                    ),
+                Arg::new("budget-guard")
+                    .long("budget-guard")
+                    .takes_value(true)
+                    .value_name("BUDGET")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Refuse to start instances when this AWS budget is spent"),
+                Arg::new("budget-max-percent")
+                    .long("budget-max-percent")
+                    .takes_value(true)
+                    .value_name("PERCENT")
+                    .required(false)
+                    .default_value("100")
+                    .value_parser(clap::value_parser!(f64))
+                    .help("Share of the budget's limit spent above which starting is refused"),
+                Arg::new("force-budget")
+                    .long("force-budget")
+                    .takes_value(false)
+                    .required(false)
+                    .requires("budget-guard")
+                    .help("Start the instances even when the budget is spent, only warning"),
+                Arg::new("show-cost")
+                    .long("show-cost")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Show the on-demand hourly price of the instances before starting them, \
+                        and what --for costs",
+                    ),
+                Arg::new("confirm-above")
+                    .long("confirm-above")
+                    .takes_value(true)
+                    .value_name("DOLLARS")
+                    .required(false)
+                    .requires("show-cost")
+                    .value_parser(clap::value_parser!(f64))
+                    .help("Ask for confirmation when the instances cost more than this an hour"),
+                Arg::new("preflight")
+                    .long("preflight")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Check that the caller is allowed to make the calls the run needs, \
+                        before acting on any instance",
+                    ),
+                Arg::new("search-regions")
+                    .long("search-regions")
+                    .takes_value(false)
+                    .required(false)
+                    .help("Look for the instance in the other regions if it isn't in this one"),
+                query_arg().conflicts_with("format"),
+                Arg::new("format")
+                    .long("format")
+                    .takes_value(true)
+                    .value_name("TEMPLATE")
+                    .required(false)
+                    .value_parser(template::parse)
+                    .help("Print the instance as this template, e.g. \"{public_ip} {state}\""),
+                Arg::new("no-wait")
+                    .long("no-wait")
+                    .takes_value(false)
+                    .required(false)
+                    .conflicts_with_all(&["wait-for-ssm", "for"])
+                    .help("Only request the action, without waiting for it to complete"),
                 Arg::new("wait-for-ssm")
                     .short('s')
                     .long("wait-for-ssm")
                     .takes_value(false)
                     .required(false)
                     .help("Wait for the instance to connect to SSM"),
-            ])
-            .get_matches();
-
-        let action = matches
-            .get_one::<Action>("action")
-            .ok_or_else(|| eyre!("Missing action"))?
-            .clone();
-        let instance_id = matches
-            .get_one::<String>("instance")
-            .ok_or_else(|| eyre!("Missing instance id"))?
-            .clone();
-        let timeout = *matches
-            .get_one::<u64>("timeout")
-            .ok_or_else(|| eyre!("Missing timeout"))?;
-        let wait_for_ssm = matches.contains_id("wait-for-ssm");
-
-        Ok(Self {
-            action,
-            instance_id,
-            timeout,
-            wait_for_ssm,
-        })
-    }
+                Arg::new("wait-for-cloud-init")
+                    .long("wait-for-cloud-init")
+                    .takes_value(false)
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .help("Wait through SSM for cloud-init to finish running the user data"),
+                Arg::new("wait-for-volumes")
+                    .long("wait-for-volumes")
+                    .takes_value(false)
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .help("Wait for the attached EBS volumes to be in use with an ok status"),
+                Arg::new("check-service")
+                    .long("check-service")
+                    .takes_value(true)
+                    .value_name("UNIT")
+                    .multiple_occurrences(true)
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Check through SSM that this systemd unit is active once started, \
+                        can be repeated",
+                    ),
+                Arg::new("wait-for-ssm-check")
+                    .long("wait-for-ssm-check")
+                    .takes_value(true)
+                    .value_name("COMMAND")
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Run this command through SSM once started until it succeeds, e.g. \
+                        to wait for a VPN",
+                    ),
+                Arg::new("wait-for-tailscale")
+                    .long("wait-for-tailscale")
+                    .takes_value(false)
+                    .required(false)
+                    .conflicts_with_all(&["no-wait", "wait-for-ssm-check"])
+                    .help("Wait for Tailscale to be connected, as --wait-for-ssm-check does"),
+                Arg::new("wait-for-port")
+                    .long("wait-for-port")
+                    .takes_value(true)
+                    .value_name("PORT")
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .value_parser(clap::value_parser!(u16))
+                    .help(
+                        "Wait for this TCP port of the instance to accept connections once started",
+                    ),
+                Arg::new("wait-for-http")
+                    .long("wait-for-http")
+                    .takes_value(true)
+                    .value_name("URL")
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Wait for this URL to answer with a success status once started, \
+                        with {ip} replaced by the instance's address",
+                    ),
+                Arg::new("wait-for-plugin")
+                    .long("wait-for-plugin")
+                    .takes_value(true)
+                    .value_name("NAME")
+                    .multiple_occurrences(true)
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Wait for the aws-start-stop-NAME plugin to report the instance ready once \
+                        started, can be repeated",
+                    ),
+                #[cfg(feature = "wasm")]
+                Arg::new("wait-for-wasm")
+                    .long("wait-for-wasm")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .multiple_occurrences(true)
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help(
+                        "Wait for the ready function of this WASM module to report the instance \
+                        ready once started, can be repeated",
+                    ),
+                Arg::new("parallel-checks")
+                    .long("parallel-checks")
+                    .takes_value(false)
+                    .required(false)
+                    .help("Run the SSM check, port and URL waits at once rather than in order"),
+                Arg::new("wait-for-target-group")
+                    .long("wait-for-target-group")
+                    .takes_value(true)
+                    .value_name("ARN")
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Wait for the instance to be healthy in this target group once started"),
+                Arg::new("target-group")
+                    .long("target-group")
+                    .takes_value(true)
+                    .value_name("ARN")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Deregister the instance from this target group before stopping, and \
+                        register it once started",
+                    ),
+                Arg::new("health-check-id")
+                    .long("health-check-id")
+                    .takes_value(true)
+                    .value_name("ID")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Disable this Route53 health check before stopping, and enable it once \
+                        started and ready",
+                    ),
+                Arg::new("check-gpu")
+                    .long("check-gpu")
+                    .takes_value(false)
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .help("Check through SSM that nvidia-smi sees healthy GPUs once started"),
+                Arg::new("gpu-warn-only")
+                    .long("gpu-warn-only")
+                    .takes_value(false)
+                    .required(false)
+                    .requires("check-gpu")
+                    .help("Only warn when the GPU check fails, rather than failing"),
+                Arg::new("for")
+                    .long("for")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .required(false)
+                    .value_parser(humantime::parse_duration)
+                    .help("Stop the instance again after this duration (e.g. 2h, 90m)"),
+                Arg::new("at")
+                    .long("at")
+                    .takes_value(true)
+                    .value_name("TIME")
+                    .required(false)
+                    .conflicts_with("in")
+                    .value_parser(parse_time)
+                    .help("Run the action at this local time (e.g. 18:30), rather than now"),
+                Arg::new("in")
+                    .long("in")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .required(false)
+                    .value_parser(humantime::parse_duration)
+                    .help("Run the action after this duration (e.g. 2h), rather than now"),
+                Arg::new("detach")
+                    .long("detach")
+                    .takes_value(false)
+                    .required(false)
+                    .requires("scheduler-role-arn")
+                    .help(
+                        "Schedule the delayed action or the stop with EventBridge Scheduler and \
+                        exit instead of waiting",
+                    ),
+                scheduler_role_arn_arg().required(false),
+                Arg::new("console-output")
+                    .long("console-output")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Print the end of the console output once the instance is running, \
+                        waiting up to 3 minutes for EC2 to capture it",
+                    ),
+                Arg::new("tail")
+                    .long("tail")
+                    .takes_value(true)
+                    .value_name("LINES")
+                    .required(false)
+                    .requires("console-output")
+                    .value_parser(clap::value_parser!(usize))
+                    .help("Number of console output lines to print [default: 50]"),
+                Arg::new("get-windows-password")
+                    .long("get-windows-password")
+                    .takes_value(false)
+                    .required(false)
+                    .requires("key-file")
+                    .help("Retrieve and decrypt the Windows administrator password after start"),
+                Arg::new("key-file")
+                    .long("key-file")
+                    .takes_value(true)
+                    .value_name("PEM_FILE")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Private key of the instance's key pair"),
+                screenshot_arg().conflicts_with("no-wait"),
+                Arg::new("serial-console")
+                    .long("serial-console")
+                    .takes_value(true)
+                    .value_name("print|exec")
+                    .min_values(0)
+                    .require_equals(true)
+                    .default_missing_value("print")
+                    .required(false)
+                    .conflicts_with("no-wait")
+                    .value_parser(["print", "exec"])
+                    .help(
+                        "Push a key for the serial console once started, enabling access if \
+                        needed, and print the ssh command connecting to it or run it",
+                    ),
+                Arg::new("rdp-file")
+                    .long("rdp-file")
+                    .takes_value(true)
+                    .value_name("RDP_FILE")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Write a Remote Desktop connection file for Windows instances"),
+                Arg::new("ssh-config-out")
+                    .long("ssh-config-out")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Write or update an ssh_config Host block for the instance in this file"),
+                Arg::new("ssh-host")
+                    .long("ssh-host")
+                    .takes_value(true)
+                    .value_name("ALIAS")
+                    .required(false)
+                    .requires("ssh-config-out")
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Host alias in the ssh config [default: the file name]"),
+                Arg::new("ssh-user")
+                    .long("ssh-user")
+                    .takes_value(true)
+                    .value_name("USER")
+                    .required(false)
+                    .requires("ssh-config-out")
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("User in the ssh config"),
+                Arg::new("identity-file")
+                    .long("identity-file")
+                    .takes_value(true)
+                    .value_name("KEY_FILE")
+                    .required(false)
+                    .requires("ssh-config-out")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("IdentityFile in the ssh config"),
+                Arg::new("host-keys")
+                    .long("host-keys")
+                    .takes_value(false)
+                    .required(false)
+                    .help("Print the SSH host keys cloud-init writes to the console"),
+                Arg::new("known-hosts")
+                    .long("known-hosts")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Add the SSH host keys from the console to this known_hosts file"),
+                Arg::new("install-idle-alarm")
+                    .long("install-idle-alarm")
+                    .takes_value(false)
+                    .required(false)
+                    .help("Create a CloudWatch alarm stopping the instance once it is idle"),
+                Arg::new("idle-alarm-threshold")
+                    .long("idle-alarm-threshold")
+                    .takes_value(true)
+                    .value_name("PERCENT")
+                    .required(false)
+                    .requires("install-idle-alarm")
+                    .value_parser(clap::value_parser!(f64))
+                    .default_value("5")
+                    .help("CPU utilization below which the instance is idle"),
+                Arg::new("idle-alarm-periods")
+                    .long("idle-alarm-periods")
+                    .takes_value(true)
+                    .value_name("PERIODS")
+                    .required(false)
+                    .requires("install-idle-alarm")
+                    .value_parser(clap::value_parser!(i32).range(1..))
+                    .default_value("6")
+                    .help("Number of five minute periods the instance must be idle for"),
+                Arg::new("parallel")
+                    .long("parallel")
+                    .takes_value(false)
+                    .required(false)
+                    .help("Act on all the instances of a group and of --then at once"),
+                Arg::new("then")
+                    .long("then")
+                    .takes_value(true)
+                    .value_name("INSTANCE_IDS")
+                    .multiple_occurrences(true)
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Comma-separated instances to act on once the previous ones are done, \
+                        can be repeated",
+                    ),
+                Arg::new("stage-delay")
+                    .long("stage-delay")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .required(false)
+                    .value_parser(humantime::parse_duration)
+                    .help("Wait this long between stages of a group or of --then (e.g. 30s)"),
+                Arg::new("fail-fast")
+                    .long("fail-fast")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "With several instances, abort the others as soon as one fails, rather \
+                        than acting on all of them",
+                    ),
+                Arg::new("retries")
+                    .long("retries")
+                    .takes_value(true)
+                    .value_name("COUNT")
+                    .required(false)
+                    .value_parser(clap::value_parser!(u32))
+                    .default_value("0")
+                    .help(
+                        "Try the whole action again this many times when it fails for a lack of \
+                        capacity, throttling, the network or a timeout",
+                    ),
+                Arg::new("rollback-on-failure")
+                    .long("rollback-on-failure")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "When starting several instances, stop those which were stopped before if \
+                        any fails",
+                    ),
+                Arg::new("pre-hook")
+                    .long("pre-hook")
+                    .takes_value(true)
+                    .value_name("COMMAND")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Shell command to run before the action, which is aborted if it fails"),
+                Arg::new("post-hook")
+                    .long("post-hook")
+                    .takes_value(true)
+                    .value_name("COMMAND")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Shell command to run once the action has completed"),
+                Arg::new("tail-logs")
+                    .long("tail-logs")
+                    .takes_value(true)
+                    .value_name("LOG_GROUP")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Show the events of this CloudWatch Logs group while the instance starts, \
+                        until it is ready",
+                    ),
+                Arg::new("warmup")
+                    .long("warmup")
+                    .takes_value(true)
+                    .value_name("COMMAND")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Command to run on the instance through SSM once it is ready, e.g. to pull \
+                        images or prime caches",
+                    ),
+                Arg::new("warmup-ignore-failure")
+                    .long("warmup-ignore-failure")
+                    .takes_value(false)
+                    .required(false)
+                    .requires("warmup")
+                    .help("Only warn when the warm-up command fails"),
+                Arg::new("pre-stop-ssm")
+                    .long("pre-stop-ssm")
+                    .takes_value(true)
+                    .value_name("SCRIPT")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Script to run on the instance through SSM, which must succeed to stop it",
+                    ),
+                Arg::new("pre-stop-ignore-failure")
+                    .long("pre-stop-ignore-failure")
+                    .takes_value(false)
+                    .required(false)
+                    .requires("pre-stop-ssm")
+                    .help("Stop the instance even if the pre-stop script fails"),
+                Arg::new("snapshot-ami")
+                    .long("snapshot-ami")
+                    .takes_value(true)
+                    .min_values(0)
+                    .value_name("NAME_TEMPLATE")
+                    .required(false)
+                    .default_missing_value(DEFAULT_AMI_NAME)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Create an AMI of the instance before stopping it, {instance_id} and \
+                        {timestamp} being replaced in the name \
+                        [default: aws-start-stop-{instance_id}-{timestamp}]",
+                    ),
+                Arg::new("no-reboot")
+                    .long("no-reboot")
+                    .takes_value(false)
+                    .required(false)
+                    .requires("snapshot-ami")
+                    .help("Create the AMI without rebooting the instance"),
+                Arg::new("snapshot-volumes")
+                    .long("snapshot-volumes")
+                    .takes_value(false)
+                    .required(false)
+                    .help("Snapshot the EBS volumes of the instance before stopping it"),
+                Arg::new("wait-for-snapshots")
+                    .long("wait-for-snapshots")
+                    .takes_value(false)
+                    .required(false)
+                    .requires("snapshot-volumes")
+                    .help("Wait for the volume snapshots to be completed before stopping"),
+                Arg::new("capacity-reservation")
+                    .long("capacity-reservation")
+                    .takes_value(true)
+                    .value_name("ID|open")
+                    .required(false)
+                    .value_parser(parse_capacity_reservation)
+                    .help(
+                        "Make the instance use this capacity reservation, or any open one, \
+                        when starting",
+                    ),
+                Arg::new("credit-spec")
+                    .long("credit-spec")
+                    .takes_value(true)
+                    .value_name("CREDITS")
+                    .required(false)
+                    .value_parser(["unlimited", "standard"])
+                    .help("CPU credit option to set on burstable (T) instances when starting"),
+                Arg::new("fallback-instance-types")
+                    .long("fallback-instance-types")
+                    .takes_value(true)
+                    .value_name("TYPES")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Comma-separated instance types to switch to, in order, when AWS lacks \
+                        capacity for the instance's",
+                    ),
+                Arg::new("authorize-my-ip")
+                    .long("authorize-my-ip")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Allow this machine's public IP through the instance's security group \
+                        once started",
+                    ),
+                Arg::new("port")
+                    .long("port")
+                    .takes_value(true)
+                    .value_name("PORT")
+                    .required(false)
+                    .requires("authorize-my-ip")
+                    .value_parser(clap::value_parser!(u16))
+                    .help("Port to allow with --authorize-my-ip [default: 22]"),
+                Arg::new("revoke-my-ip")
+                    .long("revoke-my-ip")
+                    .takes_value(false)
+                    .required(false)
+                    .help("Remove the rules added by --authorize-my-ip when stopping"),
+                Arg::new("attach-eni")
+                    .long("attach-eni")
+                    .takes_value(true)
+                    .value_name("ENI_ID")
+                    .required(false)
+                    .value_parser(parse_eni)
+                    .help(
+                        "Attach this network interface once started, and detach it before \
+                        stopping",
+                    ),
+                Arg::new("warm-pool")
+                    .long("warm-pool")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Return instances of Auto Scaling groups to the warm pool or standby when \
+                        stopping, and back in service when starting",
+                    ),
+                Arg::new("wait-min-runtime")
+                    .long("wait-min-runtime")
+                    .takes_value(false)
+                    .required(false)
+                    .conflicts_with("ignore-min-runtime")
+                    .help(
+                        "Wait for instances to have run for their minimum runtime before \
+                        stopping them, rather than failing",
+                    ),
+                Arg::new("drain-ecs")
+                    .long("drain-ecs")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Drain ECS container instances before stopping them, and set them back \
+                        to active when starting",
+                    ),
+                Arg::new("drain-timeout")
+                    .long("drain-timeout")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .required(false)
+                    .requires("drain-ecs")
+                    .value_parser(humantime::parse_duration)
+                    .help("Stop anyway once ECS tasks have been draining this long [default: 10m]"),
+                Arg::new("drain-k8s")
+                    .long("drain-k8s")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Cordon and drain EKS nodes before stopping them, and uncordon them \
+                        once started",
+                    ),
+                Arg::new("kubeconfig")
+                    .long("kubeconfig")
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .requires("drain-k8s")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Run kubectl locally with this kubeconfig, rather than on the node"),
+                Arg::new("github-output")
+                    .long("github-output")
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Write the IPs, state and duration to $GITHUB_OUTPUT, and annotate the \
+                        workflow run",
+                    ),
+                Arg::new("cancel-on-interrupt")
+                    .long("cancel-on-interrupt")
+                    .takes_value(false)
+                    .required(false)
+                    .help("When interrupted with Ctrl-C, stop the instances being started"),
+                Arg::new("quiet")
+                    .short('q')
+                    .long("quiet")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Only show warnings and errors"),
+                Arg::new("heartbeat")
+                    .long("heartbeat")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .required(false)
+                    .value_parser(humantime::parse_duration)
+                    .help(
+                        "Show a line this often while waiting, 0s for never [default: 30s \
+                        when the output isn't a terminal]",
+                    ),
+                Arg::new("timestamps")
+                    .long("timestamps")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Prefix the progress lines with an ISO 8601 timestamp"),
+                Arg::new("prefer-ipv6")
+                    .long("prefer-ipv6")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help(
+                        "Connect to instances over IPv6 when they have an address, rather than \
+                        public IPv4",
+                    ),
+                Arg::new("notify-desktop")
+                    .long("notify-desktop")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Show a desktop notification once the instance is started or stopped"),
+                Arg::new("notify-ntfy")
+                    .long("notify-ntfy")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("TOPIC_URL")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Push the outcome of the actions to this ntfy topic, \
+                        e.g. https://ntfy.sh/my-instances",
+                    ),
+                Arg::new("notify-pagerduty")
+                    .long("notify-pagerduty")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("ROUTING_KEY")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Trigger a PagerDuty alert with this Events v2 routing key when an action \
+                        fails, resolved once it succeeds again",
+                    ),
+                Arg::new("capture-output")
+                    .long("capture-output")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("DIR")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Save the output of the commands run on the instances through SSM here"),
+                Arg::new("report")
+                    .long("report")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Write a JSON report of the run, its outcome and AWS request ids here"),
+                Arg::new("emit-metrics")
+                    .long("emit-metrics")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("NAMESPACE")
+                    .min_values(0)
+                    .require_equals(true)
+                    .default_missing_value(metrics::DEFAULT_NAMESPACE)
+                    .required(false)
+                    .help(
+                        "Put TimeToRunning, TimeToSsmConnected and ActionFailures into this \
+                        CloudWatch namespace after each action, by instance id",
+                    ),
+                Arg::new("health-port")
+                    .long("health-port")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("PORT")
+                    .required(false)
+                    .value_parser(clap::value_parser!(u16))
+                    .help("Serve /healthz and /readyz on this port, e.g. for Kubernetes probes"),
+                #[cfg(feature = "http-replay")]
+                Arg::new("record-http")
+                    .long("record-http")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .conflicts_with("replay-http")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Record the exchanges with AWS to this file, to replay them in tests"),
+                #[cfg(feature = "http-replay")]
+                Arg::new("replay-http")
+                    .long("replay-http")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Replay the exchanges recorded to this file instead of calling AWS"),
+                Arg::new("bell")
+                    .long("bell")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Ring the terminal bell once the actions are done, twice if they failed"),
+                Arg::new("bell-sound")
+                    .long("bell-sound")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .requires("bell")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Sound file played along with the bell"),
+                Arg::new("no-color")
+                    .long("no-color")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Don't color the output, as when NO_COLOR is set or it isn't a terminal"),
+                Arg::new("sso-login")
+                    .long("sso-login")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Log in to IAM Identity Center again if the SSO token has expired"),
+                Arg::new("credentials-cmd")
+                    .long("credentials-cmd")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("COMMAND")
+                    .required(false)
+                    .conflicts_with("sso-login")
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help(
+                        "Get the credentials from this command, which prints them as JSON like a \
+                        credential_process, e.g. \"aws-vault exec dev --json\"",
+                    ),
+                Arg::new("whoami")
+                    .long("whoami")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Show the identity, account and region the calls are made with first"),
+                Arg::new("expect-account")
+                    .long("expect-account")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("ACCOUNT_ID")
+                    .required(false)
+                    .value_parser(parse_account_id)
+                    .help("Refuse to act unless the credentials are those of this account"),
+                Arg::new("override-protection")
+                    .long("override-protection")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Stop instances even if they are protected in the configuration file"),
+                Arg::new("audit-log")
+                    .long("audit-log")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Append a JSON record of each action to this file"),
+                Arg::new("lock")
+                    .long("lock")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Lock the instances with a tag while acting on them"),
+                Arg::new("lock-wait")
+                    .long("lock-wait")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .required(false)
+                    .requires("lock")
+                    .value_parser(humantime::parse_duration)
+                    .help("Wait this long for an instance locked by another run, e.g. 5m"),
+                Arg::new("force-window-override")
+                    .long("force-window-override")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Stop instances even during the windows of the configuration file"),
+                Arg::new("ignore-min-runtime")
+                    .long("ignore-min-runtime")
+                    .global(true)
+                    .takes_value(false)
+                    .required(false)
+                    .help("Stop instances even if they haven't run for their minimum runtime"),
+                Arg::new("require-tag")
+                    .long("require-tag")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("KEY=VALUE")
+                    .multiple_occurrences(true)
+                    .required(false)
+                    .value_parser(|value: &str| parse_tag(value).map(|_| value.to_string()))
+                    .help("Only act on instances with this tag, can be repeated"),
+                Arg::new("config")
+                    .long("config")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .env("AWS_START_STOP_CONFIG")
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help(
+                        "Configuration file [default: aws-start-stop/config.toml in the user's \
+                        configuration directory]",
+                    ),
+                Arg::new("log-format")
+                    .long("log-format")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("FORMAT")
+                    .required(false)
+                    .value_parser(["human", "json"])
+                    .default_value("human")
+                    .help("Format of the progress messages"),
+                Arg::new("log-target")
+                    .long("log-target")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("TARGET")
+                    .required(false)
+                    .value_parser(["stdout", "stderr", "syslog", "journald"])
+                    .default_value("stdout")
+                    .help("Where to write the progress messages"),
+                Arg::new("log-file")
+                    .long("log-file")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("FILE")
+                    .required(false)
+                    .value_parser(clap::value_parser!(PathBuf))
+                    .help("Also write the progress messages to this file"),
+                Arg::new("log-max-size")
+                    .long("log-max-size")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("SIZE")
+                    .required(false)
+                    .value_parser(parse_size)
+                    .help("Rotate the log file once it reaches this size (e.g. 10M)"),
+                Arg::new("log-max-age")
+                    .long("log-max-age")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .required(false)
+                    .value_parser(humantime::parse_duration)
+                    .help("Rotate the log file once it is this old (e.g. 1day)"),
+                Arg::new("log-keep")
+                    .long("log-keep")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("COUNT")
+                    .required(false)
+                    .value_parser(clap::value_parser!(usize))
+                    .default_value("5")
+                    .help("Number of rotated log files to keep"),
+                Arg::new("max-retries")
+                    .long("max-retries")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("COUNT")
+                    .required(false)
+                    .value_parser(clap::value_parser!(u32))
+                    .help("Number of times failed AWS calls are retried"),
+                Arg::new("max-rps")
+                    .long("max-rps")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("RATE")
+                    .required(false)
+                    .value_parser(parse_rate)
+                    .help(
+                        "Describe, start and stop instances at most this many times per second, \
+                        slowing down further when AWS throttles the calls",
+                    ),
+                Arg::new("retry-mode")
+                    .long("retry-mode")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("MODE")
+                    .required(false)
+                    .value_parser(["standard", "adaptive"])
+                    .help("Retry mode of the AWS calls"),
+                Arg::new("account")
+                    .long("account")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("ALIAS")
+                    .required(false)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Assume the role of this account from the configuration file"),
+                Arg::new("regions")
+                    .long("regions")
+                    .global(true)
+                    .takes_value(true)
+                    .value_name("REGIONS|all")
+                    .required(false)
+                    .value_parser(regions::parse)
+                    .help("Regions, separated by commas, listing and acting on tagged instances"),
+            ])
+            .subcommand(schedule_command())
+            .subcommand(print_iam_policy_command())
+            .subcommand(proxy_command())
+            .subcommand(proxy_command_command())
+            .subcommand(watch_idle_command())
+            .subcommand(org_command())
+            .subcommand(stop_all_command())
+            .subcommand(sweep_command())
+            .subcommand(apply_command())
+            .subcommand(from_scheduler_tags_command())
+            .subcommand(ctl_command())
+            .subcommand(
+                Command::new("plugins")
+                    .about("List the aws-start-stop-<name> plugins found on the PATH"),
+            )
+            .subcommand(config_command())
+            .subcommand(
+                Command::new("status")
+                    .about("Show the state and details of an instance")
+                    .arg(screenshot_arg())
+                    .arg(instance_arg()),
+            )
+            .subcommand(
+                Command::new("revoke")
+                    .about("Remove the security group rules added by --authorize-my-ip")
+                    .arg(instance_arg()),
+            )
+            .subcommand(list_command())
+            .subcommand(inventory_command())
+            .subcommand(describe_command())
+            .subcommand(history_command())
+            .subcommand(bench_command())
+            .subcommand(wait_command())
+            .subcommand(launch_command())
+            .subcommand(self_command());
+        #[cfg(windows)]
+        let command = command.subcommand(service_command());
+        #[cfg(feature = "grpc")]
+        let command = command.subcommand(serve_command());
+        #[cfg(feature = "mqtt")]
+        let command = command.subcommand(mqtt_command());
+        #[cfg(any(feature = "discord", feature = "slack", feature = "telegram"))]
+        let command = command.subcommand(bot_command());
+        let mut command = layers::with_env(command);
+        let mut sources = layers::load_file(&command)?;
+        let mut matches = command.get_matches_mut();
+        // The defaults of an instance alias are only known once the alias has been read
+        if layers::apply_alias(&command, innermost_matches(&matches), &mut sources)? {
+            matches = command.get_matches_mut();
+        }
+        // Before the matches of the subcommands shadow those of the main command
+        let resolved = layers::resolve(&command, &matches, &sources);
+
+        let mode = match matches.subcommand() {
+            Some(("schedule", matches)) => Mode::Schedule(ScheduleCommand::from_matches(matches)?),
+            Some(("print-iam-policy", matches)) => Mode::PrintIamPolicy(PolicyConfig {
+                instance_ids: matches
+                    .get_many::<String>("instance")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                wait_for_ssm: matches.contains_id("wait-for-ssm"),
+                schedules: matches.contains_id("schedules"),
+                watch_idle: matches.contains_id("watch-idle"),
+                idle_alarm: matches.contains_id("idle-alarm"),
+                ssm_commands: matches.contains_id("ssm-commands"),
+                launch: matches.contains_id("launch"),
+                snapshots: matches.contains_id("snapshots"),
+                auto_scaling: matches.contains_id("auto-scaling"),
+                history: matches.contains_id("history"),
+                lock: matches.contains_id("lock"),
+                ingress: matches.contains_id("ingress"),
+                enis: matches.contains_id("enis"),
+                logs: matches.contains_id("logs"),
+                budgets: matches.contains_id("budgets"),
+                pricing: matches.contains_id("pricing"),
+                parameters: matches.contains_id("parameters"),
+                serial_console: matches.contains_id("serial-console"),
+                stacks: matches.contains_id("stacks"),
+                fleets: matches.contains_id("fleets"),
+                ssm_sessions: matches.contains_id("ssm-sessions"),
+                ecs: matches.contains_id("ecs"),
+                health_checks: matches.contains_id("health-checks"),
+                target_groups: matches.contains_id("target-groups"),
+                metrics: matches.contains_id("metrics"),
+                volumes: matches.contains_id("volumes"),
+                preflight: matches.contains_id("preflight"),
+                credits: matches.contains_id("credits"),
+                console_output: matches.contains_id("console-output"),
+                passwords: matches.contains_id("passwords"),
+                capacity_reservations: matches.contains_id("capacity-reservations"),
+                instance_types: matches.contains_id("instance-types"),
+                screenshots: matches.contains_id("screenshots"),
+                roles: matches.contains_id("roles"),
+                organizations: matches.contains_id("organizations"),
+                state: matches.contains_id("state"),
+                notifications: matches.contains_id("notifications"),
+                regions: matches.contains_id("regions"),
+            }),
+            Some(("proxy", matches)) => Mode::Proxy(ProxyConfig {
+                instance_id: get_instance_id(matches)?,
+                listen: matches.get_one::<SocketAddr>("listen").copied(),
+                port: *matches
+                    .get_one::<u16>("port")
+                    .ok_or_else(|| eyre!("Missing port"))?,
+                idle_timeout: matches.get_one::<Duration>("idle-timeout").copied(),
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+                generate_systemd: matches.get_one::<PathBuf>("generate-systemd").cloned(),
+                ssm: matches.contains_id("ssm"),
+            }),
+            Some(("proxy-command", matches)) => Mode::ProxyCommand(ProxyCommandConfig {
+                host: matches
+                    .get_one::<String>("host")
+                    .ok_or_else(|| eyre!("Missing host"))?
+                    .clone(),
+                port: *matches
+                    .get_one::<u16>("port")
+                    .ok_or_else(|| eyre!("Missing port"))?,
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+            }),
+            Some(("watch-idle", matches)) => Mode::WatchIdle(IdleConfig {
+                instance_id: get_instance_id(matches)?,
+                cpu_threshold: *matches
+                    .get_one::<f64>("cpu-threshold")
+                    .ok_or_else(|| eyre!("Missing CPU threshold"))?,
+                network_threshold: matches.get_one::<f64>("network-threshold").copied(),
+                idle_for: *matches
+                    .get_one::<Duration>("idle-for")
+                    .ok_or_else(|| eyre!("Missing idle duration"))?,
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+            }),
+            Some(("org", matches)) => {
+                let (tag_key, tag_value) = matches
+                    .get_one::<(String, String)>("tag")
+                    .ok_or_else(|| eyre!("Missing tag"))?
+                    .clone();
+                Mode::Org(OrgConfig {
+                    action: get_action(matches)?,
+                    tag_key,
+                    tag_value,
+                    role_name: matches
+                        .get_one::<String>("role-name")
+                        .ok_or_else(|| eyre!("Missing role name"))?
+                        .clone(),
+                    regions: matches.get_one::<Regions>("regions").cloned(),
+                })
+            }
+            Some(("stop-all", matches)) => Mode::StopAll(StopAllConfig {
+                tags: matches
+                    .get_many::<(String, String)>("tag")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                regions: matches.get_one::<Regions>("regions").cloned(),
+                older_than: matches.get_one::<Duration>("older-than").copied(),
+                yes: matches.contains_id("yes"),
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+            }),
+            Some(("sweep", matches)) => Mode::Sweep(SweepConfig {
+                exclude_tags: matches
+                    .get_many::<(String, String)>("exclude-tag")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                regions: matches.get_one::<Regions>("regions").cloned(),
+                yes: matches.contains_id("yes"),
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+            }),
+            Some(("apply", matches)) => Mode::Apply(ApplyConfig {
+                file: matches
+                    .get_one::<PathBuf>("file")
+                    .ok_or_else(|| eyre!("Missing file"))?
+                    .clone(),
+                dry_run: matches.contains_id("dry-run"),
+            }),
+            Some(("from-scheduler-tags", matches)) => {
+                Mode::FromSchedulerTags(SchedulerTagsConfig {
+                    tag_key: matches
+                        .get_one::<String>("tag-key")
+                        .ok_or_else(|| eyre!("Missing tag key"))?
+                        .clone(),
+                    dry_run: matches.contains_id("dry-run"),
+                    every: matches.get_one::<Duration>("every").copied(),
+                    control_socket: matches.get_one::<PathBuf>("control-socket").cloned(),
+                })
+            }
+            Some(("plugins", _)) => Mode::Plugins,
+            Some(("ctl", matches)) => {
+                let (command, command_matches) = matches
+                    .subcommand()
+                    .ok_or_else(|| eyre!("Missing ctl command"))?;
+                Mode::Ctl(CtlConfig {
+                    socket: matches
+                        .get_one::<PathBuf>("socket")
+                        .ok_or_else(|| eyre!("Missing socket"))?
+                        .clone(),
+                    command: match command_matches.get_one::<String>("job") {
+                        Some(job) => format!("{} {}", command, job),
+                        None => command.to_string(),
+                    },
+                })
+            }
+            Some(("status", matches)) => Mode::Status(
+                get_instance_id(matches)?,
+                matches.get_one::<PathBuf>("screenshot").cloned(),
+            ),
+            Some(("revoke", matches)) => Mode::Revoke(get_instance_id(matches)?),
+            Some(("config", matches)) => match matches.subcommand() {
+                Some(("validate", matches)) => Mode::ValidateConfig(ValidateConfig {
+                    aws: matches.contains_id("aws"),
+                }),
+                Some(("show", show_matches)) => {
+                    Mode::ShowConfig(show_matches.contains_id("resolved").then_some(resolved))
+                }
+                _ => return Err(eyre!("Missing config command")),
+            },
+            Some(("launch", matches)) => Mode::Launch(LaunchConfig {
+                launch_template: matches.get_one::<String>("launch-template").cloned(),
+                image_id: matches.get_one::<String>("image-id").cloned(),
+                instance_type: matches.get_one::<String>("instance-type").cloned(),
+                subnet_id: matches.get_one::<String>("subnet-id").cloned(),
+                key_name: matches.get_one::<String>("key-name").cloned(),
+                tags: matches
+                    .get_many::<(String, String)>("tag")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                wait_for_ssm: matches.contains_id("wait-for-ssm"),
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+            }),
+            #[cfg(feature = "grpc")]
+            Some(("serve", matches)) => Mode::Serve(ServeConfig {
+                listen: *matches
+                    .get_one::<SocketAddr>("listen")
+                    .ok_or_else(|| eyre!("Missing listen address"))?,
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+                #[cfg(feature = "web")]
+                web: match matches.get_one::<SocketAddr>("web-listen") {
+                    Some(listen) => Some(WebConfig {
+                        listen: *listen,
+                        token: matches
+                            .get_one::<String>("web-token")
+                            .ok_or_else(|| eyre!("Missing web UI token"))?
+                            .clone(),
+                        instance_ids: matches
+                            .get_many::<String>("instance")
+                            .map(|instance_ids| instance_ids.cloned().collect())
+                            .unwrap_or_default(),
+                    }),
+                    None => None,
+                },
+            }),
+            #[cfg(feature = "mqtt")]
+            Some(("mqtt", matches)) => Mode::Mqtt(MqttConfig {
+                instance_id: get_instance_id(matches)?,
+                host: matches
+                    .get_one::<String>("host")
+                    .ok_or_else(|| eyre!("Missing broker host"))?
+                    .clone(),
+                port: *matches
+                    .get_one::<u16>("port")
+                    .ok_or_else(|| eyre!("Missing broker port"))?,
+                client_id: matches
+                    .get_one::<String>("client-id")
+                    .ok_or_else(|| eyre!("Missing client id"))?
+                    .clone(),
+                topic: matches
+                    .get_one::<String>("topic")
+                    .ok_or_else(|| eyre!("Missing topic"))?
+                    .clone(),
+                response_topic: matches.get_one::<String>("response-topic").cloned(),
+                ca_file: matches.get_one::<PathBuf>("ca-file").cloned(),
+                cert_file: matches.get_one::<PathBuf>("cert-file").cloned(),
+                key_file: matches.get_one::<PathBuf>("key-file").cloned(),
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+            }),
+            #[cfg(any(feature = "discord", feature = "slack", feature = "telegram"))]
+            Some(("bot", matches)) => match matches.subcommand() {
+                #[cfg(feature = "discord")]
+                Some(("discord", matches)) => Mode::Discord(DiscordConfig {
+                    token: matches
+                        .get_one::<String>("token")
+                        .ok_or_else(|| eyre!("Missing bot token"))?
+                        .clone(),
+                    guild_id: *matches
+                        .get_one::<u64>("guild")
+                        .ok_or_else(|| eyre!("Missing server id"))?,
+                    role_ids: matches
+                        .get_many::<u64>("allow-role")
+                        .ok_or_else(|| eyre!("Missing allowed role"))?
+                        .copied()
+                        .collect(),
+                    instances: matches
+                        .get_many::<BotInstance>("instance")
+                        .ok_or_else(|| eyre!("Missing instance id"))?
+                        .cloned()
+                        .collect(),
+                    timeout: *matches
+                        .get_one::<u64>("timeout")
+                        .ok_or_else(|| eyre!("Missing timeout"))?,
+                }),
+                #[cfg(feature = "slack")]
+                Some(("slack", matches)) => Mode::Slack(SlackConfig {
+                    listen: *matches
+                        .get_one::<SocketAddr>("listen")
+                        .ok_or_else(|| eyre!("Missing listen address"))?,
+                    signing_secret: matches
+                        .get_one::<String>("signing-secret")
+                        .ok_or_else(|| eyre!("Missing signing secret"))?
+                        .clone(),
+                    instances: matches
+                        .get_many::<BotInstance>("instance")
+                        .ok_or_else(|| eyre!("Missing instance id"))?
+                        .cloned()
+                        .collect(),
+                    timeout: *matches
+                        .get_one::<u64>("timeout")
+                        .ok_or_else(|| eyre!("Missing timeout"))?,
+                }),
+                #[cfg(feature = "telegram")]
+                Some(("telegram", matches)) => Mode::Telegram(TelegramConfig {
+                    token: matches
+                        .get_one::<String>("token")
+                        .ok_or_else(|| eyre!("Missing bot token"))?
+                        .clone(),
+                    chat_ids: matches
+                        .get_many::<i64>("allow-chat")
+                        .ok_or_else(|| eyre!("Missing allowed chat"))?
+                        .copied()
+                        .collect(),
+                    instances: matches
+                        .get_many::<BotInstance>("instance")
+                        .ok_or_else(|| eyre!("Missing instance id"))?
+                        .cloned()
+                        .collect(),
+                    timeout: *matches
+                        .get_one::<u64>("timeout")
+                        .ok_or_else(|| eyre!("Missing timeout"))?,
+                }),
+                _ => unreachable!(),
+            },
+            #[cfg(windows)]
+            Some(("service", matches)) => Mode::Service(service_from_matches(matches)?),
+            Some(("self", matches)) => match matches.subcommand() {
+                Some(("stop", matches)) => Mode::SelfStop(SelfStopConfig {
+                    delay: matches.get_one::<Duration>("delay").copied(),
+                }),
+                _ => return Err(eyre!("Missing self subcommand")),
+            },
+            Some(("list", matches)) => Mode::List(ListConfig {
+                state: matches.get_one::<String>("state").cloned(),
+                tags: matches
+                    .get_many::<(String, String)>("tag")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                regions: matches.get_one::<Regions>("regions").cloned(),
+            }),
+            Some(("describe", matches)) => Mode::Describe(DescribeConfig {
+                filters: matches
+                    .get_many::<(String, Vec<String>)>("filter")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                regions: matches.get_one::<Regions>("regions").cloned(),
+                query: matches.get_one::<String>("query").cloned(),
+            }),
+            Some(("inventory", matches)) => Mode::Inventory(InventoryConfig {
+                tags: matches
+                    .get_many::<(String, String)>("tag")
+                    .unwrap_or_default()
+                    .cloned()
+                    .collect(),
+                ensure_running: matches.contains_id("ensure-running"),
+                host: matches.get_one::<String>("host").cloned(),
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+            }),
+            Some(("history", matches)) => Mode::History(HistoryConfig {
+                instance_id: get_instance_id(matches)?,
+                days: *matches
+                    .get_one::<u32>("days")
+                    .ok_or_else(|| eyre!("Missing number of days"))?,
+            }),
+            Some(("bench", matches)) => Mode::Bench(BenchConfig {
+                instance_id: get_instance_id(matches)?,
+                iterations: *matches
+                    .get_one::<u32>("iterations")
+                    .ok_or_else(|| eyre!("Missing number of iterations"))?,
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+                ssm: !matches.contains_id("no-ssm"),
+                wait_for_port: matches.get_one::<u16>("wait-for-port").copied(),
+                wait_for_http: matches.get_one::<String>("wait-for-http").cloned(),
+            }),
+            Some(("wait", matches)) => Mode::Wait(WaitConfig {
+                instance_id: get_instance_id(matches)?,
+                until: matches
+                    .get_one::<Until>("until")
+                    .ok_or_else(|| eyre!("Missing condition"))?
+                    .clone(),
+                timeout: *matches
+                    .get_one::<u64>("timeout")
+                    .ok_or_else(|| eyre!("Missing timeout"))?,
+            }),
+            _ => Mode::Run(Config::from_matches(&matches)?),
+        };
+
+        // Global arguments are only propagated down to subcommands
+        let matches = innermost_matches(&matches);
+        let alias = matches
+            .try_get_one::<String>("instance")
+            .ok()
+            .flatten()
+            .and_then(|instance| layers::alias(instance));
+        let instance = match alias {
+            Some(alias) => Some(&alias.instance),
+            None => matches.try_get_one::<String>("instance").ok().flatten(),
+        };
+        let instance_arn = instance.and_then(|instance| InstanceArn::parse(instance));
+        let instance_account = match instance_arn {
+            Some(_) => None,
+            None => instance
+                .filter(|instance| !instance.starts_with(parameter::PREFIX))
+                .and_then(|instance| instance.split_once('/'))
+                .map(|(account, _)| account.to_string()),
+        };
+        // These print their result on stdout
+        let result_on_stdout = matches!(&mode, Mode::Run(config) if config.stdout_reserved)
+            || matches!(&mode, Mode::Inventory(_) | Mode::ProxyCommand(_));
+
+        Ok(Self {
+            mode,
+            sso_login: matches.contains_id("sso-login"),
+            credentials_cmd: matches.get_one::<String>("credentials-cmd").cloned(),
+            whoami: matches.contains_id("whoami"),
+            expect_account: matches.get_one::<String>("expect-account").cloned(),
+            no_color: matches.contains_id("no-color"),
+            quiet: matches.contains_id("quiet"),
+            heartbeat: matches.get_one::<Duration>("heartbeat").copied(),
+            timestamps: matches.contains_id("timestamps"),
+            prefer_ipv6: matches.contains_id("prefer-ipv6"),
+            notify_desktop: matches.contains_id("notify-desktop"),
+            notify_ntfy: matches.get_one::<String>("notify-ntfy").cloned(),
+            notify_pagerduty: matches.get_one::<String>("notify-pagerduty").cloned(),
+            capture_output: matches.get_one::<PathBuf>("capture-output").cloned(),
+            report: matches.get_one::<PathBuf>("report").cloned(),
+            emit_metrics: matches.get_one::<String>("emit-metrics").cloned(),
+            health_port: matches.get_one::<u16>("health-port").copied(),
+            #[cfg(feature = "http-replay")]
+            record_http: matches.get_one::<PathBuf>("record-http").cloned(),
+            #[cfg(feature = "http-replay")]
+            replay_http: matches.get_one::<PathBuf>("replay-http").cloned(),
+            bell: matches.contains_id("bell"),
+            bell_sound: matches.get_one::<PathBuf>("bell-sound").cloned(),
+            override_protection: matches.contains_id("override-protection"),
+            audit_log: matches.get_one::<PathBuf>("audit-log").cloned(),
+            lock: matches.contains_id("lock"),
+            lock_wait: matches.get_one::<Duration>("lock-wait").copied(),
+            force_window_override: matches.contains_id("force-window-override"),
+            ignore_min_runtime: matches.contains_id("ignore-min-runtime"),
+            require_tags: matches
+                .get_many::<String>("require-tag")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+            config_file: matches.get_one::<PathBuf>("config").cloned(),
+            account: matches
+                .get_one::<String>("account")
+                .cloned()
+                .or(instance_account),
+            account_id: instance_arn.as_ref().map(|arn| arn.account_id.clone()),
+            region: instance_arn
+                .map(|arn| arn.region)
+                .or_else(|| alias.and_then(|alias| alias.region.clone())),
+            log_format: match matches.get_one::<String>("log-format").map(String::as_str) {
+                Some("json") => LogFormat::Json,
+                _ => LogFormat::Human,
+            },
+            log_target: match matches.get_one::<String>("log-target").map(String::as_str) {
+                _ if result_on_stdout
+                    && matches.value_source("log-target") == Some(ValueSource::DefaultValue) =>
+                {
+                    LogTarget::Stderr
+                }
+                Some("stderr") => LogTarget::Stderr,
+                Some("syslog") => LogTarget::Syslog,
+                Some("journald") => LogTarget::Journald,
+                _ => LogTarget::Stdout,
+            },
+            log_file: matches
+                .get_one::<PathBuf>("log-file")
+                .map(|path| LogFileConfig {
+                    path: path.clone(),
+                    max_size: matches.get_one::<u64>("log-max-size").copied(),
+                    max_age: matches.get_one::<Duration>("log-max-age").copied(),
+                    keep: matches.get_one::<usize>("log-keep").copied().unwrap_or(5),
+                }),
+            retry_config: retry_config(matches),
+            max_rps: matches.get_one::<f64>("max-rps").copied(),
+        })
+    }
+}
+
+/// Retry configuration from `--retry-mode` and `--max-retries`, if any of them is given
+fn retry_config(matches: &ArgMatches) -> Option<RetryConfig> {
+    let retry_mode = matches.get_one::<String>("retry-mode").map(String::as_str);
+    let max_retries = matches.get_one::<u32>("max-retries").copied();
+    if retry_mode.is_none() && max_retries.is_none() {
+        return None;
+    }
+
+    let retry_config = match retry_mode {
+        Some("adaptive") => RetryConfig::adaptive(),
+        _ => RetryConfig::standard(),
+    };
+    Some(match max_retries {
+        Some(max_retries) => retry_config.with_max_attempts(max_retries + 1),
+        None => retry_config,
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub action: Action,
+    pub instance_id: String,
+    /// Name tag of the instance, to find its id with
+    pub instance_name: Option<String>,
+    /// CloudFormation stack whose instances are all acted on, instead of the instance
+    pub stack: Option<String>,
+    /// EC2 Fleet or Spot Fleet request whose target capacity is changed, instead of the instance
+    pub fleet: Option<String>,
+    pub timeout: u64,
+    /// Deadline of the wait for the state on its own, `timeout` then bounding the rest
+    pub wait_timeout: Option<u64>,
+    pub wait_for_ssm: bool,
+    /// Wait for cloud-init to finish, through SSM
+    pub wait_for_cloud_init: bool,
+    /// Wait for the attached EBS volumes to be in use with an ok status
+    pub wait_for_volumes: bool,
+    /// systemd units which must be active once started, checked through SSM
+    pub check_services: Vec<String>,
+    /// Load balancer target group the instance must be healthy in once started
+    pub target_group: Option<String>,
+    /// Target group to deregister the instance from before stopping, and register it in once ready
+    pub register_target_group: Option<String>,
+    /// Route53 health check to disable before stopping, and enable once ready
+    pub health_check_id: Option<String>,
+    /// Command run through SSM once started until it succeeds
+    pub ssm_check: Option<String>,
+    /// TCP port of the instance which must accept connections once started
+    pub wait_for_port: Option<u16>,
+    /// URL which must answer with a success status once started, `{ip}` being the address
+    pub wait_for_http: Option<String>,
+    /// Plugins which must report the instance ready once started
+    pub wait_for_plugins: Vec<String>,
+    /// WASM modules which must report the instance ready once started
+    #[cfg(feature = "wasm")]
+    pub wait_for_wasm: Vec<PathBuf>,
+    /// Run the SSM check, port and URL waits at once rather than in order
+    pub parallel_checks: bool,
+    /// Check the GPUs with nvidia-smi through SSM once started
+    pub check_gpu: bool,
+    /// Only warn when the GPU check fails
+    pub gpu_warn_only: bool,
+    pub stop_after: Option<Duration>,
+    /// When to run the action, rather than now
+    pub at: Option<DateTime<Utc>>,
+    pub detach: bool,
+    pub scheduler_role_arn: Option<String>,
+    /// Number of console output lines to print once the instance is running
+    pub console_output_lines: Option<usize>,
+    /// Private key to decrypt the Windows administrator password with, if it was requested
+    pub windows_password_key: Option<PathBuf>,
+    /// Connect to the serial console once started
+    pub serial_console: Option<SerialConsole>,
+    /// File a screenshot of the console is written to once started
+    pub screenshot: Option<PathBuf>,
+    /// Where to write a Remote Desktop connection file, for Windows instances
+    pub rdp_file: Option<PathBuf>,
+    pub ssh_config: Option<SshConfig>,
+    /// Print the SSH host keys found in the console output
+    pub print_host_keys: bool,
+    /// known_hosts file to add the SSH host keys found in the console output to
+    pub known_hosts: Option<PathBuf>,
+    pub idle_alarm: Option<IdleAlarm>,
+    /// Act on all the instances of a group at once rather than in order
+    pub parallel: bool,
+    /// Instances to act on after this one, in stages
+    pub then: Vec<Vec<String>>,
+    /// How long to wait between stages
+    pub stage_delay: Option<Duration>,
+    /// Abort the other instances once one fails
+    pub fail_fast: bool,
+    /// Times the whole action is tried again when it fails in a way which may not last
+    pub retries: u32,
+    /// Stop the instances started by a failed run
+    pub rollback_on_failure: bool,
+    /// Shell command to run before the action
+    pub pre_hook: Option<String>,
+    /// Shell command to run once the action has completed
+    pub post_hook: Option<String>,
+    /// CloudWatch Logs group whose events are shown until the instance is ready
+    pub tail_logs: Option<String>,
+    /// Command run on the instance through SSM once it is ready
+    pub warmup: Option<String>,
+    pub warmup_ignore_failure: bool,
+    /// Script run on the instance through SSM, which must succeed for it to be stopped
+    pub pre_stop_ssm: Option<String>,
+    pub pre_stop_ignore_failure: bool,
+    /// Name of the AMI to create before stopping, with `{instance_id}` and `{timestamp}` replaced
+    pub snapshot_ami: Option<String>,
+    /// Create the AMI without rebooting the instance first
+    pub snapshot_ami_no_reboot: bool,
+    /// Snapshot the EBS volumes of the instance before stopping
+    pub snapshot_volumes: bool,
+    /// Wait for the volume snapshots to be completed before stopping
+    pub wait_for_snapshots: bool,
+    /// Capacity reservation id, or `open`, for the instance to use when starting
+    pub capacity_reservation: Option<String>,
+    /// CPU credit option, `unlimited` or `standard`, to set on burstable instances when starting
+    pub credit_spec: Option<String>,
+    /// Instance types to switch to when AWS lacks capacity to start the instance
+    pub fallback_instance_types: Vec<String>,
+    /// Port to allow this machine's public IP to once started
+    pub authorize_my_ip: Option<u16>,
+    /// Remove the rules allowing this machine's public IP when stopping
+    pub revoke_my_ip: bool,
+    /// Network interface to attach once started, and detach before stopping
+    pub attach_eni: Option<String>,
+    /// Move instances of Auto Scaling groups out of service before stopping them, and back after
+    pub warm_pool: bool,
+    /// Wait for instances to reach their minimum runtime before stopping them
+    pub wait_min_runtime: bool,
+    /// Drain ECS container instances for at most this long before stopping them
+    pub drain_ecs: Option<Duration>,
+    /// Cordon and drain EKS nodes before stopping them, and uncordon them once started
+    pub drain_k8s: bool,
+    /// kubeconfig to run kubectl locally with, rather than on the node through SSM
+    pub kubeconfig: Option<PathBuf>,
+    /// Report the outcome to GitHub Actions, as step outputs and annotations
+    pub github_output: bool,
+    /// Stop instances whose start was interrupted by Ctrl-C
+    pub cancel_on_interrupt: bool,
+    /// Only request the action, without waiting for the instance to reach the state
+    pub no_wait: bool,
+    /// Template the instance is printed as once acted on, instead of its details
+    pub format: Option<Template>,
+    /// JMESPath expression printed from the JSON of the instances once acted on
+    pub query: Option<String>,
+    /// Print the result for Terraform's external data source, the query having been read
+    pub terraform_external: bool,
+    /// Print the result as `KEY=VALUE` lines, for shells and dotenv files
+    pub env_output: bool,
+    /// Print the outcomes in this version of the stable format, for scripts
+    pub porcelain: Option<porcelain::Version>,
+    /// Nothing but the result, or the session, is written to stdout
+    pub stdout_reserved: bool,
+    /// Don't ask for confirmation before stopping, or starting above `confirm_above`
+    pub yes: bool,
+    /// Budget whose actual spend must be below `budget_max_percent` of its limit to start
+    pub budget_guard: Option<String>,
+    pub budget_max_percent: f64,
+    pub force_budget: bool,
+    /// Show the hourly price of the instances before starting them
+    pub show_cost: bool,
+    /// Hourly price in dollars above which starting must be confirmed
+    pub confirm_above: Option<f64>,
+    /// Fit the timeout to the instance, as none was given
+    pub adaptive_timeout: bool,
+    /// Look for the instances in the other enabled regions if they aren't in this one
+    pub search_regions: bool,
+    /// Check the permissions of the caller before acting
+    pub preflight: bool,
+}
+
+impl Config {
+    /// Builds a configuration with the default options, for when there is no command line
+    pub fn new(action: Action, instance_id: String) -> Self {
+        Self {
+            action,
+            instance_id,
+            instance_name: None,
+            stack: None,
+            fleet: None,
+            timeout: 120,
+            wait_timeout: None,
+            wait_for_ssm: false,
+            wait_for_cloud_init: false,
+            wait_for_volumes: false,
+            check_services: vec![],
+            target_group: None,
+            register_target_group: None,
+            health_check_id: None,
+            ssm_check: None,
+            wait_for_port: None,
+            wait_for_http: None,
+            wait_for_plugins: vec![],
+            #[cfg(feature = "wasm")]
+            wait_for_wasm: vec![],
+            parallel_checks: false,
+            check_gpu: false,
+            gpu_warn_only: false,
+            stop_after: None,
+            at: None,
+            detach: false,
+            scheduler_role_arn: None,
+            console_output_lines: None,
+            windows_password_key: None,
+            serial_console: None,
+            screenshot: None,
+            rdp_file: None,
+            ssh_config: None,
+            print_host_keys: false,
+            known_hosts: None,
+            idle_alarm: None,
+            parallel: false,
+            then: vec![],
+            stage_delay: None,
+            fail_fast: false,
+            retries: 0,
+            rollback_on_failure: false,
+            pre_hook: None,
+            post_hook: None,
+            tail_logs: None,
+            warmup: None,
+            warmup_ignore_failure: false,
+            pre_stop_ssm: None,
+            pre_stop_ignore_failure: false,
+            snapshot_ami: None,
+            snapshot_ami_no_reboot: false,
+            snapshot_volumes: false,
+            wait_for_snapshots: false,
+            capacity_reservation: None,
+            credit_spec: None,
+            fallback_instance_types: vec![],
+            authorize_my_ip: None,
+            revoke_my_ip: false,
+            attach_eni: None,
+            warm_pool: false,
+            wait_min_runtime: false,
+            drain_ecs: None,
+            drain_k8s: false,
+            kubeconfig: None,
+            github_output: false,
+            cancel_on_interrupt: false,
+            no_wait: false,
+            format: None,
+            query: None,
+            terraform_external: false,
+            env_output: false,
+            porcelain: None,
+            stdout_reserved: false,
+            yes: false,
+            budget_guard: None,
+            budget_max_percent: 100.0,
+            force_budget: false,
+            show_cost: false,
+            confirm_above: None,
+            adaptive_timeout: false,
+            search_regions: false,
+            preflight: false,
+        }
+    }
+
+    /// Groups the instances to act on in stages, the instances of a stage being acted on together
+    pub fn stages(&self, settings: &Settings) -> Result<Vec<Vec<String>>> {
+        let mut stages = match self.instance_id.strip_prefix('@') {
+            Some(group) => settings.group(group)?,
+            None => vec![vec![self.instance_id.clone()]],
+        };
+        stages.extend(self.then.iter().cloned());
+
+        if self.parallel {
+            stages = vec![stages.concat()];
+        }
+
+        Ok(stages)
+    }
+
+    /// Copies the configuration for another instance, e.g. of the same group
+    pub fn for_instance(&self, instance_id: &str) -> Self {
+        Self {
+            instance_id: instance_id.to_string(),
+            ..self.clone()
+        }
+    }
+
+    fn from_matches(matches: &ArgMatches) -> Result<Self> {
+        let output = matches.get_one::<String>("output").map(String::as_str);
+        let terraform_external = output == Some("terraform-external");
+        let porcelain = matches
+            .get_one::<String>("porcelain")
+            .and_then(|version| porcelain::Version::parse(version));
+        if terraform_external
+            && ["action", "instance", "instance-name"]
+                .iter()
+                .any(|id| matches.value_source(id) == Some(ValueSource::CommandLine))
+        {
+            return Err(eyre!(
+                "--output terraform-external reads the action and instance from stdin"
+            ));
+        }
+        let instance_name = matches.get_one::<String>("instance-name").cloned();
+        let stack = matches.get_one::<String>("stack").cloned();
+        let fleet = matches.get_one::<String>("fleet").cloned();
+        let (action, instance_id) = if terraform_external {
+            terraform::read_query()?
+        } else {
+            // Resolved from the name, or listed from the stack, once connected to AWS
+            let instance_id = if instance_name.is_some() || stack.is_some() || fleet.is_some() {
+                String::new()
+            } else {
+                get_instance_id(matches)?
+            };
+            (get_action(matches)?, instance_id)
+        };
+        let timeout = *matches
+            .get_one::<u64>("timeout")
+            .ok_or_else(|| eyre!("Missing timeout"))?;
+        let wait_for_cloud_init = matches.contains_id("wait-for-cloud-init");
+        let check_services: Vec<String> = matches
+            .get_many::<String>("check-service")
+            .unwrap_or_default()
+            .cloned()
+            .collect();
+        let check_gpu = matches.contains_id("check-gpu");
+        let warmup = matches.get_one::<String>("warmup").cloned();
+        let drain_k8s = matches.contains_id("drain-k8s");
+        let kubeconfig = matches.get_one::<PathBuf>("kubeconfig").cloned();
+        let ssm_check = if matches.contains_id("wait-for-tailscale") {
+            Some(TAILSCALE_CHECK.to_string())
+        } else {
+            matches.get_one::<String>("wait-for-ssm-check").cloned()
+        };
+        // Commands can only be run once the instance is connected to SSM
+        let wait_for_ssm = matches.contains_id("wait-for-ssm")
+            || wait_for_cloud_init
+            || !check_services.is_empty()
+            || ssm_check.is_some()
+            || (drain_k8s && kubeconfig.is_none())
+            || check_gpu
+            || warmup.is_some();
+        let stop_after = matches.get_one::<Duration>("for").copied();
+        let at = match (
+            matches.get_one::<NaiveTime>("at"),
+            matches.get_one::<Duration>("in"),
+        ) {
+            (Some(time), _) => Some(next_occurrence(*time)?),
+            (None, Some(delay)) => Some(Utc::now() + chrono::Duration::from_std(*delay)?),
+            (None, None) => None,
+        };
+        let detach = matches.contains_id("detach");
+        if detach && stop_after.is_none() && at.is_none() {
+            return Err(eyre!("--detach needs --for, --at or --in"));
+        }
+        let scheduler_role_arn = matches.get_one::<String>("scheduler-role-arn").cloned();
+        let console_output_lines = if matches.contains_id("console-output") {
+            Some(matches.get_one::<usize>("tail").copied().unwrap_or(50))
+        } else {
+            None
+        };
+
+        let windows_password_key = if matches.contains_id("get-windows-password") {
+            matches.get_one::<PathBuf>("key-file").cloned()
+        } else {
+            None
+        };
+
+        let rdp_file = matches.get_one::<PathBuf>("rdp-file").cloned();
+        let ssh_config = matches
+            .get_one::<PathBuf>("ssh-config-out")
+            .map(|path| SshConfig {
+                path: path.clone(),
+                host: matches.get_one::<String>("ssh-host").cloned(),
+                user: matches.get_one::<String>("ssh-user").cloned(),
+                identity_file: matches.get_one::<PathBuf>("identity-file").cloned(),
+            });
+        let print_host_keys = matches.contains_id("host-keys");
+        let known_hosts = matches.get_one::<PathBuf>("known-hosts").cloned();
+        let idle_alarm = if matches.contains_id("install-idle-alarm") {
+            Some(IdleAlarm {
+                threshold: *matches
+                    .get_one::<f64>("idle-alarm-threshold")
+                    .ok_or_else(|| eyre!("Missing idle alarm threshold"))?,
+                periods: *matches
+                    .get_one::<i32>("idle-alarm-periods")
+                    .ok_or_else(|| eyre!("Missing idle alarm periods"))?,
+            })
+        } else {
+            None
+        };
+
+        if stop_after.is_some() && action != Action::Start {
+            return Err(eyre!("--for can only be used when starting an instance"));
+        }
+
+        Ok(Self {
+            instance_name,
+            stack,
+            fleet,
+            timeout,
+            wait_timeout: matches.get_one::<u64>("wait-timeout").copied(),
+            wait_for_ssm,
+            wait_for_cloud_init,
+            wait_for_volumes: matches.contains_id("wait-for-volumes"),
+            check_services,
+            target_group: matches.get_one::<String>("wait-for-target-group").cloned(),
+            register_target_group: matches.get_one::<String>("target-group").cloned(),
+            health_check_id: matches.get_one::<String>("health-check-id").cloned(),
+            ssm_check,
+            wait_for_port: matches.get_one::<u16>("wait-for-port").copied(),
+            wait_for_http: matches.get_one::<String>("wait-for-http").cloned(),
+            wait_for_plugins: matches
+                .get_many::<String>("wait-for-plugin")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+            #[cfg(feature = "wasm")]
+            wait_for_wasm: matches
+                .get_many::<PathBuf>("wait-for-wasm")
+                .unwrap_or_default()
+                .cloned()
+                .collect(),
+            parallel_checks: matches.contains_id("parallel-checks"),
+            check_gpu,
+            gpu_warn_only: matches.contains_id("gpu-warn-only"),
+            stop_after,
+            at,
+            detach,
+            scheduler_role_arn,
+            console_output_lines,
+            windows_password_key,
+            serial_console: match matches
+                .get_one::<String>("serial-console")
+                .map(String::as_str)
+            {
+                Some("exec") => Some(SerialConsole::Exec),
+                Some(_) => Some(SerialConsole::Print),
+                None => None,
+            },
+            screenshot: matches.get_one::<PathBuf>("screenshot").cloned(),
+            rdp_file,
+            ssh_config,
+            print_host_keys,
+            known_hosts,
+            idle_alarm,
+            parallel: matches.contains_id("parallel"),
+            then: matches
+                .get_many::<String>("then")
+                .unwrap_or_default()
+                .map(|stage| stage.split(',').map(str::to_string).collect())
+                .collect(),
+            stage_delay: matches.get_one::<Duration>("stage-delay").copied(),
+            fail_fast: matches.contains_id("fail-fast"),
+            retries: *matches
+                .get_one::<u32>("retries")
+                .ok_or_else(|| eyre!("Missing retries"))?,
+            rollback_on_failure: matches.contains_id("rollback-on-failure"),
+            pre_hook: matches.get_one::<String>("pre-hook").cloned(),
+            post_hook: matches.get_one::<String>("post-hook").cloned(),
+            tail_logs: matches.get_one::<String>("tail-logs").cloned(),
+            warmup,
+            warmup_ignore_failure: matches.contains_id("warmup-ignore-failure"),
+            pre_stop_ssm: matches.get_one::<String>("pre-stop-ssm").cloned(),
+            pre_stop_ignore_failure: matches.contains_id("pre-stop-ignore-failure"),
+            snapshot_ami: matches.get_one::<String>("snapshot-ami").cloned(),
+            snapshot_ami_no_reboot: matches.contains_id("no-reboot"),
+            snapshot_volumes: matches.contains_id("snapshot-volumes"),
+            wait_for_snapshots: matches.contains_id("wait-for-snapshots"),
+            capacity_reservation: matches.get_one::<String>("capacity-reservation").cloned(),
+            credit_spec: matches.get_one::<String>("credit-spec").cloned(),
+            fallback_instance_types: matches
+                .get_one::<String>("fallback-instance-types")
+                .map(|types| types.split(',').map(str::to_string).collect())
+                .unwrap_or_default(),
+            authorize_my_ip: matches
+                .contains_id("authorize-my-ip")
+                .then(|| matches.get_one::<u16>("port").copied().unwrap_or(22)),
+            revoke_my_ip: matches.contains_id("revoke-my-ip"),
+            attach_eni: matches.get_one::<String>("attach-eni").cloned(),
+            warm_pool: matches.contains_id("warm-pool"),
+            wait_min_runtime: matches.contains_id("wait-min-runtime"),
+            drain_ecs: matches.contains_id("drain-ecs").then(|| {
+                matches
+                    .get_one::<Duration>("drain-timeout")
+                    .copied()
+                    .unwrap_or(DEFAULT_DRAIN_TIMEOUT)
+            }),
+            drain_k8s,
+            kubeconfig,
+            github_output: matches.contains_id("github-output"),
+            cancel_on_interrupt: matches.contains_id("cancel-on-interrupt"),
+            no_wait: matches.contains_id("no-wait"),
+            format: matches.get_one::<Template>("format").cloned(),
+            query: matches.get_one::<String>("query").cloned(),
+            terraform_external,
+            env_output: output == Some("env"),
+            porcelain,
+            stdout_reserved: output.is_some() || porcelain.is_some(),
+            yes: matches.contains_id("yes"),
+            budget_guard: matches.get_one::<String>("budget-guard").cloned(),
+            budget_max_percent: *matches
+                .get_one::<f64>("budget-max-percent")
+                .ok_or_else(|| eyre!("Missing budget percentage"))?,
+            force_budget: matches.contains_id("force-budget"),
+            show_cost: matches.contains_id("show-cost"),
+            confirm_above: matches.get_one::<f64>("confirm-above").copied(),
+            adaptive_timeout: matches.value_source("timeout") == Some(ValueSource::DefaultValue),
+            search_regions: matches.contains_id("search-regions"),
+            preflight: matches.contains_id("preflight"),
+            ..Self::new(action, instance_id)
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ScheduleCommand {
+    Create(ScheduleConfig),
+    List,
+    Delete(String),
+}
+
+impl ScheduleCommand {
+    fn from_matches(matches: &ArgMatches) -> Result<Self> {
+        match matches.subcommand() {
+            Some(("create", matches)) => Ok(Self::Create(ScheduleConfig::from_matches(matches)?)),
+            Some(("list", _)) => Ok(Self::List),
+            Some(("delete", matches)) => Ok(Self::Delete(
+                matches
+                    .get_one::<String>("name")
+                    .ok_or_else(|| eyre!("Missing schedule name"))?
+                    .clone(),
+            )),
+            _ => Err(eyre!("Missing schedule command")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ScheduleConfig {
+    pub action: Action,
+    pub instance_id: String,
+    pub cron: String,
+    pub timezone: String,
+    pub name: Option<String>,
+    pub role_arn: String,
+    pub lambda_arn: Option<String>,
+}
+
+impl ScheduleConfig {
+    fn from_matches(matches: &ArgMatches) -> Result<Self> {
+        Ok(Self {
+            action: get_action(matches)?,
+            instance_id: get_instance_id(matches)?,
+            cron: matches
+                .get_one::<String>("cron")
+                .ok_or_else(|| eyre!("Missing cron expression"))?
+                .clone(),
+            timezone: matches
+                .get_one::<String>("timezone")
+                .ok_or_else(|| eyre!("Missing time zone"))?
+                .clone(),
+            name: matches.get_one::<String>("name").cloned(),
+            role_arn: matches
+                .get_one::<String>("scheduler-role-arn")
+                .ok_or_else(|| eyre!("Missing scheduler role ARN"))?
+                .clone(),
+            lambda_arn: matches.get_one::<String>("lambda-arn").cloned(),
+        })
+    }
+}
+
+fn schedule_command() -> Command<'static> {
+    Command::new("schedule")
+        .about("Manage recurring start and stop schedules")
+        .subcommand_required(true)
+        .subcommands([
+            Command::new("create")
+                .about("Create a schedule running an action on an instance")
+                .args(&[
+                    Arg::new("cron")
+                        .long("cron")
+                        .takes_value(true)
+                        .value_name("EXPRESSION")
+                        .required(true)
+                        .value_parser(parse_cron)
+                        .help("Cron expression, e.g. \"0 8 * * MON-FRI\""),
+                    Arg::new("timezone")
+                        .long("timezone")
+                        .takes_value(true)
+                        .value_name("TIMEZONE")
+                        .required(false)
+                        .default_value("UTC")
+                        .help("Time zone the cron expression is evaluated in"),
+                    Arg::new("name")
+                        .long("name")
+                        .takes_value(true)
+                        .value_name("NAME")
+                        .required(false)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help("Schedule name, defaults to one built from the action and instance"),
+                    scheduler_role_arn_arg().required(true),
+                    Arg::new("lambda-arn")
+                        .long("lambda-arn")
+                        .takes_value(true)
+                        .value_name("FUNCTION_ARN")
+                        .required(false)
+                        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                        .help(
+                            "Invoke this function, running in Lambda mode, instead of EC2 directly",
+                        ),
+                    action_arg(),
+                    instance_arg(),
+                ]),
+            Command::new("list").about("List the schedules created by this program"),
+            Command::new("delete").about("Delete a schedule").arg(
+                Arg::new("name")
+                    .takes_value(true)
+                    .value_name("NAME")
+                    .required(true)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Schedule name, as shown by the list command"),
+            ),
+        ])
+}
+
+fn print_iam_policy_command() -> Command<'static> {
+    Command::new("print-iam-policy")
+        .about("Print the minimal IAM policy needed for the selected features")
+        .args(&[
+            Arg::new("instance")
+                .long("instance")
+                .takes_value(true)
+                .value_name("INSTANCE_ID")
+                .multiple_occurrences(true)
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Only allow acting on this instance, can be repeated"),
+            Arg::new("wait-for-ssm")
+                .long("wait-for-ssm")
+                .takes_value(false)
+                .required(false)
+                .help("Allow waiting for the instance to connect to SSM"),
+            Arg::new("schedules")
+                .long("schedules")
+                .takes_value(false)
+                .required(false)
+                .help("Allow managing schedules, including scheduled stops with --detach"),
+            Arg::new("watch-idle")
+                .long("watch-idle")
+                .takes_value(false)
+                .required(false)
+                .help("Allow reading the metrics used by watch-idle"),
+            Arg::new("idle-alarm")
+                .long("idle-alarm")
+                .takes_value(false)
+                .required(false)
+                .help("Allow creating the alarm of --install-idle-alarm"),
+            Arg::new("ssm-commands")
+                .long("ssm-commands")
+                .takes_value(false)
+                .required(false)
+                .help(
+                    "Allow running scripts on the instances through SSM, as --pre-stop-ssm, \
+                    --warmup, --wait-for-cloud-init and --check-service do",
+                ),
+            Arg::new("launch")
+                .long("launch")
+                .takes_value(false)
+                .required(false)
+                .help("Allow launching instances with the launch subcommand"),
+            Arg::new("snapshots")
+                .long("snapshots")
+                .takes_value(false)
+                .required(false)
+                .help("Allow creating an AMI or snapshots before stopping, as --snapshot-ami does"),
+            Arg::new("auto-scaling")
+                .long("auto-scaling")
+                .takes_value(false)
+                .required(false)
+                .help(
+                    "Allow moving instances in and out of Auto Scaling groups, as --warm-pool does",
+                ),
+            Arg::new("history")
+                .long("history")
+                .takes_value(false)
+                .required(false)
+                .help("Allow looking up the CloudTrail events of the history subcommand"),
+            Arg::new("metrics")
+                .long("metrics")
+                .takes_value(false)
+                .required(false)
+                .help("Allow putting metrics into CloudWatch, as --emit-metrics does"),
+            Arg::new("volumes")
+                .long("volumes")
+                .takes_value(false)
+                .required(false)
+                .help("Allow reading the status of EBS volumes, as --wait-for-volumes does"),
+            Arg::new("preflight")
+                .long("preflight")
+                .takes_value(false)
+                .required(false)
+                .help("Allow simulating the caller's policies, as --preflight does"),
+            Arg::new("credits")
+                .long("credits")
+                .takes_value(false)
+                .required(false)
+                .help(
+                    "Allow reading and setting CPU credit options, as status and --credit-spec do",
+                ),
+            Arg::new("console-output")
+                .long("console-output")
+                .takes_value(false)
+                .required(false)
+                .help("Allow reading the console output, as --console-output and --known-hosts do"),
+            Arg::new("passwords")
+                .long("passwords")
+                .takes_value(false)
+                .required(false)
+                .help("Allow getting Windows passwords, as --get-windows-password does"),
+            Arg::new("capacity-reservations")
+                .long("capacity-reservations")
+                .takes_value(false)
+                .required(false)
+                .help("Allow targeting capacity reservations, as --capacity-reservation does"),
+            Arg::new("instance-types")
+                .long("instance-types")
+                .takes_value(false)
+                .required(false)
+                .help("Allow changing instance types, as --fallback-instance-types does"),
+            Arg::new("screenshots")
+                .long("screenshots")
+                .takes_value(false)
+                .required(false)
+                .help("Allow capturing console screenshots, as --screenshot does"),
+            Arg::new("roles")
+                .long("roles")
+                .takes_value(false)
+                .required(false)
+                .help("Allow assuming roles of other accounts, as --account and org do"),
+            Arg::new("organizations")
+                .long("organizations")
+                .takes_value(false)
+                .required(false)
+                .help("Allow listing the accounts of the organization, as org does"),
+            Arg::new("state")
+                .long("state")
+                .takes_value(false)
+                .required(false)
+                .help("Allow keeping the state in S3, as an s3:// state does"),
+            Arg::new("notifications")
+                .long("notifications")
+                .takes_value(false)
+                .required(false)
+                .help("Allow sending the SNS and SES notifications of the configuration file"),
+            Arg::new("regions")
+                .long("regions")
+                .takes_value(false)
+                .required(false)
+                .help(
+                    "Allow listing the enabled regions, as --regions all and --search-regions do",
+                ),
+            Arg::new("target-groups")
+                .long("target-groups")
+                .takes_value(false)
+                .required(false)
+                .help(
+                    "Allow reading target health and registering targets, as \
+                    --wait-for-target-group and --target-group do",
+                ),
+            Arg::new("ecs")
+                .long("ecs")
+                .takes_value(false)
+                .required(false)
+                .help("Allow draining ECS container instances, as --drain-ecs does"),
+            Arg::new("health-checks")
+                .long("health-checks")
+                .takes_value(false)
+                .required(false)
+                .help("Allow disabling Route53 health checks, as --health-check-id does"),
+            Arg::new("ssm-sessions")
+                .long("ssm-sessions")
+                .takes_value(false)
+                .required(false)
+                .help("Allow sessions through SSM, as proxy-command and proxy --ssm open"),
+            Arg::new("ingress")
+                .long("ingress")
+                .takes_value(false)
+                .required(false)
+                .help("Allow changing security group rules, as --authorize-my-ip does"),
+            Arg::new("enis")
+                .long("enis")
+                .takes_value(false)
+                .required(false)
+                .help("Allow attaching and detaching network interfaces, as --attach-eni does"),
+            Arg::new("logs")
+                .long("logs")
+                .takes_value(false)
+                .required(false)
+                .help("Allow reading CloudWatch Logs, as --tail-logs does"),
+            Arg::new("budgets")
+                .long("budgets")
+                .takes_value(false)
+                .required(false)
+                .help("Allow reading budgets, as --budget-guard does"),
+            Arg::new("pricing")
+                .long("pricing")
+                .takes_value(false)
+                .required(false)
+                .help(
+                    "Allow getting the prices of the instance types, as sweep and --show-cost do",
+                ),
+            Arg::new("serial-console")
+                .long("serial-console")
+                .takes_value(false)
+                .required(false)
+                .help("Allow connecting to the serial console, as --serial-console does"),
+            Arg::new("parameters")
+                .long("parameters")
+                .takes_value(false)
+                .required(false)
+                .help("Allow reading instance ids from Parameter Store, as ssm:PARAMETER does"),
+            Arg::new("stacks")
+                .long("stacks")
+                .takes_value(false)
+                .required(false)
+                .help(
+                    "Allow reading instance ids from CloudFormation, \
+                    as cfn:STACK:OUTPUT and --stack do",
+                ),
+            Arg::new("fleets")
+                .long("fleets")
+                .takes_value(false)
+                .required(false)
+                .help("Allow changing the target capacity of fleets, as --fleet does"),
+        ])
+}
+
+fn proxy_command() -> Command<'static> {
+    Command::new("proxy")
+        .about("Start the instance on the first incoming connection and forward traffic to it")
+        .args(&[
+            Arg::new("listen")
+                .long("listen")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .required(false)
+                .value_parser(clap::value_parser!(SocketAddr))
+                .help(
+                    "Local address to listen on, e.g. 127.0.0.1:2222, \
+                    unless started by systemd socket activation",
+                ),
+            Arg::new("port")
+                .long("port")
+                .takes_value(true)
+                .value_name("PORT")
+                .required(true)
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("Port on the instance to forward connections to"),
+            Arg::new("idle-timeout")
+                .long("idle-timeout")
+                .takes_value(true)
+                .value_name("DURATION")
+                .required(false)
+                .value_parser(humantime::parse_duration)
+                .help("Stop the instance after this long without connections (e.g. 30m)"),
+            Arg::new("generate-systemd")
+                .long("generate-systemd")
+                .takes_value(true)
+                .value_name("DIRECTORY")
+                .required(false)
+                .requires("listen")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Write systemd socket and service units running the proxy on connection"),
+            Arg::new("ssm")
+                .long("ssm")
+                .takes_value(false)
+                .required(false)
+                .help(
+                    "Forward through an SSM session, re-established when it drops, \
+                    instead of connecting to the instance's address",
+                ),
+            instance_timeout_arg(),
+            instance_arg(),
+        ])
+}
+
+fn proxy_command_command() -> Command<'static> {
+    Command::new("proxy-command")
+        .about(
+            "Start the instance if needed and bridge stdin and stdout to its SSH port through SSM, \
+            as an ssh ProxyCommand",
+        )
+        .args(&[
+            Arg::new("port")
+                .long("port")
+                .takes_value(true)
+                .value_name("PORT")
+                .required(false)
+                .default_value("22")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("SSH port of the instance"),
+            instance_timeout_arg(),
+            Arg::new("host")
+                .takes_value(true)
+                .value_name("HOST")
+                .required(true)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Instance ID, or Name tag of the instance, as given by ssh with %h"),
+        ])
+}
+
+fn watch_idle_command() -> Command<'static> {
+    Command::new("watch-idle")
+        .about("Stop the instance once its CloudWatch metrics show it has been idle")
+        .args(&[
+            Arg::new("cpu-threshold")
+                .long("cpu-threshold")
+                .takes_value(true)
+                .value_name("PERCENT")
+                .required(false)
+                .value_parser(clap::value_parser!(f64))
+                .default_value("5")
+                .help("CPU utilization below which the instance is idle"),
+            Arg::new("network-threshold")
+                .long("network-threshold")
+                .takes_value(true)
+                .value_name("BYTES")
+                .required(false)
+                .value_parser(clap::value_parser!(f64))
+                .help("Also require network in and out to each stay below this per five minutes"),
+            Arg::new("idle-for")
+                .long("idle-for")
+                .takes_value(true)
+                .value_name("DURATION")
+                .required(false)
+                .value_parser(humantime::parse_duration)
+                .default_value("30m")
+                .help("How long the instance must be idle before being stopped"),
+            instance_timeout_arg(),
+            instance_arg(),
+        ])
+}
+
+fn list_command() -> Command<'static> {
+    Command::new("list")
+        .about("List the instances of the region")
+        .args(&[
+            Arg::new("state")
+                .long("state")
+                .takes_value(true)
+                .value_name("STATE")
+                .required(false)
+                .value_parser(["pending", "running", "stopping", "stopped"])
+                .help("Only list instances in this state"),
+            tag_arg()
+                .multiple_occurrences(true)
+                .required(false)
+                .help("Only list instances with this tag, can be repeated"),
+        ])
+}
+
+fn inventory_command() -> Command<'static> {
+    Command::new("inventory")
+        .about("Print an Ansible dynamic inventory of the instances, with a group per tag")
+        .args(&[
+            tag_arg()
+                .multiple_occurrences(true)
+                .required(false)
+                .help("Only include instances with this tag, can be repeated"),
+            Arg::new("ensure-running")
+                .long("ensure-running")
+                .takes_value(false)
+                .required(false)
+                .help("Start the stopped instances first"),
+            Arg::new("list")
+                .long("list")
+                .takes_value(false)
+                .required(false)
+                .help("Print the whole inventory, which is the default, as Ansible asks"),
+            Arg::new("host")
+                .long("host")
+                .takes_value(true)
+                .value_name("HOST")
+                .required(false)
+                .conflicts_with("list")
+                .help("Only print the variables of this host"),
+            instance_timeout_arg(),
+        ])
+}
+
+fn describe_command() -> Command<'static> {
+    Command::new("describe")
+        .about("Print the instances matching EC2 filters as JSON")
+        .arg(
+            Arg::new("filter")
+                .long("filter")
+                .takes_value(true)
+                .value_name("NAME=VALUES")
+                .multiple_occurrences(true)
+                .required(false)
+                .value_parser(parse_filter)
+                .help(
+                    "EC2 filter, e.g. instance-type=t3.large or tag:team=data, values separated \
+                    by commas, can be repeated",
+                ),
+        )
+        .arg(query_arg())
+}
+
+fn history_command() -> Command<'static> {
+    Command::new("history")
+        .about("Show who started and stopped an instance, and when, from CloudTrail")
+        .args(&[
+            Arg::new("days")
+                .long("days")
+                .takes_value(true)
+                .value_name("DAYS")
+                .required(false)
+                .value_parser(clap::value_parser!(u32).range(1..=90))
+                .default_value("7")
+                .help("How many days back to look"),
+            instance_arg(),
+        ])
+}
+
+fn bench_command() -> Command<'static> {
+    Command::new("bench")
+        .about(
+            "Stop and start an instance repeatedly, and show how long it takes to be running, \
+            for SSM to connect and for a probe to pass",
+        )
+        .args(&[
+            Arg::new("iterations")
+                .long("iterations")
+                .takes_value(true)
+                .value_name("COUNT")
+                .required(false)
+                .value_parser(clap::value_parser!(u32).range(1..))
+                .default_value("5")
+                .help("Number of stop and start cycles"),
+            Arg::new("no-ssm")
+                .long("no-ssm")
+                .takes_value(false)
+                .required(false)
+                .help("Don't wait for the SSM agent, for instances without one"),
+            Arg::new("wait-for-port")
+                .long("wait-for-port")
+                .takes_value(true)
+                .value_name("PORT")
+                .required(false)
+                .conflicts_with("wait-for-http")
+                .value_parser(clap::value_parser!(u16))
+                .help("Measure how long this TCP port takes to accept connections"),
+            Arg::new("wait-for-http")
+                .long("wait-for-http")
+                .takes_value(true)
+                .value_name("URL")
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help(
+                    "Measure how long this URL takes to answer with a success status, \
+                    with {ip} replaced by the instance's address",
+                ),
+            Arg::new("timeout")
+                .short('t')
+                .long("timeout")
+                .takes_value(true)
+                .value_name("TIMEOUT")
+                .required(false)
+                .value_parser(clap::builder::RangedU64ValueParser::<u64>::new())
+                .default_value("600")
+                .help("How long stopping, and starting until ready, may take in each cycle"),
+            instance_arg(),
+        ])
+}
+
+fn wait_command() -> Command<'static> {
+    Command::new("wait")
+        .about("Wait for the instance to reach a state, without starting or stopping it")
+        .args(&[
+            Arg::new("until")
+                .long("until")
+                .takes_value(true)
+                .value_name("CONDITION")
+                .required(true)
+                .value_parser(clap::builder::EnumValueParser::<Until>::new())
+                .help("Condition to wait for"),
+            instance_timeout_arg(),
+            instance_arg(),
+        ])
+}
+
+fn launch_command() -> Command<'static> {
+    Command::new("launch")
+        .about("Create an instance from a launch template or an AMI and wait for it to be running")
+        .args(&[
+            Arg::new("launch-template")
+                .long("launch-template")
+                .takes_value(true)
+                .value_name("TEMPLATE")
+                .required_unless_present("image-id")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Id (lt-...) or name of the launch template"),
+            Arg::new("image-id")
+                .long("image-id")
+                .takes_value(true)
+                .value_name("AMI_ID")
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("AMI to launch, overriding the launch template's"),
+            Arg::new("instance-type")
+                .long("instance-type")
+                .takes_value(true)
+                .value_name("TYPE")
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Instance type (e.g. t3.micro)"),
+            Arg::new("subnet-id")
+                .long("subnet-id")
+                .takes_value(true)
+                .value_name("SUBNET_ID")
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Subnet to launch the instance in"),
+            Arg::new("key-name")
+                .long("key-name")
+                .takes_value(true)
+                .value_name("KEY_NAME")
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Key pair of the instance"),
+            tag_arg()
+                .multiple_occurrences(true)
+                .required(false)
+                .help("Tag of the instance, can be repeated"),
+            Arg::new("wait-for-ssm")
+                .short('s')
+                .long("wait-for-ssm")
+                .takes_value(false)
+                .required(false)
+                .help("Wait for the instance to connect to SSM"),
+            instance_timeout_arg(),
+        ])
+}
+
+fn self_command() -> Command<'static> {
+    Command::new("self")
+        .about("Act on the instance this program runs on, found through the instance metadata")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("stop").about("Stop this instance").arg(
+                Arg::new("delay")
+                    .long("delay")
+                    .takes_value(true)
+                    .value_name("DURATION")
+                    .required(false)
+                    .value_parser(humantime::parse_duration)
+                    .help("Wait this long before stopping (e.g. 10m)"),
+            ),
+        )
+}
+
+#[cfg(feature = "grpc")]
+fn serve_command() -> Command<'static> {
+    let command = Command::new("serve")
+        .about("Serve a gRPC API to start, stop and inspect instances")
+        .args(&[
+            Arg::new("listen")
+                .long("listen")
+                .takes_value(true)
+                .value_name("ADDRESS")
+                .required(false)
+                .value_parser(clap::value_parser!(SocketAddr))
+                .default_value("127.0.0.1:50051")
+                .help("Address to serve the API on"),
+            instance_timeout_arg(),
+        ]);
+    #[cfg(feature = "web")]
+    let command = command.args(&[
+        Arg::new("web-listen")
+            .long("web-listen")
+            .takes_value(true)
+            .value_name("ADDRESS")
+            .required(false)
+            .value_parser(clap::value_parser!(SocketAddr))
+            .requires_all(&["web-token", "instance"])
+            .help("Also serve a web UI to start and stop the instances on this address"),
+        Arg::new("web-token")
+            .long("web-token")
+            .takes_value(true)
+            .value_name("TOKEN")
+            .required(false)
+            .env("AWS_START_STOP_WEB_TOKEN")
+            .hide_env_values(true)
+            .value_parser(clap::builder::NonEmptyStringValueParser::new())
+            .help("Token the web UI asks for before showing the instances"),
+        Arg::new("instance")
+            .long("instance")
+            .takes_value(true)
+            .value_name("INSTANCE_ID")
+            .multiple_occurrences(true)
+            .required(false)
+            .value_parser(parse_instance_id)
+            .help("Instance listed in the web UI, can be repeated"),
+    ]);
+    command
+}
+
+#[cfg(feature = "mqtt")]
+fn mqtt_command() -> Command<'static> {
+    let file_arg = |name: &'static str, help: &'static str| {
+        Arg::new(name)
+            .long(name)
+            .takes_value(true)
+            .value_name("FILE")
+            .required(false)
+            .value_parser(clap::value_parser!(PathBuf))
+            .requires("ca-file")
+            .help(help)
+    };
+
+    Command::new("mqtt")
+        .about("Start or stop an instance when a message is published to an MQTT topic")
+        .args(&[
+            Arg::new("host")
+                .long("host")
+                .takes_value(true)
+                .value_name("HOST")
+                .required(true)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("MQTT broker, e.g. the AWS IoT Core endpoint of the account"),
+            Arg::new("port")
+                .long("port")
+                .takes_value(true)
+                .value_name("PORT")
+                .required(false)
+                .value_parser(clap::value_parser!(u16))
+                .default_value("8883")
+                .help("Port of the MQTT broker"),
+            Arg::new("client-id")
+                .long("client-id")
+                .takes_value(true)
+                .value_name("CLIENT_ID")
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .default_value("aws-start-stop")
+                .help("MQTT client id, the thing name for AWS IoT Core"),
+            Arg::new("topic")
+                .long("topic")
+                .takes_value(true)
+                .value_name("TOPIC")
+                .required(true)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help(
+                    "Topic to subscribe to. Messages \"start\" and \"stop\" run that action, \
+                    any other starts the instance if it is stopped and stops it otherwise",
+                ),
+            Arg::new("response-topic")
+                .long("response-topic")
+                .takes_value(true)
+                .value_name("TOPIC")
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Topic to publish the result of each action to, as JSON"),
+            file_arg(
+                "ca-file",
+                "CA certificate of the broker, connecting with TLS when set",
+            ),
+            file_arg(
+                "cert-file",
+                "Client certificate, for brokers authenticating clients",
+            ),
+            file_arg("key-file", "Private key of the client certificate").requires("cert-file"),
+            instance_timeout_arg(),
+            instance_arg(),
+        ])
+}
+
+#[cfg(any(feature = "discord", feature = "slack", feature = "telegram"))]
+fn bot_command() -> Command<'static> {
+    let instances_arg = Arg::new("instance")
+        .takes_value(true)
+        .value_name("[NAME=]INSTANCE_ID")
+        .multiple_values(true)
+        .required(true)
+        .value_parser(parse_bot_instance)
+        .help("Instance the bot manages, optionally named for use in commands, can be repeated");
+
+    let command = Command::new("bot")
+        .about("Answer commands sent through a chat bot")
+        .subcommand_required(true);
+    #[cfg(feature = "discord")]
+    let command = command.subcommand(
+        Command::new("discord")
+            .about("Answer the /start, /stop and /status slash commands of a Discord bot")
+            .args(&[
+                Arg::new("token")
+                    .long("token")
+                    .takes_value(true)
+                    .value_name("TOKEN")
+                    .required(true)
+                    .env("DISCORD_BOT_TOKEN")
+                    .hide_env_values(true)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Token of the bot, from the Discord developer portal"),
+                Arg::new("guild")
+                    .long("guild")
+                    .takes_value(true)
+                    .value_name("SERVER_ID")
+                    .required(true)
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Server to register the slash commands in"),
+                Arg::new("allow-role")
+                    .long("allow-role")
+                    .takes_value(true)
+                    .value_name("ROLE_ID")
+                    .multiple_occurrences(true)
+                    .required(true)
+                    .value_parser(clap::value_parser!(u64))
+                    .help("Role allowed to use the commands, can be repeated"),
+                instance_timeout_arg(),
+                instances_arg.clone(),
+            ]),
+    );
+    #[cfg(feature = "slack")]
+    let command = command.subcommand(
+        Command::new("slack")
+            .about(
+                "Serve a Slack slash command, e.g. /devbox start web-1, posting the outcome \
+                back to the channel",
+            )
+            .args(&[
+                Arg::new("listen")
+                    .long("listen")
+                    .takes_value(true)
+                    .value_name("ADDRESS")
+                    .required(false)
+                    .value_parser(clap::value_parser!(SocketAddr))
+                    .default_value("127.0.0.1:3000")
+                    .help("Address to serve the slash command on, behind an HTTPS proxy"),
+                Arg::new("signing-secret")
+                    .long("signing-secret")
+                    .takes_value(true)
+                    .value_name("SECRET")
+                    .required(true)
+                    .env("SLACK_SIGNING_SECRET")
+                    .hide_env_values(true)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Signing secret of the Slack app, to check requests come from Slack"),
+                instance_timeout_arg(),
+                instances_arg.clone(),
+            ]),
+    );
+    #[cfg(feature = "telegram")]
+    let command = command.subcommand(
+        Command::new("telegram")
+            .about(
+                "Answer /start_instance, /stop_instance and /status sent to a Telegram bot, \
+                followed by the instance id when the bot manages several",
+            )
+            .args(&[
+                Arg::new("token")
+                    .long("token")
+                    .takes_value(true)
+                    .value_name("TOKEN")
+                    .required(true)
+                    .env("TELEGRAM_BOT_TOKEN")
+                    .hide_env_values(true)
+                    .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                    .help("Token of the bot, as given by @BotFather"),
+                Arg::new("allow-chat")
+                    .long("allow-chat")
+                    .takes_value(true)
+                    .value_name("CHAT_ID")
+                    .multiple_occurrences(true)
+                    .required(true)
+                    .value_parser(clap::value_parser!(i64))
+                    .help("Chat allowed to send commands, can be repeated"),
+                instance_timeout_arg(),
+                instances_arg,
+            ]),
+    );
+    command
+}
+
+#[cfg(windows)]
+fn service_command() -> Command<'static> {
+    let name_arg = Arg::new("name")
+        .long("name")
+        .takes_value(true)
+        .value_name("NAME")
+        .required(false)
+        .default_value(service::DEFAULT_NAME)
+        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+        .help("Name of the service");
+    let args_arg = Arg::new("args")
+        .takes_value(true)
+        .value_name("ARGS")
+        .multiple_values(true)
+        .last(true)
+        .required(true)
+        .help("Arguments the service runs this program with, e.g. -- proxy --port 22 i-...");
+
+    Command::new("service")
+        .about("Run this program as a Windows service")
+        .subcommand_required(true)
+        .subcommands([
+            Command::new("install")
+                .about("Install a service starting with Windows")
+                .args(&[name_arg.clone(), args_arg.clone()]),
+            Command::new("uninstall")
+                .about("Stop and remove the service")
+                .arg(name_arg.clone()),
+            Command::new("run")
+                .about("Run the service, as done by the service control manager")
+                .hide(true)
+                .args(&[name_arg, args_arg]),
+        ])
+}
+
+#[cfg(windows)]
+fn service_from_matches(matches: &ArgMatches) -> Result<ServiceCommand> {
+    let (command, matches) = matches
+        .subcommand()
+        .ok_or_else(|| eyre!("Missing service command"))?;
+    let name = matches
+        .get_one::<String>("name")
+        .ok_or_else(|| eyre!("Missing service name"))?
+        .clone();
+    let args = || -> Vec<String> {
+        matches
+            .get_many::<String>("args")
+            .unwrap_or_default()
+            .cloned()
+            .collect()
+    };
+
+    match command {
+        "install" => Ok(ServiceCommand::Install { name, args: args() }),
+        "uninstall" => Ok(ServiceCommand::Uninstall { name }),
+        "run" => Ok(ServiceCommand::Run { name, args: args() }),
+        _ => Err(eyre!("Missing service command")),
+    }
+}
+
+fn org_command() -> Command<'static> {
+    Command::new("org")
+        .about("Start or stop the tagged instances of every account of the organization")
+        .args(&[
+            tag_arg().required(true),
+            Arg::new("role-name")
+                .long("role-name")
+                .takes_value(true)
+                .value_name("ROLE_NAME")
+                .required(false)
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .default_value("OrganizationAccountAccessRole")
+                .help("Role assumed in every account"),
+            action_arg(),
+        ])
+}
+
+fn stop_all_command() -> Command<'static> {
+    Command::new("stop-all")
+        .about("Stop every running instance with the tags, in all the regions given with --regions")
+        .args(&[
+            tag_arg()
+                .multiple_occurrences(true)
+                .required(true)
+                .help("Only stop instances with this tag, can be repeated"),
+            Arg::new("older-than")
+                .long("older-than")
+                .takes_value(true)
+                .value_name("DURATION")
+                .required(false)
+                .value_parser(humantime::parse_duration)
+                .help("Only stop instances launched at least this long ago (e.g. 8h)"),
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .takes_value(false)
+                .required(false)
+                .help("Don't ask for the number of instances to be confirmed"),
+            instance_timeout_arg(),
+        ])
+}
+
+fn sweep_command() -> Command<'static> {
+    Command::new("sweep")
+        .about(
+            "Stop every running instance of the regions given with --regions, but the protected \
+            and excluded ones, showing what they cost an hour first",
+        )
+        .args(&[
+            tag_arg()
+                .id("exclude-tag")
+                .long("exclude-tag")
+                .multiple_occurrences(true)
+                .required(false)
+                .help("Leave the instances with this tag running, can be repeated"),
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .takes_value(false)
+                .required(false)
+                .help("Don't ask for the number of instances to be confirmed"),
+            instance_timeout_arg(),
+        ])
+}
+
+fn apply_command() -> Command<'static> {
+    Command::new("apply")
+        .about("Start and stop instances to match the states declared in a YAML file")
+        .args(&[
+            Arg::new("file")
+                .short('f')
+                .long("file")
+                .takes_value(true)
+                .value_name("FILE")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("File listing instance ids or tags with their desired state"),
+            Arg::new("dry-run")
+                .long("dry-run")
+                .takes_value(false)
+                .required(false)
+                .help("Only print what would be done"),
+            Arg::new("every")
+                .long("every")
+                .takes_value(true)
+                .value_name("DURATION")
+                .required(false)
+                .value_parser(humantime::parse_duration)
+                .help("Keep running, applying the schedules at this interval, e.g. 5m"),
+            Arg::new("control-socket")
+                .long("control-socket")
+                .takes_value(true)
+                .value_name("PATH")
+                .required(false)
+                .requires("every")
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Accept the commands of ctl on this Unix socket while running"),
+        ])
+}
+
+fn ctl_command() -> Command<'static> {
+    let job_arg = Arg::new("job")
+        .takes_value(true)
+        .value_name("JOB")
+        .required(true)
+        .help("Name of the schedule");
+
+    Command::new("ctl")
+        .about("Inspect or control a running from-scheduler-tags --every through its socket")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("socket")
+                .long("socket")
+                .takes_value(true)
+                .value_name("PATH")
+                .required(true)
+                .value_parser(clap::value_parser!(PathBuf))
+                .help("Socket given to the daemon with --control-socket"),
+        )
+        .subcommands([
+            Command::new("status").about("List the schedules and how their last run went"),
+            Command::new("run-now")
+                .about("Apply the schedule now, even if paused")
+                .arg(job_arg.clone()),
+            Command::new("pause")
+                .about("Stop applying the schedule until resumed")
+                .arg(job_arg.clone()),
+            Command::new("resume")
+                .about("Apply the schedule again")
+                .arg(job_arg),
+        ])
+}
+
+fn from_scheduler_tags_command() -> Command<'static> {
+    Command::new("from-scheduler-tags")
+        .about(
+            "Start and stop the instances tagged with a schedule, as AWS Instance Scheduler does, \
+            so that they run during its periods",
+        )
+        .args(&[
+            Arg::new("tag-key")
+                .long("tag-key")
+                .takes_value(true)
+                .value_name("KEY")
+                .required(false)
+                .default_value("Schedule")
+                .value_parser(clap::builder::NonEmptyStringValueParser::new())
+                .help("Tag naming the schedule of each instance"),
+            Arg::new("dry-run")
+                .long("dry-run")
+                .takes_value(false)
+                .required(false)
+                .help("Only print what would be done"),
+        ])
+}
+
+fn config_command() -> Command<'static> {
+    Command::new("config")
+        .about("Manage the configuration file")
+        .subcommand_required(true)
+        .subcommand(
+            Command::new("validate")
+                .about("Check the configuration file, before schedules rely on it")
+                .arg(
+                    Arg::new("aws")
+                        .long("aws")
+                        .takes_value(false)
+                        .required(false)
+                        .help(
+                            "Also check the credentials, account roles and group instances \
+                            against AWS, read-only",
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("show")
+                .about("Print the configuration file")
+                .arg(
+                    Arg::new("resolved")
+                        .long("resolved")
+                        .takes_value(false)
+                        .required(false)
+                        .help(
+                            "Print the value of each argument of the main command instead, \
+                            and whether it came from the command line, the configuration file, \
+                            the environment or the default",
+                        ),
+                ),
+        )
+}
+
+fn screenshot_arg() -> Arg<'static> {
+    Arg::new("screenshot")
+        .long("screenshot")
+        .takes_value(true)
+        .value_name("FILE")
+        .required(false)
+        .value_parser(clap::value_parser!(PathBuf))
+        .help("Write a screenshot of the instance's console to this file, as a JPEG image")
+}
+
+fn tag_arg() -> Arg<'static> {
+    Arg::new("tag")
+        .long("tag")
+        .takes_value(true)
+        .value_name("KEY=VALUE")
+        .value_parser(parse_tag)
+        .help("Only act on instances with this tag")
+}
+
+fn query_arg() -> Arg<'static> {
+    Arg::new("query")
+        .long("query")
+        .takes_value(true)
+        .value_name("JMESPATH")
+        .required(false)
+        .value_parser(query::parse)
+        .help("Print only the result of this JMESPath expression on the JSON output")
+}
+
+fn instance_timeout_arg() -> Arg<'static> {
+    Arg::new("timeout")
+        .short('t')
+        .long("timeout")
+        .takes_value(true)
+        .value_name("TIMEOUT")
+        .required(false)
+        .value_parser(clap::builder::RangedU64ValueParser::<u64>::new())
+        .default_value("120")
+        .help("How long to wait for the instance to start or stop")
+}
+
+fn action_arg() -> Arg<'static> {
+    Arg::new("action")
+        .takes_value(true)
+        .ignore_case(true)
+        .value_name("ACTION")
+        .required(true)
+        .value_parser(clap::builder::EnumValueParser::<Action>::new())
+        .help("Action")
+}
+
+fn instance_arg() -> Arg<'static> {
+    Arg::new("instance")
+        .takes_value(true)
+        .value_name("INSTANCE_ID")
+        .required(true)
+        .value_parser(parse_instance)
+        .help(
+            "Instance ID, optionally prefixed with an account alias \
+            (e.g. dev/i-0123456789abcdef0), instance ARN, or @group",
+        )
+}
+
+fn scheduler_role_arn_arg() -> Arg<'static> {
+    Arg::new("scheduler-role-arn")
+        .long("scheduler-role-arn")
+        .takes_value(true)
+        .value_name("ROLE_ARN")
+        .value_parser(clap::builder::NonEmptyStringValueParser::new())
+        .help("Role assumed by EventBridge Scheduler to act on the instance")
+}
+
+fn get_action(matches: &ArgMatches) -> Result<Action> {
+    Ok(matches
+        .get_one::<Action>("action")
+        .ok_or_else(|| eyre!("Missing action"))?
+        .clone())
+}
+
+/// Parts of an instance ARN, e.g. `arn:aws:ec2:eu-west-3:123456789012:instance/i-0123456789abcdef0`
+struct InstanceArn {
+    region: String,
+    account_id: String,
+    instance_id: String,
+}
+
+impl InstanceArn {
+    fn parse(value: &str) -> Option<Self> {
+        let fields: Vec<&str> = value.split(':').collect();
+        let ["arn", _partition, "ec2", region, account_id, resource] = fields[..] else {
+            return None;
+        };
+        let instance_id = resource.strip_prefix("instance/")?;
+
+        Some(Self {
+            region: region.to_string(),
+            account_id: account_id.to_string(),
+            instance_id: instance_id.to_string(),
+        })
+    }
+}
+
+/// Checks the instance ids, so that typos are reported before calling AWS
+///
+/// Groups are only checked once the configuration file has been read.
+#[cfg(any(feature = "discord", feature = "slack", feature = "telegram"))]
+fn parse_bot_instance(value: &str) -> Result<BotInstance, String> {
+    let (name, instance_id) = match value.split_once('=') {
+        Some((name, instance_id)) => (Some(name.to_string()), instance_id),
+        None => (None, value),
+    };
+    Ok(BotInstance {
+        name,
+        instance_id: parse_instance_id(instance_id)?,
+    })
+}
+
+/// Accepts only plain instance ids, for features not going through account aliases and groups
+#[cfg(any(
+    feature = "discord",
+    feature = "slack",
+    feature = "telegram",
+    feature = "web"
+))]
+fn parse_instance_id(value: &str) -> Result<String, String> {
+    if value.starts_with('@') || value.contains('/') {
+        return Err(format!("{} must be the id of an instance", value));
+    }
+    parse_instance(value)
+}
+
+pub fn parse_instance(value: &str) -> Result<String, String> {
+    static INSTANCE_ID: OnceLock<Regex> = OnceLock::new();
+    let instance_id_regex = INSTANCE_ID.get_or_init(|| Regex::new("^i-[0-9a-f]{8,17}$").unwrap());
+
+    if value.starts_with('@') || layers::alias(value).is_some() {
+        return Ok(value.to_string());
+    }
+
+    let instance_id = match InstanceArn::parse(value) {
+        Some(arn) => arn.instance_id,
+        None => value
+            .split_once('/')
+            .map_or(value, |(_, instance_id)| instance_id)
+            .to_string(),
+    };
+
+    if instance_id_regex.is_match(&instance_id) {
+        Ok(value.to_string())
+    } else {
+        Err(format!(
+            "{} is not a valid instance id, which looks like i-0123456789abcdef0",
+            instance_id
+        ))
+    }
+}
+
+/// Checks the instance of the main command, which may also be read from Parameter Store or
+/// CloudFormation
+fn parse_run_instance(value: &str) -> Result<String, String> {
+    match value.strip_prefix(parameter::PREFIX) {
+        Some("") => return Err("missing the name of the parameter".to_string()),
+        Some(_) => return Ok(value.to_string()),
+        None => (),
+    }
+    if let Some(reference) = value.strip_prefix(stack::PREFIX) {
+        return match reference.rsplit_once(':') {
+            Some((stack, name)) if !stack.is_empty() && !name.is_empty() => Ok(value.to_string()),
+            _ => Err(format!(
+                "{} must be cfn:STACK:OUTPUT or cfn:STACK:RESOURCE",
+                value
+            )),
+        };
+    }
+    parse_instance(value)
+}
+
+/// Gets the instance id, without the account alias it may be prefixed with
+///
+/// Instance aliases of the configuration file are resolved first.
+fn get_instance_id(matches: &ArgMatches) -> Result<String> {
+    let instance = matches
+        .get_one::<String>("instance")
+        .ok_or_else(|| eyre!("Missing instance id"))?;
+    let instance = layers::alias(instance).map_or(instance, |alias| &alias.instance);
+    if instance.starts_with(parameter::PREFIX) || instance.starts_with(stack::PREFIX) {
+        // Read from Parameter Store or CloudFormation once connected to AWS
+        return Ok(instance.clone());
+    }
+
+    if let Some(arn) = InstanceArn::parse(instance) {
+        return Ok(arn.instance_id);
+    }
+
+    Ok(instance
+        .split_once('/')
+        .map_or(instance.as_str(), |(_, instance_id)| instance_id)
+        .to_string())
+}
+
+fn innermost_matches(matches: &ArgMatches) -> &ArgMatches {
+    match matches.subcommand() {
+        Some((_, matches)) => innermost_matches(matches),
+        None => matches,
+    }
+}
+
+fn parse_filter(value: &str) -> Result<(String, Vec<String>), String> {
+    match value.split_once('=') {
+        Some((name, values)) if !name.is_empty() && !values.is_empty() => Ok((
+            name.to_string(),
+            values.split(',').map(str::to_string).collect(),
+        )),
+        _ => Err(format!("{} must be NAME=VALUES", value)),
+    }
+}
+
+pub fn parse_tag(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err("expected KEY=VALUE".to_string()),
+    }
+}
+
+fn parse_eni(value: &str) -> Result<String, String> {
+    match value.strip_prefix("eni-") {
+        Some(id) if !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(value.to_string())
+        }
+        _ => Err("expected a network interface id (eni-...)".to_string()),
+    }
+}
+
+fn parse_fleet(value: &str) -> Result<String, String> {
+    if value.starts_with("fleet-") || value.starts_with("sfr-") {
+        Ok(value.to_string())
+    } else {
+        Err("expected an EC2 Fleet id (fleet-...) or Spot Fleet request id (sfr-...)".to_string())
+    }
+}
+
+fn parse_capacity_reservation(value: &str) -> Result<String, String> {
+    match value.strip_prefix("cr-") {
+        Some(id) if !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(value.to_string())
+        }
+        _ if value == "open" => Ok(value.to_string()),
+        _ => Err("expected a capacity reservation id (cr-...) or open".to_string()),
+    }
+}
+
+/// Parses a size in bytes, with an optional K, M or G suffix
+fn parse_account_id(value: &str) -> Result<String, String> {
+    if value.len() == 12 && value.chars().all(|c| c.is_ascii_digit()) {
+        Ok(value.to_string())
+    } else {
+        Err("expected a 12-digit AWS account id".to_string())
+    }
+}
+
+fn parse_rate(value: &str) -> Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate.is_finite() && rate > 0.0 => Ok(rate),
+        _ => Err("expected a positive number of calls per second".to_string()),
+    }
+}
+
+fn parse_size(value: &str) -> Result<u64, String> {
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 'K' | 'k')) => (&value[..index], 1 << 10),
+        Some((index, 'M' | 'm')) => (&value[..index], 1 << 20),
+        Some((index, 'G' | 'g')) => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .map(|number| number * multiplier)
+        .map_err(|_| "expected a size such as 500K or 10M".to_string())
+}
+
+/// Converts a standard five-field cron expression to EventBridge Scheduler's syntax
+///
+/// EventBridge adds a year field and requires one of the day-of-month and day-of-week fields
+/// to be `?`. It also numbers days of the week from 1 (Sunday) instead of 0,
+/// so only names are accepted for them.
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M")
+        .map_err(|_| format!("{} isn't a time like 18:30", value))
+}
+
+/// Next time it is this local time, today or tomorrow
+fn next_occurrence(time: NaiveTime) -> Result<DateTime<Utc>> {
+    let now = Local::now();
+    let mut date = now.date_naive();
+    if time <= now.time() {
+        date = date
+            .succ_opt()
+            .ok_or_else(|| eyre!("No day after {}", date))?;
+    }
+    Local
+        .from_local_datetime(&date.and_time(time))
+        .earliest()
+        .map(|at| at.with_timezone(&Utc))
+        .ok_or_else(|| eyre!("{} doesn't exist on {}", time, date))
+}
+
+fn parse_cron(value: &str) -> Result<String, String> {
+    let fields: Vec<&str> = value.split_whitespace().collect();
+    let [minutes, hours, day_of_month, month, day_of_week] = fields[..] else {
+        return Err("expected five fields: minute hour day-of-month month day-of-week".to_string());
+    };
+
+    if day_of_week.chars().any(|c| c.is_ascii_digit()) {
+        return Err("use day names (e.g. MON-FRI) for the day of week".to_string());
+    }
+
+    let (day_of_month, day_of_week) = match (day_of_month, day_of_week) {
+        (day_of_month, "*" | "?") if day_of_month != "?" => (day_of_month, "?"),
+        ("*" | "?", day_of_week) => ("?", day_of_week),
+        _ => return Err("day of month and day of week can't both be set".to_string()),
+    };
+
+    Ok(format!(
+        "cron({} {} {} {} {} *)",
+        minutes, hours, day_of_month, month, day_of_week
+    ))
 }
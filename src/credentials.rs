@@ -0,0 +1,178 @@
+use crate::clients;
+use crate::error::Hint;
+use crate::log::{self, Level};
+use crate::mfa::{self, MfaProfile};
+use crate::sso::{self, SsoProfile};
+use aws_config::credential_process::CredentialProcessProvider;
+use aws_config::profile::ProfileSet;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_credential_types::provider::{ProvideCredentials, SharedCredentialsProvider};
+use aws_runtime::env_config::file::EnvConfigFiles;
+use aws_types::os_shim_internal::{Env, Fs};
+use color_eyre::eyre::{eyre, Report};
+use color_eyre::Result;
+
+/// Makes sure credentials can be resolved before doing any work
+///
+/// Resolution failures are otherwise only reported by the first API call, as a cryptic error.
+/// When the selected profile uses IAM Identity Center and `sso_login` is set, logs in again and
+/// returns the configuration with its credentials using the refreshed token.
+/// When it requires MFA and its credentials aren't temporary ones already, prompts for a code and
+/// returns a configuration using the resulting session.
+pub async fn ensure(aws_config: SdkConfig, sso_login: bool) -> Result<SdkConfig> {
+    let Some(provider) = aws_config.credentials_provider() else {
+        return Err(eyre!("No AWS credentials provider is configured"));
+    };
+    let resolved = provider.provide_credentials().await;
+
+    // Credentials from the environment, or already temporary, are used as they are
+    let usable = resolved.as_ref().is_ok_and(|credentials| {
+        credentials.session_token().is_some() || std::env::var_os("AWS_ACCESS_KEY_ID").is_some()
+    });
+    if !usable {
+        if let Some(mfa_profile) = MfaProfile::load().await? {
+            return mfa::authenticate(&aws_config, &mfa_profile).await;
+        }
+    }
+
+    let err = match resolved {
+        Ok(_) => return Ok(aws_config),
+        Err(err) => err,
+    };
+
+    let Some(sso_profile) = SsoProfile::load().await? else {
+        return Err(Report::new(err).wrap_err("Failed to load AWS credentials"));
+    };
+
+    if !sso_login {
+        return Err(Report::new(err).wrap_err(
+            "Failed to load AWS credentials. The SSO token may have expired: \
+            run `aws sso login` or pass --sso-login",
+        ));
+    }
+
+    sso::login(&aws_config, &sso_profile).await?;
+
+    // Only the credentials are loaded again, with the refreshed token, the retries, HTTP client
+    // and region of the configuration being kept
+    let refreshed = aws_config::load_defaults(BehaviorVersion::latest()).await;
+    let provider = refreshed
+        .credentials_provider()
+        .ok_or_else(|| eyre!("No AWS credentials provider is configured"))?;
+    Ok(aws_config
+        .to_builder()
+        .credentials_provider(provider)
+        .build())
+}
+
+/// Provider running the command, which prints the credentials as JSON like a profile's
+/// `credential_process`, and again once they have expired
+pub fn from_command(command: &str) -> CredentialProcessProvider {
+    CredentialProcessProvider::new(command.to_string())
+}
+
+/// Makes sure the credentials of `--credentials-cmd` can be resolved, as `ensure` does for those
+/// of the profile
+pub async fn ensure_command(aws_config: SdkConfig, command: &str) -> Result<SdkConfig> {
+    let Some(provider) = aws_config.credentials_provider() else {
+        return Err(eyre!("No AWS credentials provider is configured"));
+    };
+    provider.provide_credentials().await.map_err(|err| {
+        Report::new(err).wrap_err(format!("Failed to get credentials from {}", command))
+    })?;
+    Ok(aws_config)
+}
+
+/// Returns a configuration using credentials for the given role, assumed with the current ones
+pub async fn assume_role(aws_config: SdkConfig, role_arn: &str) -> Result<SdkConfig> {
+    let provider = AssumeRoleProvider::builder(role_arn)
+        .session_name("aws-start-stop")
+        .configure(&aws_config)
+        .build()
+        .await;
+
+    // Resolve the credentials now, for the same reason as in `ensure`
+    provider
+        .provide_credentials()
+        .await
+        .map_err(|err| Report::new(err).wrap_err(format!("Failed to assume role {}", role_arn)))?;
+
+    Ok(aws_config
+        .to_builder()
+        .credentials_provider(SharedCredentialsProvider::new(provider))
+        .build())
+}
+
+/// ARN of the identity the calls are made as
+pub async fn caller(aws_config: &SdkConfig) -> Result<String> {
+    let identity = clients::sts(aws_config)
+        .get_caller_identity()
+        .send()
+        .await
+        .hint("sts:GetCallerIdentity")?;
+    Ok(identity.arn().unwrap_or("unknown").to_string())
+}
+
+/// Shows the identity, account and region the calls are about to be made with, if asked to, and
+/// fails when the account isn't the expected one
+///
+/// Stale variables in the environment could otherwise act in another account than intended.
+pub async fn whoami(
+    aws_config: &SdkConfig,
+    show: bool,
+    expected_account: Option<&str>,
+) -> Result<()> {
+    let identity = clients::sts(aws_config)
+        .get_caller_identity()
+        .send()
+        .await
+        .hint("sts:GetCallerIdentity")?;
+    let account = identity.account().unwrap_or("unknown");
+    let arn = identity.arn().unwrap_or("unknown");
+    let region = aws_config
+        .region()
+        .map_or("unknown".to_string(), ToString::to_string);
+
+    if show {
+        log::event(
+            Level::Info,
+            "whoami",
+            format!(
+                "Acting as {} in account {}, region {}",
+                arn, account, region
+            ),
+            serde_json::json!({ "account": account, "arn": arn, "region": region }),
+        );
+    }
+    match expected_account {
+        Some(expected) if expected != account => Err(eyre!(
+            "Refusing to act in account {} as {}, --expect-account is {}",
+            account,
+            arn,
+            expected
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Id of the account the calls are made in
+pub async fn account_id(aws_config: &SdkConfig) -> Result<String> {
+    let identity = clients::sts(aws_config)
+        .get_caller_identity()
+        .send()
+        .await
+        .hint("sts:GetCallerIdentity")?;
+    identity
+        .account()
+        .map(str::to_string)
+        .ok_or_else(|| eyre!("No account id in the caller identity"))
+}
+
+/// Loads the shared config and credentials files, in the same way as the SDK
+pub async fn load_profiles() -> Result<ProfileSet> {
+    Ok(
+        aws_config::profile::load(&Fs::real(), &Env::real(), &EnvConfigFiles::default(), None)
+            .await?,
+    )
+}
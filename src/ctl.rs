@@ -0,0 +1,150 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::path::{Path, PathBuf};
+use tokio::sync::{mpsc, oneshot};
+
+#[derive(Debug)]
+pub struct CtlConfig {
+    /// Socket the daemon listens on
+    pub socket: PathBuf,
+    /// Command line sent to the daemon, e.g. `run-now office-hours`
+    pub command: String,
+}
+
+/// What a running `from-scheduler-tags --every` daemon can be asked, its jobs being the schedules
+#[derive(Debug)]
+pub enum Command {
+    /// List the jobs, whether they are paused and how their last run went
+    Status,
+    /// Apply the schedule now, even if paused
+    RunNow(String),
+    /// Stop applying the schedule until resumed
+    Pause(String),
+    Resume(String),
+}
+
+impl Command {
+    #[cfg_attr(not(unix), allow(dead_code))]
+    fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["status"] => Ok(Self::Status),
+            ["run-now", job] => Ok(Self::RunNow(job.to_string())),
+            ["pause", job] => Ok(Self::Pause(job.to_string())),
+            ["resume", job] => Ok(Self::Resume(job.to_string())),
+            _ => Err(format!(
+                "unknown command {:?}, expected status, run-now JOB, pause JOB or resume JOB",
+                line.trim()
+            )),
+        }
+    }
+}
+
+/// Command received on the socket, along with where its answer goes
+pub struct Request {
+    pub command: Command,
+    pub reply: oneshot::Sender<Result<String, String>>,
+}
+
+/// Accepts commands on the Unix socket at the path in the background, one per connection
+///
+/// The answer is written back as `ok` or `error: <message>` on the first line, followed by the
+/// output. Only the user running the daemon may connect.
+pub fn listen(path: &Path) -> Result<mpsc::Receiver<Request>> {
+    #[cfg(unix)]
+    {
+        use crate::log;
+        use std::os::unix::fs::PermissionsExt;
+        use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+        use tokio::net::UnixListener;
+
+        // Left behind by a daemon which didn't exit cleanly
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        log::info("ctl", format!("Accepting commands on {}", path.display()));
+
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::spawn(async move {
+            loop {
+                let socket = match listener.accept().await {
+                    Ok((socket, _)) => socket,
+                    Err(e) => {
+                        log::warn("ctl", format!("Failed to accept: {}", e));
+                        continue;
+                    }
+                };
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    let (reader, mut writer) = socket.into_split();
+                    let mut line = String::new();
+                    if BufReader::new(reader).read_line(&mut line).await.is_err() {
+                        return;
+                    }
+                    let answer = match Command::parse(&line) {
+                        Ok(command) => {
+                            let (reply, answer) = oneshot::channel();
+                            let _ = sender.send(Request { command, reply }).await;
+                            answer
+                                .await
+                                .unwrap_or_else(|_| Err("the daemon is exiting".to_string()))
+                        }
+                        Err(e) => Err(e),
+                    };
+                    let answer = match answer {
+                        Ok(output) => format!("ok\n{}", output),
+                        Err(e) => format!("error: {}\n", e),
+                    };
+                    let _ = writer.write_all(answer.as_bytes()).await;
+                });
+            }
+        });
+        Ok(receiver)
+    }
+
+    #[cfg(not(unix))]
+    Err(eyre!(
+        "Control sockets are only available on Unix, not for {}",
+        path.display()
+    ))
+}
+
+/// Sends the command to the daemon and prints its answer
+pub async fn run(config: CtlConfig) -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::UnixStream;
+
+        let mut socket = UnixStream::connect(&config.socket).await.map_err(|e| {
+            eyre!(
+                "Failed to connect to {}, is the daemon running? {}",
+                config.socket.display(),
+                e
+            )
+        })?;
+        socket
+            .write_all(format!("{}\n", config.command).as_bytes())
+            .await?;
+        let mut answer = String::new();
+        socket.read_to_string(&mut answer).await?;
+
+        match answer.split_once('\n') {
+            Some(("ok", output)) => {
+                print!("{}", output);
+                Ok(())
+            }
+            Some((error, _)) => Err(eyre!("{}", error.strip_prefix("error: ").unwrap_or(error))),
+            None => Err(eyre!("The daemon closed the connection without answering")),
+        }
+    }
+
+    #[cfg(not(unix))]
+    Err(eyre!(
+        "Control sockets are only available on Unix, not for {} {}",
+        config.socket.display(),
+        config.command
+    ))
+}
@@ -0,0 +1,90 @@
+use crate::clients;
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use chrono::{TimeZone, Utc};
+use color_eyre::Result;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// How far back the events shown first go, so that those of the beginning of the boot aren't missed
+const LOOKBACK: Duration = Duration::from_secs(300);
+/// How often new events are fetched
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Events of a log group being shown, until this is dropped
+pub struct Tail(JoinHandle<()>);
+
+impl Drop for Tail {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Shows the recent and new events of the log group in the background, tagged with the instance,
+/// until the returned `Tail` is dropped
+pub fn tail(aws_config: &SdkConfig, log_group: &str, instance_id: &str) -> Tail {
+    let client = clients::cloudwatchlogs(aws_config);
+    let log_group = log_group.to_string();
+    let instance_id = instance_id.to_string();
+    Tail(tokio::spawn(async move {
+        let future = follow(&client, &log_group);
+        if let Err(e) = log::with_instance(&instance_id, future).await {
+            log::warn("logs", format!("Failed to read {}: {}", log_group, e));
+        }
+    }))
+}
+
+async fn follow(client: &aws_sdk_cloudwatchlogs::client::Client, log_group: &str) -> Result<()> {
+    log::info("logs", format!("Showing the events of {}", log_group));
+    let mut start_time = (Utc::now() - chrono::Duration::from_std(LOOKBACK)?).timestamp_millis();
+    // Events of the same millisecond as the last one shown may arrive later
+    let mut seen: Vec<String> = vec![];
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut next_token = None;
+        loop {
+            let response = client
+                .filter_log_events()
+                .log_group_name(log_group)
+                .start_time(start_time)
+                .set_next_token(next_token)
+                .send()
+                .await
+                .hint("logs:FilterLogEvents")?;
+            for event in response.events() {
+                let (Some(event_id), Some(timestamp)) = (event.event_id(), event.timestamp())
+                else {
+                    continue;
+                };
+                if seen.iter().any(|seen| seen == event_id) {
+                    continue;
+                }
+                if timestamp > start_time {
+                    start_time = timestamp;
+                    seen.clear();
+                }
+                seen.push(event_id.to_string());
+                let time = Utc
+                    .timestamp_millis_opt(timestamp)
+                    .single()
+                    .map(|time| time.format("%H:%M:%S").to_string())
+                    .unwrap_or_default();
+                log::info(
+                    "logs",
+                    format!(
+                        "{} {}: {}",
+                        time,
+                        event.log_stream_name().unwrap_or("-"),
+                        event.message().unwrap_or_default().trim_end()
+                    ),
+                );
+            }
+            next_token = response.next_token;
+            if next_token.is_none() {
+                break;
+            }
+        }
+    }
+}
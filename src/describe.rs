@@ -0,0 +1,91 @@
+use crate::aws::{AwsEc2InstancesClient, Instance};
+use crate::clients;
+use crate::details;
+use crate::query;
+use crate::regions::{self, Regions};
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::Filter;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+#[derive(Debug)]
+pub struct DescribeConfig {
+    /// EC2 filters, by name and accepted values
+    pub filters: Vec<(String, Vec<String>)>,
+    pub regions: Option<Regions>,
+    /// JMESPath expression printed instead of the whole JSON
+    pub query: Option<String>,
+}
+
+/// Prints the instances matching all the filters as JSON, from each of the regions if given
+pub async fn run(aws_config: &SdkConfig, config: DescribeConfig) -> Result<()> {
+    let filters: Vec<Filter> = config
+        .filters
+        .into_iter()
+        .map(|(name, values)| {
+            Filter::builder()
+                .name(name)
+                .set_values(Some(values))
+                .build()
+        })
+        .collect();
+    let results = regions::fan_out(aws_config, config.regions.as_ref(), |aws_config| {
+        let filters = filters.clone();
+        async move {
+            let client = AwsEc2InstancesClient::new(clients::ec2(&aws_config));
+            client.describe_instances(filters).await
+        }
+    })
+    .await?;
+
+    let mut failed = false;
+    let mut instances = vec![];
+    for (region, result) in results {
+        match result {
+            Ok(found) => {
+                instances.extend(found.into_iter().map(|instance| (region.clone(), instance)))
+            }
+            Err(e) => {
+                failed = true;
+                eprintln!("Failed to describe the instances of {}: {}", region, e);
+            }
+        }
+    }
+
+    let instances: Vec<serde_json::Value> = instances
+        .iter()
+        .map(|(region, instance)| {
+            let mut description = description(instance);
+            description["region"] = region.clone().into();
+            description
+        })
+        .collect();
+    let instances = serde_json::Value::from(instances);
+    match &config.query {
+        Some(query) => query::print(query, &instances)?,
+        None => println!("{}", serde_json::to_string_pretty(&instances)?),
+    }
+
+    if failed {
+        return Err(eyre!("Failed in some regions"));
+    }
+    Ok(())
+}
+
+/// The instance as JSON
+pub fn description(instance: &Instance) -> serde_json::Value {
+    let mut description = serde_json::json!({
+        "instance_id": instance.instance_id(),
+        "name": instance.name(),
+        "state": instance.state().as_str(),
+        "instance_type": instance.instance_type(),
+        "availability_zone": instance.availability_zone(),
+        "tags": instance.tags(),
+    });
+    if let (Some(description), serde_json::Value::Object(details)) =
+        (description.as_object_mut(), details(instance))
+    {
+        description.extend(details);
+    }
+    description
+}
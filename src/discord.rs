@@ -0,0 +1,147 @@
+use crate::bot::{self, BotCommand, BotInstance};
+use crate::log;
+use aws_config::SdkConfig;
+use color_eyre::Result;
+use serenity::all::{
+    CommandInteraction, CommandOptionType, Context, CreateCommand, CreateCommandOption,
+    CreateInteractionResponse, CreateInteractionResponseMessage, EditInteractionResponse,
+    EventHandler, GatewayIntents, GuildId, Interaction, Ready,
+};
+use serenity::Client;
+
+#[derive(Debug)]
+pub struct DiscordConfig {
+    pub token: String,
+    /// Server the slash commands are registered in
+    pub guild_id: u64,
+    /// Roles allowed to use the commands, members without any of them are refused
+    pub role_ids: Vec<u64>,
+    pub instances: Vec<BotInstance>,
+    pub timeout: u64,
+}
+
+struct Handler {
+    aws_config: SdkConfig,
+    config: DiscordConfig,
+}
+
+impl Handler {
+    fn commands(&self) -> Vec<CreateCommand> {
+        let instance_option = || {
+            // Discord offers at most 25 choices
+            self.config.instances.iter().take(25).fold(
+                CreateCommandOption::new(CommandOptionType::String, "instance", "Instance")
+                    .required(self.config.instances.len() > 1),
+                |option, instance| option.add_string_choice(instance.name(), instance.name()),
+            )
+        };
+        [
+            ("start", "Start the instance"),
+            ("stop", "Stop the instance"),
+            ("status", "Show the state and IP addresses of the instance"),
+        ]
+        .into_iter()
+        .map(|(name, description)| {
+            CreateCommand::new(name)
+                .description(description)
+                .add_option(instance_option())
+        })
+        .collect()
+    }
+
+    fn allowed(&self, interaction: &CommandInteraction) -> bool {
+        interaction.member.as_ref().map_or(false, |member| {
+            member
+                .roles
+                .iter()
+                .any(|role| self.config.role_ids.contains(&role.get()))
+        })
+    }
+
+    async fn answer(&self, interaction: &CommandInteraction) -> Result<String> {
+        let command = match interaction.data.name.as_str() {
+            "start" => BotCommand::Start,
+            "stop" => BotCommand::Stop,
+            _ => BotCommand::Status,
+        };
+        let requested = interaction
+            .data
+            .options
+            .iter()
+            .find(|option| option.name == "instance")
+            .and_then(|option| option.value.as_str());
+        let instance_id = bot::select_instance(requested, &self.config.instances)?;
+
+        log::info(
+            "discord",
+            format!(
+                "{} asked for {:?} of {}",
+                interaction.user.name, command, instance_id
+            ),
+        );
+        bot::execute(&self.aws_config, &instance_id, command, self.config.timeout).await
+    }
+}
+
+#[serenity::async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, ctx: Context, _ready: Ready) {
+        // Guild commands are available right away, unlike global ones
+        let guild_id = GuildId::new(self.config.guild_id);
+        match guild_id.set_commands(&ctx.http, self.commands()).await {
+            Ok(_) => log::info("discord", "Waiting for commands"),
+            Err(e) => log::error("discord", format!("Failed to register the commands: {}", e)),
+        }
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        let Interaction::Command(interaction) = interaction else {
+            return;
+        };
+
+        if !self.allowed(&interaction) {
+            let refusal = CreateInteractionResponse::Message(
+                CreateInteractionResponseMessage::new()
+                    .content("You don't have a role allowed to use this command")
+                    .ephemeral(true),
+            );
+            if let Err(e) = interaction.create_response(&ctx.http, refusal).await {
+                log::warn("discord", format!("Failed to reply: {}", e));
+            }
+            return;
+        }
+
+        // Discord expects an answer within 3 seconds, starting an instance takes longer
+        if let Err(e) = interaction.defer(&ctx.http).await {
+            log::warn(
+                "discord",
+                format!("Failed to acknowledge the command: {}", e),
+            );
+            return;
+        }
+        let reply = match self.answer(&interaction).await {
+            Ok(reply) => reply,
+            Err(e) => format!("Failed: {}", e),
+        };
+        let edit = EditInteractionResponse::new().content(reply);
+        if let Err(e) = interaction.edit_response(&ctx.http, edit).await {
+            log::warn("discord", format!("Failed to reply: {}", e));
+        }
+    }
+}
+
+/// Answers the slash commands of the bot until interrupted
+pub async fn run(aws_config: &SdkConfig, config: DiscordConfig) -> Result<()> {
+    let token = config.token.clone();
+    let handler = Handler {
+        aws_config: aws_config.clone(),
+        config,
+    };
+    // Slash commands come with the interactions, no privileged intent is needed
+    let mut client = Client::builder(token, GatewayIntents::empty())
+        .event_handler(handler)
+        .await?;
+    client.start().await?;
+
+    Ok(())
+}
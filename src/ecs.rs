@@ -0,0 +1,154 @@
+use crate::clients;
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_ecs::types::ContainerInstanceStatus;
+use color_eyre::Result;
+use tokio::time::{Duration, Instant};
+
+/// Container instance registered for an EC2 instance, in its cluster
+struct ContainerInstance {
+    cluster: String,
+    arn: String,
+}
+
+/// Looks for the container instance of the EC2 instance in all the clusters
+async fn find(
+    client: &aws_sdk_ecs::client::Client,
+    instance_id: &str,
+) -> Result<Option<ContainerInstance>> {
+    let mut next_token = None;
+    loop {
+        let response = client
+            .list_clusters()
+            .set_next_token(next_token)
+            .send()
+            .await
+            .hint("ecs:ListClusters")?;
+        for cluster in response.cluster_arns() {
+            let instances = client
+                .list_container_instances()
+                .cluster(cluster)
+                .filter(format!("ec2InstanceId == {}", instance_id))
+                .send()
+                .await
+                .hint("ecs:ListContainerInstances")?;
+            if let Some(arn) = instances.container_instance_arns().first() {
+                return Ok(Some(ContainerInstance {
+                    cluster: cluster.clone(),
+                    arn: arn.clone(),
+                }));
+            }
+        }
+        next_token = response.next_token().map(str::to_string);
+        if next_token.is_none() {
+            return Ok(None);
+        }
+    }
+}
+
+async fn set_status(
+    client: &aws_sdk_ecs::client::Client,
+    container_instance: &ContainerInstance,
+    status: ContainerInstanceStatus,
+) -> Result<()> {
+    client
+        .update_container_instances_state()
+        .cluster(&container_instance.cluster)
+        .container_instances(&container_instance.arn)
+        .status(status)
+        .send()
+        .await
+        .hint("ecs:UpdateContainerInstancesState")?;
+    Ok(())
+}
+
+async fn running_tasks(
+    client: &aws_sdk_ecs::client::Client,
+    container_instance: &ContainerInstance,
+) -> Result<i32> {
+    let response = client
+        .describe_container_instances()
+        .cluster(&container_instance.cluster)
+        .container_instances(&container_instance.arn)
+        .send()
+        .await
+        .hint("ecs:DescribeContainerInstances")?;
+    Ok(response
+        .container_instances()
+        .first()
+        .map_or(0, |instance| instance.running_tasks_count()))
+}
+
+/// Sets the container instance of the EC2 instance to draining, and waits for its tasks to be
+/// moved elsewhere, for at most `cap`
+///
+/// Does nothing if the instance isn't an ECS container instance.
+pub async fn drain(aws_config: &SdkConfig, instance_id: &str, cap: Duration) -> Result<()> {
+    let client = clients::ecs(aws_config);
+    let Some(container_instance) = find(&client, instance_id).await? else {
+        log::info("ecs", "Not an ECS container instance, nothing to drain");
+        return Ok(());
+    };
+
+    log::info(
+        "ecs",
+        format!(
+            "Draining container instance in {}...",
+            container_instance.cluster
+        ),
+    );
+    set_status(
+        &client,
+        &container_instance,
+        ContainerInstanceStatus::Draining,
+    )
+    .await?;
+
+    let deadline = Instant::now() + cap;
+    let mut wait_interval = tokio::time::interval(Duration::from_secs(10));
+    let mut last_count = None;
+    loop {
+        wait_interval.tick().await;
+        let count = running_tasks(&client, &container_instance).await?;
+        if count == 0 {
+            log::info("ecs", "No tasks left running");
+            return Ok(());
+        }
+        if Instant::now() >= deadline {
+            log::warn(
+                "ecs",
+                format!(
+                    "{} tasks still running after draining, stopping anyway",
+                    count
+                ),
+            );
+            return Ok(());
+        }
+        if last_count != Some(count) {
+            log::info("ecs", format!("{} tasks still running", count));
+            last_count = Some(count);
+        }
+    }
+}
+
+/// Sets the container instance of the EC2 instance back to active, if it is one
+pub async fn activate(aws_config: &SdkConfig, instance_id: &str) -> Result<()> {
+    let client = clients::ecs(aws_config);
+    if let Some(container_instance) = find(&client, instance_id).await? {
+        set_status(
+            &client,
+            &container_instance,
+            ContainerInstanceStatus::Active,
+        )
+        .await?;
+        log::info(
+            "ecs",
+            format!(
+                "Container instance active in {}",
+                container_instance.cluster
+            ),
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,176 @@
+use crate::aws::Instance;
+use crate::clients;
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_elasticloadbalancingv2::types::{
+    TargetDescription, TargetHealthStateEnum, TargetTypeEnum,
+};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::time::{Duration, Instant};
+
+/// Waits for the instance to be healthy in the target group, or for the timeout to expire
+///
+/// Targets of `ip` target groups are matched by the private address of the instance.
+pub async fn wait_healthy(
+    aws_config: &SdkConfig,
+    instance: &Instance,
+    target_group_arn: &str,
+    timeout: u64,
+) -> Result<()> {
+    let client = clients::elb(aws_config);
+    let instance_id = instance.instance_id();
+    let address = instance.ipv4_address_private();
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+    let mut wait_interval = tokio::time::interval(Duration::from_secs(10));
+    let mut last_state = None;
+
+    log::info("target-group", "Waiting for the instance to be healthy...");
+    loop {
+        wait_interval.tick().await;
+        let response = client
+            .describe_target_health()
+            .target_group_arn(target_group_arn)
+            .send()
+            .await
+            .hint("elasticloadbalancing:DescribeTargetHealth")?;
+        let health = response
+            .target_health_descriptions()
+            .iter()
+            .find(|description| {
+                let id = description.target().map(|target| target.id());
+                id == Some(instance_id) || (address.is_some() && id == address)
+            })
+            .and_then(|description| description.target_health());
+        let Some(health) = health else {
+            return Err(eyre!(
+                "The instance isn't registered in {}",
+                target_group_arn
+            ));
+        };
+
+        let state = health.state().cloned();
+        if state == Some(TargetHealthStateEnum::Healthy) {
+            log::info("target-group", "Instance is healthy");
+            return Ok(());
+        }
+        if state != last_state {
+            let state_name = state.as_ref().map_or("unknown", |state| state.as_str());
+            match health.description() {
+                Some(description) => log::info(
+                    "target-group",
+                    format!("Instance is {}: {}", state_name, description),
+                ),
+                None => log::info("target-group", format!("Instance is {}", state_name)),
+            }
+            last_state = state;
+        }
+        if Instant::now() >= deadline {
+            return Err(eyre!(
+                "The instance isn't healthy in {} after {}s",
+                target_group_arn,
+                timeout
+            ));
+        }
+    }
+}
+
+/// Describes the instance as a target of the group, by its private address for `ip` groups
+async fn target(
+    client: &aws_sdk_elasticloadbalancingv2::client::Client,
+    instance: &Instance,
+    target_group_arn: &str,
+) -> Result<TargetDescription> {
+    let response = client
+        .describe_target_groups()
+        .target_group_arns(target_group_arn)
+        .send()
+        .await
+        .hint("elasticloadbalancing:DescribeTargetGroups")?;
+    let target_type = response
+        .target_groups()
+        .first()
+        .and_then(|group| group.target_type());
+    let id = match target_type {
+        Some(TargetTypeEnum::Ip) => instance
+            .ipv4_address_private()
+            .ok_or_else(|| eyre!("The instance has no private address to register"))?,
+        _ => instance.instance_id(),
+    };
+    Ok(TargetDescription::builder().id(id).build()?)
+}
+
+/// Registers the instance in the target group, once it is ready to take traffic
+pub async fn register(
+    aws_config: &SdkConfig,
+    instance: &Instance,
+    target_group_arn: &str,
+) -> Result<()> {
+    let client = clients::elb(aws_config);
+    let target = target(&client, instance, target_group_arn).await?;
+    client
+        .register_targets()
+        .target_group_arn(target_group_arn)
+        .targets(target)
+        .send()
+        .await
+        .hint("elasticloadbalancing:RegisterTargets")?;
+    log::info(
+        "target-group",
+        format!("Registered instance in {}", target_group_arn),
+    );
+
+    Ok(())
+}
+
+/// Deregisters the instance from the target group, and waits for its connections to be drained
+///
+/// There is no timeout, as draining ends by itself after the deregistration delay of the group.
+pub async fn deregister(
+    aws_config: &SdkConfig,
+    instance: &Instance,
+    target_group_arn: &str,
+) -> Result<()> {
+    let client = clients::elb(aws_config);
+    let target = target(&client, instance, target_group_arn).await?;
+    client
+        .deregister_targets()
+        .target_group_arn(target_group_arn)
+        .targets(target.clone())
+        .send()
+        .await
+        .hint("elasticloadbalancing:DeregisterTargets")?;
+
+    log::info(
+        "target-group",
+        format!(
+            "Deregistered instance from {}, draining...",
+            target_group_arn
+        ),
+    );
+    let mut wait_interval = tokio::time::interval(Duration::from_secs(10));
+    loop {
+        wait_interval.tick().await;
+        let response = client
+            .describe_target_health()
+            .target_group_arn(target_group_arn)
+            .targets(target.clone())
+            .send()
+            .await
+            .hint("elasticloadbalancing:DescribeTargetHealth")?;
+        let draining = response
+            .target_health_descriptions()
+            .iter()
+            .any(|description| {
+                description
+                    .target_health()
+                    .and_then(|health| health.state())
+                    == Some(&TargetHealthStateEnum::Draining)
+            });
+        if !draining {
+            log::info("target-group", "Connections drained");
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use crate::aws::Instance;
+use crate::clients;
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::{AttachmentStatus, NetworkInterface, NetworkInterfaceStatus};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::time::Duration;
+
+/// Attachments usually complete within seconds
+const WAIT_INTERVAL: Duration = Duration::from_secs(3);
+
+async fn describe(client: &aws_sdk_ec2::client::Client, eni_id: &str) -> Result<NetworkInterface> {
+    client
+        .describe_network_interfaces()
+        .network_interface_ids(eni_id)
+        .send()
+        .await
+        .hint("ec2:DescribeNetworkInterfaces")?
+        .network_interfaces()
+        .first()
+        .cloned()
+        .ok_or_else(|| eyre!("Network interface {} not found", eni_id))
+}
+
+/// Attaches the network interface to the running instance, after its own ones, and waits for the
+/// attachment to complete
+pub async fn attach(aws_config: &SdkConfig, instance: &Instance, eni_id: &str) -> Result<()> {
+    let client = clients::ec2(aws_config);
+    let eni = describe(&client, eni_id).await?;
+    match eni
+        .attachment()
+        .and_then(|attachment| attachment.instance_id())
+    {
+        Some(instance_id) if instance_id == instance.instance_id() => {
+            log::info("eni", format!("{} is already attached", eni_id));
+        }
+        Some(instance_id) => {
+            return Err(eyre!(
+                "{} is attached to {}, detach it first",
+                eni_id,
+                instance_id
+            ))
+        }
+        None => {
+            log::info("eni", format!("Attaching {}...", eni_id));
+            client
+                .attach_network_interface()
+                .instance_id(instance.instance_id())
+                .network_interface_id(eni_id)
+                .device_index(instance.next_device_index())
+                .send()
+                .await
+                .hint("ec2:AttachNetworkInterface")?;
+        }
+    }
+
+    let mut wait_interval = tokio::time::interval(WAIT_INTERVAL);
+    let eni = loop {
+        wait_interval.tick().await;
+        let eni = describe(&client, eni_id).await?;
+        let status = eni.attachment().and_then(|attachment| attachment.status());
+        if status == Some(&AttachmentStatus::Attached) {
+            break eni;
+        }
+    };
+
+    let private_ips: Vec<&str> = eni
+        .private_ip_addresses()
+        .iter()
+        .filter_map(|address| address.private_ip_address())
+        .collect();
+    let public_ip = eni
+        .association()
+        .and_then(|association| association.public_ip())
+        .unwrap_or("None");
+    log::info(
+        "eni",
+        format!(
+            "Attached {}, private IPs: {}, public IP: {}",
+            eni_id,
+            private_ips.join(", "),
+            public_ip
+        ),
+    );
+
+    Ok(())
+}
+
+/// Detaches the network interface from the instance, if it is attached to it, and waits for it
+/// to be available to other instances
+pub async fn detach(aws_config: &SdkConfig, instance_id: &str, eni_id: &str) -> Result<()> {
+    let client = clients::ec2(aws_config);
+    let eni = describe(&client, eni_id).await?;
+    let attachment = eni
+        .attachment()
+        .filter(|attachment| attachment.instance_id() == Some(instance_id));
+    let Some(attachment_id) = attachment.and_then(|attachment| attachment.attachment_id()) else {
+        log::info("eni", format!("{} isn't attached to the instance", eni_id));
+        return Ok(());
+    };
+
+    log::info("eni", format!("Detaching {}...", eni_id));
+    client
+        .detach_network_interface()
+        .attachment_id(attachment_id)
+        .send()
+        .await
+        .hint("ec2:DetachNetworkInterface")?;
+
+    let mut wait_interval = tokio::time::interval(WAIT_INTERVAL);
+    loop {
+        wait_interval.tick().await;
+        let eni = describe(&client, eni_id).await?;
+        if eni.status() == Some(&NetworkInterfaceStatus::Available) {
+            log::info("eni", format!("Detached {}", eni_id));
+            return Ok(());
+        }
+    }
+}
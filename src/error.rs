@@ -0,0 +1,175 @@
+use aws_sdk_ec2::error::{DisplayErrorContext, ProvideErrorMetadata, SdkError};
+use std::fmt;
+
+/// What went wrong with an AWS call, to suggest how to fix it
+#[derive(Debug, PartialEq)]
+enum Category {
+    /// The caller isn't allowed to make the call
+    Permission,
+    /// The instance doesn't exist, at least in this account and region
+    NotFound,
+    /// The credentials are missing or have expired
+    Credentials,
+    /// The instance isn't in a state allowing the call
+    State,
+    /// AWS has no capacity for the instance type
+    Capacity,
+    /// The account made too many calls
+    Throttling,
+    /// AWS failed to handle the call
+    Unavailable,
+    /// AWS couldn't be reached
+    Network,
+    Other,
+}
+
+impl Category {
+    fn from_code(code: &str) -> Self {
+        match code {
+            "UnauthorizedOperation" | "AccessDenied" | "AccessDeniedException" => Self::Permission,
+            "InvalidInstanceID.NotFound" | "InvalidInstanceID.Malformed" => Self::NotFound,
+            "AuthFailure"
+            | "RequestExpired"
+            | "ExpiredToken"
+            | "ExpiredTokenException"
+            | "UnrecognizedClientException" => Self::Credentials,
+            "IncorrectInstanceState" | "IncorrectState" => Self::State,
+            "InsufficientInstanceCapacity" | "InsufficientCapacity" => Self::Capacity,
+            "RequestLimitExceeded" | "Throttling" | "ThrottlingException" => Self::Throttling,
+            "InternalError" | "InternalFailure" | "ServiceUnavailable" | "Unavailable" => {
+                Self::Unavailable
+            }
+            _ => Self::Other,
+        }
+    }
+
+    /// How to fix the failed call to `iam_action`, if known
+    fn suggestion(&self, iam_action: &str) -> Option<String> {
+        match self {
+            Self::Permission => Some(format!(
+                "allow {} in the IAM policy of the caller, see print-iam-policy",
+                iam_action
+            )),
+            Self::NotFound => Some(
+                "check the instance id, and that the region and profile (AWS_REGION, AWS_PROFILE) \
+                are those of the instance"
+                    .to_string(),
+            ),
+            Self::Credentials => Some("refresh the credentials, e.g. with --sso-login".to_string()),
+            Self::State => {
+                Some("check the state of the instance with the status subcommand".to_string())
+            }
+            Self::Capacity => Some(
+                "retry later, target a capacity reservation with --capacity-reservation, \
+                or change the instance type"
+                    .to_string(),
+            ),
+            Self::Throttling => {
+                Some("retry later, or lower the rate of the calls with --max-rps".to_string())
+            }
+            Self::Unavailable => Some("retry later, AWS failed to handle the call".to_string()),
+            Self::Network => Some("check the network connection and proxy settings".to_string()),
+            Self::Other => None,
+        }
+    }
+}
+
+/// Failed AWS call, with a suggestion of how to fix it when its category is known
+#[derive(Debug)]
+pub struct AwsError {
+    category: Category,
+    message: String,
+    suggestion: Option<String>,
+}
+
+impl AwsError {
+    /// Categorizes the error of a call to `iam_action` (e.g. `ec2:StartInstances`)
+    pub fn new<E, R>(error: SdkError<E, R>, iam_action: &str) -> Self
+    where
+        E: ProvideErrorMetadata + std::error::Error + 'static,
+        R: fmt::Debug,
+    {
+        let category = match &error {
+            SdkError::DispatchFailure(_) | SdkError::TimeoutError(_) => Category::Network,
+            _ => error
+                .code()
+                .map(Category::from_code)
+                .unwrap_or(Category::Other),
+        };
+        let message = match (error.code(), error.message()) {
+            (Some(code), Some(message)) => format!("{}: {}", code, message),
+            _ => DisplayErrorContext(&error).to_string(),
+        };
+        let suggestion = category.suggestion(iam_action);
+
+        Self {
+            category,
+            message,
+            suggestion,
+        }
+    }
+
+    /// Error AWS would return for the call to `iam_action`, injected to test how it is handled
+    #[cfg(feature = "test-faults")]
+    pub fn injected(code: &str, message: &str, iam_action: &str) -> Self {
+        let category = Category::from_code(code);
+        Self {
+            suggestion: category.suggestion(iam_action),
+            message: format!("{}: {} (injected)", code, message),
+            category,
+        }
+    }
+
+    /// Whether AWS lacks capacity for the instance type, which may change over time
+    pub fn is_capacity(&self) -> bool {
+        self.category == Category::Capacity
+    }
+
+    /// Whether the call may succeed when made again later, as AWS lacked capacity, throttled the
+    /// account, failed to handle the call or couldn't be reached
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.category,
+            Category::Capacity | Category::Throttling | Category::Unavailable | Category::Network
+        )
+    }
+}
+
+impl fmt::Display for AwsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.suggestion {
+            Some(suggestion) => write!(f, "{} (hint: {})", self.message, suggestion),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for AwsError {}
+
+/// The action didn't complete within its timeout, e.g. as the instance was slow to boot or its
+/// agent to connect
+#[derive(Debug)]
+pub struct TimedOut(pub u64);
+
+impl fmt::Display for TimedOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "timed out after {}s", self.0)
+    }
+}
+
+impl std::error::Error for TimedOut {}
+
+/// Turns the errors of AWS calls into [`AwsError`]s
+pub trait Hint<T> {
+    fn hint(self, iam_action: &str) -> Result<T, AwsError>;
+}
+
+impl<T, E, R> Hint<T> for Result<T, SdkError<E, R>>
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: fmt::Debug,
+{
+    fn hint(self, iam_action: &str) -> Result<T, AwsError> {
+        self.map_err(|error| AwsError::new(error, iam_action))
+    }
+}
@@ -0,0 +1,151 @@
+use crate::aws::{AwsEc2Client, Instance};
+use crate::clients;
+use crate::config::{Action, Config};
+use crate::log;
+use crate::state;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use color_eyre::Result;
+use std::future::Future;
+use tokio::time::{interval, Duration, Instant};
+
+/// How often the time left is shown
+const REPORT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Timeout of the action fitted to the instance, in seconds, when none was given
+///
+/// Windows and large instances take longer, and the action is given at least twice as long as
+/// it took at most the last times.
+pub async fn timeout(aws_config: &SdkConfig, config: &Config, action: &Action) -> u64 {
+    if !config.adaptive_timeout {
+        return config.timeout;
+    }
+
+    // The target state is only used when waiting
+    let aws_ec2_client = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        &config.instance_id,
+        InstanceStateName::Running,
+        Duration::from_secs(10),
+    );
+    let instance = match aws_ec2_client.get_instance().await {
+        Ok(instance) => instance,
+        // The action reports it better
+        Err(_) => return config.timeout,
+    };
+    let mut timeout = Duration::from_secs(config.timeout).max(typical_timeout(&instance));
+    if let Some(store) = state::store() {
+        if let Ok(durations) = store.durations(&config.instance_id, action).await {
+            if let Some(longest) = durations.into_iter().max() {
+                timeout = timeout.max(longest * 2);
+            }
+        }
+    }
+    // Waiting for SSM, cloud-init or services comes on top
+    if *action == Action::Start && config.wait_for_ssm {
+        timeout += Duration::from_secs(120);
+    }
+
+    if timeout.as_secs() > config.timeout {
+        log::info(
+            "timeout",
+            format!("Waiting up to {}s for this instance", timeout.as_secs()),
+        );
+    }
+    timeout.as_secs().max(config.timeout)
+}
+
+fn typical_timeout(instance: &Instance) -> Duration {
+    let size = instance
+        .instance_type()
+        .split('.')
+        .nth(1)
+        .unwrap_or_default();
+    let large = size == "metal"
+        || size
+            .strip_suffix("xlarge")
+            .and_then(|count| count.parse::<u32>().ok())
+            .is_some_and(|count| count >= 8);
+    match (instance.is_windows(), large) {
+        (true, true) => Duration::from_secs(900),
+        (true, false) => Duration::from_secs(600),
+        (false, true) => Duration::from_secs(600),
+        (false, false) => Duration::from_secs(120),
+    }
+}
+
+/// Waits for the instance to reach the state, showing the time left as estimated from the last
+/// times, and recording how long it took for the next ones
+pub async fn wait<T>(
+    instance_id: &str,
+    action: &Action,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(store) = state::store() else {
+        return future.await;
+    };
+
+    let durations = match store.durations(instance_id, action).await {
+        Ok(durations) => durations,
+        Err(e) => {
+            log::warn(
+                "eta",
+                format!("Failed to load the previous durations: {}", e),
+            );
+            vec![]
+        }
+    };
+    let started = Instant::now();
+    let result = if durations.is_empty() {
+        future.await
+    } else {
+        with_estimate(action, &durations, started, future).await
+    };
+
+    if result.is_ok() {
+        if let Err(e) = store
+            .record_duration(instance_id, action, started.elapsed())
+            .await
+        {
+            log::warn("eta", format!("Failed to record the duration: {}", e));
+        }
+    }
+    result
+}
+
+async fn with_estimate<T>(
+    action: &Action,
+    durations: &[Duration],
+    started: Instant,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let estimate = durations.iter().sum::<Duration>() / durations.len() as u32;
+    let state = match action {
+        Action::Start => "running",
+        Action::Stop => "stopped",
+    };
+    log::info(
+        "eta",
+        format!(
+            "Instance {} in ~{}s, based on the last {} {}s",
+            state,
+            estimate.as_secs(),
+            durations.len(),
+            action
+        ),
+    );
+
+    tokio::pin!(future);
+    let mut reports = interval(REPORT_INTERVAL);
+    // The first tick is immediate, and the estimate was just shown
+    reports.tick().await;
+    loop {
+        tokio::select! {
+            result = &mut future => return result,
+            _ = reports.tick() => match estimate.checked_sub(started.elapsed()) {
+                Some(left) => log::info("eta", format!("~{}s left", left.as_secs())),
+                None => log::info("eta", "Taking longer than usual"),
+            },
+        }
+    }
+}
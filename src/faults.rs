@@ -0,0 +1,287 @@
+use crate::error::AwsError;
+use crate::log;
+use aws_sdk_ec2::types::{Instance, InstanceState, InstanceStateName};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Variable listing the faults to inject, e.g. `throttling:2,ssm-never-connects`
+const VARIABLE: &str = "AWS_START_STOP_FAULTS";
+/// How much longer the calls take when latency is injected
+const LATENCY: Duration = Duration::from_secs(5);
+
+/// Simulated failure, to exercise the waiters, retries and timeouts in tests and drills
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Fault {
+    /// EC2 and SSM calls fail with `RequestLimitExceeded`
+    Throttling,
+    /// EC2 and SSM calls fail with `InternalError`, as AWS does when it fails to handle them
+    ServerError,
+    /// EC2 and SSM calls take `LATENCY` longer
+    Latency,
+    /// EC2 calls fail with `InvalidInstanceID.NotFound`
+    NotFound,
+    /// The instance is described as pending rather than running
+    StuckInPending,
+    /// The SSM agent of the instance is reported as not connected, without calling SSM
+    SsmNeverConnects,
+}
+
+impl Fault {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "throttling" => Some(Self::Throttling),
+            "server-error" => Some(Self::ServerError),
+            "latency" => Some(Self::Latency),
+            "not-found" => Some(Self::NotFound),
+            "stuck-in-pending" => Some(Self::StuckInPending),
+            "ssm-never-connects" => Some(Self::SsmNeverConnects),
+            _ => None,
+        }
+    }
+}
+
+/// Faults to inject, with how many more times, each time if unbounded
+type Faults = HashMap<Fault, Option<u32>>;
+
+static FAULTS: OnceLock<Mutex<Faults>> = OnceLock::new();
+
+fn faults() -> &'static Mutex<Faults> {
+    FAULTS.get_or_init(|| {
+        let Ok(value) = std::env::var(VARIABLE) else {
+            return Mutex::default();
+        };
+        let faults = parse(&value);
+        if !faults.is_empty() {
+            log::warn("faults", format!("Injecting faults from {}", VARIABLE));
+        }
+        Mutex::new(faults)
+    })
+}
+
+/// Faults listed by the variable, ignoring those which aren't known
+fn parse(value: &str) -> Faults {
+    let mut faults = HashMap::new();
+    for entry in value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+    {
+        let (name, times) = match entry.split_once(':') {
+            Some((name, times)) => (name, times.parse().ok().map(Some)),
+            None => (entry, Some(None)),
+        };
+        match (Fault::parse(name), times) {
+            (Some(fault), Some(times)) => {
+                faults.insert(fault, times);
+            }
+            _ => log::warn(
+                "faults",
+                format!(
+                    "Ignoring {:?} in {}, expected throttling, server-error, latency, not-found, \
+                    stuck-in-pending or ssm-never-connects, optionally followed by :TIMES",
+                    entry, VARIABLE
+                ),
+            ),
+        }
+    }
+    faults
+}
+
+/// Whether the fault is to be injected now, counting it if it is only injected some times
+fn count(faults: &mut Faults, fault: Fault) -> bool {
+    match faults.get_mut(&fault) {
+        None | Some(Some(0)) => false,
+        Some(Some(times)) => {
+            *times -= 1;
+            true
+        }
+        Some(None) => true,
+    }
+}
+
+/// Whether the fault is to be injected now, counting it if it is only injected some times
+pub fn inject(fault: Fault) -> bool {
+    let injected = count(&mut faults().lock().unwrap(), fault);
+    if injected {
+        log::warn("faults", format!("Injecting {:?}", fault));
+    }
+    injected
+}
+
+/// Delays the call to `iam_action` when latency is injected, and fails it as AWS would when
+/// throttling, failing to handle it or not finding the instance is
+pub async fn call(iam_action: &str) -> Result<(), AwsError> {
+    if inject(Fault::Latency) {
+        tokio::time::sleep(LATENCY).await;
+    }
+    if inject(Fault::Throttling) {
+        return Err(AwsError::injected(
+            "RequestLimitExceeded",
+            "Request limit exceeded.",
+            iam_action,
+        ));
+    }
+    if inject(Fault::ServerError) {
+        return Err(AwsError::injected(
+            "InternalError",
+            "An internal error has occurred.",
+            iam_action,
+        ));
+    }
+    if iam_action.starts_with("ec2:") && inject(Fault::NotFound) {
+        return Err(AwsError::injected(
+            "InvalidInstanceID.NotFound",
+            "The instance ID does not exist",
+            iam_action,
+        ));
+    }
+    Ok(())
+}
+
+/// The instance as described, but still pending once running when that is injected
+pub fn described(mut instance: Instance) -> Instance {
+    let running = instance
+        .state
+        .as_ref()
+        .and_then(InstanceState::name)
+        .is_some_and(|state| *state == InstanceStateName::Running);
+    if running && inject(Fault::StuckInPending) {
+        instance.state = Some(
+            InstanceState::builder()
+                .name(InstanceStateName::Pending)
+                .code(0)
+                .build(),
+        );
+    }
+    instance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::aws::AwsEc2Client;
+    use crate::fixtures;
+    use aws_config::SdkConfig;
+    use color_eyre::Report;
+    use tokio::sync::MutexGuard;
+    use tokio::time::timeout;
+
+    /// Right to inject faults, the other tests calling AWS waiting until it's dropped, which
+    /// clears the faults
+    struct Injecting {
+        _exclusive: MutexGuard<'static, ()>,
+    }
+
+    impl Injecting {
+        async fn new() -> Self {
+            Self {
+                _exclusive: fixtures::EXCLUSIVE.lock().await,
+            }
+        }
+
+        /// Injects the faults listed as in the variable, into the store the calls to AWS check
+        fn set(&self, value: &str) {
+            *faults().lock().unwrap() = parse(value);
+        }
+    }
+
+    impl Drop for Injecting {
+        fn drop(&mut self) {
+            faults().lock().unwrap().clear();
+        }
+    }
+
+    /// How many more times the fault is to be injected
+    fn left(fault: Fault) -> Option<u32> {
+        faults().lock().unwrap().get(&fault).copied().flatten()
+    }
+
+    fn ec2_client(aws_config: &SdkConfig) -> AwsEc2Client {
+        AwsEc2Client::new(
+            aws_sdk_ec2::client::Client::new(aws_config),
+            fixtures::INSTANCE_ID,
+            InstanceStateName::Running,
+            Duration::from_millis(10),
+        )
+    }
+
+    #[test]
+    fn parses_the_variable() {
+        let faults = parse("throttling:2, latency,not-found:twice,unknown");
+        assert_eq!(faults.get(&Fault::Throttling), Some(&Some(2)));
+        assert_eq!(faults.get(&Fault::Latency), Some(&None));
+        assert_eq!(faults.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn retries_throttling_and_server_errors_only() {
+        let injecting = Injecting::new().await;
+        injecting.set("throttling:1,server-error:1,not-found:1");
+        for code in ["RequestLimitExceeded", "InternalError"] {
+            let error = call("ec2:StartInstances").await.unwrap_err();
+            assert!(error.to_string().starts_with(code), "{}", error);
+            assert!(crate::is_retryable(&Report::new(error)));
+        }
+        let error = call("ec2:StartInstances").await.unwrap_err();
+        assert!(
+            error.to_string().starts_with("InvalidInstanceID.NotFound"),
+            "{}",
+            error
+        );
+        assert!(!crate::is_retryable(&Report::new(error)));
+        assert!(call("ec2:StartInstances").await.is_ok());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_through_throttling_server_errors_and_latency() {
+        let (client, aws_config) = fixtures::replay("start");
+        let ec2 = ec2_client(&aws_config);
+        let injecting = Injecting::new().await;
+        let state = ec2.start_instance().await.unwrap();
+
+        // The first three of the five descriptions fail, the fixture answering the last two
+        injecting.set("latency:1,throttling:2,server-error:1");
+        let instance = ec2.wait_for_state(Some(&state)).await.unwrap();
+        assert_eq!(*instance.state(), InstanceStateName::Running);
+        assert_eq!(left(Fault::Latency), Some(0));
+        assert_eq!(left(Fault::Throttling), Some(0));
+        assert_eq!(left(Fault::ServerError), Some(0));
+
+        fixtures::validate(&client).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn waits_through_throttling_after_the_consistency_delay() {
+        let (client, aws_config) = fixtures::replay("start");
+        let ec2 = ec2_client(&aws_config);
+        let injecting = Injecting::new().await;
+        let state = ec2.start_instance().await.unwrap();
+
+        // Each description takes 5s and is throttled, the seventh one starting after 30s
+        injecting.set("latency:7,throttling:7");
+        let started = tokio::time::Instant::now();
+        let instance = ec2.wait_for_state(Some(&state)).await.unwrap();
+        assert_eq!(*instance.state(), InstanceStateName::Running);
+        assert!(started.elapsed() >= LATENCY * 7);
+        assert_eq!(left(Fault::Throttling), Some(0));
+
+        fixtures::validate(&client).await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn times_out_waiting_through_latency() {
+        let (_, aws_config) = fixtures::replay("start");
+        let ec2 = ec2_client(&aws_config);
+        let injecting = Injecting::new().await;
+        let state = ec2.start_instance().await.unwrap();
+
+        // The first description is answered after 5s, the second one is still delayed
+        injecting.set("latency:10");
+        let Err(elapsed) = timeout(LATENCY * 3 / 2, ec2.wait_for_state(Some(&state))).await else {
+            panic!("The wait didn't time out");
+        };
+        assert_eq!(elapsed.to_string(), "deadline has elapsed");
+        assert_eq!(left(Fault::Latency), Some(8));
+    }
+}
@@ -0,0 +1,52 @@
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_credential_types::provider::SharedCredentialsProvider;
+use aws_credential_types::Credentials;
+use aws_smithy_runtime::client::http::test_util::dvr::ReplayingClient;
+use aws_types::region::Region;
+use tokio::sync::Mutex;
+
+/// Held by the tests calling AWS, as the faults injected by some of them apply to all the calls
+pub static EXCLUSIVE: Mutex<()> = Mutex::const_new(());
+
+/// Instance the fixtures act on
+pub const INSTANCE_ID: &str = "i-0123456789abcdef0";
+
+/// Configuration answering the requests with the exchanges of `tests/fixtures/{name}.json`
+/// rather than calling AWS, and the client to check the requests against those of the fixture
+pub fn replay(name: &str) -> (ReplayingClient, SdkConfig) {
+    let path = format!(
+        "{}/tests/fixtures/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    let client = ReplayingClient::from_file(&path)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", path, e));
+    let credentials = Credentials::new("AKIDTEST", "test", None, None, "test");
+    let aws_config = SdkConfig::builder()
+        .behavior_version(BehaviorVersion::latest())
+        .region(Region::new("eu-west-1"))
+        .credentials_provider(SharedCredentialsProvider::new(credentials))
+        .http_client(client.clone())
+        .build();
+    (client, aws_config)
+}
+
+/// Checks that the requests made are those of the fixture, whatever the order of their parameters
+pub async fn validate(client: &ReplayingClient) {
+    client
+        .validate(&["content-type"], |expected: &[u8], actual: &[u8]| {
+            assert_eq!(parameters(expected), parameters(actual));
+            Ok(())
+        })
+        .await
+        .unwrap();
+}
+
+fn parameters(body: &[u8]) -> Vec<String> {
+    let mut parameters: Vec<String> = String::from_utf8_lossy(body)
+        .split('&')
+        .map(str::to_string)
+        .collect();
+    parameters.sort();
+    parameters
+}
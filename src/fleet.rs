@@ -0,0 +1,224 @@
+use crate::clients;
+use crate::config::{Action, Config};
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::{Tag, TargetCapacitySpecificationRequest};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::io::{BufRead, IsTerminal, Write};
+use tokio::time::{timeout, Duration};
+
+/// Tag of the fleet holding its target capacity while stopped, to restore it when starting
+const CAPACITY_TAG: &str = "aws-start-stop:capacity";
+
+/// Capacity of an EC2 Fleet or a Spot Fleet request
+struct Capacity {
+    target: i32,
+    fulfilled: f64,
+    /// Target capacity before the fleet was stopped
+    saved: Option<i32>,
+}
+
+/// Spot Fleet requests have their own API, their ids telling them apart from EC2 Fleets
+fn is_spot_fleet(fleet_id: &str) -> bool {
+    fleet_id.starts_with("sfr-")
+}
+
+/// Stops the fleet by setting its target capacity to zero, saving the previous one, or starts it
+/// by restoring it, then waits for the fulfilled capacity to match
+pub async fn run(aws_config: &SdkConfig, config: Config) -> Result<()> {
+    let fleet_id = config
+        .fleet
+        .as_deref()
+        .ok_or_else(|| eyre!("Missing fleet"))?;
+    let client = clients::ec2(aws_config);
+    let capacity = describe(&client, fleet_id).await?;
+
+    let target = match config.action {
+        Action::Stop => {
+            if capacity.target > 0 {
+                if !config.yes && std::io::stdin().is_terminal() {
+                    confirm(fleet_id, capacity.target)?;
+                }
+                save(&client, fleet_id, capacity.target).await?;
+            }
+            0
+        }
+        Action::Start => capacity.saved.ok_or_else(|| {
+            eyre!(
+                "{} has no saved capacity, it must have been stopped with --fleet first",
+                fleet_id
+            )
+        })?,
+    };
+    if capacity.target != target {
+        log::info(
+            "fleet",
+            format!(
+                "Setting the target capacity of {} from {} to {}...",
+                fleet_id, capacity.target, target
+            ),
+        );
+        set_target(&client, fleet_id, target).await?;
+    }
+
+    log::info(
+        "fleet",
+        format!("Waiting for {} units to be fulfilled...", target),
+    );
+    let fulfilled = timeout(
+        Duration::from_secs(config.timeout),
+        wait_fulfilled(&client, fleet_id, target),
+    )
+    .await
+    .map_err(|_| {
+        eyre!(
+            "{} hasn't reached its target capacity of {} after {}s",
+            fleet_id,
+            target,
+            config.timeout
+        )
+    })??;
+    println!("{}\ttarget: {}\tfulfilled: {}", fleet_id, target, fulfilled);
+
+    Ok(())
+}
+
+/// Setting the capacity to zero terminates the instances of the fleet, unlike stopping one
+fn confirm(fleet_id: &str, target: i32) -> Result<()> {
+    print!(
+        "Set the target capacity of {} from {} to 0, which may terminate its instances? [y/N] ",
+        fleet_id, target
+    );
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        return Err(eyre!("Not stopping"));
+    }
+
+    Ok(())
+}
+
+async fn describe(client: &aws_sdk_ec2::client::Client, fleet_id: &str) -> Result<Capacity> {
+    let saved = |tags: &[Tag]| {
+        tags.iter()
+            .find(|tag| tag.key() == Some(CAPACITY_TAG))
+            .and_then(|tag| tag.value())
+            .and_then(|value| value.parse().ok())
+    };
+
+    if is_spot_fleet(fleet_id) {
+        let response = client
+            .describe_spot_fleet_requests()
+            .spot_fleet_request_ids(fleet_id)
+            .send()
+            .await
+            .hint("ec2:DescribeSpotFleetRequests")?;
+        let request = response
+            .spot_fleet_request_configs()
+            .first()
+            .ok_or_else(|| eyre!("Spot Fleet request {} not found", fleet_id))?;
+        let data = request
+            .spot_fleet_request_config()
+            .ok_or_else(|| eyre!("Spot Fleet request {} has no configuration", fleet_id))?;
+        Ok(Capacity {
+            target: data.target_capacity().unwrap_or_default(),
+            fulfilled: data.fulfilled_capacity().unwrap_or_default(),
+            saved: saved(request.tags()),
+        })
+    } else {
+        let response = client
+            .describe_fleets()
+            .fleet_ids(fleet_id)
+            .send()
+            .await
+            .hint("ec2:DescribeFleets")?;
+        let fleet = response
+            .fleets()
+            .first()
+            .ok_or_else(|| eyre!("Fleet {} not found", fleet_id))?;
+        Ok(Capacity {
+            target: fleet
+                .target_capacity_specification()
+                .and_then(|specification| specification.total_target_capacity())
+                .unwrap_or_default(),
+            fulfilled: fleet.fulfilled_capacity().unwrap_or_default(),
+            saved: saved(fleet.tags()),
+        })
+    }
+}
+
+async fn save(client: &aws_sdk_ec2::client::Client, fleet_id: &str, target: i32) -> Result<()> {
+    client
+        .create_tags()
+        .resources(fleet_id)
+        .tags(
+            Tag::builder()
+                .key(CAPACITY_TAG)
+                .value(target.to_string())
+                .build(),
+        )
+        .send()
+        .await
+        .hint("ec2:CreateTags")?;
+
+    Ok(())
+}
+
+async fn set_target(
+    client: &aws_sdk_ec2::client::Client,
+    fleet_id: &str,
+    target: i32,
+) -> Result<()> {
+    if is_spot_fleet(fleet_id) {
+        client
+            .modify_spot_fleet_request()
+            .spot_fleet_request_id(fleet_id)
+            .target_capacity(target)
+            .send()
+            .await
+            .hint("ec2:ModifySpotFleetRequest")?;
+    } else {
+        client
+            .modify_fleet()
+            .fleet_id(fleet_id)
+            .target_capacity_specification(
+                TargetCapacitySpecificationRequest::builder()
+                    .total_target_capacity(target)
+                    .build(),
+            )
+            .send()
+            .await
+            .hint("ec2:ModifyFleet")?;
+    }
+
+    Ok(())
+}
+
+/// Returns the fulfilled capacity once it has reached the target
+async fn wait_fulfilled(
+    client: &aws_sdk_ec2::client::Client,
+    fleet_id: &str,
+    target: i32,
+) -> Result<f64> {
+    let mut wait_interval = tokio::time::interval(Duration::from_secs(10));
+    let mut last_fulfilled = None;
+    loop {
+        wait_interval.tick().await;
+        let fulfilled = describe(client, fleet_id).await?.fulfilled;
+        if last_fulfilled != Some(fulfilled) {
+            log::info("fleet", format!("Fulfilled capacity: {}", fulfilled));
+            last_fulfilled = Some(fulfilled);
+        }
+        let reached = if target == 0 {
+            fulfilled <= 0.0
+        } else {
+            fulfilled >= f64::from(target)
+        };
+        if reached {
+            return Ok(fulfilled);
+        }
+    }
+}
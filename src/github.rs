@@ -0,0 +1,73 @@
+use crate::aws::Instance;
+use crate::config::Action;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use std::io::Write;
+use std::time::Duration;
+
+/// Writes the outcome of the action as outputs of the GitHub Actions step, and annotates the
+/// workflow run with it
+///
+/// With several instances, the outputs are those of the last one.
+pub fn report(
+    action: &Action,
+    instance_id: &str,
+    result: &Result<Instance>,
+    duration: Duration,
+) -> Result<()> {
+    match result {
+        Ok(instance) => {
+            write_outputs(&[
+                ("instance_id", instance_id),
+                (
+                    "public_ip",
+                    instance.ipv4_address_public().unwrap_or_default(),
+                ),
+                (
+                    "private_ip",
+                    instance.ipv4_address_private().unwrap_or_default(),
+                ),
+                ("instance_state", instance.state().as_str()),
+                ("duration", &duration.as_secs().to_string()),
+            ])?;
+            println!(
+                "::notice title=aws-start-stop::{}",
+                escape(&format!(
+                    "{} of {} succeeded in {}s",
+                    action,
+                    instance_id,
+                    duration.as_secs()
+                ))
+            );
+        }
+        Err(e) => println!(
+            "::error title=aws-start-stop::{}",
+            escape(&format!("Failed to {} {}: {}", action, instance_id, e))
+        ),
+    }
+
+    Ok(())
+}
+
+fn write_outputs(outputs: &[(&str, &str)]) -> Result<()> {
+    let path = std::env::var_os("GITHUB_OUTPUT")
+        .ok_or_else(|| eyre!("GITHUB_OUTPUT isn't set, not running in GitHub Actions"))?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .wrap_err("Failed to open GITHUB_OUTPUT")?;
+    for (name, value) in outputs {
+        writeln!(file, "{}={}", name, value)?;
+    }
+
+    Ok(())
+}
+
+/// Escapes the characters workflow commands give a meaning to
+fn escape(message: &str) -> String {
+    message
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
@@ -0,0 +1,256 @@
+use crate::auth::ApiAuth;
+use crate::aws::{AwsEc2Client, AwsSsmClient, Instance};
+use crate::clients;
+use crate::config::{Action, Config};
+use crate::log;
+use crate::settings::{ApiTlsSettings, Settings};
+#[cfg(feature = "web")]
+use crate::web::{self, WebConfig};
+use crate::work;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+#[cfg(feature = "web")]
+use color_eyre::eyre::eyre;
+use color_eyre::eyre::WrapErr;
+use color_eyre::Result;
+use std::net::SocketAddr;
+use tokio::sync::mpsc;
+use tokio::time::{timeout, Duration};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::transport::{Certificate, Identity, ServerTlsConfig};
+use tonic::{Request, Response, Status};
+
+mod proto {
+    tonic::include_proto!("aws_start_stop.control");
+}
+
+use proto::control_server::{Control, ControlServer};
+use proto::{InstanceReply, InstanceRequest, Progress};
+
+#[derive(Debug)]
+pub struct ServeConfig {
+    pub listen: SocketAddr,
+    /// How long to wait for actions to complete
+    pub timeout: u64,
+    /// Web UI served alongside the API
+    #[cfg(feature = "web")]
+    pub web: Option<WebConfig>,
+}
+
+struct ControlService {
+    aws_config: SdkConfig,
+    auth: ApiAuth,
+    timeout: u64,
+}
+
+impl From<Instance> for InstanceReply {
+    fn from(instance: Instance) -> Self {
+        Self {
+            instance_id: instance.instance_id().to_string(),
+            state: instance.state().as_str().to_string(),
+            public_ipv4: instance.ipv4_address_public().map(str::to_string),
+            private_ipv4: instance.ipv4_address_private().map(str::to_string),
+            ipv6: instance.ipv6_address().map(str::to_string),
+        }
+    }
+}
+
+impl ControlService {
+    /// Checks that the bearer token of the request allows the action on its instance
+    fn authorize(&self, request: &Request<InstanceRequest>, action: &str) -> Result<(), Status> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        let instance_id = &request.get_ref().instance_id;
+        match self.auth.authorize(token) {
+            None => Err(Status::unauthenticated("Missing or invalid token")),
+            Some(grant) if !grant.allows(instance_id, action) => {
+                log::warn(
+                    "serve",
+                    format!("{} may not {} {}", grant.name, action, instance_id),
+                );
+                Err(Status::permission_denied(format!(
+                    "{} may not {} {}",
+                    grant.name, action, instance_id
+                )))
+            }
+            Some(_) => Ok(()),
+        }
+    }
+
+    async fn act(
+        &self,
+        request: Request<InstanceRequest>,
+        action: Action,
+    ) -> Result<Response<InstanceReply>, Status> {
+        self.authorize(&request, &action.to_string())?;
+        let instance_id = request.into_inner().instance_id;
+        let mut config = Config::new(action.clone(), instance_id.clone());
+        config.timeout = self.timeout;
+
+        let future = timeout(
+            Duration::from_secs(self.timeout),
+            work(&self.aws_config, &config, &action),
+        );
+        match log::with_instance(&instance_id, future).await {
+            Ok(Ok(instance)) => Ok(Response::new(instance.into())),
+            Ok(Err(e)) => Err(Status::internal(e.to_string())),
+            Err(_) => Err(Status::deadline_exceeded(format!(
+                "Timed out waiting for the instance to {}",
+                action
+            ))),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Control for ControlService {
+    async fn start(
+        &self,
+        request: Request<InstanceRequest>,
+    ) -> Result<Response<InstanceReply>, Status> {
+        self.act(request, Action::Start).await
+    }
+
+    async fn stop(
+        &self,
+        request: Request<InstanceRequest>,
+    ) -> Result<Response<InstanceReply>, Status> {
+        self.act(request, Action::Stop).await
+    }
+
+    async fn status(
+        &self,
+        request: Request<InstanceRequest>,
+    ) -> Result<Response<InstanceReply>, Status> {
+        self.authorize(&request, "status")?;
+        // The target state is only used when waiting
+        let aws_ec2_client = AwsEc2Client::new(
+            clients::ec2(&self.aws_config),
+            &request.into_inner().instance_id,
+            InstanceStateName::Running,
+            Duration::from_secs(10),
+        );
+        match aws_ec2_client.get_instance().await {
+            Ok(instance) => Ok(Response::new(instance.into())),
+            Err(e) => Err(Status::internal(e.to_string())),
+        }
+    }
+
+    type WaitForSsmStream = ReceiverStream<Result<Progress, Status>>;
+
+    async fn wait_for_ssm(
+        &self,
+        request: Request<InstanceRequest>,
+    ) -> Result<Response<Self::WaitForSsmStream>, Status> {
+        self.authorize(&request, "status")?;
+        let aws_ssm_client = AwsSsmClient {
+            client: clients::ssm(&self.aws_config),
+            instance_id: request.into_inner().instance_id,
+            wait: Duration::from_secs(10),
+        };
+        let deadline = Duration::from_secs(self.timeout);
+
+        let (sender, receiver) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let progress = |message: &str, connected| {
+                Ok(Progress {
+                    message: message.to_string(),
+                    connected,
+                })
+            };
+            let polling = async {
+                let mut wait_interval = tokio::time::interval(aws_ssm_client.wait);
+                loop {
+                    wait_interval.tick().await;
+                    match aws_ssm_client.get_connection_status().await {
+                        Ok(true) => return progress("Connected to SSM", true),
+                        Ok(false) => {
+                            let waiting = progress("Waiting for connection to SSM...", false);
+                            if sender.send(waiting).await.is_err() {
+                                // The client went away
+                                return Err(Status::cancelled("Cancelled"));
+                            }
+                        }
+                        Err(e) => return Err(Status::internal(e.to_string())),
+                    }
+                }
+            };
+            let last = match timeout(deadline, polling).await {
+                Ok(last) => last,
+                Err(_) => Err(Status::deadline_exceeded("Timed out waiting for SSM")),
+            };
+            let _ = sender.send(last).await;
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
+    }
+}
+
+/// Serves the control API until interrupted
+pub async fn run(aws_config: &SdkConfig, settings: &Settings, config: ServeConfig) -> Result<()> {
+    let auth = ApiAuth::new(settings.api_auth.as_ref());
+    if !auth.is_enabled() && settings.api_tls.is_none() && !config.listen.ip().is_loopback() {
+        log::warn(
+            "serve",
+            "No api_auth nor api_tls in the configuration file, anyone reaching the API can use it",
+        );
+    }
+    // api_tls only covers gRPC, the tokens of the web UI would go over the network in plain text
+    #[cfg(feature = "web")]
+    if let (Some(_), Some(web_config)) = (&settings.api_tls, &config.web) {
+        if !web_config.listen.ip().is_loopback() {
+            return Err(eyre!(
+                "api_tls only covers the gRPC API, serve the web UI on a loopback address with \
+                --web-listen, behind an HTTPS proxy"
+            ));
+        }
+    }
+    let mut server = tonic::transport::Server::builder();
+    if let Some(tls) = &settings.api_tls {
+        server = server.tls_config(tls_config(tls)?)?;
+    }
+    let service = ControlService {
+        aws_config: aws_config.clone(),
+        auth: auth.clone(),
+        timeout: config.timeout,
+    };
+
+    log::info(
+        "serve",
+        format!("Serving the gRPC control API on {}", config.listen),
+    );
+    let listen = config.listen;
+    let api = async move {
+        server
+            .add_service(ControlServer::new(service))
+            .serve_with_shutdown(listen, async {
+                let _ = tokio::signal::ctrl_c().await;
+            })
+            .await?;
+        Ok::<_, color_eyre::Report>(())
+    };
+
+    #[cfg(feature = "web")]
+    if let Some(web_config) = config.web {
+        let auth = auth.with_token("web-token", &web_config.token);
+        tokio::try_join!(api, web::run(aws_config, web_config, auth, config.timeout))?;
+        return Ok(());
+    }
+    api.await
+}
+
+/// Serves the API over TLS, only to the clients with a certificate signed by the CA
+fn tls_config(settings: &ApiTlsSettings) -> Result<ServerTlsConfig> {
+    let read = |path: &std::path::Path| {
+        std::fs::read(path).wrap_err_with(|| format!("Failed to read {}", path.display()))
+    };
+    Ok(ServerTlsConfig::new()
+        .identity(Identity::from_pem(
+            read(&settings.cert)?,
+            read(&settings.key)?,
+        ))
+        .client_ca_root(Certificate::from_pem(read(&settings.client_ca)?)))
+}
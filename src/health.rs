@@ -0,0 +1,191 @@
+use crate::log;
+use aws_config::SdkConfig;
+use aws_credential_types::provider::ProvideCredentials;
+use aws_smithy_runtime_api::box_error::BoxError;
+use aws_smithy_runtime_api::client::interceptors::{
+    context::BeforeDeserializationInterceptorContextRef, Intercept,
+};
+use aws_smithy_runtime_api::client::runtime_components::RuntimeComponents;
+use aws_smithy_types::config_bag::ConfigBag;
+use chrono::{DateTime, SecondsFormat, Utc};
+use color_eyre::Result;
+use serde_json::{json, Value};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// How many of its periods a loop may miss before the process is reported dead
+const MISSED_PERIODS: u32 = 3;
+
+/// Last time the polling loop of the mode went round, and how often it should
+static LOOP: Mutex<Option<(Instant, Duration)>> = Mutex::new(None);
+/// Last time AWS answered a request, and last time it failed to, e.g. for bad credentials
+static CALLS: Mutex<(Option<DateTime<Utc>>, Option<DateTime<Utc>>)> = Mutex::new((None, None));
+
+/// Records that the polling loop of the mode went round, and is expected to again within `period`
+pub fn alive(period: Duration) {
+    *LOOP.lock().unwrap() = Some((Instant::now(), period));
+}
+
+/// Serves `/healthz` and `/readyz` on the port in the background, for Kubernetes or systemd to
+/// supervise the long-running modes
+///
+/// `/healthz` fails once the polling loop of the mode has stopped going round. `/readyz` fails
+/// when the credentials can't be loaded or have expired, or when the last AWS call was refused.
+pub async fn serve(aws_config: &SdkConfig, port: u16) -> Result<()> {
+    let listen = SocketAddr::from((Ipv4Addr::UNSPECIFIED, port));
+    let listener = TcpListener::bind(listen).await?;
+    log::info(
+        "health",
+        format!("Serving /healthz and /readyz on {}", listen),
+    );
+
+    let aws_config = aws_config.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((socket, _)) => {
+                    let aws_config = aws_config.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = answer(&aws_config, socket).await {
+                            log::warn("health", format!("Failed to answer: {}", e));
+                        }
+                    });
+                }
+                Err(e) => log::warn("health", format!("Failed to accept: {}", e)),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn answer(aws_config: &SdkConfig, mut socket: TcpStream) -> Result<()> {
+    // The request line is all that matters, and probes send short requests
+    let mut buffer = [0; 1024];
+    let read = socket.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let (method, path) = (request_line.next(), request_line.next());
+
+    let (healthy, body) = match (method, path) {
+        (Some("GET" | "HEAD"), Some("/healthz")) => liveness(),
+        (Some("GET" | "HEAD"), Some("/readyz")) => readiness(aws_config).await,
+        _ => {
+            let response = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n";
+            socket.write_all(response.as_bytes()).await?;
+            return Ok(());
+        }
+    };
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+        Connection: close\r\n\r\n{}",
+        if healthy {
+            "200 OK"
+        } else {
+            "503 Service Unavailable"
+        },
+        body.len(),
+        if method == Some("HEAD") {
+            ""
+        } else {
+            body.as_str()
+        }
+    );
+    socket.write_all(response.as_bytes()).await?;
+    Ok(())
+}
+
+fn liveness() -> (bool, Value) {
+    match *LOOP.lock().unwrap() {
+        // Modes without a polling loop are alive as long as they answer
+        None => (true, json!({"status": "ok"})),
+        Some((last, period)) => {
+            let since = last.elapsed();
+            let healthy = since <= period * MISSED_PERIODS;
+            (
+                healthy,
+                json!({
+                    "status": if healthy { "ok" } else { "stalled" },
+                    "last_loop_seconds_ago": since.as_secs_f64(),
+                    "loop_period_seconds": period.as_secs_f64(),
+                }),
+            )
+        }
+    }
+}
+
+async fn readiness(aws_config: &SdkConfig) -> (bool, Value) {
+    let credentials = match aws_config.credentials_provider() {
+        None => Err("no credentials configured".to_string()),
+        Some(provider) => match provider.provide_credentials().await {
+            Err(e) => Err(e.to_string()),
+            Ok(credentials) => match credentials.expiry() {
+                Some(expiry) if expiry <= SystemTime::now() => {
+                    Err("the credentials have expired".to_string())
+                }
+                expiry => Ok(expiry.map(|expiry| {
+                    DateTime::<Utc>::from(expiry).to_rfc3339_opts(SecondsFormat::Secs, true)
+                })),
+            },
+        },
+    };
+    let (succeeded, failed) = *CALLS.lock().unwrap();
+    let aws_reachable = match (succeeded, failed) {
+        (Some(succeeded), Some(failed)) => succeeded > failed,
+        (_, failed) => failed.is_none(),
+    };
+
+    let ready = credentials.is_ok() && aws_reachable;
+    let format = |time: Option<DateTime<Utc>>| {
+        time.map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, true))
+    };
+    (
+        ready,
+        json!({
+            "status": if ready { "ok" } else { "unavailable" },
+            "credentials": match &credentials {
+                Ok(_) => "valid".to_string(),
+                Err(e) => e.clone(),
+            },
+            "credentials_expire": credentials.ok().flatten(),
+            "last_aws_success": format(succeeded),
+            "last_aws_failure": format(failed),
+        }),
+    )
+}
+
+/// Records whether AWS answered the requests of the clients it is added to, for `/readyz`
+///
+/// Requests AWS refused, for the credentials or its own failure, count as failed. Others, like
+/// those for an instance which doesn't exist, still show that AWS can be used.
+#[derive(Debug)]
+pub struct Calls;
+
+impl Intercept for Calls {
+    fn name(&self) -> &'static str {
+        "Calls"
+    }
+
+    fn read_after_transmit(
+        &self,
+        context: &BeforeDeserializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        _cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        let status = context.response().status().as_u16();
+        let mut calls = CALLS.lock().unwrap();
+        match status {
+            401 | 403 | 500.. => calls.1 = Some(Utc::now()),
+            _ => calls.0 = Some(Utc::now()),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,46 @@
+use crate::aws::{AwsEc2Client, Instance};
+use color_eyre::Result;
+
+/// Instance families which can hibernate, except for their metal sizes
+///
+/// https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/hibernating-prerequisites.html
+const FAMILIES: &[&str] = &[
+    "c3", "c4", "c5", "c5a", "c5d", "c6a", "c6g", "c6gd", "c6i", "c6id", "c7a", "c7g", "c7i", "i3",
+    "m3", "m4", "m5", "m5a", "m5ad", "m5d", "m6a", "m6g", "m6gd", "m6i", "m6id", "m7a", "m7g",
+    "m7i", "r3", "r4", "r5", "r5a", "r5ad", "r5d", "r6a", "r6g", "r6gd", "r6i", "r6id", "r7a",
+    "r7g", "r7i", "t2", "t3", "t3a", "t4g",
+];
+
+fn supported_type(instance_type: &str) -> bool {
+    match instance_type.split_once('.') {
+        Some((family, size)) => FAMILIES.contains(&family) && !size.starts_with("metal"),
+        None => false,
+    }
+}
+
+/// Reasons why stopping the instance with hibernation would fail, empty if it would work
+pub async fn preflight(aws_ec2_client: &AwsEc2Client, instance: &Instance) -> Result<Vec<String>> {
+    let mut problems = vec![];
+
+    if !instance.hibernation_configured() {
+        problems.push("hibernation was not enabled at launch".to_string());
+    }
+
+    match instance.root_volume_id() {
+        Some(volume_id) => {
+            if !aws_ec2_client.volume_encrypted(volume_id).await? {
+                problems.push("the root volume is not encrypted".to_string());
+            }
+        }
+        None => problems.push("the root device is not an EBS volume".to_string()),
+    }
+
+    if !supported_type(instance.instance_type()) {
+        problems.push(format!(
+            "instance type {} does not support hibernation",
+            instance.instance_type()
+        ));
+    }
+
+    Ok(problems)
+}
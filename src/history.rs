@@ -0,0 +1,49 @@
+use crate::aws::AwsCloudTrailClient;
+use crate::clients;
+use aws_config::SdkConfig;
+use chrono::Utc;
+use color_eyre::Result;
+
+/// Calls changing the state of instances
+const EVENT_NAMES: &[&str] = &[
+    "RunInstances",
+    "StartInstances",
+    "StopInstances",
+    "RebootInstances",
+    "TerminateInstances",
+];
+
+#[derive(Debug)]
+pub struct HistoryConfig {
+    pub instance_id: String,
+    /// How many days back to look, CloudTrail keeps 90 days of events
+    pub days: u32,
+}
+
+/// Prints who started and stopped the instance, and when
+pub async fn run(aws_config: &SdkConfig, config: HistoryConfig) -> Result<()> {
+    let client = AwsCloudTrailClient {
+        client: clients::cloudtrail(aws_config),
+    };
+    let since = Utc::now() - chrono::Duration::days(config.days.into());
+    let events = client
+        .instance_events(&config.instance_id, EVENT_NAMES, since)
+        .await?;
+
+    if events.is_empty() {
+        println!(
+            "No action on {} in the last {} days",
+            config.instance_id, config.days
+        );
+    }
+    for event in events {
+        println!(
+            "{}  {:<18}  {}",
+            event.time.format("%Y-%m-%d %H:%M:%S UTC"),
+            event.name,
+            event.username.as_deref().unwrap_or("unknown")
+        );
+    }
+
+    Ok(())
+}
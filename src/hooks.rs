@@ -0,0 +1,99 @@
+use crate::aws::Instance;
+use crate::config::Action;
+use crate::plugins;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::process::Command;
+
+/// Runs a user-provided shell command, with variables describing the action in its environment
+///
+/// `result` is only set for post-hooks. `plugin:<name>` runs the plugin instead, and
+/// `wasm:<file>` the WASM module, with the same values as JSON.
+pub async fn run(
+    command: &str,
+    instance_id: &str,
+    action: &Action,
+    result: Option<&Result<Instance>>,
+) -> Result<()> {
+    if let Some(name) = command.strip_prefix("plugin:") {
+        let payload = payload(instance_id, action, result);
+        return plugins::call(name, "hook", &payload).await;
+    }
+    if let Some(path) = command.strip_prefix("wasm:") {
+        #[cfg(feature = "wasm")]
+        return crate::wasm::hook(
+            std::path::Path::new(path),
+            &payload(instance_id, action, result),
+        )
+        .await;
+        #[cfg(not(feature = "wasm"))]
+        return Err(eyre!("Hook {} needs a build with the wasm feature", path));
+    }
+
+    let mut process = shell(command);
+    process
+        .env("INSTANCE_ID", instance_id)
+        .env("ACTION", action.to_string());
+
+    if let Some(result) = result {
+        let instance = result.as_ref().ok();
+        process
+            .env("RESULT", if result.is_ok() { "success" } else { "failure" })
+            .env(
+                "PUBLIC_IP",
+                instance
+                    .and_then(Instance::ipv4_address_public)
+                    .unwrap_or_default(),
+            )
+            .env(
+                "PRIVATE_IP",
+                instance
+                    .and_then(Instance::ipv4_address_private)
+                    .unwrap_or_default(),
+            )
+            .env(
+                "IPV6",
+                instance
+                    .and_then(Instance::ipv6_address)
+                    .unwrap_or_default(),
+            );
+    }
+
+    let status = process.status().await?;
+    if !status.success() {
+        return Err(eyre!("Hook `{}` failed: {}", command, status));
+    }
+
+    Ok(())
+}
+
+fn payload(
+    instance_id: &str,
+    action: &Action,
+    result: Option<&Result<Instance>>,
+) -> serde_json::Value {
+    let instance = result.and_then(|result| result.as_ref().ok());
+    serde_json::json!({
+        "hook": if result.is_some() { "post" } else { "pre" },
+        "instance_id": instance_id,
+        "action": action.to_string(),
+        "result": result.map(|result| if result.is_ok() { "success" } else { "failure" }),
+        "public_ip": instance.and_then(Instance::ipv4_address_public),
+        "private_ip": instance.and_then(Instance::ipv4_address_private),
+        "ipv6": instance.and_then(Instance::ipv6_address),
+    })
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut process = Command::new("sh");
+    process.arg("-c").arg(command);
+    process
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    let mut process = Command::new("cmd");
+    process.arg("/C").arg(command);
+    process
+}
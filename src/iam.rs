@@ -0,0 +1,609 @@
+use crate::aws::SCHEDULE_PREFIX;
+use crate::config::Config;
+use serde_json::{json, Value};
+
+/// Which features the generated policy must allow
+#[derive(Debug)]
+pub struct PolicyConfig {
+    /// Restrict start and stop to these instances, or allow all instances when empty
+    pub instance_ids: Vec<String>,
+    pub wait_for_ssm: bool,
+    pub schedules: bool,
+    pub watch_idle: bool,
+    pub idle_alarm: bool,
+    pub ssm_commands: bool,
+    pub launch: bool,
+    pub snapshots: bool,
+    pub auto_scaling: bool,
+    pub history: bool,
+    /// Allow tagging the instances to lock them, as `--lock` does
+    pub lock: bool,
+    /// Allow adding and removing security group rules, as `--authorize-my-ip` does
+    pub ingress: bool,
+    /// Allow attaching and detaching network interfaces, as `--attach-eni` does
+    pub enis: bool,
+    /// Allow reading CloudWatch Logs, as `--tail-logs` does
+    pub logs: bool,
+    /// Allow reading budgets, as `--budget-guard` does
+    pub budgets: bool,
+    /// Allow getting the prices of the instance types, as `sweep` and `--show-cost` do
+    pub pricing: bool,
+    /// Allow reading instance ids from Parameter Store, as `ssm:PARAMETER` does
+    pub parameters: bool,
+    /// Allow connecting to the serial console, as `--serial-console` does
+    pub serial_console: bool,
+    /// Allow reading instance ids from CloudFormation, as `cfn:STACK:OUTPUT` and `--stack` do
+    pub stacks: bool,
+    /// Allow changing the target capacity of EC2 Fleets and Spot Fleets, as `--fleet` does
+    pub fleets: bool,
+    /// Allow sessions through SSM, as `proxy-command` and `proxy --ssm` open
+    pub ssm_sessions: bool,
+    /// Allow draining ECS container instances, as `--drain-ecs` does
+    pub ecs: bool,
+    /// Allow disabling and enabling Route53 health checks, as `--health-check-id` does
+    pub health_checks: bool,
+    /// Allow reading the health of load balancer targets and registering them, as
+    /// `--wait-for-target-group` and `--target-group` do
+    pub target_groups: bool,
+    /// Allow putting the metrics of the actions into CloudWatch, as `--emit-metrics` does
+    pub metrics: bool,
+    /// Allow reading the status of EBS volumes, as `--wait-for-volumes` does
+    pub volumes: bool,
+    /// Allow simulating the policies of the caller, as `--preflight` does
+    pub preflight: bool,
+    /// Allow reading and setting the CPU credit option of burstable instances, as `status` and
+    /// `--credit-spec` do
+    pub credits: bool,
+    /// Allow reading the console output, as `--console-output`, `--host-keys` and `--known-hosts`
+    /// do
+    pub console_output: bool,
+    /// Allow getting the Windows administrator password, as `--get-windows-password` does
+    pub passwords: bool,
+    /// Allow targeting capacity reservations, as `--capacity-reservation` does
+    pub capacity_reservations: bool,
+    /// Allow changing the instance type, as `--fallback-instance-types` does
+    pub instance_types: bool,
+    /// Allow capturing screenshots of the console, as `--screenshot` does
+    pub screenshots: bool,
+    /// Allow assuming the roles of other accounts, as `--account` and `org` do
+    pub roles: bool,
+    /// Allow listing the accounts of the organization, as `org` does
+    pub organizations: bool,
+    /// Allow keeping the state in S3, as an `s3://` state in the configuration file does
+    pub state: bool,
+    /// Allow sending the SNS and SES notifications of the configuration file
+    pub notifications: bool,
+    /// Allow listing the enabled regions, as `--regions all` and `--search-regions` do
+    pub regions: bool,
+}
+
+impl PolicyConfig {
+    /// Features a run with this configuration uses, acting on these instances
+    ///
+    /// Those set outside of the run's configuration, such as `--lock` or the notifications, are
+    /// left disabled for the caller to set.
+    pub fn for_run(config: &Config, instance_ids: &[String]) -> Self {
+        Self {
+            instance_ids: instance_ids.to_vec(),
+            wait_for_ssm: config.wait_for_ssm,
+            schedules: config.detach,
+            watch_idle: false,
+            idle_alarm: config.idle_alarm.is_some(),
+            ssm_commands: config.wait_for_cloud_init
+                || !config.check_services.is_empty()
+                || config.ssm_check.is_some()
+                || config.check_gpu
+                || config.warmup.is_some()
+                || config.pre_stop_ssm.is_some()
+                || (config.drain_k8s && config.kubeconfig.is_none()),
+            launch: false,
+            snapshots: config.snapshot_ami.is_some() || config.snapshot_volumes,
+            auto_scaling: config.warm_pool,
+            history: false,
+            lock: false,
+            ingress: config.authorize_my_ip.is_some() || config.revoke_my_ip,
+            enis: config.attach_eni.is_some(),
+            logs: config.tail_logs.is_some(),
+            budgets: config.budget_guard.is_some(),
+            pricing: config.show_cost,
+            parameters: false,
+            serial_console: config.serial_console.is_some(),
+            stacks: false,
+            fleets: false,
+            ssm_sessions: false,
+            ecs: config.drain_ecs.is_some(),
+            health_checks: config.health_check_id.is_some(),
+            target_groups: config.target_group.is_some() || config.register_target_group.is_some(),
+            metrics: false,
+            volumes: config.wait_for_volumes,
+            preflight: false,
+            credits: config.credit_spec.is_some(),
+            console_output: config.console_output_lines.is_some()
+                || config.print_host_keys
+                || config.known_hosts.is_some(),
+            passwords: config.windows_password_key.is_some(),
+            capacity_reservations: config.capacity_reservation.is_some(),
+            instance_types: !config.fallback_instance_types.is_empty(),
+            screenshots: config.screenshot.is_some(),
+            roles: false,
+            organizations: false,
+            state: false,
+            notifications: false,
+            regions: config.search_regions,
+        }
+    }
+}
+
+/// Builds the minimal IAM policy document needed for the requested features
+pub fn policy(config: &PolicyConfig) -> Value {
+    let instances: Vec<String> = if config.instance_ids.is_empty() {
+        vec!["arn:aws:ec2:*:*:instance/*".to_string()]
+    } else {
+        config
+            .instance_ids
+            .iter()
+            .map(|instance_id| format!("arn:aws:ec2:*:*:instance/{}", instance_id))
+            .collect()
+    };
+
+    let mut statements = vec![
+        json!({
+            "Sid": "DescribeInstances",
+            "Effect": "Allow",
+            "Action": ["ec2:DescribeInstances", "ec2:DescribeInstanceStatus"],
+            "Resource": "*",
+        }),
+        json!({
+            "Sid": "StartStopInstances",
+            "Effect": "Allow",
+            "Action": ["ec2:StartInstances", "ec2:StopInstances"],
+            "Resource": instances,
+        }),
+    ];
+
+    if config.lock {
+        statements.push(json!({
+            "Sid": "LockInstances",
+            "Effect": "Allow",
+            "Action": ["ec2:CreateTags", "ec2:DeleteTags"],
+            "Resource": instances,
+            "Condition": {
+                "ForAllValues:StringEquals": {"aws:TagKeys": ["aws-start-stop:lock"]},
+            },
+        }));
+    }
+
+    if config.ingress {
+        statements.push(json!({
+            "Sid": "ManageIngressRules",
+            "Effect": "Allow",
+            "Action": [
+                "ec2:AuthorizeSecurityGroupIngress",
+                "ec2:DescribeSecurityGroupRules",
+                "ec2:RevokeSecurityGroupIngress",
+            ],
+            "Resource": "*",
+        }));
+    }
+
+    if config.enis {
+        statements.push(json!({
+            "Sid": "AttachNetworkInterfaces",
+            "Effect": "Allow",
+            "Action": [
+                "ec2:AttachNetworkInterface",
+                "ec2:DescribeNetworkInterfaces",
+                "ec2:DetachNetworkInterface",
+            ],
+            "Resource": "*",
+        }));
+    }
+
+    if config.logs {
+        statements.push(json!({
+            "Sid": "TailLogs",
+            "Effect": "Allow",
+            "Action": "logs:FilterLogEvents",
+            "Resource": "*",
+        }));
+    }
+
+    if config.budgets {
+        statements.push(json!({
+            "Sid": "ViewBudgets",
+            "Effect": "Allow",
+            "Action": "budgets:ViewBudget",
+            "Resource": "*",
+        }));
+    }
+
+    if config.pricing {
+        statements.push(json!({
+            "Sid": "GetPrices",
+            "Effect": "Allow",
+            "Action": "pricing:GetProducts",
+            "Resource": "*",
+        }));
+    }
+
+    if config.serial_console {
+        statements.push(json!({
+            "Sid": "EnableSerialConsole",
+            "Effect": "Allow",
+            "Action": [
+                "ec2:GetSerialConsoleAccessStatus",
+                "ec2:EnableSerialConsoleAccess",
+            ],
+            "Resource": "*",
+        }));
+        statements.push(json!({
+            "Sid": "ConnectSerialConsole",
+            "Effect": "Allow",
+            "Action": ["ec2-instance-connect:SendSerialConsoleSSHPublicKey"],
+            "Resource": instances,
+        }));
+    }
+
+    if config.parameters {
+        statements.push(json!({
+            "Sid": "ReadInstanceParameters",
+            "Effect": "Allow",
+            "Action": ["ssm:GetParameter"],
+            "Resource": "arn:aws:ssm:*:*:parameter/*",
+        }));
+    }
+
+    if config.stacks {
+        statements.push(json!({
+            "Sid": "ReadInstanceStacks",
+            "Effect": "Allow",
+            "Action": [
+                "cloudformation:DescribeStacks",
+                "cloudformation:DescribeStackResource",
+                "cloudformation:ListStackResources",
+            ],
+            "Resource": "arn:aws:cloudformation:*:*:stack/*",
+        }));
+    }
+
+    if config.fleets {
+        statements.push(json!({
+            "Sid": "ScaleFleets",
+            "Effect": "Allow",
+            "Action": [
+                "ec2:DescribeFleets",
+                "ec2:DescribeSpotFleetRequests",
+                "ec2:ModifyFleet",
+                "ec2:ModifySpotFleetRequest",
+            ],
+            "Resource": "*",
+        }));
+        statements.push(json!({
+            "Sid": "SaveFleetCapacity",
+            "Effect": "Allow",
+            "Action": ["ec2:CreateTags"],
+            "Resource": [
+                "arn:aws:ec2:*:*:fleet/*",
+                "arn:aws:ec2:*:*:spot-fleet-request/*",
+            ],
+            "Condition": {
+                "ForAllValues:StringEquals": {"aws:TagKeys": ["aws-start-stop:capacity"]},
+            },
+        }));
+    }
+
+    if config.target_groups {
+        statements.push(json!({
+            "Sid": "DescribeTargets",
+            "Effect": "Allow",
+            "Action": [
+                "elasticloadbalancing:DescribeTargetGroups",
+                "elasticloadbalancing:DescribeTargetHealth",
+            ],
+            "Resource": "*",
+        }));
+        statements.push(json!({
+            "Sid": "RegisterTargets",
+            "Effect": "Allow",
+            "Action": [
+                "elasticloadbalancing:RegisterTargets",
+                "elasticloadbalancing:DeregisterTargets",
+            ],
+            "Resource": "arn:aws:elasticloadbalancing:*:*:targetgroup/*",
+        }));
+    }
+
+    if config.ssm_sessions {
+        let mut resources = instances.clone();
+        resources.push("arn:aws:ssm:*:*:document/AWS-StartSSHSession".to_string());
+        resources.push("arn:aws:ssm:*:*:document/AWS-StartPortForwardingSession".to_string());
+        statements.push(json!({
+            "Sid": "StartSessions",
+            "Effect": "Allow",
+            "Action": ["ssm:StartSession"],
+            "Resource": resources,
+        }));
+        statements.push(json!({
+            "Sid": "EndSessions",
+            "Effect": "Allow",
+            "Action": ["ssm:TerminateSession", "ssm:ResumeSession"],
+            "Resource": "arn:aws:ssm:*:*:session/${aws:userid}-*",
+        }));
+    }
+
+    if config.ecs {
+        statements.push(json!({
+            "Sid": "DrainContainerInstances",
+            "Effect": "Allow",
+            "Action": [
+                "ecs:ListClusters",
+                "ecs:ListContainerInstances",
+                "ecs:DescribeContainerInstances",
+                "ecs:UpdateContainerInstancesState",
+            ],
+            "Resource": "*",
+        }));
+    }
+
+    if config.health_checks {
+        statements.push(json!({
+            "Sid": "UpdateHealthChecks",
+            "Effect": "Allow",
+            "Action": ["route53:UpdateHealthCheck"],
+            "Resource": "arn:aws:route53:::healthcheck/*",
+        }));
+    }
+
+    if config.wait_for_ssm {
+        statements.push(json!({
+            "Sid": "SsmConnectionStatus",
+            "Effect": "Allow",
+            "Action": ["ssm:GetConnectionStatus"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.ssm_commands {
+        statements.push(json!({
+            "Sid": "RunSsmCommands",
+            "Effect": "Allow",
+            "Action": ["ssm:SendCommand", "ssm:GetCommandInvocation"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.launch {
+        statements.push(json!({
+            "Sid": "LaunchInstances",
+            "Effect": "Allow",
+            "Action": ["ec2:RunInstances", "ec2:CreateTags"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.snapshots {
+        statements.push(json!({
+            "Sid": "CreateSnapshots",
+            "Effect": "Allow",
+            "Action": [
+                "ec2:CreateImage",
+                "ec2:DescribeImages",
+                "ec2:CreateSnapshot",
+                "ec2:DescribeSnapshots",
+                "ec2:CreateTags",
+            ],
+            "Resource": "*",
+        }));
+    }
+
+    if config.history {
+        statements.push(json!({
+            "Sid": "LookupHistory",
+            "Effect": "Allow",
+            "Action": ["cloudtrail:LookupEvents"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.auto_scaling {
+        statements.push(json!({
+            "Sid": "DescribeAutoScaling",
+            "Effect": "Allow",
+            "Action": [
+                "autoscaling:DescribeAutoScalingInstances",
+                "autoscaling:DescribeWarmPool",
+            ],
+            "Resource": "*",
+        }));
+        statements.push(json!({
+            "Sid": "MoveAutoScalingInstances",
+            "Effect": "Allow",
+            "Action": [
+                "autoscaling:EnterStandby",
+                "autoscaling:ExitStandby",
+                "autoscaling:TerminateInstanceInAutoScalingGroup",
+            ],
+            "Resource": "*",
+        }));
+    }
+
+    if config.schedules {
+        statements.push(json!({
+            "Sid": "ListSchedules",
+            "Effect": "Allow",
+            "Action": ["scheduler:ListSchedules"],
+            "Resource": "*",
+        }));
+        statements.push(json!({
+            "Sid": "ManageSchedules",
+            "Effect": "Allow",
+            "Action": [
+                "scheduler:CreateSchedule",
+                "scheduler:GetSchedule",
+                "scheduler:DeleteSchedule",
+            ],
+            "Resource": format!("arn:aws:scheduler:*:*:schedule/default/{}*", SCHEDULE_PREFIX),
+        }));
+        statements.push(json!({
+            "Sid": "PassSchedulerRole",
+            "Effect": "Allow",
+            "Action": ["iam:PassRole"],
+            "Resource": "*",
+            "Condition": {
+                "StringEquals": {"iam:PassedToService": "scheduler.amazonaws.com"},
+            },
+        }));
+    }
+
+    if config.watch_idle {
+        statements.push(json!({
+            "Sid": "ReadMetrics",
+            "Effect": "Allow",
+            "Action": ["cloudwatch:GetMetricStatistics"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.metrics {
+        statements.push(json!({
+            "Sid": "PutMetrics",
+            "Effect": "Allow",
+            "Action": ["cloudwatch:PutMetricData"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.volumes {
+        statements.push(json!({
+            "Sid": "DescribeVolumes",
+            "Effect": "Allow",
+            "Action": ["ec2:DescribeVolumes", "ec2:DescribeVolumeStatus"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.preflight {
+        statements.push(json!({
+            "Sid": "SimulatePolicies",
+            "Effect": "Allow",
+            "Action": ["iam:SimulatePrincipalPolicy"],
+            "Resource": ["arn:aws:iam::*:user/*", "arn:aws:iam::*:role/*"],
+        }));
+    }
+
+    if config.credits {
+        statements.push(json!({
+            "Sid": "ManageCpuCredits",
+            "Effect": "Allow",
+            "Action": [
+                "ec2:DescribeInstanceCreditSpecifications",
+                "ec2:ModifyInstanceCreditSpecification",
+            ],
+            "Resource": "*",
+        }));
+    }
+
+    if config.console_output {
+        statements.push(json!({
+            "Sid": "ReadConsoleOutput",
+            "Effect": "Allow",
+            "Action": ["ec2:GetConsoleOutput"],
+            "Resource": instances,
+        }));
+    }
+
+    if config.passwords {
+        statements.push(json!({
+            "Sid": "GetWindowsPasswords",
+            "Effect": "Allow",
+            "Action": ["ec2:GetPasswordData"],
+            "Resource": instances,
+        }));
+    }
+
+    if config.capacity_reservations {
+        statements.push(json!({
+            "Sid": "TargetCapacityReservations",
+            "Effect": "Allow",
+            "Action": ["ec2:ModifyInstanceCapacityReservationAttributes"],
+            "Resource": instances,
+        }));
+    }
+
+    if config.instance_types {
+        statements.push(json!({
+            "Sid": "ChangeInstanceTypes",
+            "Effect": "Allow",
+            "Action": ["ec2:ModifyInstanceAttribute"],
+            "Resource": instances,
+        }));
+    }
+
+    if config.screenshots {
+        statements.push(json!({
+            "Sid": "CaptureScreenshots",
+            "Effect": "Allow",
+            "Action": ["ec2:GetConsoleScreenshot"],
+            "Resource": instances,
+        }));
+    }
+
+    if config.roles {
+        statements.push(json!({
+            "Sid": "AssumeAccountRoles",
+            "Effect": "Allow",
+            "Action": ["sts:AssumeRole"],
+            "Resource": "arn:aws:iam::*:role/*",
+        }));
+    }
+
+    if config.organizations {
+        statements.push(json!({
+            "Sid": "ListAccounts",
+            "Effect": "Allow",
+            "Action": ["organizations:ListAccounts"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.state {
+        statements.push(json!({
+            "Sid": "KeepState",
+            "Effect": "Allow",
+            "Action": ["s3:GetObject", "s3:PutObject"],
+            "Resource": "arn:aws:s3:::*/*",
+        }));
+    }
+
+    if config.notifications {
+        statements.push(json!({
+            "Sid": "SendNotifications",
+            "Effect": "Allow",
+            "Action": ["sns:Publish", "ses:SendEmail"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.regions {
+        statements.push(json!({
+            "Sid": "DescribeRegions",
+            "Effect": "Allow",
+            "Action": ["ec2:DescribeRegions"],
+            "Resource": "*",
+        }));
+    }
+
+    if config.idle_alarm {
+        statements.push(json!({
+            "Sid": "ManageIdleAlarms",
+            "Effect": "Allow",
+            "Action": ["cloudwatch:PutMetricAlarm"],
+            "Resource": format!("arn:aws:cloudwatch:*:*:alarm:{}idle-*", SCHEDULE_PREFIX),
+        }));
+    }
+
+    json!({
+        "Version": "2012-10-17",
+        "Statement": statements,
+    })
+}
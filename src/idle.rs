@@ -0,0 +1,133 @@
+use crate::aws::{AwsCloudWatchClient, AwsEc2Client};
+use crate::clients;
+use crate::config::{Action, Config};
+use crate::health;
+use crate::log;
+use crate::work;
+use aws_config::SdkConfig;
+use aws_sdk_cloudwatch::types::Statistic;
+use aws_sdk_ec2::types::InstanceStateName;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::time::{timeout, Duration};
+
+#[derive(Debug)]
+pub struct IdleConfig {
+    pub instance_id: String,
+    /// Maximum CPU utilization, in percent, for the instance to count as idle
+    pub cpu_threshold: f64,
+    /// Maximum bytes in and out per five minutes for the instance to count as idle
+    pub network_threshold: Option<f64>,
+    /// How long the instance must be idle before being stopped
+    pub idle_for: Duration,
+    pub timeout: u64,
+}
+
+/// CloudWatch alarm stopping the instance once idle, so it doesn't depend on this program running
+#[derive(Debug, Clone)]
+pub struct IdleAlarm {
+    /// CPU utilization, in percent, below which the instance is idle
+    pub threshold: f64,
+    /// Number of five minute periods the instance must be idle for
+    pub periods: i32,
+}
+
+/// Creates or updates the idle alarm of the instance
+pub async fn install_alarm(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    alarm: &IdleAlarm,
+) -> Result<String> {
+    let region = aws_config
+        .region()
+        .ok_or_else(|| eyre!("No AWS region is configured"))?;
+    let aws_cloudwatch_client = AwsCloudWatchClient {
+        client: clients::cloudwatch(aws_config),
+        instance_id: instance_id.to_string(),
+    };
+
+    aws_cloudwatch_client
+        .put_idle_alarm(region.as_ref(), alarm.threshold, alarm.periods)
+        .await
+}
+
+/// Watches the instance's metrics and stops it once it has been idle long enough
+///
+/// Returns when the instance has been stopped, by this or by something else.
+pub async fn run(aws_config: &SdkConfig, config: IdleConfig) -> Result<()> {
+    let aws_ec2_client = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        &config.instance_id,
+        InstanceStateName::Stopped,
+        Duration::from_secs(10),
+    );
+    let aws_cloudwatch_client = AwsCloudWatchClient {
+        client: clients::cloudwatch(aws_config),
+        instance_id: config.instance_id.clone(),
+    };
+
+    log::info(
+        "idle",
+        format!(
+            "Watching {} until it has been idle for {}",
+            config.instance_id,
+            humantime::format_duration(config.idle_for)
+        ),
+    );
+
+    let mut wait_interval = tokio::time::interval(AwsCloudWatchClient::PERIOD);
+    loop {
+        wait_interval.tick().await;
+        health::alive(AwsCloudWatchClient::PERIOD);
+
+        let state = aws_ec2_client.get_instance().await?.state().clone();
+        if state != InstanceStateName::Running {
+            log::info(
+                "idle",
+                format!("Instance is {}, nothing to watch", state.as_str()),
+            );
+            return Ok(());
+        }
+
+        if is_idle(&aws_cloudwatch_client, &config).await? {
+            log::info("idle", "Instance is idle, stopping it");
+            let stop_config = Config::new(Action::Stop, config.instance_id.clone());
+            timeout(
+                Duration::from_secs(config.timeout),
+                work(aws_config, &stop_config, &Action::Stop),
+            )
+            .await
+            .map_err(|_| eyre!("Timed out stopping the instance"))??;
+            return Ok(());
+        }
+    }
+}
+
+/// Whether every datapoint of the window is below the thresholds
+///
+/// The latest datapoint may not be published yet, but all the others must be there, so that
+/// an instance which was just started isn't considered idle.
+async fn is_idle(client: &AwsCloudWatchClient, config: &IdleConfig) -> Result<bool> {
+    let periods = (config.idle_for.as_secs() / AwsCloudWatchClient::PERIOD.as_secs()).max(1);
+    let required = periods.saturating_sub(1).max(1) as usize;
+
+    let cpu = client
+        .get_statistics("CPUUtilization", Statistic::Maximum, config.idle_for)
+        .await?;
+    if cpu.len() < required || cpu.iter().any(|value| *value >= config.cpu_threshold) {
+        return Ok(false);
+    }
+
+    if let Some(network_threshold) = config.network_threshold {
+        for metric in ["NetworkIn", "NetworkOut"] {
+            let network = client
+                .get_statistics(metric, Statistic::Sum, config.idle_for)
+                .await?;
+            if network.iter().any(|value| *value >= network_threshold) {
+                return Ok(false);
+            }
+        }
+    }
+
+    Ok(true)
+}
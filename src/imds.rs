@@ -0,0 +1,39 @@
+use crate::config::{Action, Config};
+use crate::log;
+use crate::work;
+use aws_config::imds;
+use aws_config::{Region, SdkConfig};
+use color_eyre::Result;
+use tokio::time::{sleep, Duration};
+
+#[derive(Debug)]
+pub struct SelfStopConfig {
+    /// How long to wait before stopping
+    pub delay: Option<Duration>,
+}
+
+/// Stops the instance this program runs on, found through the instance metadata service
+pub async fn stop(aws_config: &SdkConfig, config: SelfStopConfig) -> Result<()> {
+    let client = imds::Client::builder().build();
+    let instance_id: String = client.get("/latest/meta-data/instance-id").await?.into();
+    let region: String = client
+        .get("/latest/meta-data/placement/region")
+        .await?
+        .into();
+    let aws_config = aws_config.to_builder().region(Region::new(region)).build();
+
+    if let Some(delay) = config.delay {
+        log::info(
+            "stop",
+            format!("Stopping in {}", humantime::format_duration(delay)),
+        );
+        sleep(delay).await;
+    }
+
+    // The instance shuts this program down while stopping, so there is no point in waiting
+    let mut stop_config = Config::new(Action::Stop, instance_id.clone());
+    stop_config.no_wait = true;
+    log::with_instance(&instance_id, work(&aws_config, &stop_config, &Action::Stop)).await?;
+
+    Ok(())
+}
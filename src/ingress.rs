@@ -0,0 +1,120 @@
+use crate::aws::{AwsEc2Client, Instance};
+use crate::clients;
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::error::ProvideErrorMetadata;
+use aws_sdk_ec2::types::{Filter, InstanceStateName, IpPermission, IpRange};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::net::Ipv4Addr;
+use tokio::time::Duration;
+
+/// Description of the rules added by this program, to find them again
+const RULE_DESCRIPTION: &str = "aws-start-stop authorize-my-ip";
+
+/// Public address of this machine, as AWS sees it
+async fn my_ip() -> Result<Ipv4Addr> {
+    let ip = reqwest::get("https://checkip.amazonaws.com")
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    ip.trim()
+        .parse()
+        .map_err(|_| eyre!("Unexpected public IP {}", ip.trim()))
+}
+
+fn security_group(instance: &Instance) -> Result<&str> {
+    instance
+        .security_group_ids()
+        .first()
+        .copied()
+        .ok_or_else(|| eyre!("The instance has no security group"))
+}
+
+/// Allows connecting from this machine's public address to the port of the instance, through its
+/// first security group
+pub async fn authorize(aws_config: &SdkConfig, instance: &Instance, port: u16) -> Result<()> {
+    let ip = my_ip().await?;
+    let group_id = security_group(instance)?;
+    let permission = IpPermission::builder()
+        .ip_protocol("tcp")
+        .from_port(port.into())
+        .to_port(port.into())
+        .ip_ranges(
+            IpRange::builder()
+                .cidr_ip(format!("{}/32", ip))
+                .description(RULE_DESCRIPTION)
+                .build(),
+        )
+        .build();
+
+    let response = clients::ec2(aws_config)
+        .authorize_security_group_ingress()
+        .group_id(group_id)
+        .ip_permissions(permission)
+        .send()
+        .await;
+    match response {
+        Err(e) if e.code() == Some("InvalidPermission.Duplicate") => {
+            log::info("ingress", format!("{} can already reach port {}", ip, port))
+        }
+        response => {
+            response.hint("ec2:AuthorizeSecurityGroupIngress")?;
+            log::info(
+                "ingress",
+                format!("Allowed {} to port {} in {}", ip, port, group_id),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes the rules added by `authorize` from the security groups of the instance
+pub async fn revoke(aws_config: &SdkConfig, instance_id: &str) -> Result<()> {
+    // The target state is only used when waiting
+    let aws_ec2_client = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        instance_id,
+        InstanceStateName::Running,
+        Duration::from_secs(10),
+    );
+    let instance = aws_ec2_client.get_instance().await?;
+    let client = clients::ec2(aws_config);
+
+    for group_id in instance.security_group_ids() {
+        let response = client
+            .describe_security_group_rules()
+            .filters(Filter::builder().name("group-id").values(group_id).build())
+            .send()
+            .await
+            .hint("ec2:DescribeSecurityGroupRules")?;
+        let rule_ids: Vec<String> = response
+            .security_group_rules()
+            .iter()
+            .filter(|rule| rule.is_egress() == Some(false))
+            .filter(|rule| rule.description() == Some(RULE_DESCRIPTION))
+            .filter_map(|rule| rule.security_group_rule_id())
+            .map(str::to_string)
+            .collect();
+        if rule_ids.is_empty() {
+            continue;
+        }
+
+        client
+            .revoke_security_group_ingress()
+            .group_id(group_id)
+            .set_security_group_rule_ids(Some(rule_ids.clone()))
+            .send()
+            .await
+            .hint("ec2:RevokeSecurityGroupIngress")?;
+        log::info(
+            "ingress",
+            format!("Removed {} rules from {}", rule_ids.len(), group_id),
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use crate::aws::{self, AwsEc2Client, AwsEc2InstancesClient, Instance};
+use crate::clients;
+use crate::config::Action;
+use crate::log;
+use crate::protection;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::{Filter, InstanceStateName};
+use color_eyre::Result;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use tokio::time::{timeout, Duration};
+
+#[derive(Debug)]
+pub struct InventoryConfig {
+    /// Only include instances with all these tags
+    pub tags: Vec<(String, String)>,
+    /// Start the stopped instances first
+    pub ensure_running: bool,
+    /// Only print the variables of this host, as Ansible asks with `--host`
+    pub host: Option<String>,
+    pub timeout: u64,
+}
+
+/// Prints an Ansible dynamic inventory of the instances, with a group per tag
+pub async fn run(aws_config: &SdkConfig, config: InventoryConfig) -> Result<()> {
+    let client = AwsEc2InstancesClient::new(clients::ec2(aws_config));
+    let mut filters: Vec<Filter> = config
+        .tags
+        .iter()
+        .map(|(key, value)| aws::tag_filter(key, value))
+        .collect();
+    filters.push(
+        Filter::builder()
+            .name("instance-state-name")
+            .values("pending")
+            .values("running")
+            .values("stopping")
+            .values("stopped")
+            .build(),
+    );
+    let mut instances = client.describe_instances(filters.clone()).await?;
+
+    if config.ensure_running {
+        let stopped = protection::filter(
+            "inventory",
+            &Action::Start,
+            instances
+                .iter()
+                .filter(|instance| instance.state() == &InstanceStateName::Stopped),
+        );
+        if !stopped.is_empty() {
+            log::info("inventory", format!("Starting {}", stopped.join(", ")));
+            client.change_state(&Action::Start, &stopped).await?;
+            for instance_id in &stopped {
+                let aws_ec2_client = AwsEc2Client::new(
+                    clients::ec2(aws_config),
+                    instance_id,
+                    InstanceStateName::Running,
+                    Duration::from_secs(5),
+                );
+                let waited = timeout(
+                    Duration::from_secs(config.timeout),
+                    aws_ec2_client.wait_for_state(None),
+                );
+                match waited.await {
+                    Ok(result) => {
+                        result?;
+                    }
+                    Err(_) => log::warn(
+                        "inventory",
+                        format!("{} isn't running after {}s", instance_id, config.timeout),
+                    ),
+                }
+            }
+            // The addresses are only known once running
+            instances = client.describe_instances(filters).await?;
+        }
+    }
+
+    let inventory = match &config.host {
+        Some(host) => instances
+            .iter()
+            .find(|instance| instance.instance_id() == host)
+            .map_or_else(|| json!({}), host_vars),
+        None => inventory(&instances),
+    };
+    println!("{}", serde_json::to_string_pretty(&inventory)?);
+
+    Ok(())
+}
+
+fn inventory(instances: &[Instance]) -> Value {
+    let mut groups: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+    let mut hostvars = Map::new();
+    for instance in instances {
+        let host = instance.instance_id();
+        for (key, value) in instance.tags() {
+            groups.entry(group_name(key, value)).or_default().push(host);
+        }
+        hostvars.insert(host.to_string(), host_vars(instance));
+    }
+
+    let mut inventory = Map::new();
+    inventory.insert("_meta".to_string(), json!({ "hostvars": hostvars }));
+    inventory.insert(
+        "all".to_string(),
+        json!({
+            "hosts": instances.iter().map(Instance::instance_id).collect::<Vec<_>>(),
+            "children": groups.keys().collect::<Vec<_>>(),
+        }),
+    );
+    for (group, hosts) in groups {
+        inventory.insert(group, json!({ "hosts": hosts }));
+    }
+    Value::Object(inventory)
+}
+
+fn host_vars(instance: &Instance) -> Value {
+    json!({
+        "ansible_host": instance.address().map(|ip| ip.to_string()),
+        "instance_id": instance.instance_id(),
+        "name": instance.name(),
+        "state": instance.state().as_str(),
+        "public_ip": instance.ipv4_address_public(),
+        "private_ip": instance.ipv4_address_private(),
+        "ipv6": instance.ipv6_address(),
+        "instance_type": instance.instance_type(),
+        "availability_zone": instance.availability_zone(),
+        "tags": instance.tags(),
+    })
+}
+
+/// `tag_KEY_VALUE`, with the characters Ansible doesn't allow in group names replaced
+fn group_name(key: &str, value: &str) -> String {
+    format!("tag_{}_{}", key, value)
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
@@ -0,0 +1,104 @@
+use crate::aws::{AwsSsmClient, Instance};
+use crate::clients;
+use crate::log;
+use aws_config::SdkConfig;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::path::Path;
+use tokio::process::Command;
+use tokio::time::Duration;
+
+/// Name of the Kubernetes node running on the instance, if it is an EKS worker node
+///
+/// EKS names the nodes after the private DNS name of their instance.
+pub fn node_name(instance: &Instance) -> Option<&str> {
+    let is_node = instance.tag("eks:cluster-name").is_some()
+        || instance
+            .tags()
+            .keys()
+            .any(|key| key.starts_with("kubernetes.io/cluster/"));
+    if is_node {
+        instance.private_dns_name()
+    } else {
+        None
+    }
+}
+
+/// Runs kubectl locally with the kubeconfig if one is given, or on the node itself through SSM
+async fn kubectl(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    kubeconfig: Option<&Path>,
+    args: &[&str],
+) -> Result<()> {
+    match kubeconfig {
+        Some(kubeconfig) => {
+            let status = Command::new("kubectl")
+                .arg("--kubeconfig")
+                .arg(kubeconfig)
+                .args(args)
+                .status()
+                .await?;
+            if !status.success() {
+                return Err(eyre!("kubectl {} failed: {}", args.join(" "), status));
+            }
+        }
+        None => {
+            let aws_ssm_client = AwsSsmClient {
+                client: clients::ssm(aws_config),
+                instance_id: instance_id.to_string(),
+                wait: Duration::from_secs(5),
+            };
+            let result = aws_ssm_client
+                .run_command("AWS-RunShellScript", &format!("kubectl {}", args.join(" ")))
+                .await?;
+            if !result.success {
+                return Err(eyre!(
+                    "kubectl {} {}: {}",
+                    args.join(" "),
+                    result.status,
+                    result.output.trim_end()
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Cordons the node and evicts its pods, giving up after the timeout
+pub async fn drain(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    node: &str,
+    kubeconfig: Option<&Path>,
+    timeout: u64,
+) -> Result<()> {
+    log::info("k8s", format!("Draining node {}...", node));
+    kubectl(aws_config, instance_id, kubeconfig, &["cordon", node]).await?;
+    let timeout = format!("--timeout={}s", timeout);
+    let args = [
+        "drain",
+        node,
+        "--ignore-daemonsets",
+        "--delete-emptydir-data",
+        &timeout,
+    ];
+    kubectl(aws_config, instance_id, kubeconfig, &args).await?;
+    log::info("k8s", "Node drained");
+
+    Ok(())
+}
+
+/// Lets pods be scheduled on the node again
+pub async fn uncordon(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    node: &str,
+    kubeconfig: Option<&Path>,
+) -> Result<()> {
+    kubectl(aws_config, instance_id, kubeconfig, &["uncordon", node]).await?;
+    log::info("k8s", format!("Uncordoned node {}", node));
+
+    Ok(())
+}
@@ -0,0 +1,65 @@
+use crate::config::{Action, Config};
+use crate::work;
+use aws_config::{BehaviorVersion, SdkConfig};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use lambda_runtime::{service_fn, LambdaEvent};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct Event {
+    action: Action,
+    instance_id: String,
+    #[serde(default)]
+    wait_for_ssm: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Response {
+    instance_id: String,
+    state: String,
+    public_ipv4: Option<String>,
+    private_ipv4: Option<String>,
+    ipv6: Option<String>,
+    public_dns_name: Option<String>,
+    private_dns_name: Option<String>,
+    security_groups: Vec<String>,
+    key_name: Option<String>,
+}
+
+/// Whether the program was started by the Lambda runtime
+pub fn is_lambda_environment() -> bool {
+    std::env::var_os("AWS_LAMBDA_RUNTIME_API").is_some()
+}
+
+/// Serves Lambda invocations until the runtime shuts the function down
+pub async fn run() -> Result<()> {
+    let aws_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
+
+    lambda_runtime::run(service_fn(move |event: LambdaEvent<Event>| {
+        let aws_config = aws_config.clone();
+        async move { handle(&aws_config, event.payload).await }
+    }))
+    .await
+    .map_err(|err| eyre!(err))
+}
+
+/// Runs the requested action, bounded by the function's own timeout rather than `Config::timeout`
+async fn handle(aws_config: &SdkConfig, event: Event) -> Result<Response> {
+    let mut config = Config::new(event.action, event.instance_id);
+    config.wait_for_ssm = event.wait_for_ssm;
+
+    let instance = work(aws_config, &config, &config.action).await?;
+
+    Ok(Response {
+        instance_id: config.instance_id,
+        state: instance.state().as_str().to_string(),
+        public_ipv4: instance.ipv4_address_public().map(str::to_string),
+        private_ipv4: instance.ipv4_address_private().map(str::to_string),
+        ipv6: instance.ipv6_address().map(str::to_string),
+        public_dns_name: instance.public_dns_name().map(str::to_string),
+        private_dns_name: instance.private_dns_name().map(str::to_string),
+        security_groups: instance.security_groups(),
+        key_name: instance.key_name().map(str::to_string),
+    })
+}
@@ -0,0 +1,62 @@
+use crate::aws::{AwsEc2Client, AwsEc2InstancesClient};
+use crate::clients;
+use crate::config::{Action, Config};
+use crate::log;
+use crate::work;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::time::{timeout, Duration};
+
+#[derive(Debug)]
+pub struct LaunchConfig {
+    /// Id (`lt-...`) or name of the launch template
+    pub launch_template: Option<String>,
+    /// AMI to launch, overriding the one of the launch template
+    pub image_id: Option<String>,
+    pub instance_type: Option<String>,
+    pub subnet_id: Option<String>,
+    pub key_name: Option<String>,
+    pub tags: Vec<(String, String)>,
+    pub wait_for_ssm: bool,
+    pub timeout: u64,
+}
+
+/// Creates the instance, then waits for it like when starting one
+pub async fn run(aws_config: &SdkConfig, config: LaunchConfig) -> Result<()> {
+    let client = AwsEc2InstancesClient::new(clients::ec2(aws_config));
+    log::info("launch", "Launching instance...");
+    let instance_id = client.run_instance(&config).await?;
+    log::info("launch", format!("Launched instance {}", instance_id));
+
+    let mut start_config = Config::new(Action::Start, instance_id.clone());
+    start_config.wait_for_ssm = config.wait_for_ssm;
+    start_config.timeout = config.timeout;
+    let future = timeout(Duration::from_secs(config.timeout), async {
+        wait_until_described(aws_config, &instance_id).await;
+        work(aws_config, &start_config, &Action::Start).await
+    });
+    log::with_instance(&instance_id, future)
+        .await
+        .map_err(|_| eyre!("Timed out waiting for instance {} to start", instance_id))??;
+
+    Ok(())
+}
+
+/// Waits for the new instance to be known to DescribeInstances, which is eventually consistent
+async fn wait_until_described(aws_config: &SdkConfig, instance_id: &str) {
+    let aws_ec2_client = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        instance_id,
+        InstanceStateName::Running,
+        Duration::from_secs(2),
+    );
+    let mut wait_interval = tokio::time::interval(Duration::from_secs(2));
+    loop {
+        wait_interval.tick().await;
+        if aws_ec2_client.get_instance().await.is_ok() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,241 @@
+use crate::settings::{self, Alias, Settings};
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Command};
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Prefix of the environment variables giving arguments, e.g. `AWS_START_STOP_TIMEOUT`
+const ENV_PREFIX: &str = "AWS_START_STOP_";
+
+/// Value of an argument of the main command, and where it came from
+#[derive(Debug)]
+pub struct Resolved {
+    pub name: String,
+    pub value: String,
+    pub source: String,
+}
+
+/// Lets each argument, of the command and its subcommands, be given by an environment variable
+///
+/// Arguments already read from their own variable, such as the bot tokens, keep it.
+pub fn with_env(command: Command<'static>) -> Command<'static> {
+    // Arguments and subcommands are mutated in their order, which mutating moves to the end
+    let ids: Vec<&'static str> = command
+        .get_arguments()
+        .filter(|arg| arg.get_env().is_none())
+        .map(|arg| arg.get_id())
+        .collect();
+    let command = ids.into_iter().fold(command, |command, id| {
+        let name: &'static str = Box::leak(env_name(id).into_boxed_str());
+        command.mut_arg(id, |arg| arg.env(name).hide_env(true))
+    });
+
+    let subcommands: Vec<String> = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect();
+    subcommands.iter().fold(command, |command, name| {
+        command.mut_subcommand(name.as_str(), with_env)
+    })
+}
+
+fn env_name(id: &str) -> String {
+    format!("{}{}", ENV_PREFIX, id.to_uppercase().replace('-', "_"))
+}
+
+/// Where the values this program put in the environment came from, by variable
+pub type Sources = HashMap<String, String>;
+
+static ALIASES: OnceLock<HashMap<String, Alias>> = OnceLock::new();
+
+/// Sets the variables of the arguments given in the `[defaults]` of the configuration file
+///
+/// The file thus takes precedence over the environment, while the command line still overrides
+/// both. Also keeps the instance aliases of the file.
+pub fn load_file(command: &Command<'static>) -> Result<Sources> {
+    let settings = Settings::load(config_path().as_deref())?;
+    let mut sources = Sources::new();
+    set_defaults(
+        command,
+        settings.defaults,
+        "configuration file",
+        &mut sources,
+    )?;
+    ALIASES
+        .set(settings.aliases)
+        .map_err(|_| eyre!("Aliases already loaded"))?;
+
+    Ok(sources)
+}
+
+/// Instance alias of the configuration file with this name
+pub fn alias(name: &str) -> Option<&'static Alias> {
+    ALIASES.get()?.get(name)
+}
+
+/// Sets the variables of the defaults of the instance alias, if the instance is one
+///
+/// They take precedence over the environment and the defaults of the file. Returns whether any
+/// was set, in which case the command line must be parsed again.
+pub fn apply_alias(
+    command: &Command<'static>,
+    matches: &ArgMatches,
+    sources: &mut Sources,
+) -> Result<bool> {
+    let name = match matches.try_get_one::<String>("instance") {
+        Ok(Some(name)) => name,
+        _ => return Ok(false),
+    };
+    let alias = match alias(name) {
+        Some(alias) if !alias.defaults.is_empty() => alias,
+        _ => return Ok(false),
+    };
+    set_defaults(
+        command,
+        alias.defaults.clone(),
+        &format!("alias {}", name),
+        sources,
+    )?;
+
+    Ok(true)
+}
+
+fn set_defaults(
+    command: &Command<'static>,
+    defaults: HashMap<String, toml::Value>,
+    source: &str,
+    sources: &mut Sources,
+) -> Result<()> {
+    for (id, value) in defaults {
+        let name = find_env(command, &id)
+            .ok_or_else(|| eyre!("Unknown argument {} in the defaults of the {}", id, source))?;
+        let value = match value {
+            toml::Value::String(value) => value,
+            toml::Value::Integer(_) | toml::Value::Float(_) | toml::Value::Boolean(_) => {
+                value.to_string()
+            }
+            _ => {
+                return Err(eyre!(
+                    "Default {} must be a string, a number or a boolean",
+                    id
+                ))
+            }
+        };
+        std::env::set_var(&name, value);
+        sources.insert(name, source.to_string());
+    }
+
+    Ok(())
+}
+
+/// Variable the argument is read from, in the command or any of its subcommands
+fn find_env(command: &Command, id: &str) -> Option<String> {
+    command
+        .get_arguments()
+        .find(|arg| arg.get_id() == id)
+        .and_then(|arg| arg.get_env())
+        .map(|name| name.to_string_lossy().into_owned())
+        .or_else(|| {
+            command
+                .get_subcommands()
+                .find_map(|subcommand| find_env(subcommand, id))
+        })
+}
+
+/// Configuration file given with `--config`, looked for before the command line is parsed
+fn config_path() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if arg == "--config" {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(path) = arg.to_str().and_then(|arg| arg.strip_prefix("--config=")) {
+            return Some(PathBuf::from(path));
+        }
+    }
+
+    std::env::var_os("AWS_START_STOP_CONFIG").map(PathBuf::from)
+}
+
+/// Arguments of the main command which have a value, with where it came from
+pub fn resolve(command: &Command, matches: &ArgMatches, sources: &Sources) -> Vec<Resolved> {
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let id = arg.get_id();
+            let source = match matches.value_source(id)? {
+                ValueSource::CommandLine => "command line".to_string(),
+                ValueSource::EnvVariable => {
+                    let name = arg.get_env()?.to_string_lossy().into_owned();
+                    sources
+                        .get(&name)
+                        .cloned()
+                        .unwrap_or_else(|| format!("environment ({})", name))
+                }
+                _ => "default".to_string(),
+            };
+            let value = if arg.is_takes_value_set() {
+                matches
+                    .get_raw(id)?
+                    .map(|value| value.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(",")
+            } else {
+                "true".to_string()
+            };
+            Some(Resolved {
+                name: id.to_string(),
+                value,
+                source,
+            })
+        })
+        .collect()
+}
+
+pub fn print_resolved(resolved: &[Resolved]) {
+    let name_width = resolved
+        .iter()
+        .map(|setting| setting.name.len())
+        .chain(["SETTING".len()])
+        .max()
+        .unwrap_or_default();
+    let value_width = resolved
+        .iter()
+        .map(|setting| setting.value.len())
+        .chain(["VALUE".len()])
+        .max()
+        .unwrap_or_default();
+    println!(
+        "{:name_width$}  {:value_width$}  SOURCE",
+        "SETTING", "VALUE"
+    );
+    for setting in resolved {
+        println!(
+            "{:name_width$}  {:value_width$}  {}",
+            setting.name, setting.value, setting.source
+        );
+    }
+}
+
+/// Prints the configuration file, if there is one
+pub fn print_file(path: Option<&Path>) -> Result<()> {
+    let path = match path.map(Path::to_path_buf).or_else(settings::default_path) {
+        Some(path) if path.exists() => path,
+        _ => {
+            println!("No configuration file");
+            return Ok(());
+        }
+    };
+    let contents = std::fs::read_to_string(&path)
+        .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+    println!("# {}", path.display());
+    print!("{}", contents);
+
+    Ok(())
+}
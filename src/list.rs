@@ -0,0 +1,107 @@
+use crate::aws::{self, AwsEc2InstancesClient};
+use crate::clients;
+use crate::color;
+use crate::regions::{self, Regions};
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::Filter;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+#[derive(Debug)]
+pub struct ListConfig {
+    /// Only list instances in this state
+    pub state: Option<String>,
+    /// Only list instances with all these tags
+    pub tags: Vec<(String, String)>,
+    pub regions: Option<Regions>,
+}
+
+/// Prints a table of the instances of the region, or of each of the regions
+pub async fn run(aws_config: &SdkConfig, config: ListConfig) -> Result<()> {
+    let mut filters: Vec<Filter> = config
+        .tags
+        .iter()
+        .map(|(key, value)| aws::tag_filter(key, value))
+        .collect();
+    if let Some(state) = &config.state {
+        filters.push(
+            Filter::builder()
+                .name("instance-state-name")
+                .values(state)
+                .build(),
+        );
+    }
+
+    let results = regions::fan_out(aws_config, config.regions.as_ref(), |aws_config| {
+        let filters = filters.clone();
+        async move {
+            let client = AwsEc2InstancesClient::new(clients::ec2(&aws_config));
+            client.describe_instances(filters).await
+        }
+    })
+    .await?;
+
+    let mut failed = false;
+    let mut instances = vec![];
+    for (region, result) in results {
+        match result {
+            Ok(found) => {
+                instances.extend(found.into_iter().map(|instance| (region.clone(), instance)))
+            }
+            Err(e) => {
+                failed = true;
+                eprintln!("Failed to list the instances of {}: {}", region, e);
+            }
+        }
+    }
+
+    let mut rows = vec![[
+        "REGION".to_string(),
+        "ID".to_string(),
+        "NAME".to_string(),
+        "STATE".to_string(),
+        "TYPE".to_string(),
+        "AZ".to_string(),
+        "PUBLIC IP".to_string(),
+    ]];
+    rows.extend(instances.iter().map(|(region, instance)| {
+        [
+            region.clone(),
+            instance.instance_id().to_string(),
+            instance.name().unwrap_or("-").to_string(),
+            instance.state().as_str().to_string(),
+            instance.instance_type().to_string(),
+            instance.availability_zone().unwrap_or("-").to_string(),
+            instance.ipv4_address_public().unwrap_or("-").to_string(),
+        ]
+    }));
+
+    let mut widths = [0; 7];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for (index, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                // Padded apart, as the color codes don't take any room
+                let padding = " ".repeat(width - cell.len());
+                match column {
+                    3 if index > 0 => color::state(cell) + &padding,
+                    6 if index > 0 && cell != "-" => color::ip(cell) + &padding,
+                    _ => cell.clone() + &padding,
+                }
+            })
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+
+    if failed {
+        return Err(eyre!("Failed in some regions"));
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use crate::aws::AwsEc2Client;
+use crate::clients;
+use crate::config::Action;
+use crate::credentials;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use tokio::time::{sleep, Duration, Instant};
+
+/// Tag holding the lock, as `<owner> <action> until <expiry>`
+const LOCK_TAG: &str = "aws-start-stop:lock";
+
+/// Time for a competing run to overwrite the tag before checking the lock is still ours
+const SETTLE: Duration = Duration::from_secs(2);
+const POLL: Duration = Duration::from_secs(5);
+/// How long a lock lasts unless refreshed, so that one of a run which died is soon free again
+const LEASE: Duration = Duration::from_secs(300);
+/// How often the lock is refreshed while the action runs, however long it waits
+const REFRESH: Duration = Duration::from_secs(60);
+
+struct Locking {
+    /// Who the locks are taken as
+    owner: String,
+    /// How long to wait for a lock held by someone else, rather than failing
+    wait: Option<Duration>,
+}
+
+static LOCKING: OnceLock<Locking> = OnceLock::new();
+
+/// Locks this run holds, by instance, with the client to release them and the value of their tag
+static HELD: Mutex<BTreeMap<String, (aws_sdk_ec2::client::Client, String)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Releases the lock of the instance when dropped, should the action be cancelled or time out
+struct Guard(String);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        let held = HELD.lock().unwrap().remove(&self.0);
+        if let (Some((client, value)), Ok(runtime)) = (held, tokio::runtime::Handle::try_current())
+        {
+            runtime.spawn(remove(client, self.0.clone(), value));
+        }
+    }
+}
+
+/// Locks each instance for the duration of the action from now on
+pub async fn init(aws_config: &SdkConfig, wait: Option<Duration>) -> Result<()> {
+    let owner = format!(
+        "{}#{}",
+        credentials::caller(aws_config).await?,
+        std::process::id()
+    );
+
+    LOCKING
+        .set(Locking { owner, wait })
+        .map_err(|_| eyre!("Locking is already configured"))
+}
+
+/// Runs the action while holding the lock of the instance, if locking is enabled
+///
+/// The lock is refreshed until the action completes, and otherwise expires after a few minutes,
+/// so that a run which died doesn't hold it forever.
+pub async fn hold<T>(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    action: &Action,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let Some(locking) = LOCKING.get() else {
+        return future.await;
+    };
+
+    // The target state is only used when waiting
+    let aws_ec2_client = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        instance_id,
+        InstanceStateName::Running,
+        Duration::from_secs(10),
+    );
+    let value = acquire(&aws_ec2_client, locking, action).await?;
+    HELD.lock()
+        .unwrap()
+        .insert(instance_id.to_string(), (clients::ec2(aws_config), value));
+    let _guard = Guard(instance_id.to_string());
+    let result = tokio::select! {
+        result = future => result,
+        () = refresh(&aws_ec2_client, instance_id, locking, action) => unreachable!(),
+    };
+    let held = HELD.lock().unwrap().remove(instance_id);
+    if let Some((client, value)) = held {
+        remove(client, instance_id.to_string(), value).await;
+    }
+    result
+}
+
+/// Releases the locks still held, before the process exits on a timeout or an interrupt
+pub async fn release_all() {
+    let held = std::mem::take(&mut *HELD.lock().unwrap());
+    for (instance_id, (client, value)) in held {
+        remove(client, instance_id, value).await;
+    }
+}
+
+async fn remove(client: aws_sdk_ec2::client::Client, instance_id: String, value: String) {
+    let aws_ec2_client = AwsEc2Client::new(client, &instance_id, InstanceStateName::Running, POLL);
+    if let Err(e) = aws_ec2_client.remove_tag(LOCK_TAG, &value).await {
+        log::warn("lock", format!("Failed to release the lock: {}", e));
+    }
+}
+
+/// Pushes the expiry of the lock back every so often, never returning
+async fn refresh(
+    aws_ec2_client: &AwsEc2Client,
+    instance_id: &str,
+    locking: &Locking,
+    action: &Action,
+) {
+    loop {
+        sleep(REFRESH).await;
+        let value = tag_value(locking, action);
+        match aws_ec2_client.set_tag(LOCK_TAG, &value).await {
+            Ok(()) => {
+                if let Some(held) = HELD.lock().unwrap().get_mut(instance_id) {
+                    held.1 = value;
+                }
+            }
+            Err(e) => log::warn("lock", format!("Failed to refresh the lock: {}", e)),
+        }
+    }
+}
+
+/// Value of the tag holding the lock from now on
+fn tag_value(locking: &Locking, action: &Action) -> String {
+    let expiry = Utc::now() + chrono::Duration::seconds(LEASE.as_secs() as i64);
+    format!("{} {} until {}", locking.owner, action, expiry.to_rfc3339())
+}
+
+/// Returns the value of the tag once the lock is ours
+async fn acquire(
+    aws_ec2_client: &AwsEc2Client,
+    locking: &Locking,
+    action: &Action,
+) -> Result<String> {
+    let give_up = locking.wait.map(|wait| Instant::now() + wait);
+    loop {
+        let instance = aws_ec2_client.get_instance().await?;
+        if let Some(holder) = instance.tag(LOCK_TAG).filter(|holder| !expired(holder)) {
+            match give_up {
+                Some(give_up) if Instant::now() < give_up => {
+                    log::info("lock", format!("Waiting for the lock held by {}", holder));
+                    sleep(POLL).await;
+                    continue;
+                }
+                _ => {
+                    return Err(eyre!(
+                        "{} is locked by {}, use --lock-wait to wait for it",
+                        instance.instance_id(),
+                        holder
+                    ))
+                }
+            }
+        }
+
+        let value = tag_value(locking, action);
+        aws_ec2_client.set_tag(LOCK_TAG, &value).await?;
+        sleep(SETTLE).await;
+        if aws_ec2_client.get_instance().await?.tag(LOCK_TAG) == Some(value.as_str()) {
+            return Ok(value);
+        }
+    }
+}
+
+fn expired(holder: &str) -> bool {
+    holder
+        .rsplit_once(" until ")
+        .and_then(|(_, expiry)| DateTime::parse_from_rfc3339(expiry).ok())
+        .map_or(true, |expiry| expiry < Utc::now())
+}
@@ -0,0 +1,349 @@
+use chrono::{SecondsFormat, Utc};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde_json::{json, Value};
+use std::fmt::{self, Display};
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime};
+
+/// How progress messages are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    /// Plain messages
+    Human,
+    /// One JSON object per line, with a timestamp, level, phase and fields
+    Json,
+}
+
+/// Where progress messages are written
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogTarget {
+    Stdout,
+    /// For when stdout is kept for the result
+    Stderr,
+    /// The local syslog daemon, through `/dev/log`
+    Syslog,
+    /// The systemd journal, with the phase and instance id as fields
+    Journald,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Level {
+    Info,
+    Warn,
+    Error,
+}
+
+impl Level {
+    /// Syslog severity, as also used by the journal
+    fn severity(&self) -> u8 {
+        match self {
+            Self::Info => 6,
+            Self::Warn => 4,
+            Self::Error => 3,
+        }
+    }
+}
+
+impl Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Padded, so that the human format lines up in the log file
+        match self {
+            Self::Info => f.pad("info"),
+            Self::Warn => f.pad("warn"),
+            Self::Error => f.pad("error"),
+        }
+    }
+}
+
+/// File the messages are also written to, and when to rotate it
+#[derive(Debug, Clone)]
+pub struct LogFileConfig {
+    pub path: PathBuf,
+    pub max_size: Option<u64>,
+    pub max_age: Option<Duration>,
+    /// Number of rotated files to keep
+    pub keep: usize,
+}
+
+struct LogFile {
+    config: LogFileConfig,
+    file: File,
+    size: u64,
+    opened_at: SystemTime,
+}
+
+impl LogFile {
+    fn open(config: LogFileConfig) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        let metadata = file.metadata()?;
+
+        Ok(Self {
+            size: metadata.len(),
+            opened_at: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            config,
+            file,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        if self.must_rotate() {
+            self.rotate()?;
+        }
+
+        writeln!(self.file, "{}", line)?;
+        self.size += line.len() as u64 + 1;
+
+        Ok(())
+    }
+
+    fn must_rotate(&self) -> bool {
+        let too_big = self
+            .config
+            .max_size
+            .is_some_and(|max_size| self.size >= max_size);
+        let too_old = self
+            .config
+            .max_age
+            .is_some_and(|max_age| self.opened_at.elapsed().unwrap_or_default() >= max_age);
+
+        self.size > 0 && (too_big || too_old)
+    }
+
+    /// Renames the file to `<path>.1`, shifting the previous ones and dropping the oldest
+    fn rotate(&mut self) -> Result<()> {
+        let rotated = |index: usize| -> PathBuf {
+            let mut path = self.config.path.clone().into_os_string();
+            path.push(format!(".{}", index));
+            path.into()
+        };
+
+        if self.config.keep == 0 {
+            std::fs::remove_file(&self.config.path)?;
+        } else {
+            remove_if_exists(&rotated(self.config.keep))?;
+            for index in (1..self.config.keep).rev() {
+                if rotated(index).exists() {
+                    std::fs::rename(rotated(index), rotated(index + 1))?;
+                }
+            }
+            std::fs::rename(&self.config.path, rotated(1))?;
+        }
+
+        *self = Self::open(self.config.clone())?;
+        self.opened_at = SystemTime::now();
+
+        Ok(())
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match std::fs::remove_file(path) {
+        Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+        _ => Ok(()),
+    }
+}
+
+enum Sink {
+    Stdout,
+    Stderr,
+    #[cfg(unix)]
+    Syslog(UnixDatagram),
+    #[cfg(unix)]
+    Journald(UnixDatagram),
+}
+
+impl Sink {
+    #[cfg(unix)]
+    fn open(target: LogTarget) -> Result<Self> {
+        let connect = |path: &str| -> Result<UnixDatagram> {
+            let socket = UnixDatagram::unbound()?;
+            socket
+                .connect(path)
+                .map_err(|e| eyre!("Failed to connect to {}: {}", path, e))?;
+            Ok(socket)
+        };
+
+        Ok(match target {
+            LogTarget::Stdout => Self::Stdout,
+            LogTarget::Stderr => Self::Stderr,
+            LogTarget::Syslog => Self::Syslog(connect("/dev/log")?),
+            LogTarget::Journald => Self::Journald(connect("/run/systemd/journal/socket")?),
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn open(target: LogTarget) -> Result<Self> {
+        match target {
+            LogTarget::Stdout => Ok(Self::Stdout),
+            LogTarget::Stderr => Ok(Self::Stderr),
+            _ => Err(eyre!("Logging to {:?} is only supported on Unix", target)),
+        }
+    }
+
+    #[cfg_attr(not(unix), allow(unused_variables))]
+    fn write(&self, level: Level, phase: &str, line: &str) -> Result<()> {
+        match self {
+            Self::Stdout => println!("{}", line),
+            Self::Stderr => eprintln!("{}", line),
+            #[cfg(unix)]
+            Self::Syslog(socket) => {
+                // The user facility
+                let priority = 8 + level.severity();
+                let message = format!(
+                    "<{}>{}[{}]: {}",
+                    priority,
+                    IDENTIFIER,
+                    std::process::id(),
+                    line
+                );
+                socket.send(message.as_bytes())?;
+            }
+            #[cfg(unix)]
+            Self::Journald(socket) => {
+                let mut message = vec![];
+                journal_field(&mut message, "MESSAGE", line);
+                journal_field(&mut message, "PRIORITY", &level.severity().to_string());
+                journal_field(&mut message, "SYSLOG_IDENTIFIER", IDENTIFIER);
+                journal_field(&mut message, "PHASE", phase);
+                if let Ok(instance_id) = INSTANCE_ID.try_with(Clone::clone) {
+                    journal_field(&mut message, "INSTANCE_ID", &instance_id);
+                }
+                socket.send(&message)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Appends a field in the journal's native protocol, which needs a length for multi-line values
+#[cfg(unix)]
+fn journal_field(message: &mut Vec<u8>, name: &str, value: &str) {
+    message.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        message.push(b'\n');
+        message.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        message.push(b'=');
+    }
+    message.extend_from_slice(value.as_bytes());
+    message.push(b'\n');
+}
+
+const IDENTIFIER: &str = "aws-start-stop";
+
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+/// Only show warnings and errors, the log file still getting everything
+static QUIET: OnceLock<bool> = OnceLock::new();
+/// Prefix the human messages with the time, as the log file does
+static TIMESTAMPS: OnceLock<bool> = OnceLock::new();
+static SINK: OnceLock<Sink> = OnceLock::new();
+static LOG_FILE: Mutex<Option<LogFile>> = Mutex::new(None);
+
+tokio::task_local! {
+    /// Instance the current task acts on, added to the JSON messages
+    static INSTANCE_ID: String;
+}
+
+pub fn init(
+    format: LogFormat,
+    target: LogTarget,
+    log_file: Option<LogFileConfig>,
+    quiet: bool,
+    timestamps: bool,
+) -> Result<()> {
+    let _ = FORMAT.set(format);
+    let _ = QUIET.set(quiet);
+    let _ = TIMESTAMPS.set(timestamps);
+    let _ = SINK.set(Sink::open(target)?);
+    if let Some(log_file) = log_file {
+        *LOG_FILE.lock().unwrap() = Some(LogFile::open(log_file)?);
+    }
+
+    Ok(())
+}
+
+pub fn is_json() -> bool {
+    FORMAT.get() == Some(&LogFormat::Json)
+}
+
+/// Runs `future` with its messages tagged with the instance id
+pub async fn with_instance<F: Future>(instance_id: &str, future: F) -> F::Output {
+    INSTANCE_ID.scope(instance_id.to_string(), future).await
+}
+
+/// Instance the current task acts on, if any
+pub fn instance_id() -> Option<String> {
+    INSTANCE_ID.try_with(Clone::clone).ok()
+}
+
+pub fn info(phase: &str, message: impl Display) {
+    event(Level::Info, phase, message, Value::Null);
+}
+
+pub fn warn(phase: &str, message: impl Display) {
+    event(Level::Warn, phase, message, Value::Null);
+}
+
+pub fn error(phase: &str, message: impl Display) {
+    event(Level::Error, phase, message, Value::Null);
+}
+
+/// Writes a message, with `fields` only shown in the JSON format
+///
+/// In the log file, messages in the human format are prefixed with the time and level.
+pub fn event(level: Level, phase: &str, message: impl Display, fields: Value) {
+    let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+
+    let (line, file_line) = if is_json() {
+        let mut line = json!({
+            "timestamp": timestamp,
+            "level": level.to_string(),
+            "phase": phase,
+            "message": message.to_string(),
+        });
+        if let Ok(instance_id) = INSTANCE_ID.try_with(Clone::clone) {
+            line["instance_id"] = Value::String(instance_id);
+        }
+        if let Value::Object(fields) = fields {
+            line["fields"] = Value::Object(fields);
+        }
+        let line = line.to_string();
+        (line.clone(), line)
+    } else {
+        let message = message.to_string();
+        let file_line = format!("{} {:5} {}", timestamp, level, message);
+        let line = if TIMESTAMPS.get() == Some(&true) {
+            format!("{} {}", timestamp, message)
+        } else {
+            message
+        };
+        (line, file_line)
+    };
+
+    if !(matches!(level, Level::Info) && QUIET.get() == Some(&true)) {
+        let result = match SINK.get() {
+            Some(sink) => sink.write(level, phase, &line),
+            None => Sink::Stdout.write(level, phase, &line),
+        };
+        if let Err(e) = result {
+            eprintln!("Failed to write a log message: {}", e);
+        }
+    }
+
+    if let Some(log_file) = LOG_FILE.lock().unwrap().as_mut() {
+        if let Err(e) = log_file.write_line(&file_line) {
+            eprintln!("Failed to write to the log file: {}", e);
+        }
+    }
+}
@@ -1,94 +1,2000 @@
+mod apply;
+mod asg;
+mod audit;
+#[cfg(feature = "grpc")]
+mod auth;
 mod aws;
+mod bell;
+mod bench;
+#[cfg(any(feature = "discord", feature = "slack", feature = "telegram"))]
+mod bot;
+mod budget;
+mod capture;
+mod clients;
+mod color;
 mod config;
+mod credentials;
+mod ctl;
+mod cwlogs;
+mod describe;
+#[cfg(feature = "discord")]
+mod discord;
+mod ecs;
+mod elb;
+mod eni;
+mod error;
+mod eta;
+#[cfg(feature = "test-faults")]
+mod faults;
+#[cfg(all(test, any(feature = "test-faults", feature = "http-replay")))]
+mod fixtures;
+mod fleet;
+mod github;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod health;
+mod hibernation;
+mod history;
+mod hooks;
+mod iam;
+mod idle;
+mod imds;
+mod ingress;
+mod inventory;
+mod k8s;
+#[cfg(feature = "lambda")]
+mod lambda;
+mod launch;
+mod layers;
+mod list;
+mod lock;
+mod log;
+mod metrics;
+mod mfa;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+mod names;
+mod notify;
+mod org;
+mod parameter;
+mod plugins;
+mod porcelain;
+mod preflight;
+mod pricing;
+mod progress;
+mod protection;
+mod proxy;
+mod proxy_command;
+mod query;
+mod ratelimit;
+mod readiness;
+mod regions;
+#[cfg(feature = "http-replay")]
+mod replay;
+mod report;
+mod results;
+mod route53;
+mod schedule;
+mod scheduler_tags;
+mod serial;
+#[cfg(windows)]
+mod service;
+mod settings;
+#[cfg(feature = "slack")]
+mod slack;
+mod ssh;
+mod sso;
+mod stack;
+mod state;
+mod stop_all;
+mod sweep;
+mod systemd;
+#[cfg(feature = "telegram")]
+mod telegram;
+mod telemetry;
+mod template;
+mod terraform;
+mod tunnel;
+mod validate;
+mod wait;
+#[cfg(feature = "wasm")]
+mod wasm;
+#[cfg(feature = "web")]
+mod web;
+mod windows;
 
-use crate::aws::{AwsEc2Client, AwsSsmClient};
-use crate::config::{Action, Config};
-use aws_config::BehaviorVersion;
+use crate::aws::{AwsEc2Client, AwsSsmClient, Instance};
+use crate::config::{Action, Cli, Config, Mode};
+use crate::error::{AwsError, TimedOut};
+use crate::log::Level;
+use crate::readiness::ReadinessCheck;
+use crate::settings::{NotifierSettings, Settings};
+use aws_config::{BehaviorVersion, Region, SdkConfig};
 use aws_sdk_ec2::types::InstanceStateName;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, WrapErr};
 use color_eyre::Result;
+use std::future::Future;
+use std::io::{BufRead, IsTerminal, Write};
+use std::net::SocketAddr;
+use std::path::Path;
 use std::process::exit;
-use tokio::time::{timeout, Duration};
+use std::sync::Arc;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout, Duration};
+
+/// Times starting is tried while AWS lacks capacity for an instance type
+const CAPACITY_ATTEMPTS: u32 = 3;
+/// How often the time left before a delayed action is shown
+const COUNTDOWN_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Delay before trying again, doubled each time
+const CAPACITY_RETRY_DELAY: Duration = Duration::from_secs(10);
+/// Delay before trying the whole action again after a first failure, with `--retries`
+const RETRY_DELAY: Duration = Duration::from_secs(15);
+/// Longest delay between two attempts of the whole action
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+/// How long the console output may take to be captured once the instance is running, on top of
+/// the timeout of the action
+const CONSOLE_OUTPUT_TIMEOUT: Duration = Duration::from_secs(180);
+/// How long the Windows password may take to be available once the instance is running, on top
+/// of the timeout of the action, as Windows usually takes about 4 minutes to generate it
+const PASSWORD_TIMEOUT: Duration = Duration::from_secs(900);
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() -> Result<()> {
     color_eyre::install()?;
-    let config = Config::from_args()?;
 
-    let res = timeout(Duration::from_secs(config.timeout), work(config)).await;
+    #[cfg(feature = "lambda")]
+    if lambda::is_lambda_environment() {
+        return lambda::run().await;
+    }
+
+    let cli = Cli::from_args()?;
+    color::init(cli.no_color);
+    log::init(
+        cli.log_format,
+        cli.log_target,
+        cli.log_file.clone(),
+        cli.quiet,
+        cli.timestamps,
+    )?;
+    progress::init_heartbeat(cli.heartbeat);
+    aws::prefer_ipv6(cli.prefer_ipv6);
+    bell::init(cli.bell, cli.bell_sound.clone());
+    ratelimit::init(cli.max_rps);
+    capture::init(cli.capture_output.as_deref())?;
+    report::init(cli.report.clone());
+    metrics::init(cli.emit_metrics.clone());
+    telemetry::init()?;
+    let settings = Settings::load(cli.config_file.as_deref())?;
+    if !cli.override_protection {
+        protection::init(&settings.protected)?;
+    }
+    if !cli.force_window_override {
+        protection::no_stop_windows(&settings.no_stop_windows)?;
+    }
+    if !cli.ignore_min_runtime {
+        protection::min_runtime(settings.min_runtime.as_deref(), &settings.min_runtimes)?;
+    }
+    protection::require_tags(&[settings.require_tags.clone(), cli.require_tags.clone()].concat())?;
+    let mut loader = aws_config::defaults(BehaviorVersion::latest());
+    if let Some(retry_config) = cli.retry_config.clone() {
+        loader = loader.retry_config(retry_config);
+    }
+    if let Some(command) = &cli.credentials_cmd {
+        loader = loader.credentials_provider(credentials::from_command(command));
+    }
+    #[cfg(feature = "http-replay")]
+    let _recording = replay::SaveOnDrop;
+    #[cfg(feature = "http-replay")]
+    if let Some(client) =
+        replay::http_client(cli.record_http.as_deref(), cli.replay_http.as_deref())?
+    {
+        loader = loader.http_client(client);
+        if cli.replay_http.is_some() {
+            loader = loader.credentials_provider(replay::credentials());
+        }
+    }
+    let mut aws_config = loader.load().await;
+    if cli.mode.uses_aws() {
+        aws_config = match &cli.credentials_cmd {
+            Some(command) => credentials::ensure_command(aws_config, command).await?,
+            None => credentials::ensure(aws_config, cli.sso_login).await?,
+        };
+        let role_arn = match (&cli.account, &cli.account_id) {
+            (Some(account), _) => Some(settings.account_role(account)?),
+            (None, Some(account_id)) => settings.account_id_role(account_id),
+            (None, None) => None,
+        };
+        if let Some(role_arn) = role_arn {
+            aws_config = credentials::assume_role(aws_config, role_arn).await?;
+        }
+        if let Some(region) = &cli.region {
+            aws_config = aws_config
+                .to_builder()
+                .region(Region::new(region.clone()))
+                .build();
+        }
+        if cli.whoami || cli.expect_account.is_some() {
+            let expected_account = cli.expect_account.as_deref();
+            credentials::whoami(&aws_config, cli.whoami, expected_account).await?;
+        }
+        if let Some(path) = &cli.audit_log {
+            audit::init(&aws_config, path).await?;
+        }
+        if cli.lock {
+            lock::init(&aws_config, cli.lock_wait).await?;
+        }
+        notify::init(
+            &aws_config,
+            &settings.notifications,
+            cli.notify_desktop,
+            cli.notify_ntfy.as_deref(),
+            cli.notify_pagerduty.as_deref(),
+        )?;
+        state::init(&aws_config, settings.state.as_deref())?;
+    }
+    if let Some(port) = cli.health_port {
+        health::serve(&aws_config, port).await?;
+    }
+
+    match cli.mode {
+        Mode::Run(config) if config.stack.is_some() => {
+            bell::after(stack::run(&aws_config, config)).await?
+        }
+        Mode::Run(config) if config.fleet.is_some() => {
+            bell::after(fleet::run(&aws_config, config)).await?
+        }
+        Mode::Run(mut config) => {
+            if let Some(name) = &config.instance_name {
+                match names::resolve(&aws_config, name).await? {
+                    Some(instance_id) => config.instance_id = instance_id,
+                    None => {
+                        shutdown();
+                        exit(names::AMBIGUOUS_EXIT_CODE)
+                    }
+                }
+            }
+            if let Some(name) = config.instance_id.strip_prefix(parameter::PREFIX) {
+                config.instance_id = parameter::resolve(&aws_config, name).await?;
+            }
+            if let Some(reference) = config.instance_id.strip_prefix(stack::PREFIX) {
+                config.instance_id = stack::resolve(&aws_config, reference).await?;
+            }
+            let stages = config.stages(&settings)?;
+            if config.search_regions {
+                aws_config = regions::locate(&aws_config, &stages.concat()).await?;
+            }
+            let group = config
+                .instance_id
+                .strip_prefix('@')
+                .zip(state::store())
+                .map(|(group, store)| (group.to_string(), store));
+            if let Some((group, store)) = &group {
+                match store.last_group_action(group).await {
+                    Ok(Some(last)) => log::info(
+                        "state",
+                        format!(
+                            "Last {} of {}: {} by {} at {}",
+                            last.action,
+                            group,
+                            if last.succeeded {
+                                "succeeded"
+                            } else {
+                                "failed"
+                            },
+                            last.by,
+                            last.at.to_rfc3339()
+                        ),
+                    ),
+                    Ok(None) => {}
+                    Err(e) => log::warn("state", format!("Failed to load the state: {}", e)),
+                }
+            }
+            if config.preflight {
+                let policy_config = iam::PolicyConfig {
+                    lock: cli.lock,
+                    metrics: cli.emit_metrics.is_some(),
+                    state: settings
+                        .state
+                        .as_deref()
+                        .is_some_and(|state| state.starts_with("s3://")),
+                    notifications: settings.notifications.iter().any(|notifier| {
+                        matches!(
+                            notifier,
+                            NotifierSettings::Sns { .. } | NotifierSettings::Email { .. }
+                        )
+                    }),
+                    ..iam::PolicyConfig::for_run(&config, &stages.concat())
+                };
+                preflight::check(&aws_config, &config.action, &policy_config).await?;
+            }
+            let action = config.action.clone();
+            let cancel_on_interrupt = config.cancel_on_interrupt;
+            tokio::select! {
+                result = run(&aws_config, config, stages) => {
+                    if let Some((group, store)) = &group {
+                        let recorded = match credentials::caller(&aws_config).await {
+                            Ok(caller) => {
+                                store
+                                    .record_group_action(group, &action, &caller, result.is_ok())
+                                    .await
+                            }
+                            Err(e) => Err(e),
+                        };
+                        if let Err(e) = recorded {
+                            log::warn("state", format!("Failed to save the state: {}", e));
+                        }
+                    }
+                    report::finish(&result);
+                    bell::ring(result.is_ok()).await;
+                    if let Err(e) = &result {
+                        if e.downcast_ref::<results::PartialFailure>().is_some() {
+                            log::error("run", e);
+                            shutdown();
+                            exit(results::PARTIAL_FAILURE_EXIT_CODE)
+                        }
+                    }
+                    result?
+                }
+                _ = tokio::signal::ctrl_c() => {
+                    progress::interrupted(&aws_config, cancel_on_interrupt).await;
+                    lock::release_all().await;
+                    shutdown();
+                    exit(130)
+                }
+            }
+        }
+        Mode::Schedule(command) => schedule::run(&aws_config, command).await?,
+        Mode::PrintIamPolicy(config) => {
+            println!("{}", serde_json::to_string_pretty(&iam::policy(&config))?)
+        }
+        Mode::Status(instance_id, screenshot) => {
+            status(&aws_config, &instance_id, screenshot.as_deref()).await?
+        }
+        Mode::Revoke(instance_id) => ingress::revoke(&aws_config, &instance_id).await?,
+        Mode::ValidateConfig(config) => {
+            validate::run(&aws_config, &settings, cli.config_file.as_deref(), config).await?
+        }
+        Mode::ShowConfig(Some(resolved)) => layers::print_resolved(&resolved),
+        Mode::ShowConfig(None) => layers::print_file(cli.config_file.as_deref())?,
+        Mode::List(config) => list::run(&aws_config, config).await?,
+        Mode::Inventory(config) => inventory::run(&aws_config, config).await?,
+        Mode::ProxyCommand(config) => proxy_command::run(&aws_config, config).await?,
+        Mode::Describe(config) => describe::run(&aws_config, config).await?,
+        Mode::History(config) => history::run(&aws_config, config).await?,
+        Mode::Bench(config) => bench::run(&aws_config, config).await?,
+        Mode::Wait(config) => bell::after(wait::run(&aws_config, config)).await?,
+        Mode::Launch(config) => launch::run(&aws_config, config).await?,
+        Mode::SelfStop(config) => imds::stop(&aws_config, config).await?,
+        #[cfg(windows)]
+        Mode::Service(command) => service::run(command)?,
+        #[cfg(feature = "grpc")]
+        Mode::Serve(config) => grpc::run(&aws_config, &settings, config).await?,
+        #[cfg(feature = "mqtt")]
+        Mode::Mqtt(config) => mqtt::run(&aws_config, config).await?,
+        #[cfg(feature = "discord")]
+        Mode::Discord(config) => discord::run(&aws_config, config).await?,
+        #[cfg(feature = "slack")]
+        Mode::Slack(config) => slack::run(&aws_config, config).await?,
+        #[cfg(feature = "telegram")]
+        Mode::Telegram(config) => telegram::run(&aws_config, config).await?,
+        Mode::Proxy(config) => proxy::run(&aws_config, config).await?,
+        Mode::WatchIdle(config) => idle::run(&aws_config, config).await?,
+        Mode::Org(config) => org::run(&aws_config, config).await?,
+        Mode::StopAll(config) => bell::after(stop_all::run(&aws_config, config)).await?,
+        Mode::Sweep(config) => bell::after(sweep::run(&aws_config, config)).await?,
+        Mode::Apply(config) => bell::after(apply::run(&aws_config, config)).await?,
+        Mode::FromSchedulerTags(config) => {
+            scheduler_tags::run(&aws_config, &settings, cli.config_file.as_deref(), config).await?
+        }
+        Mode::Ctl(config) => ctl::run(config).await?,
+        Mode::Plugins => plugins::print(),
+    }
+
+    shutdown();
+    Ok(())
+}
+
+async fn status(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    screenshot: Option<&Path>,
+) -> Result<()> {
+    // The target state is only used when waiting
+    let aws_ec2_client = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        instance_id,
+        InstanceStateName::Running,
+        Duration::from_secs(10),
+    );
+    let instance = aws_ec2_client.get_instance().await?;
+
+    println!(
+        "Instance {} is {}:",
+        instance_id,
+        color::state(instance.state().as_str())
+    );
+    print_details(&instance);
+    if instance.is_burstable() {
+        match aws_ec2_client.credit_specification().await {
+            Ok(Some(credits)) => println!("\t     CPU credits: {}", credits),
+            Ok(None) => println!("\t     CPU credits: unknown"),
+            Err(e) => println!("\t     CPU credits: unknown, {}", e),
+        }
+    }
+    match aws_ec2_client.scheduled_events().await {
+        Ok(events) if events.is_empty() => println!("\tscheduled events: none"),
+        Ok(events) => {
+            for (index, event) in events.iter().enumerate() {
+                let label = if index == 0 { "scheduled events:" } else { "" };
+                println!("\t{:>17} {}", label, event);
+            }
+        }
+        Err(e) => println!("\tscheduled events: unknown, {}", e),
+    }
+    match hibernation::preflight(&aws_ec2_client, &instance).await {
+        Ok(problems) if problems.is_empty() => println!("\t     hibernation: possible"),
+        Ok(problems) => println!("\t     hibernation: impossible, {}", problems.join(", ")),
+        Err(e) => println!("\t     hibernation: unknown, {}", e),
+    }
+    if let Some(path) = screenshot {
+        save_screenshot(&aws_ec2_client, path).await?;
+    }
+
+    Ok(())
+}
+
+/// Reports the maintenance AWS scheduled for the instance before starting it, suggesting to move it
+/// off hardware which is to be retired
+async fn warn_scheduled_events(aws_ec2_client: &AwsEc2Client) {
+    let events = match aws_ec2_client.scheduled_events().await {
+        Ok(events) => events,
+        Err(e) => {
+            log::warn(
+                "events",
+                format!("Failed to get the scheduled events: {}", e),
+            );
+            return;
+        }
+    };
+    for event in events {
+        if event.is_retirement() {
+            log::warn(
+                "events",
+                format!(
+                    "Scheduled {}. Stop and start the instance before then to move it to other \
+                    hardware",
+                    event
+                ),
+            );
+        } else {
+            log::info("events", format!("Scheduled {}", event));
+        }
+    }
+}
+
+/// Writes a screenshot of the instance's console to the file
+async fn save_screenshot(aws_ec2_client: &AwsEc2Client, path: &Path) -> Result<()> {
+    let image = aws_ec2_client.get_console_screenshot().await?;
+    std::fs::write(path, image).wrap_err_with(|| format!("Failed to write {}", path.display()))?;
+    log::info("screenshot", format!("Wrote {}", path.display()));
+
+    Ok(())
+}
+
+/// Details of the instance, as fields of JSON messages
+fn details(instance: &Instance) -> serde_json::Value {
+    serde_json::json!({
+        "public_ipv4": instance.ipv4_address_public(),
+        "private_ipv4": instance.ipv4_address_private(),
+        "ipv6": instance.ipv6_address(),
+        "public_dns_name": instance.public_dns_name(),
+        "private_dns_name": instance.private_dns_name(),
+        "security_groups": instance.security_groups(),
+        "key_name": instance.key_name(),
+        "lifecycle": instance.lifecycle(),
+        "capacity_reservation_id": instance.capacity_reservation_id(),
+        "tenancy": instance.tenancy(),
+    })
+}
+
+fn print_details(instance: &Instance) {
+    let ip = |ip: Option<&str>| ip.map_or("None".to_string(), color::ip);
+    println!("\t     public IPv4: {}", ip(instance.ipv4_address_public()));
+    println!(
+        "\t    private IPv4: {}",
+        ip(instance.ipv4_address_private())
+    );
+    println!("\t            IPv6: {}", ip(instance.ipv6_address()));
+    println!(
+        "\t      public DNS: {}",
+        instance.public_dns_name().unwrap_or("None")
+    );
+    println!(
+        "\t     private DNS: {}",
+        instance.private_dns_name().unwrap_or("None")
+    );
+    println!(
+        "\t security groups: {}",
+        instance.security_groups().join(", ")
+    );
+    println!(
+        "\t        key pair: {}",
+        instance.key_name().unwrap_or("None")
+    );
+    println!("\t       lifecycle: {}", instance.lifecycle());
+    println!(
+        "\t     reservation: {}",
+        instance.capacity_reservation_id().unwrap_or("None")
+    );
+    println!("\t         tenancy: {}", instance.tenancy());
+}
+
+/// Prints the instance as `KEY=VALUE` lines, which need no quoting as none of the values can
+/// contain spaces
+fn print_env(instance: &Instance) {
+    println!("INSTANCE_ID={}", instance.instance_id());
+    println!("STATE={}", instance.state().as_str());
+    println!(
+        "PUBLIC_IP={}",
+        instance.ipv4_address_public().unwrap_or_default()
+    );
+    println!(
+        "PRIVATE_IP={}",
+        instance.ipv4_address_private().unwrap_or_default()
+    );
+    println!("IPV6={}", instance.ipv6_address().unwrap_or_default());
+}
+
+async fn run(aws_config: &SdkConfig, config: Config, stages: Vec<Vec<String>>) -> Result<()> {
+    report::targets(&config.action, &stages.concat());
+    if config.action == Action::Stop && !config.yes && std::io::stdin().is_terminal() {
+        confirm_stop(aws_config, &stages.concat()).await?;
+    }
+    if let Some(budget_name) = &config.budget_guard {
+        if config.action == Action::Start {
+            let (max_percent, force) = (config.budget_max_percent, config.force_budget);
+            budget::guard(aws_config, budget_name, max_percent, force).await?;
+        }
+    }
+    if config.action == Action::Start && config.show_cost {
+        show_cost(aws_config, &config, &stages.concat()).await?;
+    }
+    if let Some(at) = config.at {
+        if config.detach {
+            for instance_id in stages.concat() {
+                let config = config.for_instance(&instance_id);
+                schedule::schedule_once(aws_config, &config, &config.action, at).await?;
+                if let Some(stop_after) = config.stop_after {
+                    let stop_at = at + chrono::Duration::from_std(stop_after)?;
+                    schedule::schedule_once(aws_config, &config, &Action::Stop, stop_at).await?;
+                }
+            }
+            return Ok(());
+        }
+        wait_until(&config.action, at).await;
+    }
+    let stopped = if config.rollback_on_failure && config.action == Action::Start {
+        Some(stopped_instances(aws_config, &stages.concat()).await?)
+    } else {
+        None
+    };
+    let instances = match (
+        run_stages(aws_config, &config, &stages, &config.action).await,
+        stopped,
+    ) {
+        (Err(e), Some(stopped)) => {
+            rollback(aws_config, &config, &stages, &stopped).await;
+            return Err(e);
+        }
+        (result, _) => result?,
+    };
+    if let Some(query) = &config.query {
+        let instances: Vec<serde_json::Value> =
+            instances.iter().map(describe::description).collect();
+        let result = serde_json::json!({
+            "action": config.action.to_string(),
+            "instances": instances,
+        });
+        query::print(query, &result)?;
+    }
+    if config.terraform_external {
+        let instance = instances
+            .first()
+            .ok_or_else(|| eyre!("No instance was acted on"))?;
+        terraform::print_result(instance)?;
+    }
+    if config.env_output {
+        for instance in &instances {
+            print_env(instance);
+        }
+    }
+
+    if let Some(stop_after) = config.stop_after {
+        if config.detach {
+            for instance_id in stages.concat() {
+                run_or_exit(
+                    "schedule stop",
+                    config.timeout,
+                    schedule::schedule_stop(
+                        aws_config,
+                        &config.for_instance(&instance_id),
+                        stop_after,
+                    ),
+                )
+                .await;
+            }
+        } else {
+            log::info(
+                "wait",
+                format!(
+                    "Instance will be stopped in {}",
+                    humantime::format_duration(stop_after)
+                ),
+            );
+            sleep(stop_after).await;
+            run_stages(aws_config, &config, &stages, &Action::Stop).await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Shows the on-demand hourly price of the instances, and what `--for` costs, asking whether to
+/// start them when it is above `--confirm-above`
+async fn show_cost(aws_config: &SdkConfig, config: &Config, instance_ids: &[String]) -> Result<()> {
+    let region = aws_config
+        .region()
+        .ok_or_else(|| eyre!("No region to get the prices of"))?
+        .to_string();
+    let mut total = 0.0;
+    for instance_id in instance_ids {
+        // The target state is only used when waiting
+        let aws_ec2_client = AwsEc2Client::new(
+            clients::ec2(aws_config),
+            instance_id,
+            InstanceStateName::Running,
+            Duration::from_secs(10),
+        );
+        let instance = aws_ec2_client.get_instance().await?;
+        let price = pricing::hourly_price(
+            aws_config,
+            &region,
+            instance.instance_type(),
+            instance.is_windows(),
+        )
+        .await?
+        .ok_or_else(|| eyre!("No price found for {}", instance.instance_type()))?;
+        total += price;
+        println!(
+            "{} ({}) is a {}: ${:.3}/h",
+            instance_id,
+            instance.name().unwrap_or("no name"),
+            instance.instance_type(),
+            price
+        );
+    }
+    if instance_ids.len() > 1 {
+        println!("Total: ${:.3}/h", total);
+    }
+    if let Some(stop_after) = config.stop_after {
+        println!(
+            "For {}: ${:.2}",
+            humantime::format_duration(stop_after),
+            total * stop_after.as_secs_f64() / 3600.0
+        );
+    }
+
+    let Some(threshold) = config.confirm_above.filter(|threshold| total > *threshold) else {
+        return Ok(());
+    };
+    if config.yes {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre!(
+            "The instances cost ${:.3}/h, above ${}/h, use --yes to start them anyway",
+            total,
+            threshold
+        ));
+    }
+    print!("Start at ${:.3}/h? [y/N] ", total);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        return Err(eyre!("Not starting"));
+    }
+
+    Ok(())
+}
+
+/// Instances which aren't running nor starting, i.e. those a failed start must stop again
+async fn stopped_instances(aws_config: &SdkConfig, instance_ids: &[String]) -> Result<Vec<String>> {
+    let mut stopped = vec![];
+    for instance_id in instance_ids {
+        // The target state is only used when waiting
+        let aws_ec2_client = AwsEc2Client::new(
+            clients::ec2(aws_config),
+            instance_id,
+            InstanceStateName::Running,
+            Duration::from_secs(10),
+        );
+        let instance = aws_ec2_client.get_instance().await?;
+        if !matches!(
+            instance.state(),
+            InstanceStateName::Running | InstanceStateName::Pending
+        ) {
+            stopped.push(instance_id.clone());
+        }
+    }
+
+    Ok(stopped)
+}
+
+/// Stops the instances which were stopped before a failed start, whether they were started or
+/// failed to get ready, so that the group isn't left half started
+async fn rollback(
+    aws_config: &SdkConfig,
+    config: &Config,
+    stages: &[Vec<String>],
+    stopped: &[String],
+) {
+    let stages: Vec<Vec<String>> = stages
+        .iter()
+        .map(|stage| {
+            stage
+                .iter()
+                .filter(|instance_id| stopped.contains(*instance_id))
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .filter(|stage| !stage.is_empty())
+        .collect();
+    if stages.is_empty() {
+        return;
+    }
+
+    log::warn(
+        "rollback",
+        format!(
+            "Stopping {} instance(s) again, as the start failed",
+            stages.concat().len()
+        ),
+    );
+    if let Err(e) = run_stages(aws_config, config, &stages, &Action::Stop).await {
+        log::warn("rollback", format!("Failed to roll back: {}", e));
+    }
+}
+
+/// Waits for the time of a delayed action, showing how long is left
+async fn wait_until(action: &Action, at: DateTime<Utc>) {
+    loop {
+        let left = (at - Utc::now()).to_std().unwrap_or_default();
+        if left.is_zero() {
+            return;
+        }
+        log::info(
+            "wait",
+            format!(
+                "Will {} the instance in {}",
+                action,
+                humantime::format_duration(Duration::from_secs(left.as_secs()))
+            ),
+        );
+        sleep(left.min(COUNTDOWN_INTERVAL)).await;
+    }
+}
+
+/// Shows what the instances are and asks whether to stop them, failing if not
+async fn confirm_stop(aws_config: &SdkConfig, instance_ids: &[String]) -> Result<()> {
+    for instance_id in instance_ids {
+        // The target state is only used when waiting
+        let aws_ec2_client = AwsEc2Client::new(
+            clients::ec2(aws_config),
+            instance_id,
+            InstanceStateName::Stopped,
+            Duration::from_secs(10),
+        );
+        let instance = aws_ec2_client.get_instance().await?;
+        let uptime = instance
+            .launch_time()
+            .and_then(|launch_time| launch_time.elapsed().ok())
+            .map(|uptime| {
+                humantime::format_duration(Duration::from_secs(uptime.as_secs() / 60 * 60))
+            });
+
+        println!(
+            "{} ({}) is {}",
+            instance_id,
+            instance.name().unwrap_or("no name"),
+            instance.state().as_str()
+        );
+        if let Some(uptime) = uptime {
+            println!("\t  running for: {}", uptime);
+        }
+        println!(
+            "\t  launched by: {}",
+            instance.launched_by().unwrap_or("unknown")
+        );
+    }
+
+    print!("Stop {} instance(s)? [y/N] ", instance_ids.len());
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+        return Err(eyre!("Not stopping"));
+    }
+
+    Ok(())
+}
+
+/// Runs the action on the instances of each stage at once, waiting for a stage to complete
+/// before starting the next one
+///
+/// Stages are stopped in reverse order, so that instances are stopped before those they depend on.
+/// With a single instance, a failure exits the process. With several, the others are still acted
+/// on, unless `--fail-fast` aborts them, and a table of the outcomes is shown before failing with
+/// all the errors.
+async fn run_stages(
+    aws_config: &SdkConfig,
+    config: &Config,
+    stages: &[Vec<String>],
+    action: &Action,
+) -> Result<Vec<Instance>> {
+    let mut stages: Vec<&Vec<String>> = stages.iter().collect();
+    if *action == Action::Stop {
+        stages.reverse();
+    }
+    let several = stages.iter().map(|stage| stage.len()).sum::<usize>() > 1;
+
+    let mut outcomes = vec![];
+    for (index, stage) in stages.into_iter().enumerate() {
+        if index > 0 {
+            if let Some(stage_delay) = config.stage_delay {
+                sleep(stage_delay).await;
+            }
+        }
+
+        let mut tasks = JoinSet::new();
+        for (position, instance_id) in stage.iter().enumerate() {
+            let aws_config = aws_config.clone();
+            let mut config = config.for_instance(instance_id);
+            let action = action.clone();
+            tasks.spawn(async move {
+                let started = std::time::Instant::now();
+                let timeout = eta::timeout(&aws_config, &config, &action);
+                config.timeout = log::with_instance(&config.instance_id, timeout).await;
+                let description = format!("{} instance", action);
+                // The console output and password are waited for on top of the timeout
+                let extra_secs = [
+                    (
+                        config.console_output_lines.is_some(),
+                        CONSOLE_OUTPUT_TIMEOUT,
+                    ),
+                    (config.windows_password_key.is_some(), PASSWORD_TIMEOUT),
+                ]
+                .iter()
+                .filter(|(enabled, _)| *enabled)
+                .map(|(_, timeout)| timeout.as_secs())
+                .sum::<u64>();
+                let attempt_secs =
+                    config.timeout + config.wait_timeout.unwrap_or_default() + extra_secs;
+                // Each attempt has the whole timeout, the delays between them on top
+                let timeout_secs = (1..=config.retries).fold(attempt_secs, |total, attempt| {
+                    total + attempt_secs + retry_delay(attempt).as_secs()
+                });
+                let future = telemetry::span(
+                    &action.to_string(),
+                    &config.instance_id,
+                    work_with_retries(&aws_config, &config, &action, attempt_secs),
+                );
+                let result = if several {
+                    let future = run_or_fail(&description, timeout_secs, future);
+                    log::with_instance(&config.instance_id, future).await
+                } else {
+                    let future = run_or_exit(&description, timeout_secs, future);
+                    Ok(log::with_instance(&config.instance_id, future).await)
+                };
+                if let (Some(format), Ok(instance)) = (&config.format, &result) {
+                    println!("{}", format.render(instance));
+                }
+                let outcome = results::Outcome {
+                    instance_id: config.instance_id.clone(),
+                    action,
+                    result,
+                    duration: started.elapsed(),
+                };
+                (position, outcome)
+            });
+        }
+        let stage_started = std::time::Instant::now();
+        let mut stage_outcomes: Vec<Option<results::Outcome>> =
+            stage.iter().map(|_| None).collect();
+        while let Some(joined) = tasks.join_next().await {
+            let (position, outcome) = match joined {
+                Ok(joined) => joined,
+                Err(e) if e.is_cancelled() => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if config.fail_fast && outcome.result.is_err() {
+                tasks.abort_all();
+            }
+            stage_outcomes[position] = Some(outcome);
+        }
+        // Those missing were aborted by --fail-fast
+        let aborted = stage
+            .iter()
+            .zip(stage_outcomes)
+            .map(|(instance_id, outcome)| {
+                outcome.unwrap_or_else(|| results::Outcome {
+                    instance_id: instance_id.clone(),
+                    action: action.clone(),
+                    result: Err(eyre!("Aborted, as another instance failed")),
+                    duration: stage_started.elapsed(),
+                })
+            });
+        outcomes.extend(aborted);
+        // The next stages depend on this one, --fail-fast only cutting this one short
+        if outcomes.iter().any(|outcome| outcome.result.is_err()) {
+            log::warn(
+                "stages",
+                "Not acting on the next stages, as an instance failed",
+            );
+            break;
+        }
+    }
+
+    if several {
+        results::print(&outcomes, config.stdout_reserved);
+    }
+    if let Some(version) = config.porcelain {
+        porcelain::print(version, &outcomes);
+    }
+    results::check(action, &outcomes)?;
+
+    Ok(outcomes
+        .into_iter()
+        .filter_map(|outcome| outcome.result.ok())
+        .collect())
+}
+
+/// Starts the instance, retrying with a backoff while AWS lacks capacity for its type, then
+/// switching to the fallback types in turn
+async fn start_with_fallbacks(
+    config: &Config,
+    aws_ec2_client: &AwsEc2Client,
+) -> Result<InstanceStateName> {
+    let mut fallbacks = config.fallback_instance_types.iter();
+    let mut attempts = 0;
+    loop {
+        let error = match aws_ec2_client.start_instance().await {
+            Err(e)
+                if e.downcast_ref::<AwsError>()
+                    .is_some_and(AwsError::is_capacity) =>
+            {
+                e
+            }
+            result => return result,
+        };
+
+        attempts += 1;
+        if attempts < CAPACITY_ATTEMPTS {
+            let delay = CAPACITY_RETRY_DELAY * 2u32.pow(attempts - 1);
+            log::warn(
+                "capacity",
+                format!("{}, retrying in {}s", error, delay.as_secs()),
+            );
+            sleep(delay).await;
+            continue;
+        }
+
+        let Some(instance_type) = fallbacks.next() else {
+            return Err(error);
+        };
+        log::warn(
+            "capacity",
+            format!("No capacity left, switching to {}", instance_type),
+        );
+        aws_ec2_client.set_instance_type(instance_type).await?;
+        attempts = 0;
+    }
+}
+
+/// Runs the pre-stop script on the instance, failing unless told to ignore its failure
+async fn pre_stop(
+    aws_config: &SdkConfig,
+    config: &Config,
+    aws_ec2_client: &AwsEc2Client,
+    script: &str,
+) -> Result<()> {
+    let document = if aws_ec2_client.get_instance().await?.is_windows() {
+        "AWS-RunPowerShellScript"
+    } else {
+        "AWS-RunShellScript"
+    };
+    let aws_ssm_client = AwsSsmClient {
+        client: clients::ssm(aws_config),
+        instance_id: config.instance_id.clone(),
+        wait: Duration::from_secs(5),
+    };
+
+    log::info("pre-stop", "Running pre-stop command...");
+    let result = aws_ssm_client.run_command(document, script).await?;
+    if result.success {
+        return Ok(());
+    }
+
+    let message = format!(
+        "Pre-stop command {}: {}",
+        result.status,
+        result.error.trim_end()
+    );
+    if config.pre_stop_ignore_failure {
+        log::warn("pre-stop", format!("{}, stopping anyway", message));
+        Ok(())
+    } else {
+        Err(eyre!(message))
+    }
+}
+
+/// Runs the warm-up command once the instance is ready, showing how long it took apart from the
+/// readiness checks
+async fn warmup(
+    aws_config: &SdkConfig,
+    config: &Config,
+    instance: &Instance,
+    command: &str,
+) -> Result<()> {
+    let document = if instance.is_windows() {
+        "AWS-RunPowerShellScript"
+    } else {
+        "AWS-RunShellScript"
+    };
+    let aws_ssm_client = AwsSsmClient {
+        client: clients::ssm(aws_config),
+        instance_id: config.instance_id.clone(),
+        wait: Duration::from_secs(5),
+    };
+
+    log::info("warmup", "Running warm-up command...");
+    let started = std::time::Instant::now();
+    let result = aws_ssm_client.run_command(document, command).await?;
+    let elapsed = started.elapsed().as_secs_f64();
+    if !result.success {
+        return Err(eyre!(
+            "Warm-up command {} after {:.1}s: {}",
+            result.status,
+            elapsed,
+            result.error.trim_end()
+        ));
+    }
+    log::info("warmup", format!("Warm-up done in {:.1}s", elapsed));
+
+    Ok(())
+}
+
+/// Waits for cloud-init to have run the user data, failing if it reported errors
+async fn wait_for_cloud_init(aws_config: &SdkConfig, config: &Config) -> Result<()> {
+    let aws_ssm_client = AwsSsmClient {
+        client: clients::ssm(aws_config),
+        instance_id: config.instance_id.clone(),
+        wait: Duration::from_secs(5),
+    };
+
+    log::info("cloud-init", "Waiting for cloud-init to finish...");
+    let result = aws_ssm_client
+        .run_command("AWS-RunShellScript", "cloud-init status --wait")
+        .await?;
+    if !result.success {
+        return Err(eyre!(
+            "cloud-init {}: {}",
+            result.status,
+            result.output.trim_end()
+        ));
+    }
+    log::info("cloud-init", "cloud-init has finished");
+
+    Ok(())
+}
+
+/// Checks that the systemd units given with `--check-service` are active
+async fn check_services(aws_config: &SdkConfig, config: &Config) -> Result<()> {
+    let aws_ssm_client = AwsSsmClient {
+        client: clients::ssm(aws_config),
+        instance_id: config.instance_id.clone(),
+        wait: Duration::from_secs(5),
+    };
+    let units: Vec<String> = config
+        .check_services
+        .iter()
+        .map(|unit| format!("'{}'", unit.replace('\'', "'\\''")))
+        .collect();
+
+    log::info("services", "Checking services...");
+    let result = aws_ssm_client
+        .run_command(
+            "AWS-RunShellScript",
+            &format!("systemctl is-active {}", units.join(" ")),
+        )
+        .await?;
+    // systemctl prints the state of each unit, in order
+    let states: Vec<&str> = result.output.lines().collect();
+    for (index, unit) in config.check_services.iter().enumerate() {
+        let state = states.get(index).copied().unwrap_or("unknown");
+        log::info("services", format!("{} is {}", unit, state));
+    }
+    if !result.success {
+        return Err(eyre!("Some services are not active"));
+    }
+
+    Ok(())
+}
+
+/// Checks that nvidia-smi finds the GPUs, which broken drivers prevent
+async fn check_gpu(aws_config: &SdkConfig, config: &Config) -> Result<()> {
+    let aws_ssm_client = AwsSsmClient {
+        client: clients::ssm(aws_config),
+        instance_id: config.instance_id.clone(),
+        wait: Duration::from_secs(5),
+    };
+
+    log::info("gpu", "Checking GPUs...");
+    let result = aws_ssm_client
+        .run_command(
+            "AWS-RunShellScript",
+            "nvidia-smi --query-gpu=name,driver_version,temperature.gpu --format=csv,noheader",
+        )
+        .await?;
+    if !result.success {
+        return Err(eyre!(
+            "GPU check {}: {}",
+            result.status,
+            result.output.trim_end()
+        ));
+    }
+    for gpu in result.output.lines() {
+        log::info("gpu", gpu);
+    }
+
+    Ok(())
+}
+
+/// Runs the command given with `--wait-for-ssm-check` until it succeeds, e.g. once the instance
+/// has joined its overlay network, or the timeout expires
+async fn wait_for_ssm_check(aws_config: &SdkConfig, config: &Config, command: &str) -> Result<()> {
+    let aws_ssm_client = AwsSsmClient {
+        client: clients::ssm(aws_config),
+        instance_id: config.instance_id.clone(),
+        wait: Duration::from_secs(5),
+    };
+    let deadline = std::time::Instant::now() + Duration::from_secs(config.timeout);
+
+    log::info(
+        "ssm-check",
+        format!("Waiting for `{}` to succeed...", command),
+    );
+    loop {
+        let result = aws_ssm_client
+            .run_command("AWS-RunShellScript", command)
+            .await?;
+        if result.success {
+            log::info("ssm-check", format!("`{}` succeeded", command));
+            return Ok(());
+        }
+        if std::time::Instant::now() >= deadline {
+            return Err(eyre!(
+                "`{}` still fails after {}s: {}",
+                command,
+                config.timeout,
+                result.output.trim_end()
+            ));
+        }
+        tokio::time::sleep(Duration::from_secs(10)).await;
+    }
+}
+
+/// Checks given on the command line for the started instance to be ready
+fn readiness_checks(
+    aws_config: &SdkConfig,
+    config: &Config,
+    instance: &Instance,
+) -> Result<Vec<Arc<dyn ReadinessCheck>>> {
+    let mut checks: Vec<Arc<dyn ReadinessCheck>> = vec![];
+
+    if let Some(command) = &config.ssm_check {
+        let (aws_config, config, command) = (aws_config.clone(), config.clone(), command.clone());
+        checks.push(Arc::new(readiness::Custom::new("ssm-check", move |_| {
+            let (aws_config, config, command) =
+                (aws_config.clone(), config.clone(), command.clone());
+            Box::pin(async move { wait_for_ssm_check(&aws_config, &config, &command).await })
+        })));
+    }
+
+    let payload = serde_json::json!({
+        "instance_id": instance.instance_id(),
+        "public_ipv4": instance.ipv4_address_public(),
+        "private_ipv4": instance.ipv4_address_private(),
+        "ipv6": instance.ipv6_address(),
+    });
+    for name in &config.wait_for_plugins {
+        checks.push(Arc::new(plugins::Readiness {
+            name: name.clone(),
+            payload: payload.clone(),
+        }));
+    }
+    #[cfg(feature = "wasm")]
+    for path in &config.wait_for_wasm {
+        checks.push(Arc::new(wasm::Readiness {
+            path: path.clone(),
+            payload: payload.clone(),
+        }));
+    }
+
+    if config.wait_for_port.is_none() && config.wait_for_http.is_none() {
+        return Ok(checks);
+    }
+    let ip = instance
+        .address()
+        .ok_or_else(|| eyre!("The instance has no IP address to probe"))?;
+
+    if let Some(port) = config.wait_for_port {
+        let address = SocketAddr::new(ip, port);
+        checks.push(Arc::new(readiness::TcpProbe { address }));
+    }
+
+    if let Some(url) = &config.wait_for_http {
+        let url = url.replace("{ip}", &aws::url_host(&ip));
+        checks.push(Arc::new(readiness::HttpProbe { url }));
+    }
+
+    Ok(checks)
+}
+
+/// Creates an AMI of the instance and waits for it to be available
+async fn snapshot_ami(config: &Config, aws_ec2_client: &AwsEc2Client, name: &str) -> Result<()> {
+    let name = name.replace("{instance_id}", &config.instance_id).replace(
+        "{timestamp}",
+        &chrono::Utc::now().format("%Y%m%dT%H%M%SZ").to_string(),
+    );
+
+    log::info("snapshot-ami", format!("Creating AMI {}...", name));
+    let image_id = aws_ec2_client
+        .create_image(&name, config.snapshot_ami_no_reboot)
+        .await?;
+    log::info(
+        "snapshot-ami",
+        format!("Waiting for AMI {} to be available...", image_id),
+    );
+    aws_ec2_client.wait_for_image(&image_id).await?;
+    log::info("snapshot-ami", format!("Created AMI {}", image_id));
+
+    Ok(())
+}
+
+/// Waits for the EBS volumes attached to the started instance to be in use with an ok status
+async fn wait_for_volumes(instance: &Instance, aws_ec2_client: &AwsEc2Client) -> Result<()> {
+    let volume_ids: Vec<String> = instance
+        .volumes()
+        .into_iter()
+        .map(|(_, volume_id)| volume_id)
+        .collect();
+    if volume_ids.is_empty() {
+        return Ok(());
+    }
+    log::info(
+        "volumes",
+        format!("Waiting for {} volumes to be ready...", volume_ids.len()),
+    );
+    aws_ec2_client.wait_for_volumes(&volume_ids).await?;
+    log::info("volumes", "Volumes ready");
+    Ok(())
+}
+
+/// Snapshots the EBS volumes of the instance, waiting for the snapshots if asked to
+async fn snapshot_volumes(config: &Config, aws_ec2_client: &AwsEc2Client) -> Result<()> {
+    let instance = aws_ec2_client.get_instance().await?;
+    let mut snapshot_ids = vec![];
+    for (device_name, volume_id) in instance.volumes() {
+        let snapshot_id = aws_ec2_client
+            .create_snapshot(&device_name, &volume_id)
+            .await?;
+        log::info(
+            "snapshot-volumes",
+            format!(
+                "Creating snapshot {} of {} ({})",
+                snapshot_id, device_name, volume_id
+            ),
+        );
+        snapshot_ids.push(snapshot_id);
+    }
+
+    if config.wait_for_snapshots && !snapshot_ids.is_empty() {
+        log::info(
+            "snapshot-volumes",
+            "Waiting for the snapshots to be completed...",
+        );
+        aws_ec2_client.wait_for_snapshots(&snapshot_ids).await?;
+        log::info("snapshot-volumes", "Snapshots completed");
+    }
+
+    Ok(())
+}
+
+/// Shows how long each phase of the action took, e.g. to tell what slows the boot down
+fn print_timings(timings: &[(String, Duration)], total: Duration) {
+    let phases: Vec<String> = timings
+        .iter()
+        .map(|(phase, duration)| format!("{} {:.1}s", phase, duration.as_secs_f64()))
+        .collect();
+    let fields: Vec<serde_json::Value> = timings
+        .iter()
+        .map(|(phase, duration)| {
+            serde_json::json!({"phase": phase, "seconds": duration.as_secs_f64()})
+        })
+        .collect();
+    log::event(
+        Level::Info,
+        "timings",
+        format!(
+            "Timings: {}{}total {:.1}s",
+            phases.join(", "),
+            if phases.is_empty() { "" } else { ", " },
+            total.as_secs_f64()
+        ),
+        serde_json::json!({"phases": fields, "total_seconds": total.as_secs_f64()}),
+    );
+}
+
+/// Runs the action between the pre-hook and the post-hook, if any
+///
+/// The post-hook also runs when the action fails, but its own failure is only reported.
+async fn work_with_hooks(
+    aws_config: &SdkConfig,
+    config: &Config,
+    action: &Action,
+) -> Result<Instance> {
+    if let Some(pre_hook) = &config.pre_hook {
+        hooks::run(pre_hook, &config.instance_id, action, None).await?;
+    }
+
+    let result = work(aws_config, config, action).await;
+
+    if let Some(post_hook) = &config.post_hook {
+        if let Err(e) = hooks::run(post_hook, &config.instance_id, action, Some(&result)).await {
+            log::warn("hook", e);
+        }
+    }
+
+    result
+}
+
+/// Runs the action with its hooks within the timeout, trying it all again with a backoff, up to
+/// `--retries` times, while it fails in a way which may not last
+async fn work_with_retries(
+    aws_config: &SdkConfig,
+    config: &Config,
+    action: &Action,
+    timeout_secs: u64,
+) -> Result<Instance> {
+    let attempts = config.retries + 1;
+    let mut attempt = 1;
+    loop {
+        if attempt > 1 {
+            log::info("retry", format!("Attempt {} of {}", attempt, attempts));
+        }
+        let future = work_with_hooks(aws_config, config, action);
+        let result = match timeout(Duration::from_secs(timeout_secs), future).await {
+            Err(_) => Err(TimedOut(timeout_secs).into()),
+            Ok(result) => result,
+        };
+        match result {
+            Err(e) if attempt < attempts && is_retryable(&e) => {
+                let delay = retry_delay(attempt);
+                log::warn(
+                    "retry",
+                    format!(
+                        "Attempt {} of {} failed: {}, retrying in {}s",
+                        attempt,
+                        attempts,
+                        e,
+                        delay.as_secs()
+                    ),
+                );
+                sleep(delay).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Whether the action may succeed when tried again, as it failed for AWS or timed out
+fn is_retryable(error: &color_eyre::Report) -> bool {
+    error.chain().any(|cause| {
+        cause.is::<TimedOut>()
+            || cause
+                .downcast_ref::<AwsError>()
+                .is_some_and(AwsError::is_retryable)
+    })
+}
+
+/// Delay after the attempt failed, doubled each time up to `MAX_RETRY_DELAY`
+fn retry_delay(attempt: u32) -> Duration {
+    RETRY_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt - 1))
+        .min(MAX_RETRY_DELAY)
+}
+
+/// Runs `future` within the given timeout, logging its failure rather than exiting the process
+async fn run_or_fail<T>(
+    description: &str,
+    timeout_secs: u64,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let result = match timeout(Duration::from_secs(timeout_secs), future).await {
+        Err(_) => Err(eyre!("timed out after {}s", timeout_secs)),
+        Ok(result) => result,
+    };
+    if let Err(err) = &result {
+        log::error(description, format!("Failed to {}: {}", description, err));
+    }
+    result
+}
+
+/// Flushes the traces, and the recording of the exchanges with AWS, before the process exits
+fn shutdown() {
+    telemetry::shutdown();
+    #[cfg(feature = "http-replay")]
+    replay::save();
+}
+
+/// Runs `future` within the given timeout, exiting the process if it fails
+async fn run_or_exit<T>(
+    description: &str,
+    timeout_secs: u64,
+    future: impl Future<Output = Result<T>>,
+) -> T {
+    let res = timeout(Duration::from_secs(timeout_secs), future).await;
 
     match res {
         Err(_) => {
-            println!("Failed to start instance: timeout");
+            log::error(
+                description,
+                format!(
+                    "Failed to {}: timed out after {}s",
+                    description, timeout_secs
+                ),
+            );
+            lock::release_all().await;
+            shutdown();
             exit(1)
         }
         Ok(result) => match result {
-            Ok(()) => {}
+            Ok(value) => value,
             Err(err) => {
-                println!("Failed to start instance: {}", err);
+                log::error(description, format!("Failed to {}: {}", description, err));
+                shutdown();
                 exit(2)
             }
         },
     }
+}
 
-    Ok(())
+/// Acts on the instance while holding its lock, recording the outcome to the audit log and
+/// sending it to the notifiers
+async fn work(aws_config: &SdkConfig, config: &Config, action: &Action) -> Result<Instance> {
+    let started = std::time::Instant::now();
+    let result = lock::hold(
+        aws_config,
+        &config.instance_id,
+        action,
+        perform(aws_config, config, action),
+    )
+    .await;
+    let timings = telemetry::take_timings(&config.instance_id);
+    print_timings(&timings, started.elapsed());
+    report::outcome(
+        action,
+        &config.instance_id,
+        &result,
+        started.elapsed(),
+        &timings,
+    );
+    metrics::put(aws_config, action, &config.instance_id, &result, &timings).await;
+    audit::record(action, &config.instance_id, &result, started.elapsed());
+    notify::send(action, &config.instance_id, &result).await;
+    if config.github_output {
+        if let Err(e) = github::report(action, &config.instance_id, &result, started.elapsed()) {
+            log::warn("github", e);
+        }
+    }
+    result
 }
 
-async fn work(config: Config) -> Result<()> {
-    let desired_state = match config.action {
+/// Bounds a request by `--timeout`, so that it isn't mistaken for the instance being slow to
+/// reach the state when a separate `--wait-timeout` is given
+async fn within_timeout<T>(config: &Config, request: impl Future<Output = Result<T>>) -> Result<T> {
+    timeout(Duration::from_secs(config.timeout), request)
+        .await
+        .map_err(|_| {
+            eyre!(
+                "The request didn't complete within the --timeout of {}s",
+                config.timeout
+            )
+        })?
+}
+
+async fn perform(aws_config: &SdkConfig, config: &Config, action: &Action) -> Result<Instance> {
+    let desired_state = match action {
         Action::Stop => InstanceStateName::Stopped,
         Action::Start => InstanceStateName::Running,
     };
-    let aws_config = aws_config::load_defaults(BehaviorVersion::latest()).await;
 
     let aws_ec2_client = AwsEc2Client::new(
-        aws_sdk_ec2::client::Client::new(&aws_config),
+        clients::ec2(aws_config),
         &config.instance_id,
-        desired_state,
+        desired_state.clone(),
         Duration::from_secs(10),
     );
 
-    match config.action {
-        Action::Start => {
-            println!("Starting instance...");
-            aws_ec2_client.start_instance().await?
+    progress::begin(&config.instance_id, action);
+    let standby_group = match action {
+        Action::Start if config.warm_pool => {
+            asg::standby_group(aws_config, &config.instance_id).await?
         }
-        Action::Stop => {
-            println!("Stopping instance...");
-            aws_ec2_client.stop_instance().await?
+        _ => None,
+    };
+    let requested = telemetry::span(
+        "check-state",
+        &config.instance_id,
+        aws_ec2_client.already_requested(),
+    );
+    let state = if let Some(state) = requested.await? {
+        log::info(
+            &action.to_string(),
+            format!("Instance is already {}, waiting", state.as_str()),
+        );
+        state
+    } else {
+        match action {
+            Action::Start => {
+                if protection::requires_tags() {
+                    protection::check_action(action, &aws_ec2_client.get_instance().await?)?;
+                }
+                if let Some(reservation) = &config.capacity_reservation {
+                    log::info(
+                        "capacity",
+                        format!("Targeting capacity reservation {}", reservation),
+                    );
+                    aws_ec2_client.set_capacity_reservation(reservation).await?;
+                }
+                if let Some(credit_spec) = &config.credit_spec {
+                    if aws_ec2_client.get_instance().await?.is_burstable() {
+                        log::info("credits", format!("Setting CPU credits to {}", credit_spec));
+                        aws_ec2_client.set_credit_specification(credit_spec).await?;
+                    } else {
+                        log::warn(
+                            "credits",
+                            "Not a burstable instance, ignoring --credit-spec",
+                        );
+                    }
+                }
+                warn_scheduled_events(&aws_ec2_client).await;
+                log::info("start", "Starting instance...");
+                let request = telemetry::span(
+                    "api-call",
+                    &config.instance_id,
+                    start_with_fallbacks(config, &aws_ec2_client),
+                );
+                within_timeout(config, request).await?
+            }
+            Action::Stop => {
+                let instance = aws_ec2_client.get_instance().await?;
+                match protection::check_action(action, &instance) {
+                    Err(e) if config.wait_min_runtime => {
+                        let Some(too_soon) = e.downcast_ref::<protection::TooSoon>() else {
+                            return Err(e);
+                        };
+                        log::info(
+                            "stop",
+                            format!(
+                                "Waiting {} for the minimum runtime of {}",
+                                humantime::format_duration(Duration::from_secs(
+                                    too_soon.left.as_secs()
+                                )),
+                                humantime::format_duration(too_soon.min_runtime)
+                            ),
+                        );
+                        sleep(too_soon.left).await;
+                    }
+                    result => result?,
+                }
+                if let Some(script) = &config.pre_stop_ssm {
+                    let future = pre_stop(aws_config, config, &aws_ec2_client, script);
+                    telemetry::span("pre-stop", &config.instance_id, future).await?;
+                }
+                if let Some(name) = &config.snapshot_ami {
+                    let future = snapshot_ami(config, &aws_ec2_client, name);
+                    telemetry::span("snapshot-ami", &config.instance_id, future).await?;
+                }
+                if config.snapshot_volumes {
+                    let future = snapshot_volumes(config, &aws_ec2_client);
+                    telemetry::span("snapshot-volumes", &config.instance_id, future).await?;
+                }
+                if let Some(target_group_arn) = &config.register_target_group {
+                    let instance = aws_ec2_client.get_instance().await?;
+                    let future = elb::deregister(aws_config, &instance, target_group_arn);
+                    telemetry::span("target-group", &config.instance_id, future).await?;
+                }
+                if config.revoke_my_ip {
+                    let future = ingress::revoke(aws_config, &config.instance_id);
+                    if let Err(e) = telemetry::span("ingress", &config.instance_id, future).await {
+                        log::warn("ingress", format!("Failed to remove the rules: {}", e));
+                    }
+                }
+                if let Some(eni_id) = &config.attach_eni {
+                    let future = eni::detach(aws_config, &config.instance_id, eni_id);
+                    telemetry::span("eni", &config.instance_id, future).await?;
+                }
+                if let Some(health_check_id) = &config.health_check_id {
+                    let future = route53::set_health_check(aws_config, health_check_id, true);
+                    telemetry::span("health-check", &config.instance_id, future).await?;
+                }
+                if config.drain_k8s {
+                    let instance = aws_ec2_client.get_instance().await?;
+                    match k8s::node_name(&instance) {
+                        Some(node) => {
+                            let future = k8s::drain(
+                                aws_config,
+                                &config.instance_id,
+                                node,
+                                config.kubeconfig.as_deref(),
+                                config.timeout,
+                            );
+                            telemetry::span("k8s", &config.instance_id, future).await?;
+                        }
+                        None => log::info("k8s", "Not an EKS node, nothing to drain"),
+                    }
+                }
+                if let Some(cap) = config.drain_ecs {
+                    let future = ecs::drain(aws_config, &config.instance_id, cap);
+                    telemetry::span("ecs", &config.instance_id, future).await?;
+                }
+                if config.warm_pool {
+                    let future = asg::leave_service(aws_config, &config.instance_id);
+                    if telemetry::span("asg", &config.instance_id, future).await? {
+                        // The group has already stopped the instance, or keeps it running
+                        progress::finish(&config.instance_id);
+                        return aws_ec2_client.get_instance().await;
+                    }
+                }
+                log::info("stop", "Stopping instance...");
+                let request = telemetry::span(
+                    "api-call",
+                    &config.instance_id,
+                    aws_ec2_client.stop_instance(),
+                );
+                within_timeout(config, request).await?
+            }
         }
     };
 
-    let instance = aws_ec2_client.wait_for_state().await?;
+    progress::requested(&config.instance_id);
+    let tail = match (action, &config.tail_logs) {
+        (Action::Start, Some(log_group)) => {
+            Some(cwlogs::tail(aws_config, log_group, &config.instance_id))
+        }
+        _ => None,
+    };
+
+    if config.no_wait {
+        log::info(
+            &action.to_string(),
+            format!("Instance is {}, not waiting", state.as_str()),
+        );
+        progress::finish(&config.instance_id);
+        return aws_ec2_client.get_instance().await;
+    }
+
+    let wait = telemetry::span(
+        "wait-state",
+        &config.instance_id,
+        eta::wait(
+            &config.instance_id,
+            action,
+            aws_ec2_client.wait_for_state(Some(&state)),
+        ),
+    );
+    let instance = match config.wait_timeout {
+        Some(wait_timeout) => timeout(Duration::from_secs(wait_timeout), wait)
+            .await
+            .map_err(|_| {
+                eyre!(
+                    "The instance isn't {} after the --wait-timeout of {}s",
+                    desired_state.as_str(),
+                    wait_timeout
+                )
+            })??,
+        None => wait.await?,
+    };
+    progress::finish(&config.instance_id);
+
+    if let Some(group) = &standby_group {
+        let future = asg::enter_service(aws_config, &config.instance_id, group);
+        telemetry::span("asg", &config.instance_id, future).await?;
+    }
+
+    if *action == Action::Start {
+        if let Some(eni_id) = &config.attach_eni {
+            let future = eni::attach(aws_config, &instance, eni_id);
+            telemetry::span("eni", &config.instance_id, future).await?;
+        }
+
+        if config.wait_for_volumes {
+            let future = wait_for_volumes(&instance, &aws_ec2_client);
+            telemetry::span("volumes", &config.instance_id, future).await?;
+        }
 
-    if config.action == Action::Start {
         if config.wait_for_ssm {
-            println!("Waiting for connection to SSM...");
-            let aws_ssm_client = AwsSsmClient {
-                client: aws_sdk_ssm::client::Client::new(&aws_config),
-                instance_id: config.instance_id,
-                wait: Duration::from_secs(10),
-            };
-            if let Err(e) = aws_ssm_client.wait_for_connection().await {
-                println!("Failed to retrieve SSM connection status: {}", e);
+            log::info("ssm", "Waiting for connection to SSM...");
+            let check = readiness::SsmConnected::new(aws_config);
+            let connected = telemetry::span(
+                "wait-ssm",
+                &config.instance_id,
+                check.wait(&config.instance_id),
+            );
+            if let Err(e) = connected.await {
+                log::warn(
+                    "ssm",
+                    format!("Failed to retrieve SSM connection status: {}", e),
+                );
             }
         }
 
-        println!("Started instance:");
-        println!(
-            "\t public IPv4: {}",
-            instance.ipv4_address_public().unwrap_or("None")
-        );
-        println!(
-            "\tprivate IPv4: {}",
-            instance.ipv4_address_private().unwrap_or("None")
-        );
-        println!(
-            "\t        IPv6: {}",
-            instance.ipv6_address().unwrap_or("None")
+        if config.wait_for_cloud_init {
+            let future = wait_for_cloud_init(aws_config, config);
+            telemetry::span("cloud-init", &config.instance_id, future).await?;
+        }
+
+        if !config.check_services.is_empty() {
+            let future = check_services(aws_config, config);
+            telemetry::span("check-services", &config.instance_id, future).await?;
+        }
+
+        let checks = readiness_checks(aws_config, config, &instance)?;
+        let order = if config.parallel_checks {
+            readiness::Order::Parallel
+        } else {
+            readiness::Order::Sequential
+        };
+        readiness::wait_for_ready(&checks, &config.instance_id, order).await?;
+        drop(tail);
+
+        if config.check_gpu {
+            let future = check_gpu(aws_config, config);
+            match telemetry::span("check-gpu", &config.instance_id, future).await {
+                Err(e) if config.gpu_warn_only => log::warn("gpu", e),
+                result => result?,
+            }
+        }
+
+        if let Some(command) = &config.warmup {
+            let future = warmup(aws_config, config, &instance, command);
+            match telemetry::span("warmup", &config.instance_id, future).await {
+                Err(e) if config.warmup_ignore_failure => log::warn("warmup", e),
+                result => result?,
+            }
+        }
+
+        if config.drain_ecs.is_some() {
+            let future = ecs::activate(aws_config, &config.instance_id);
+            telemetry::span("ecs", &config.instance_id, future).await?;
+        }
+
+        if let Some(node) = k8s::node_name(&instance).filter(|_| config.drain_k8s) {
+            let future = k8s::uncordon(
+                aws_config,
+                &config.instance_id,
+                node,
+                config.kubeconfig.as_deref(),
+            );
+            telemetry::span("k8s", &config.instance_id, future).await?;
+        }
+
+        if let Some(target_group_arn) = &config.register_target_group {
+            let future = elb::register(aws_config, &instance, target_group_arn);
+            telemetry::span("target-group", &config.instance_id, future).await?;
+        }
+
+        if let Some(target_group_arn) = &config.target_group {
+            let future = elb::wait_healthy(aws_config, &instance, target_group_arn, config.timeout);
+            telemetry::span("target-group", &config.instance_id, future).await?;
+        }
+
+        if let Some(health_check_id) = &config.health_check_id {
+            let future = route53::set_health_check(aws_config, health_check_id, false);
+            telemetry::span("health-check", &config.instance_id, future).await?;
+        }
+
+        if let Some(port) = config.authorize_my_ip {
+            let future = ingress::authorize(aws_config, &instance, port);
+            if let Err(e) = telemetry::span("ingress", &config.instance_id, future).await {
+                log::warn(
+                    "ingress",
+                    format!("Failed to allow this machine's IP: {}", e),
+                );
+            }
+        }
+
+        log::event(
+            Level::Info,
+            "started",
+            "Started instance",
+            details(&instance),
         );
+        if !log::is_json()
+            && config.format.is_none()
+            && config.query.is_none()
+            && !config.stdout_reserved
+        {
+            print_details(&instance);
+        }
+
+        if instance.is_windows() {
+            if let Some(ip) = instance.address() {
+                let address = SocketAddr::new(ip, windows::RDP_PORT);
+                println!("Remote Desktop:");
+                println!("\t address: {}", address);
+                println!("\tusername: {}", windows::ADMINISTRATOR);
+                if let Some(rdp_file) = &config.rdp_file {
+                    match std::fs::write(rdp_file, windows::rdp_file_contents(&address)) {
+                        Ok(()) => log::info("rdp", format!("Wrote {}", rdp_file.display())),
+                        Err(e) => log::warn(
+                            "rdp",
+                            format!("Failed to write {}: {}", rdp_file.display(), e),
+                        ),
+                    }
+                }
+            }
+        }
+
+        if let Some(idle_alarm) = &config.idle_alarm {
+            match idle::install_alarm(aws_config, &config.instance_id, idle_alarm).await {
+                Ok(name) => log::info(
+                    "idle-alarm",
+                    format!("Instance will be stopped when idle by alarm {}", name),
+                ),
+                Err(e) => log::warn(
+                    "idle-alarm",
+                    format!("Failed to create the idle alarm: {}", e),
+                ),
+            }
+        }
+
+        if let Some(ssh_config) = &config.ssh_config {
+            match ssh::write_config(ssh_config, &instance) {
+                Ok(()) => log::info("ssh", format!("Updated {}", ssh_config.path.display())),
+                Err(e) => log::warn("ssh", format!("Failed to update the ssh config: {}", e)),
+            }
+        }
+
+        if config.print_host_keys || config.known_hosts.is_some() {
+            log::info(
+                "ssh",
+                "Waiting for the SSH host keys in the console output...",
+            );
+            let output = telemetry::span(
+                "host-keys",
+                &config.instance_id,
+                aws_ec2_client.wait_for_console_output(ssh::HOST_KEYS_END),
+            );
+            match output.await {
+                Ok(output) => {
+                    let host_keys = ssh::parse_host_keys(&output);
+                    if config.print_host_keys {
+                        println!("SSH host keys:");
+                        for key in &host_keys {
+                            println!("\t{}", key);
+                        }
+                    }
+                    if let Some(known_hosts) = &config.known_hosts {
+                        match ssh::update_known_hosts(known_hosts, &instance, &host_keys) {
+                            Ok(()) => {
+                                log::info("ssh", format!("Updated {}", known_hosts.display()))
+                            }
+                            Err(e) => log::warn(
+                                "ssh",
+                                format!("Failed to update {}: {}", known_hosts.display(), e),
+                            ),
+                        }
+                    }
+                }
+                Err(e) => log::warn(
+                    "ssh",
+                    format!("Failed to retrieve the SSH host keys: {}", e),
+                ),
+            }
+        }
+
+        if let Some(lines) = config.console_output_lines {
+            // Any line, as EC2 only captures the output some time after the instance is running
+            let output = timeout(
+                CONSOLE_OUTPUT_TIMEOUT,
+                aws_ec2_client.wait_for_console_output("\n"),
+            );
+            match output.await {
+                Ok(Ok(output)) => {
+                    let output: Vec<&str> = output.lines().collect();
+                    println!("Console output:");
+                    for line in &output[output.len().saturating_sub(lines)..] {
+                        println!("\t{}", line);
+                    }
+                }
+                Ok(Err(e)) => log::warn(
+                    "console",
+                    format!("Failed to retrieve console output: {}", e),
+                ),
+                Err(_) => log::info(
+                    "console",
+                    format!(
+                        "No console output captured after {}s",
+                        CONSOLE_OUTPUT_TIMEOUT.as_secs()
+                    ),
+                ),
+            }
+        }
+
+        if config.windows_password_key.is_some() && !instance.is_windows() {
+            log::warn(
+                "password",
+                "Not a Windows instance, ignoring --get-windows-password",
+            );
+        }
+        let key_file = config
+            .windows_password_key
+            .as_ref()
+            .filter(|_| instance.is_windows());
+        if let Some(key_file) = key_file {
+            log::info("password", "Waiting for the Windows password...");
+            let password = timeout(PASSWORD_TIMEOUT, aws_ec2_client.wait_for_password_data())
+                .await
+                .unwrap_or_else(|_| {
+                    Err(eyre!(
+                        "it isn't available after {}s",
+                        PASSWORD_TIMEOUT.as_secs()
+                    ))
+                })
+                .and_then(|data| windows::decrypt_password(key_file, &data));
+            match password {
+                Ok(password) => println!("Administrator password: {}", password),
+                Err(e) => log::warn(
+                    "password",
+                    format!("Failed to retrieve the Windows password: {}", e),
+                ),
+            }
+        }
+
+        if let Some(path) = &config.screenshot {
+            if let Err(e) = save_screenshot(&aws_ec2_client, path).await {
+                log::warn("screenshot", format!("Failed to take a screenshot: {}", e));
+            }
+        }
+
+        if let Some(serial_console) = config.serial_console {
+            if let Err(e) = serial::connect(aws_config, &instance, serial_console).await {
+                log::warn(
+                    "serial",
+                    format!("Failed to connect to the serial console: {}", e),
+                );
+            }
+        }
     } else {
-        println!("stopped instance");
+        log::info("stopped", "stopped instance");
     }
 
-    Ok(())
+    Ok(instance)
 }
@@ -0,0 +1,75 @@
+use crate::aws::{AwsCloudWatchClient, Instance};
+use crate::clients;
+use crate::config::Action;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_cloudwatch::types::StandardUnit;
+use color_eyre::Result;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Namespace the metrics are put into when `--emit-metrics` is given without one
+pub const DEFAULT_NAMESPACE: &str = "AwsStartStop";
+
+/// Namespace the metrics are put into, if any
+static NAMESPACE: OnceLock<String> = OnceLock::new();
+
+pub fn init(namespace: Option<String>) {
+    if let Some(namespace) = namespace {
+        let _ = NAMESPACE.set(namespace);
+    }
+}
+
+/// Puts the metrics of the action on the instance into CloudWatch, when asked to, dimensioned
+/// by its id
+///
+/// `TimeToRunning` and `TimeToSsmConnected` are measured from the start request, and only put
+/// when the instance started. `ActionFailures` is 1 when the action failed and 0 otherwise, so
+/// that its sum counts the failures. Failing to put them is only reported.
+pub async fn put(
+    aws_config: &SdkConfig,
+    action: &Action,
+    instance_id: &str,
+    result: &Result<Instance>,
+    timings: &[(String, Duration)],
+) {
+    let Some(namespace) = NAMESPACE.get() else {
+        return;
+    };
+
+    let mut data = vec![(
+        "ActionFailures",
+        if result.is_ok() { 0.0 } else { 1.0 },
+        StandardUnit::Count,
+    )];
+    if *action == Action::Start && result.is_ok() {
+        for (metric, phase) in [
+            ("TimeToRunning", "wait-state"),
+            ("TimeToSsmConnected", "wait-ssm"),
+        ] {
+            if let Some(duration) = since_request(timings, phase) {
+                data.push((metric, duration.as_secs_f64(), StandardUnit::Seconds));
+            }
+        }
+    }
+
+    let client = AwsCloudWatchClient {
+        client: clients::cloudwatch(aws_config),
+        instance_id: instance_id.to_string(),
+    };
+    if let Err(e) = client.put_metrics(namespace, &data).await {
+        log::warn("metrics", format!("Failed to put the metrics: {}", e));
+    }
+}
+
+/// Time from the request to the end of the phase, the phases of an action following each other
+fn since_request(timings: &[(String, Duration)], phase: &str) -> Option<Duration> {
+    let request = timings.iter().position(|(name, _)| name == "api-call")?;
+    let end = timings.iter().rposition(|(name, _)| name == phase)?;
+    (end >= request).then(|| {
+        timings[request..=end]
+            .iter()
+            .map(|(_, duration)| *duration)
+            .sum()
+    })
+}
@@ -0,0 +1,194 @@
+use crate::clients;
+use crate::credentials;
+use crate::error::Hint;
+use aws_config::identity::IdentityCache;
+use aws_config::sts::AssumeRoleProvider;
+use aws_config::{BehaviorVersion, SdkConfig};
+use aws_credential_types::provider::error::CredentialsError;
+use aws_credential_types::provider::{future, ProvideCredentials, SharedCredentialsProvider};
+use aws_credential_types::Credentials;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::io::{BufRead, IsTerminal, Write};
+use std::time::{Duration, SystemTime};
+use tokio::sync::Mutex;
+
+/// How long a session got with an MFA code lasts, before a new code is asked for
+const SESSION_DURATION: i32 = 12 * 3600;
+/// How long before the session expires a new code is asked for
+const EXPIRY_MARGIN: Duration = Duration::from_secs(300);
+/// How long answering the prompt for a code may take, the SDK otherwise giving up on loading the
+/// credentials after a few seconds
+const PROMPT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Settings of a profile requiring MFA
+pub struct MfaProfile {
+    serial_number: String,
+    role: Option<RoleSettings>,
+}
+
+struct RoleSettings {
+    role_arn: String,
+    source_profile: Option<String>,
+    session_name: Option<String>,
+    external_id: Option<String>,
+}
+
+impl MfaProfile {
+    /// Reads the selected profile, returning `None` if it has no `mfa_serial`
+    pub async fn load() -> Result<Option<Self>> {
+        let profiles = credentials::load_profiles().await?;
+        let Some(profile) = profiles.get_profile(profiles.selected_profile()) else {
+            return Ok(None);
+        };
+        let Some(serial_number) = profile.get("mfa_serial") else {
+            return Ok(None);
+        };
+
+        let role = profile.get("role_arn").map(|role_arn| RoleSettings {
+            role_arn: role_arn.to_string(),
+            source_profile: profile.get("source_profile").map(str::to_string),
+            session_name: profile.get("role_session_name").map(str::to_string),
+            external_id: profile.get("external_id").map(str::to_string),
+        });
+
+        Ok(Some(Self {
+            serial_number: serial_number.to_string(),
+            role,
+        }))
+    }
+}
+
+/// Session got with an MFA code, got again with a new code once it expires
+#[derive(Debug)]
+struct MfaSession {
+    client: aws_sdk_sts::client::Client,
+    serial_number: String,
+    credentials: Mutex<Option<Credentials>>,
+}
+
+impl MfaSession {
+    async fn credentials(&self) -> Result<Credentials> {
+        let mut cached = self.credentials.lock().await;
+        let valid = cached.as_ref().filter(|credentials| {
+            credentials
+                .expiry()
+                .is_some_and(|expiry| expiry > SystemTime::now() + EXPIRY_MARGIN)
+        });
+        if let Some(credentials) = valid {
+            return Ok(credentials.clone());
+        }
+
+        let token_code = prompt_code(&self.serial_number)?;
+        let credentials = self
+            .client
+            .get_session_token()
+            .serial_number(&self.serial_number)
+            .token_code(&token_code)
+            .duration_seconds(SESSION_DURATION)
+            .send()
+            .await
+            .hint("sts:GetSessionToken")?
+            .credentials
+            .ok_or_else(|| eyre!("STS returned no credentials"))?;
+        let credentials = Credentials::new(
+            credentials.access_key_id(),
+            credentials.secret_access_key(),
+            Some(credentials.session_token().to_string()),
+            Some(SystemTime::try_from(*credentials.expiration())?),
+            "mfa",
+        );
+        *cached = Some(credentials.clone());
+        Ok(credentials)
+    }
+}
+
+impl ProvideCredentials for MfaSession {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(async move {
+            self.credentials()
+                .await
+                .map_err(CredentialsError::provider_error)
+        })
+    }
+}
+
+/// Prompts for an MFA code and returns a configuration using the resulting session, asking for a
+/// new code once it expires
+///
+/// Role profiles are assumed from the session, which the SDK can't do by itself, and assumed again
+/// as long as it lasts. Other profiles use the session itself.
+pub async fn authenticate(aws_config: &SdkConfig, profile: &MfaProfile) -> Result<SdkConfig> {
+    let source_profile = profile
+        .role
+        .as_ref()
+        .and_then(|role| role.source_profile.as_ref());
+    let source_config = match source_profile {
+        Some(source_profile) => {
+            let source = aws_config::defaults(BehaviorVersion::latest())
+                .profile_name(source_profile)
+                .load()
+                .await;
+            let provider = source
+                .credentials_provider()
+                .ok_or_else(|| eyre!("No credentials for the profile {}", source_profile))?;
+            aws_config
+                .to_builder()
+                .credentials_provider(provider)
+                .build()
+        }
+        None => aws_config.clone(),
+    };
+    let session = MfaSession {
+        client: clients::sts(&source_config),
+        serial_number: profile.serial_number.clone(),
+        credentials: Mutex::new(None),
+    };
+    // Prompt now, rather than on the first call
+    session.credentials().await?;
+
+    let provider = match &profile.role {
+        Some(role) => {
+            let mut builder = AssumeRoleProvider::builder(&role.role_arn)
+                .session_name(role.session_name.as_deref().unwrap_or("aws-start-stop"))
+                .configure(aws_config);
+            if let Some(external_id) = &role.external_id {
+                builder = builder.external_id(external_id);
+            }
+            SharedCredentialsProvider::new(builder.build_from_provider(session).await)
+        }
+        None => SharedCredentialsProvider::new(session),
+    };
+    provider.provide_credentials().await?;
+
+    Ok(aws_config
+        .to_builder()
+        .credentials_provider(provider)
+        .identity_cache(IdentityCache::lazy().load_timeout(PROMPT_TIMEOUT).build())
+        .build())
+}
+
+fn prompt_code(serial_number: &str) -> Result<String> {
+    // Non-interactive runs, e.g. as a daemon, would otherwise wait for the code forever
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre!(
+            "An MFA code for {} is needed, but can't be asked for as the input isn't a terminal",
+            serial_number
+        ));
+    }
+    print!("MFA code for {}: ", serial_number);
+    std::io::stdout().flush()?;
+
+    let mut code = String::new();
+    std::io::stdin().lock().read_line(&mut code)?;
+    let code = code.trim();
+
+    if code.len() != 6 || !code.chars().all(|c| c.is_ascii_digit()) {
+        return Err(eyre!("The MFA code must be 6 digits"));
+    }
+
+    Ok(code.to_string())
+}
@@ -0,0 +1,144 @@
+use crate::aws::AwsEc2Client;
+use crate::clients;
+use crate::config::{Action, Config};
+use crate::log;
+use crate::work;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use color_eyre::Result;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS, Transport};
+use std::path::PathBuf;
+use tokio::time::{timeout, Duration};
+
+#[derive(Debug)]
+pub struct MqttConfig {
+    pub instance_id: String,
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    /// Topic whose messages trigger the action
+    pub topic: String,
+    /// Topic the result of the action is published to
+    pub response_topic: Option<String>,
+    /// CA certificate, client certificate and key, for TLS as required by AWS IoT Core
+    pub ca_file: Option<PathBuf>,
+    pub cert_file: Option<PathBuf>,
+    pub key_file: Option<PathBuf>,
+    pub timeout: u64,
+}
+
+/// Acts on the instance whenever a message is published to the topic
+///
+/// A message of `start` or `stop` runs that action, any other message, such as the JSON an IoT
+/// button sends, starts the instance if it is stopped and stops it otherwise.
+pub async fn run(aws_config: &SdkConfig, config: MqttConfig) -> Result<()> {
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    if let Some(ca_file) = &config.ca_file {
+        let client_auth = match (&config.cert_file, &config.key_file) {
+            (Some(cert_file), Some(key_file)) => {
+                Some((std::fs::read(cert_file)?, std::fs::read(key_file)?))
+            }
+            _ => None,
+        };
+        options.set_transport(Transport::tls(std::fs::read(ca_file)?, client_auth, None));
+    }
+
+    let (client, mut event_loop) = AsyncClient::new(options, 10);
+    client.subscribe(&config.topic, QoS::AtLeastOnce).await?;
+    log::info("mqtt", format!("Waiting for messages on {}", config.topic));
+
+    loop {
+        let Event::Incoming(Packet::Publish(publish)) = event_loop.poll().await? else {
+            continue;
+        };
+        let message = String::from_utf8_lossy(&publish.payload)
+            .trim()
+            .to_lowercase();
+        log::info("mqtt", format!("Received {:?}", message));
+
+        // The event loop must keep running while the action completes
+        let aws_config = aws_config.clone();
+        let client = client.clone();
+        let response_topic = config.response_topic.clone();
+        let instance_id = config.instance_id.clone();
+        let timeout_secs = config.timeout;
+        tokio::spawn(async move {
+            let response = act(&aws_config, &instance_id, &message, timeout_secs).await;
+            if let Some(response_topic) = response_topic {
+                let payload = response.to_string();
+                if let Err(e) = client
+                    .publish(response_topic, QoS::AtLeastOnce, false, payload)
+                    .await
+                {
+                    log::warn("mqtt", format!("Failed to publish the result: {}", e));
+                }
+            }
+        });
+    }
+}
+
+/// Runs the action the message asks for, returning the result to publish
+async fn act(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    message: &str,
+    timeout_secs: u64,
+) -> serde_json::Value {
+    let action = match message {
+        "start" => Ok(Action::Start),
+        "stop" => Ok(Action::Stop),
+        _ => toggle(aws_config, instance_id).await,
+    };
+    let result = match action {
+        Ok(action) => {
+            let mut config = Config::new(action.clone(), instance_id.to_string());
+            config.timeout = timeout_secs;
+            let future = timeout(
+                Duration::from_secs(timeout_secs),
+                work(aws_config, &config, &action),
+            );
+            match log::with_instance(instance_id, future).await {
+                Ok(result) => result.map(|instance| (action, instance)),
+                Err(_) => Err(color_eyre::eyre::eyre!(
+                    "Timed out waiting for the instance"
+                )),
+            }
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok((action, instance)) => serde_json::json!({
+            "instance_id": instance_id,
+            "action": action.to_string(),
+            "result": "success",
+            "state": instance.state().as_str(),
+            "public_ipv4": instance.ipv4_address_public(),
+            "private_ipv4": instance.ipv4_address_private(),
+        }),
+        Err(e) => {
+            log::error("mqtt", format!("Failed to act on {}: {}", instance_id, e));
+            serde_json::json!({
+                "instance_id": instance_id,
+                "result": "failure",
+                "error": e.to_string(),
+            })
+        }
+    }
+}
+
+/// Starts the instance if it is stopped, and stops it otherwise
+async fn toggle(aws_config: &SdkConfig, instance_id: &str) -> Result<Action> {
+    // The target state is only used when waiting
+    let aws_ec2_client = AwsEc2Client::new(
+        clients::ec2(aws_config),
+        instance_id,
+        InstanceStateName::Running,
+        Duration::from_secs(10),
+    );
+    Ok(match aws_ec2_client.get_instance().await?.state() {
+        InstanceStateName::Stopped | InstanceStateName::Stopping => Action::Start,
+        _ => Action::Stop,
+    })
+}
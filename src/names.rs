@@ -0,0 +1,183 @@
+use crate::aws::{self, AwsEc2InstancesClient, Instance};
+use crate::clients;
+use crate::color;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::Filter;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::io::{BufRead, IsTerminal, Write};
+
+/// Exit code when several instances have the name, and none could be picked
+pub const AMBIGUOUS_EXIT_CODE: i32 = 3;
+
+/// Finds the id of the instance with this Name tag
+///
+/// When several instances have it, the user picks one on a terminal. Otherwise they are printed
+/// and `None` is returned.
+pub async fn resolve(aws_config: &SdkConfig, name: &str) -> Result<Option<String>> {
+    let client = AwsEc2InstancesClient::new(clients::ec2(aws_config));
+    let instances = client
+        .describe_instances(vec![aws::tag_filter("Name", name), live_filter()])
+        .await?;
+
+    match instances.as_slice() {
+        [] => suggest(&client, name).await,
+        [instance] => Ok(Some(instance.instance_id().to_string())),
+        _ => {
+            println!("Several instances are named {}:", name);
+            print_table(&instances);
+            if is_interactive() {
+                pick(&instances).map(Some)
+            } else {
+                Ok(None)
+            }
+        }
+    }
+}
+
+/// Offers the instances with names close to the one given, when none has it
+///
+/// Names are close when equal but for the case, when one contains the other, or when they are a
+/// few edits apart. Without a terminal, they are only suggested in the error.
+async fn suggest(client: &AwsEc2InstancesClient, name: &str) -> Result<Option<String>> {
+    let filters = vec![
+        Filter::builder().name("tag-key").values("Name").build(),
+        live_filter(),
+    ];
+    let mut candidates: Vec<(usize, Instance)> = client
+        .describe_instances(filters)
+        .await?
+        .into_iter()
+        .filter_map(|instance| Some((closeness(name, instance.name()?)?, instance)))
+        .collect();
+    candidates.sort_by_key(|(distance, _)| *distance);
+    let instances: Vec<Instance> = candidates
+        .into_iter()
+        .map(|(_, instance)| instance)
+        .collect();
+
+    if instances.is_empty() {
+        return Err(eyre!("No instance is named {}", name));
+    }
+    if !is_interactive() {
+        let names: Vec<&str> = instances.iter().filter_map(Instance::name).collect();
+        return Err(eyre!(
+            "No instance is named {}, did you mean {}?",
+            name,
+            names.join(", ")
+        ));
+    }
+    println!("No instance is named {}, but these are close:", name);
+    print_table(&instances);
+    pick(&instances).map(Some)
+}
+
+/// How far the names are apart, when close enough to suggest one for the other
+fn closeness(wanted: &str, name: &str) -> Option<usize> {
+    let (wanted, name) = (wanted.to_lowercase(), name.to_lowercase());
+    if wanted == name {
+        return Some(0);
+    }
+    if name.contains(&wanted) || wanted.contains(&name) {
+        return Some(1);
+    }
+    let distance = levenshtein(&wanted, &name);
+    (distance <= (wanted.chars().count() / 4).max(2)).then_some(distance + 1)
+}
+
+/// Number of characters to insert, remove or replace to go from one string to the other
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// Instances which haven't been terminated
+fn live_filter() -> Filter {
+    Filter::builder()
+        .name("instance-state-name")
+        .values("pending")
+        .values("running")
+        .values("stopping")
+        .values("stopped")
+        .build()
+}
+
+fn is_interactive() -> bool {
+    std::io::stdin().is_terminal() && std::io::stdout().is_terminal()
+}
+
+fn print_table(instances: &[Instance]) {
+    let mut rows = vec![[
+        "#".to_string(),
+        "ID".to_string(),
+        "NAME".to_string(),
+        "STATE".to_string(),
+        "AZ".to_string(),
+        "LAUNCHED".to_string(),
+    ]];
+    rows.extend(instances.iter().enumerate().map(|(index, instance)| {
+        [
+            (index + 1).to_string(),
+            instance.instance_id().to_string(),
+            instance.name().unwrap_or("-").to_string(),
+            instance.state().as_str().to_string(),
+            instance.availability_zone().unwrap_or("-").to_string(),
+            instance
+                .launch_time()
+                .map(|launch_time| {
+                    DateTime::<Utc>::from(launch_time)
+                        .format("%Y-%m-%d %H:%M")
+                        .to_string()
+                })
+                .unwrap_or_else(|| "-".to_string()),
+        ]
+    }));
+
+    let mut widths = [0; 6];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    for (index, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                // Padded apart, as the color codes don't take any room
+                let padding = " ".repeat(width - cell.len());
+                match column {
+                    3 if index > 0 => color::state(cell) + &padding,
+                    _ => cell.clone() + &padding,
+                }
+            })
+            .collect();
+        println!("{}", cells.join("  ").trim_end());
+    }
+}
+
+/// Asks which of the instances to act on
+fn pick(instances: &[Instance]) -> Result<String> {
+    print!("Instance to use [1-{}]: ", instances.len());
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    answer
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|choice| instances.get(choice.checked_sub(1)?))
+        .map(|instance| instance.instance_id().to_string())
+        .ok_or_else(|| eyre!("No instance picked"))
+}
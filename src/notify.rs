@@ -0,0 +1,381 @@
+use crate::aws::Instance;
+use crate::clients;
+use crate::config::Action;
+use crate::error::Hint;
+use crate::log;
+use crate::plugins;
+use crate::settings::NotifierSettings;
+use aws_config::SdkConfig;
+use aws_sdk_sesv2::types::{Body, Content, Destination, EmailContent, Message};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+
+/// Outcome of an action, as sent to the notifiers
+#[derive(Debug, serde::Serialize)]
+pub struct Notification {
+    pub instance_id: String,
+    pub name: Option<String>,
+    pub action: String,
+    pub succeeded: bool,
+    pub public_ipv4: Option<String>,
+    pub error: Option<String>,
+}
+
+impl Notification {
+    fn new(action: &Action, instance_id: &str, result: &Result<Instance>) -> Self {
+        let instance = result.as_ref().ok();
+        Self {
+            instance_id: instance_id.to_string(),
+            name: instance.and_then(Instance::name).map(str::to_string),
+            action: action.to_string(),
+            succeeded: result.is_ok(),
+            public_ipv4: instance
+                .and_then(Instance::ipv4_address_public)
+                .map(str::to_string),
+            error: result.as_ref().err().map(ToString::to_string),
+        }
+    }
+
+    /// One line describing the outcome, for humans
+    pub fn summary(&self) -> String {
+        let instance = match &self.name {
+            Some(name) => format!("{} ({})", self.instance_id, name),
+            None => self.instance_id.clone(),
+        };
+        let done = if self.action == "stop" {
+            "stopped"
+        } else {
+            "started"
+        };
+        match (&self.error, &self.public_ipv4) {
+            (Some(error), _) => format!("Failed to {} {}: {}", self.action, instance, error),
+            (None, Some(ip)) => format!("{} {}, public IP {}", instance, done, ip),
+            (None, None) => format!("{} {}", instance, done),
+        }
+    }
+}
+
+/// Endpoint of the PagerDuty Events API v2
+const PAGERDUTY_EVENTS_URL: &str = "https://events.pagerduty.com/v2/enqueue";
+/// Longest summary PagerDuty accepts
+const PAGERDUTY_SUMMARY_LENGTH: usize = 1024;
+
+pub type NotifyFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Channel the outcome of the actions is sent to
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a>;
+}
+
+/// Posts the notification as JSON
+struct Webhook {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Notifier for Webhook {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            self.client
+                .post(&self.url)
+                .json(notification)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Posts the summary to a Slack incoming webhook
+struct Slack {
+    client: reqwest::Client,
+    webhook_url: String,
+}
+
+impl Notifier for Slack {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            self.client
+                .post(&self.webhook_url)
+                .json(&serde_json::json!({ "text": notification.summary() }))
+                .send()
+                .await
+                // The URL is the secret
+                .map_err(reqwest::Error::without_url)?
+                .error_for_status()
+                .map_err(reqwest::Error::without_url)?;
+            Ok(())
+        })
+    }
+}
+
+/// Publishes the notification to an SNS topic, as JSON with the summary as subject
+struct Sns {
+    client: aws_sdk_sns::client::Client,
+    topic_arn: String,
+}
+
+impl Notifier for Sns {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            // Subjects are limited to 100 characters
+            let subject: String = notification.summary().chars().take(100).collect();
+            self.client
+                .publish()
+                .topic_arn(&self.topic_arn)
+                .subject(subject)
+                .message(serde_json::to_string(notification)?)
+                .send()
+                .await
+                .hint("sns:Publish")?;
+            Ok(())
+        })
+    }
+}
+
+/// Sends the summary by email through SES
+struct Email {
+    client: aws_sdk_sesv2::client::Client,
+    from: String,
+    to: Vec<String>,
+}
+
+impl Notifier for Email {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let summary = notification.summary();
+            let message = Message::builder()
+                .subject(Content::builder().data(&summary).build()?)
+                .body(
+                    Body::builder()
+                        .text(Content::builder().data(&summary).build()?)
+                        .build(),
+                )
+                .build()?;
+            self.client
+                .send_email()
+                .from_email_address(&self.from)
+                .destination(
+                    Destination::builder()
+                        .set_to_addresses(Some(self.to.clone()))
+                        .build(),
+                )
+                .content(EmailContent::builder().simple(message).build())
+                .send()
+                .await
+                .hint("ses:SendEmail")?;
+            Ok(())
+        })
+    }
+}
+
+/// Publishes the summary to an ntfy topic, for a push notification on the phones subscribed to it
+struct Ntfy {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Notifier for Ntfy {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let (title, tags, priority) = if notification.succeeded {
+                (
+                    format!("{} done", notification.action),
+                    "white_check_mark",
+                    "default",
+                )
+            } else {
+                (format!("{} failed", notification.action), "x", "high")
+            };
+            self.client
+                .post(&self.url)
+                .header("Title", title)
+                .header("Tags", tags)
+                .header("Priority", priority)
+                .body(notification.summary())
+                .send()
+                .await
+                // The topic name is the secret on public servers
+                .map_err(reqwest::Error::without_url)?
+                .error_for_status()
+                .map_err(reqwest::Error::without_url)?;
+            Ok(())
+        })
+    }
+}
+
+/// Triggers a PagerDuty alert when the action fails, and resolves it when the action succeeds
+///
+/// The alerts are deduplicated by action and instance, so that a failing nightly stop stays one
+/// alert until a stop succeeds.
+struct PagerDuty {
+    client: reqwest::Client,
+    routing_key: String,
+}
+
+impl Notifier for PagerDuty {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let dedup_key = format!(
+                "aws-start-stop-{}-{}",
+                notification.action, notification.instance_id
+            );
+            let event = if notification.succeeded {
+                serde_json::json!({
+                    "routing_key": self.routing_key,
+                    "event_action": "resolve",
+                    "dedup_key": dedup_key,
+                })
+            } else {
+                serde_json::json!({
+                    "routing_key": self.routing_key,
+                    "event_action": "trigger",
+                    "dedup_key": dedup_key,
+                    "payload": {
+                        // Longer summaries are refused
+                        "summary": notification
+                            .summary()
+                            .chars()
+                            .take(PAGERDUTY_SUMMARY_LENGTH)
+                            .collect::<String>(),
+                        "source": notification.instance_id,
+                        "severity": "error",
+                        "component": "aws-start-stop",
+                        "custom_details": notification,
+                    },
+                })
+            };
+            self.client
+                .post(PAGERDUTY_EVENTS_URL)
+                .json(&event)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+/// Shows the summary as a native desktop notification
+struct Desktop;
+
+impl Notifier for Desktop {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            let summary = notification.summary();
+            // Showing blocks until the notification server answers
+            tokio::task::spawn_blocking(move || {
+                notify_rust::Notification::new()
+                    .appname("aws-start-stop")
+                    .summary("aws-start-stop")
+                    .body(&summary)
+                    .show()
+                    .map(drop)
+            })
+            .await??;
+            Ok(())
+        })
+    }
+}
+
+/// Sends the notification as JSON to a plugin
+struct Plugin {
+    name: String,
+}
+
+impl Notifier for Plugin {
+    fn notify<'a>(&'a self, notification: &'a Notification) -> NotifyFuture<'a> {
+        Box::pin(async move {
+            plugins::call(&self.name, "notify", &serde_json::to_value(notification)?).await
+        })
+    }
+}
+
+static NOTIFIERS: OnceLock<Vec<Box<dyn Notifier>>> = OnceLock::new();
+
+/// Sends the outcome of the actions to the notifiers of the configuration file from now on, and to
+/// the desktop, ntfy and PagerDuty if asked to on the command line
+pub fn init(
+    aws_config: &SdkConfig,
+    settings: &[NotifierSettings],
+    desktop: bool,
+    ntfy_url: Option<&str>,
+    pagerduty_routing_key: Option<&str>,
+) -> Result<()> {
+    let client = reqwest::Client::new();
+    let mut notifiers: Vec<Box<dyn Notifier>> = settings
+        .iter()
+        .map(|settings| -> Box<dyn Notifier> {
+            match settings {
+                NotifierSettings::Webhook { url } => Box::new(Webhook {
+                    client: client.clone(),
+                    url: url.clone(),
+                }),
+                NotifierSettings::Slack { webhook_url } => Box::new(Slack {
+                    client: client.clone(),
+                    webhook_url: webhook_url.clone(),
+                }),
+                NotifierSettings::Sns { topic_arn } => Box::new(Sns {
+                    client: clients::sns(aws_config),
+                    topic_arn: topic_arn.clone(),
+                }),
+                NotifierSettings::Email { from, to } => Box::new(Email {
+                    client: clients::sesv2(aws_config),
+                    from: from.clone(),
+                    to: to.clone(),
+                }),
+                NotifierSettings::Desktop => Box::new(Desktop),
+                NotifierSettings::Ntfy { url } => Box::new(Ntfy {
+                    client: client.clone(),
+                    url: url.clone(),
+                }),
+                NotifierSettings::PagerDuty { routing_key } => Box::new(PagerDuty {
+                    client: client.clone(),
+                    routing_key: routing_key.clone(),
+                }),
+                NotifierSettings::Plugin { name } => Box::new(Plugin { name: name.clone() }),
+            }
+        })
+        .collect();
+    if desktop
+        && !settings
+            .iter()
+            .any(|settings| matches!(settings, NotifierSettings::Desktop))
+    {
+        notifiers.push(Box::new(Desktop));
+    }
+    if let Some(url) = ntfy_url {
+        notifiers.push(Box::new(Ntfy {
+            client: client.clone(),
+            url: url.to_string(),
+        }));
+    }
+    if let Some(routing_key) = pagerduty_routing_key {
+        notifiers.push(Box::new(PagerDuty {
+            client,
+            routing_key: routing_key.to_string(),
+        }));
+    }
+    NOTIFIERS
+        .set(notifiers)
+        .map_err(|_| eyre!("Notifications are already configured"))
+}
+
+/// Sends the outcome of an action to every notifier, only reporting their failures
+pub async fn send(action: &Action, instance_id: &str, result: &Result<Instance>) {
+    let notifiers = NOTIFIERS.get().map(Vec::as_slice).unwrap_or_default();
+    if notifiers.is_empty() {
+        return;
+    }
+
+    let notification = Notification::new(action, instance_id, result);
+    for notifier in notifiers {
+        if let Err(e) = notifier.notify(&notification).await {
+            log::warn("notify", format!("Failed to send a notification: {}", e));
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use crate::aws::{self, AwsEc2InstancesClient, AwsOrganizationsClient};
+use crate::clients;
+use crate::config::Action;
+use crate::credentials;
+use crate::protection;
+use crate::regions::{self, Regions};
+use aws_config::SdkConfig;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::task::JoinSet;
+
+#[derive(Debug)]
+pub struct OrgConfig {
+    pub action: Action,
+    pub tag_key: String,
+    pub tag_value: String,
+    /// Name of the role assumed in every account
+    pub role_name: String,
+    pub regions: Option<Regions>,
+}
+
+/// Applies the action to the tagged instances of every account of the organization
+pub async fn run(aws_config: &SdkConfig, config: OrgConfig) -> Result<()> {
+    let organizations_client = AwsOrganizationsClient::new(clients::organizations(aws_config));
+    let accounts = organizations_client.list_accounts().await?;
+
+    let mut tasks = JoinSet::new();
+    for (account_id, account_name) in accounts {
+        let aws_config = aws_config.clone();
+        let role_arn = format!("arn:aws:iam::{}:role/{}", account_id, config.role_name);
+        let action = config.action.clone();
+        let (tag_key, tag_value) = (config.tag_key.clone(), config.tag_value.clone());
+        let regions = config.regions.clone();
+        tasks.spawn(async move {
+            let result = act_in_account(
+                aws_config,
+                &role_arn,
+                regions.as_ref(),
+                &action,
+                &tag_key,
+                &tag_value,
+            )
+            .await;
+            (account_id, account_name, result)
+        });
+    }
+
+    let mut failed = false;
+    while let Some(joined) = tasks.join_next().await {
+        let (account_id, account_name, result) = joined?;
+        match result {
+            Ok(instance_ids) if instance_ids.is_empty() => {
+                println!("{}\t{}\tno instances", account_id, account_name)
+            }
+            Ok(instance_ids) => println!(
+                "{}\t{}\t{}: {}",
+                account_id,
+                account_name,
+                config.action,
+                instance_ids.join(", ")
+            ),
+            Err(e) => {
+                failed = true;
+                println!("{}\t{}\tfailed: {}", account_id, account_name, e)
+            }
+        }
+    }
+
+    if failed {
+        return Err(eyre!("Failed in some accounts"));
+    }
+
+    Ok(())
+}
+
+/// Returns the ids of the instances the action was applied to, in all the regions
+async fn act_in_account(
+    aws_config: SdkConfig,
+    role_arn: &str,
+    regions: Option<&Regions>,
+    action: &Action,
+    tag_key: &str,
+    tag_value: &str,
+) -> Result<Vec<String>> {
+    let aws_config = credentials::assume_role(aws_config, role_arn).await?;
+    let results = regions::fan_out(&aws_config, regions, |aws_config| {
+        let (action, tag_key, tag_value) =
+            (action.clone(), tag_key.to_string(), tag_value.to_string());
+        async move { act_in_region(&aws_config, &action, &tag_key, &tag_value).await }
+    })
+    .await?;
+
+    let mut instance_ids = vec![];
+    for (region, result) in results {
+        match result {
+            Ok(ids) => instance_ids.extend(ids),
+            Err(e) => return Err(eyre!("{}: {}", region, e)),
+        }
+    }
+
+    Ok(instance_ids)
+}
+
+async fn act_in_region(
+    aws_config: &SdkConfig,
+    action: &Action,
+    tag_key: &str,
+    tag_value: &str,
+) -> Result<Vec<String>> {
+    let client = AwsEc2InstancesClient::new(clients::ec2(aws_config));
+
+    let instances = client
+        .describe_instances(vec![
+            aws::tag_filter(tag_key, tag_value),
+            aws::actionable_filter(action),
+        ])
+        .await?;
+    let instance_ids = protection::filter("org", action, &instances);
+    client.change_state(action, &instance_ids).await?;
+
+    Ok(instance_ids)
+}
@@ -0,0 +1,31 @@
+use crate::clients;
+use crate::config;
+use crate::error::Hint;
+use aws_config::SdkConfig;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+/// Prefix of instances read from Parameter Store, e.g. `ssm:/dev/webserver/instance-id`
+pub const PREFIX: &str = "ssm:";
+
+/// Reads the id of the instance from the parameter, so that it can change when the instance is
+/// recreated
+pub async fn resolve(aws_config: &SdkConfig, name: &str) -> Result<String> {
+    let response = clients::ssm(aws_config)
+        .get_parameter()
+        .name(name)
+        .with_decryption(true)
+        .send()
+        .await
+        .hint("ssm:GetParameter")?;
+    let value = response
+        .parameter()
+        .and_then(|parameter| parameter.value())
+        .ok_or_else(|| eyre!("Parameter {} has no value", name))?
+        .trim();
+    if value.starts_with('@') || value.contains('/') {
+        return Err(eyre!("Parameter {} must hold the id of an instance", name));
+    }
+
+    config::parse_instance(value).map_err(|e| eyre!("Parameter {}: {}", name, e))
+}
@@ -0,0 +1,145 @@
+use crate::log;
+use crate::readiness::{CheckFuture, ReadinessCheck};
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::{sleep, Duration};
+
+/// Executables named with this prefix on the `PATH` are plugins, named by the rest
+const PREFIX: &str = "aws-start-stop-";
+/// Exit code of a readiness plugin telling the instance isn't ready yet, `EX_TEMPFAIL`
+const NOT_READY_EXIT_CODE: i32 = 75;
+/// How often readiness plugins are run again
+const READINESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Plugins found on the `PATH` by name, the first one found winning as for commands
+pub fn discover() -> BTreeMap<String, PathBuf> {
+    let mut plugins = BTreeMap::new();
+    let Some(path) = std::env::var_os("PATH") else {
+        return plugins;
+    };
+    for directory in std::env::split_paths(&path) {
+        let Ok(entries) = std::fs::read_dir(&directory) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let Some(name) = file_name.to_str().and_then(plugin_name) else {
+                continue;
+            };
+            if is_executable(&entry.path()) {
+                plugins.entry(name.to_string()).or_insert(entry.path());
+            }
+        }
+    }
+    plugins
+}
+
+fn plugin_name(file_name: &str) -> Option<&str> {
+    let name = file_name.strip_prefix(PREFIX)?;
+    #[cfg(windows)]
+    let name = name.strip_suffix(".exe")?;
+    Some(name).filter(|name| !name.is_empty())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &std::path::Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &std::path::Path) -> bool {
+    path.is_file()
+}
+
+/// Prints the plugins found on the `PATH`
+pub fn print() {
+    let plugins = discover();
+    if plugins.is_empty() {
+        println!("No {}<name> executable on the PATH", PREFIX);
+    }
+    for (name, path) in plugins {
+        println!("{}\t{}", name, path.display());
+    }
+}
+
+/// Runs the plugin with the kind of call as argument and the payload as JSON on stdin, returning
+/// its exit code once it succeeded or asked to be called again
+///
+/// Its output is logged, and its error output is given in the error when it fails.
+pub async fn invoke(name: &str, kind: &str, payload: &Value) -> Result<i32> {
+    let path = discover()
+        .remove(name)
+        .ok_or_else(|| eyre!("No plugin {}: {}{} isn't on the PATH", name, PREFIX, name))?;
+    let mut child = Command::new(&path)
+        .arg(kind)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .wrap_err_with(|| format!("Failed to run {}", path.display()))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Plugins which don't need the payload may exit without reading it
+        let _ = stdin.write_all(payload.to_string().as_bytes()).await;
+    }
+    let output = child.wait_with_output().await?;
+
+    let phase = format!("plugin-{}", name);
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        log::info(&phase, line);
+    }
+    match output.status.code() {
+        Some(code) if output.status.success() || code == NOT_READY_EXIT_CODE => Ok(code),
+        _ => Err(eyre!(
+            "Plugin {} failed: {}: {}",
+            name,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim_end()
+        )),
+    }
+}
+
+/// Runs the plugin once, failing unless it exits with 0
+pub async fn call(name: &str, kind: &str, payload: &Value) -> Result<()> {
+    match invoke(name, kind, payload).await? {
+        0 => Ok(()),
+        code => Err(eyre!("Plugin {} failed with exit code {}", name, code)),
+    }
+}
+
+/// Plugin run until it tells the instance is ready, by exiting with 0, or that it won't be, by
+/// failing other than with `NOT_READY_EXIT_CODE`
+pub struct Readiness {
+    pub name: String,
+    /// Sent to the plugin on each run
+    pub payload: Value,
+}
+
+impl ReadinessCheck for Readiness {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn wait<'a>(&'a self, _instance_id: &'a str) -> CheckFuture<'a> {
+        Box::pin(async move {
+            log::info(
+                &self.name,
+                format!("Waiting for plugin {} to report ready...", self.name),
+            );
+            while invoke(&self.name, "readiness", &self.payload).await? != 0 {
+                sleep(READINESS_INTERVAL).await;
+            }
+            log::info(&self.name, format!("Plugin {} reported ready", self.name));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,69 @@
+use crate::aws::Instance;
+use crate::results::Outcome;
+
+/// Versions of the porcelain format, each of which is kept as it is once released
+///
+/// Anything a script may need which isn't in a version goes in a new one, so that those parsing
+/// the older ones keep working.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    /// `# porcelain v1`, then a line per instance with these fields, separated by tabs:
+    ///
+    /// `instance`, the instance id, the action, `ok` or `failed`, the state, the duration in
+    /// milliseconds, the public IPv4, private IPv4 and IPv6 addresses, the name and the error.
+    ///
+    /// Fields with no value are `-`. Tabs and line breaks in the name and error are replaced by
+    /// spaces.
+    V1,
+}
+
+impl Version {
+    /// Parses the value of `--porcelain`, which only accepts released versions
+    pub fn parse(version: &str) -> Option<Self> {
+        match version {
+            "v1" => Some(Self::V1),
+            _ => None,
+        }
+    }
+}
+
+/// Prints the outcomes to stdout in the format of the version, whatever the logging format
+pub fn print(version: Version, outcomes: &[Outcome]) {
+    match version {
+        Version::V1 => {
+            println!("# porcelain v1");
+            for outcome in outcomes {
+                let fields = [
+                    "instance".to_string(),
+                    outcome.instance_id.clone(),
+                    outcome.action.to_string(),
+                    if outcome.result.is_ok() {
+                        "ok"
+                    } else {
+                        "failed"
+                    }
+                    .to_string(),
+                    outcome.state().to_string(),
+                    outcome.duration.as_millis().to_string(),
+                    field(outcome.ip(Instance::ipv4_address_public)),
+                    field(outcome.ip(Instance::ipv4_address_private)),
+                    field(outcome.ip(Instance::ipv6_address)),
+                    field(outcome.name()),
+                    field(outcome.error().as_deref()),
+                ];
+                println!("{}", fields.join("\t"));
+            }
+        }
+    }
+}
+
+fn field(value: Option<&str>) -> String {
+    match value {
+        Some(value) if !value.trim().is_empty() => value
+            .split(['\t', '\r', '\n'])
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => "-".to_string(),
+    }
+}
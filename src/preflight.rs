@@ -0,0 +1,252 @@
+use crate::clients;
+use crate::config::Action;
+use crate::credentials;
+use crate::error::{AwsError, Hint};
+use crate::iam::{self, PolicyConfig};
+use crate::log::{self, Level};
+use aws_config::SdkConfig;
+use aws_sdk_ec2::error::{ProvideErrorMetadata, SdkError};
+use aws_sdk_iam::types::{ContextEntry, ContextKeyTypeEnum, PolicyEvaluationDecisionType};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde_json::Value;
+use std::fmt;
+
+/// Call the caller isn't allowed to make
+struct Denial {
+    action: String,
+    resource: String,
+    decision: String,
+}
+
+/// Fails when the caller isn't allowed to make the calls the run needs, before any instance is
+/// acted on
+///
+/// The statements `print-iam-policy` generates for the features of the run are simulated with
+/// the policies of the caller in IAM, which also takes the permission boundaries and SCPs into
+/// account, so that both agree on what the run needs. When they can't be, e.g. as the caller
+/// isn't allowed to simulate them, starting or stopping the instances is dry run instead, which
+/// only checks that call.
+/// A call being denied halfway through, e.g. after the instance was stopped but before it could be
+/// snapshotted, would otherwise leave it in a state to fix by hand.
+pub async fn check(
+    aws_config: &SdkConfig,
+    action: &Action,
+    policy_config: &PolicyConfig,
+) -> Result<()> {
+    let instance_ids = &policy_config.instance_ids;
+    let denials = match simulate(aws_config, policy_config).await {
+        Ok(denials) => denials,
+        Err(e) => {
+            log::warn(
+                "preflight",
+                format!(
+                    "Failed to simulate the policies, only dry running the {}: {}",
+                    action, e
+                ),
+            );
+            dry_run(aws_config, action, instance_ids).await?
+        }
+    };
+
+    if denials.is_empty() {
+        log::info("preflight", "The calls the run needs are allowed");
+        return Ok(());
+    }
+    let fields: Vec<serde_json::Value> = denials
+        .iter()
+        .map(|denial| {
+            serde_json::json!({
+                "action": denial.action,
+                "resource": denial.resource,
+                "decision": denial.decision,
+            })
+        })
+        .collect();
+    log::event(
+        Level::Error,
+        "preflight",
+        denials
+            .iter()
+            .map(|denial| {
+                format!(
+                    "{} on {} ({})",
+                    denial.action, denial.resource, denial.decision
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", "),
+        serde_json::json!({ "denied": fields }),
+    );
+    Err(eyre!(
+        "The caller isn't allowed to make {} of the calls the run needs, see print-iam-policy",
+        denials.len()
+    ))
+}
+
+/// Strings of a statement's field, which is either one or an array of them
+fn strings(value: &Value) -> Vec<String> {
+    match value {
+        Value::String(string) => vec![string.clone()],
+        Value::Array(values) => values
+            .iter()
+            .filter_map(Value::as_str)
+            .map(str::to_string)
+            .collect(),
+        _ => vec![],
+    }
+}
+
+/// Values the condition of a statement expects of its keys, e.g. the tag keys which may be set,
+/// given to the simulation as the context of the calls
+fn context_entries(condition: &Value) -> Vec<ContextEntry> {
+    let Some(operators) = condition.as_object() else {
+        return vec![];
+    };
+    operators
+        .values()
+        .filter_map(Value::as_object)
+        .flatten()
+        .map(|(key, values)| {
+            ContextEntry::builder()
+                .context_key_name(key)
+                .context_key_type(ContextKeyTypeEnum::StringList)
+                .set_context_key_values(Some(strings(values)))
+                .build()
+        })
+        .collect()
+}
+
+/// Simulates the statements of the policy with the policies of the caller, those on instances on
+/// each of them and the others on any resource
+async fn simulate(aws_config: &SdkConfig, policy_config: &PolicyConfig) -> Result<Vec<Denial>> {
+    let caller = credentials::caller(aws_config).await?;
+    let principal = principal(&caller);
+    let fields: Vec<&str> = caller.split(':').collect();
+    let (partition, account) = match fields[..] {
+        ["arn", partition, _, _, account, ..] => (partition, account),
+        _ => return Err(eyre!("Unexpected caller ARN {}", caller)),
+    };
+    let region = aws_config
+        .region()
+        .ok_or_else(|| eyre!("No region is configured"))?;
+    let instance_arns: Vec<String> = policy_config
+        .instance_ids
+        .iter()
+        .map(|instance_id| {
+            format!(
+                "arn:{}:ec2:{}:{}:instance/{}",
+                partition, region, account, instance_id
+            )
+        })
+        .collect();
+
+    let policy = iam::policy(policy_config);
+    let statements = policy["Statement"].as_array().cloned().unwrap_or_default();
+    let mut denials = vec![];
+    for statement in statements {
+        let on_instances = strings(&statement["Resource"])
+            .iter()
+            .any(|resource| resource.contains(":instance/"));
+        let output = clients::iam(aws_config)
+            .simulate_principal_policy()
+            .policy_source_arn(&principal)
+            .set_action_names(Some(strings(&statement["Action"])))
+            .set_resource_arns(on_instances.then(|| instance_arns.clone()))
+            .set_context_entries(Some(context_entries(&statement["Condition"])))
+            .max_items(1000)
+            .send()
+            .await
+            .hint("iam:SimulatePrincipalPolicy")?;
+        denials.extend(
+            output
+                .evaluation_results()
+                .iter()
+                .filter(|result| *result.eval_decision() != PolicyEvaluationDecisionType::Allowed)
+                .map(|result| Denial {
+                    action: result.eval_action_name().to_string(),
+                    resource: result.eval_resource_name().unwrap_or("*").to_string(),
+                    decision: result.eval_decision().as_str().to_string(),
+                }),
+        );
+    }
+    Ok(denials)
+}
+
+/// ARN of the user or role whose policies apply to the caller, as those of an assumed role
+/// session can't be simulated
+///
+/// The path of the role isn't part of the session's ARN, so the simulation of a role with one
+/// fails, and the calls are dry run instead.
+fn principal(caller: &str) -> String {
+    match caller.split_once(":assumed-role/") {
+        Some((prefix, session)) => {
+            let role = session.split('/').next().unwrap_or(session);
+            format!("{}:role/{}", prefix.replacen(":sts:", ":iam:", 1), role)
+        }
+        None => caller.to_string(),
+    }
+}
+
+/// Dry runs starting or stopping the instances, which EC2 checks the permissions of
+async fn dry_run(
+    aws_config: &SdkConfig,
+    action: &Action,
+    instance_ids: &[String],
+) -> Result<Vec<Denial>> {
+    let client = clients::ec2(aws_config);
+    let ids = Some(instance_ids.to_vec());
+    let (iam_action, allowed) = match action {
+        Action::Start => {
+            let iam_action = "ec2:StartInstances";
+            let result = client
+                .start_instances()
+                .set_instance_ids(ids)
+                .dry_run(true)
+                .send()
+                .await;
+            (iam_action, dry_run_allowed(result, iam_action)?)
+        }
+        Action::Stop => {
+            let iam_action = "ec2:StopInstances";
+            let result = client
+                .stop_instances()
+                .set_instance_ids(ids)
+                .dry_run(true)
+                .send()
+                .await;
+            (iam_action, dry_run_allowed(result, iam_action)?)
+        }
+    };
+
+    if allowed {
+        return Ok(vec![]);
+    }
+    Ok(instance_ids
+        .iter()
+        .map(|instance_id| Denial {
+            action: iam_action.to_string(),
+            resource: instance_id.clone(),
+            decision: "dryRunDenied".to_string(),
+        })
+        .collect())
+}
+
+/// Whether the dry run of the call was allowed, other errors than being denied failing
+fn dry_run_allowed<T, E, R>(
+    result: Result<T, SdkError<E, R>>,
+    iam_action: &str,
+) -> Result<bool, AwsError>
+where
+    E: ProvideErrorMetadata + std::error::Error + 'static,
+    R: fmt::Debug,
+{
+    match result {
+        Ok(_) => Ok(true),
+        Err(error) => match error.code() {
+            Some("DryRunOperation") => Ok(true),
+            Some("UnauthorizedOperation") => Ok(false),
+            _ => Err(AwsError::new(error, iam_action)),
+        },
+    }
+}
@@ -0,0 +1,64 @@
+use crate::clients;
+use crate::error::Hint;
+use aws_config::{Region, SdkConfig};
+use aws_sdk_pricing::types::{Filter, FilterType};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+
+/// Only region the Price List API is available in, for all the others
+const PRICING_REGION: &str = "us-east-1";
+
+/// On-demand hourly price of the instance type in the region, in dollars, from the Price List API
+pub async fn hourly_price(
+    aws_config: &SdkConfig,
+    region: &str,
+    instance_type: &str,
+    windows: bool,
+) -> Result<Option<f64>> {
+    let (operating_system, license_model) = if windows {
+        ("Windows", "License included")
+    } else {
+        ("Linux", "No License required")
+    };
+    let filter = |field: &str, value: &str| {
+        Filter::builder()
+            .r#type(FilterType::TermMatch)
+            .field(field)
+            .value(value)
+            .build()
+    };
+    let pricing_config = aws_config
+        .to_builder()
+        .region(Region::new(PRICING_REGION))
+        .build();
+    let response = clients::pricing(&pricing_config)
+        .get_products()
+        .service_code("AmazonEC2")
+        .filters(filter("instanceType", instance_type)?)
+        .filters(filter("regionCode", region)?)
+        .filters(filter("operatingSystem", operating_system)?)
+        .filters(filter("licenseModel", license_model)?)
+        .filters(filter("tenancy", "Shared")?)
+        .filters(filter("preInstalledSw", "NA")?)
+        .filters(filter("capacitystatus", "Used")?)
+        .max_results(1)
+        .send()
+        .await
+        .hint("pricing:GetProducts")?;
+
+    let Some(product) = response.price_list().first() else {
+        return Ok(None);
+    };
+    let product: serde_json::Value = serde_json::from_str(product)?;
+    // The only on-demand term has a single dimension, the hour
+    let price = product["terms"]["OnDemand"]
+        .as_object()
+        .and_then(|terms| terms.values().next())
+        .and_then(|term| term["priceDimensions"].as_object())
+        .and_then(|dimensions| dimensions.values().next())
+        .and_then(|dimension| dimension["pricePerUnit"]["USD"].as_str())
+        .ok_or_else(|| eyre!("No on-demand price for {}", instance_type))?
+        .parse()?;
+
+    Ok(Some(price))
+}
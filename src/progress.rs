@@ -0,0 +1,162 @@
+use crate::aws::AwsEc2Client;
+use crate::clients;
+use crate::config::Action;
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use std::collections::BTreeMap;
+use std::io::IsTerminal;
+use std::sync::{Mutex, OnceLock};
+use tokio::sync::broadcast;
+use tokio::time::{timeout, Duration};
+
+const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(30);
+
+/// Action in progress on an instance
+struct Progress {
+    action: Action,
+    /// Whether StartInstances or StopInstances was called
+    requested: bool,
+}
+
+static PROGRESS: Mutex<BTreeMap<String, Progress>> = Mutex::new(BTreeMap::new());
+
+/// How often to show that an instance is still being waited for
+static HEARTBEAT: OnceLock<Option<Duration>> = OnceLock::new();
+
+/// Sets the interval of the heartbeat lines, which are off when it is zero
+///
+/// When not given, they are shown every 30 seconds if the output isn't a terminal, as CI jobs
+/// without output for a while may be killed.
+pub fn init_heartbeat(interval: Option<Duration>) {
+    let interval = match interval {
+        Some(interval) => Some(interval).filter(|interval| !interval.is_zero()),
+        None if std::io::stdout().is_terminal() => None,
+        None => Some(DEFAULT_HEARTBEAT),
+    };
+    let _ = HEARTBEAT.set(interval);
+}
+
+pub fn heartbeat_interval() -> Option<Duration> {
+    HEARTBEAT.get().copied().flatten()
+}
+
+/// Shows that the instance is still being waited for
+pub fn heartbeat(state: &InstanceStateName, elapsed: Duration) {
+    log::info(
+        "heartbeat",
+        format!(
+            "still waiting: state={}, elapsed={}s",
+            state.as_str(),
+            elapsed.as_secs()
+        ),
+    );
+}
+
+/// State an instance was seen moving to while waiting for it
+#[derive(Debug, Clone)]
+pub struct StateChange {
+    pub instance_id: String,
+    pub state: InstanceStateName,
+}
+
+static STATE_CHANGES: OnceLock<broadcast::Sender<StateChange>> = OnceLock::new();
+
+fn state_changes() -> &'static broadcast::Sender<StateChange> {
+    STATE_CHANGES.get_or_init(|| broadcast::channel(64).0)
+}
+
+/// Receives the state changes of all instances from now on
+pub fn subscribe() -> broadcast::Receiver<StateChange> {
+    state_changes().subscribe()
+}
+
+pub fn state_changed(instance_id: &str, state: &InstanceStateName) {
+    // Nobody may be listening, which is fine
+    let _ = state_changes().send(StateChange {
+        instance_id: instance_id.to_string(),
+        state: state.clone(),
+    });
+}
+
+pub fn begin(instance_id: &str, action: &Action) {
+    PROGRESS.lock().unwrap().insert(
+        instance_id.to_string(),
+        Progress {
+            action: action.clone(),
+            requested: false,
+        },
+    );
+}
+
+pub fn requested(instance_id: &str) {
+    if let Some(progress) = PROGRESS.lock().unwrap().get_mut(instance_id) {
+        progress.requested = true;
+    }
+}
+
+pub fn finish(instance_id: &str) {
+    PROGRESS.lock().unwrap().remove(instance_id);
+}
+
+/// Reports the state of the actions which were interrupted
+///
+/// With `cancel`, instances whose start was already requested are stopped again.
+pub async fn interrupted(aws_config: &SdkConfig, cancel: bool) {
+    let in_progress: Vec<(String, Action, bool)> = PROGRESS
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(instance_id, progress)| {
+            (
+                instance_id.clone(),
+                progress.action.clone(),
+                progress.requested,
+            )
+        })
+        .collect();
+
+    for (instance_id, action, requested) in in_progress {
+        let aws_ec2_client = AwsEc2Client::new(
+            clients::ec2(aws_config),
+            &instance_id,
+            InstanceStateName::Stopped,
+            Duration::from_secs(10),
+        );
+
+        let state = match timeout(Duration::from_secs(5), aws_ec2_client.get_instance()).await {
+            Ok(Ok(instance)) => instance.state().as_str().to_string(),
+            _ => "unknown".to_string(),
+        };
+        let request = if requested {
+            "was requested"
+        } else {
+            "was not requested yet"
+        };
+        log::warn(
+            "interrupt",
+            format!(
+                "Interrupted: {} of {} {}, the instance is {}",
+                action, instance_id, request, state
+            ),
+        );
+
+        if cancel && requested && action == Action::Start {
+            match aws_ec2_client.stop_instance().await {
+                Ok(_) => log::warn("interrupt", format!("Stopping {} again", instance_id)),
+                Err(e) => log::error(
+                    "interrupt",
+                    format!("Failed to stop {} again: {}", instance_id, e),
+                ),
+            }
+        } else {
+            log::warn(
+                "interrupt",
+                format!(
+                    "Run `aws-start-stop {} {}` again to finish",
+                    action, instance_id
+                ),
+            );
+        }
+    }
+}
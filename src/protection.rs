@@ -0,0 +1,345 @@
+use crate::aws::Instance;
+use crate::config::Action;
+use crate::log;
+use crate::settings::Window;
+use chrono::{Datelike, NaiveTime, Utc, Weekday};
+use chrono_tz::Tz;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use regex::Regex;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Instances this program refuses to stop
+enum Protected {
+    Instance(String),
+    /// Tag key, and pattern its value must match
+    Tag(String, Regex),
+}
+
+static PROTECTED: OnceLock<Vec<Protected>> = OnceLock::new();
+
+/// Tag instances must have to be acted on
+struct Required {
+    /// As given, `key=value`
+    tag: String,
+    key: String,
+    value: Regex,
+}
+
+static REQUIRED: OnceLock<Vec<Required>> = OnceLock::new();
+
+/// Days and hours of the week, during which instances must not be stopped, or must run
+pub struct TimeWindow {
+    /// As given, for messages
+    description: String,
+    days: Vec<Weekday>,
+    from: NaiveTime,
+    to: NaiveTime,
+    timezone: Tz,
+}
+
+impl TimeWindow {
+    /// The window as given, e.g. `Mon-Fri 08:00-19:00 Europe/Paris`
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn parse(window: &Window) -> Result<Self> {
+        let description = format!(
+            "{} {}-{} {}",
+            window.days, window.from, window.to, window.timezone
+        );
+        let day = |day: &str| {
+            day.trim()
+                .parse::<Weekday>()
+                .map_err(|_| eyre!("{} isn't a day of the week", day))
+        };
+        let mut days = vec![];
+        for range in window.days.split(',') {
+            match range.split_once('-') {
+                Some((first, last)) => {
+                    let (mut current, last) = (day(first)?, day(last)?);
+                    days.push(current);
+                    while current != last {
+                        current = current.succ();
+                        days.push(current);
+                    }
+                }
+                None => days.push(day(range)?),
+            }
+        }
+        let time = |time: &str| {
+            NaiveTime::parse_from_str(time, "%H:%M")
+                .wrap_err_with(|| format!("{} isn't a time like 08:00", time))
+        };
+
+        Ok(Self {
+            days,
+            from: time(&window.from)?,
+            to: time(&window.to)?,
+            timezone: window
+                .timezone
+                .parse()
+                .map_err(|_| eyre!("{} isn't a known time zone", window.timezone))?,
+            description,
+        })
+    }
+
+    pub fn is_now(&self) -> bool {
+        let now = Utc::now().with_timezone(&self.timezone);
+        let (day, time) = (now.weekday(), now.time());
+        if self.from <= self.to {
+            self.days.contains(&day) && self.from <= time && time < self.to
+        } else {
+            // Overnight, the window starts on one of the days and ends on the next one
+            (self.days.contains(&day) && self.from <= time)
+                || (self.days.contains(&day.pred()) && time < self.to)
+        }
+    }
+}
+
+static NO_STOP_WINDOWS: OnceLock<Vec<TimeWindow>> = OnceLock::new();
+
+/// How long instances must have been running before they can be stopped
+struct MinRuntimes {
+    default: Option<Duration>,
+    /// Minimum runtimes of matching instances, the longest applying
+    instances: Vec<(Protected, Duration)>,
+}
+
+static MIN_RUNTIMES: OnceLock<MinRuntimes> = OnceLock::new();
+
+/// Refusal to stop an instance which hasn't run for its minimum runtime yet
+#[derive(Debug)]
+pub struct TooSoon {
+    instance_id: String,
+    /// Time it must still run for
+    pub left: Duration,
+    pub min_runtime: Duration,
+}
+
+impl fmt::Display for TooSoon {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} has run for less than {}, stop it in {} or use --ignore-min-runtime to stop it now",
+            self.instance_id,
+            humantime::format_duration(self.min_runtime),
+            humantime::format_duration(Duration::from_secs(self.left.as_secs()))
+        )
+    }
+}
+
+impl std::error::Error for TooSoon {}
+
+/// Parses a `key=value` tag, where `*` in the value matches anything
+fn tag_pattern(tag: &str) -> Option<Result<(String, Regex)>> {
+    let (key, value) = tag.split_once('=')?;
+    let value = regex::escape(value).replace(r"\*", ".*");
+    Some(
+        Regex::new(&format!("^{}$", value))
+            .map(|value| (key.to_string(), value))
+            .map_err(Into::into),
+    )
+}
+
+impl Protected {
+    fn parse(pattern: &str) -> Result<Self> {
+        match tag_pattern(pattern) {
+            Some(tag) => tag.map(|(key, value)| Self::Tag(key, value)),
+            None => Ok(Self::Instance(pattern.to_string())),
+        }
+    }
+
+    fn matches(&self, instance: &Instance) -> bool {
+        match self {
+            Self::Instance(instance_id) => instance_id == instance.instance_id(),
+            Self::Tag(key, value) => instance.tag(key).is_some_and(|tag| value.is_match(tag)),
+        }
+    }
+}
+
+/// Protects the instances matching the patterns of the configuration file
+///
+/// Patterns are instance ids, or tags as `key=value`, where `*` in the value matches anything.
+pub fn init(patterns: &[String]) -> Result<()> {
+    let protected = patterns
+        .iter()
+        .map(|pattern| Protected::parse(pattern))
+        .collect::<Result<_>>()?;
+    PROTECTED
+        .set(protected)
+        .map_err(|_| eyre!("Protection is already configured"))
+}
+
+/// Fails if the instance is protected
+pub fn check(instance: &Instance) -> Result<()> {
+    let protected = PROTECTED.get().map(Vec::as_slice).unwrap_or_default();
+    for protection in protected {
+        let reason = match protection {
+            Protected::Instance(instance_id) if instance_id == instance.instance_id() => {
+                "its id".to_string()
+            }
+            Protected::Tag(key, value) => match instance.tag(key) {
+                Some(tag) if value.is_match(tag) => format!("its tag {}={}", key, tag),
+                _ => continue,
+            },
+            Protected::Instance(_) => continue,
+        };
+        return Err(eyre!(
+            "{} is protected by {} in the configuration file, use --override-protection to stop it",
+            instance.instance_id(),
+            reason
+        ));
+    }
+
+    Ok(())
+}
+
+/// Fails if the action mustn't be taken on the instance, whichever command takes it
+///
+/// The minimum runtime is checked last, so that a `TooSoon` error means the instance can be
+/// stopped once it has run for long enough.
+pub fn check_action(action: &Action, instance: &Instance) -> Result<()> {
+    check_required(instance)?;
+    if let Action::Stop = action {
+        check(instance)?;
+        check_window(instance)?;
+        if let Some((left, min_runtime)) = min_runtime_left(instance) {
+            return Err(TooSoon {
+                instance_id: instance.instance_id().to_string(),
+                left,
+                min_runtime,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Ids of the instances the action can be taken on, the others being skipped with the reason
+pub fn filter<'a>(
+    phase: &str,
+    action: &Action,
+    instances: impl IntoIterator<Item = &'a Instance>,
+) -> Vec<String> {
+    instances
+        .into_iter()
+        .filter(|instance| match check_action(action, instance) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn(phase, format!("{}, skipping", e));
+                false
+            }
+        })
+        .map(|instance| instance.instance_id().to_string())
+        .collect()
+}
+
+/// Only allows acting on the instances having all the tags, given as `key=value`
+pub fn require_tags(tags: &[String]) -> Result<()> {
+    let required = tags
+        .iter()
+        .map(|tag| match tag_pattern(tag) {
+            Some(pattern) => pattern.map(|(key, value)| Required {
+                tag: tag.clone(),
+                key,
+                value,
+            }),
+            None => Err(eyre!("{} isn't key=value", tag)),
+        })
+        .collect::<Result<_>>()?;
+    REQUIRED
+        .set(required)
+        .map_err(|_| eyre!("Required tags are already configured"))
+}
+
+/// Whether instances need tags to be acted on, so that they must be looked up even to be started
+pub fn requires_tags() -> bool {
+    REQUIRED.get().is_some_and(|required| !required.is_empty())
+}
+
+/// Fails if the instance lacks a required tag
+fn check_required(instance: &Instance) -> Result<()> {
+    let required = REQUIRED.get().map(Vec::as_slice).unwrap_or_default();
+    for required in required {
+        match instance.tag(&required.key) {
+            Some(tag) if required.value.is_match(tag) => {}
+            tag => {
+                return Err(eyre!(
+                    "{} doesn't have the required tag {} ({}), refusing to act on it",
+                    instance.instance_id(),
+                    required.tag,
+                    tag.map_or("not set".to_string(), |tag| format!("set to {}", tag))
+                ))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Forbids stopping instances during the windows of the configuration file
+pub fn no_stop_windows(windows: &[Window]) -> Result<()> {
+    let windows = windows
+        .iter()
+        .map(TimeWindow::parse)
+        .collect::<Result<_>>()?;
+    NO_STOP_WINDOWS
+        .set(windows)
+        .map_err(|_| eyre!("Time windows are already configured"))
+}
+
+/// Fails if the instance mustn't be stopped now
+fn check_window(instance: &Instance) -> Result<()> {
+    let windows = NO_STOP_WINDOWS.get().map(Vec::as_slice).unwrap_or_default();
+    match windows.iter().find(|window| window.is_now()) {
+        Some(window) => Err(eyre!(
+            "{} isn't stopped during {}, use --force-window-override to stop it anyway",
+            instance.instance_id(),
+            window.description
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Sets how long instances must have been running before being stopped, for all of them and for
+/// those matching the patterns, which are the same as for protected instances
+pub fn min_runtime(default: Option<&str>, instances: &HashMap<String, String>) -> Result<()> {
+    let duration = |duration: &str| {
+        humantime::parse_duration(duration)
+            .wrap_err_with(|| format!("{} isn't a duration like 15m", duration))
+    };
+    let min_runtimes = MinRuntimes {
+        default: default.map(duration).transpose()?,
+        instances: instances
+            .iter()
+            .map(|(pattern, min_runtime)| Ok((Protected::parse(pattern)?, duration(min_runtime)?)))
+            .collect::<Result<_>>()?,
+    };
+    MIN_RUNTIMES
+        .set(min_runtimes)
+        .map_err(|_| eyre!("Minimum runtimes are already configured"))
+}
+
+/// Time the instance must still run for before it can be stopped, with its minimum runtime
+fn min_runtime_left(instance: &Instance) -> Option<(Duration, Duration)> {
+    let min_runtimes = MIN_RUNTIMES.get()?;
+    let min_runtime = min_runtimes
+        .instances
+        .iter()
+        .filter(|(pattern, _)| pattern.matches(instance))
+        .map(|(_, min_runtime)| *min_runtime)
+        .max()
+        .or(min_runtimes.default)?;
+    // The launch time is that of the last start
+    let running_for = instance.launch_time()?.elapsed().ok()?;
+    min_runtime
+        .checked_sub(running_for)
+        .filter(|left| !left.is_zero())
+        .map(|left| (left, min_runtime))
+}
@@ -0,0 +1,191 @@
+use crate::config::{Action, Config};
+use crate::log;
+use crate::systemd;
+use crate::tunnel::Tunnel;
+use crate::work;
+use aws_config::SdkConfig;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::{timeout, Duration, Instant};
+
+#[derive(Debug)]
+pub struct ProxyConfig {
+    pub instance_id: String,
+    /// Local address to listen on, unless given a socket by systemd
+    pub listen: Option<SocketAddr>,
+    /// Port on the instance connections are forwarded to
+    pub port: u16,
+    /// Stop the instance once there has been no connection for this long
+    pub idle_timeout: Option<Duration>,
+    pub timeout: u64,
+    /// Write systemd units starting the proxy on connection to this directory, instead of running
+    pub generate_systemd: Option<PathBuf>,
+    /// Reach the instance through an SSM session, re-established when it drops
+    pub ssm: bool,
+}
+
+/// Connections being forwarded and when the last one ended
+#[derive(Clone)]
+struct Activity {
+    active: Arc<AtomicUsize>,
+    last_seen: Arc<Mutex<Instant>>,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Self {
+            active: Arc::new(AtomicUsize::new(0)),
+            last_seen: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    fn touch(&self) {
+        *self.last_seen.lock().unwrap() = Instant::now();
+    }
+
+    fn is_idle(&self, idle_timeout: Duration) -> bool {
+        self.active.load(Ordering::SeqCst) == 0
+            && self.last_seen.lock().unwrap().elapsed() >= idle_timeout
+    }
+}
+
+/// Listens locally, starting the instance on the first connection and forwarding traffic to it
+pub async fn run(aws_config: &SdkConfig, config: ProxyConfig) -> Result<()> {
+    if let Some(directory) = &config.generate_systemd {
+        for path in systemd::write_units(directory, &config)? {
+            println!("Wrote {}", path.display());
+        }
+        return Ok(());
+    }
+
+    let listener = match systemd::activated_listener()? {
+        Some(listener) => TcpListener::from_std(listener)?,
+        None => {
+            let listen = config.listen.ok_or_else(|| {
+                eyre!("Missing --listen, and not started by systemd socket activation")
+            })?;
+            TcpListener::bind(listen).await?
+        }
+    };
+    log::info(
+        "proxy",
+        format!(
+            "Forwarding {} to port {} of {}",
+            listener.local_addr()?,
+            config.port,
+            config.instance_id
+        ),
+    );
+
+    let activity = Activity::new();
+    let mut target: Option<SocketAddr> = None;
+    // Only held to keep the SSM session open, which closes when dropped
+    let mut _tunnel: Option<Tunnel> = None;
+    let mut idle_check = tokio::time::interval(Duration::from_secs(10));
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (socket, peer) = accepted?;
+                if target.is_none() {
+                    log::info("proxy", format!("Connection from {}, starting instance", peer));
+                    match start(aws_config, &config).await {
+                        Ok((address, ssm_tunnel)) => {
+                            target = Some(address);
+                            _tunnel = ssm_tunnel;
+                        }
+                        Err(e) => log::error("proxy", format!("Failed to start instance: {}", e)),
+                    }
+                }
+                if let Some(address) = target {
+                    activity.active.fetch_add(1, Ordering::SeqCst);
+                    tokio::spawn(forward(socket, address, activity.clone()));
+                }
+            }
+            _ = idle_check.tick(), if target.is_some() && config.idle_timeout.is_some() => {
+                let idle_timeout = config.idle_timeout.unwrap_or_default();
+                if activity.is_idle(idle_timeout) {
+                    log::info(
+                        "proxy",
+                        format!(
+                            "No connection for {}, stopping instance",
+                            humantime::format_duration(idle_timeout)
+                        ),
+                    );
+                    match run_action(aws_config, &config, Action::Stop).await {
+                        Ok(_) => {
+                            target = None;
+                            _tunnel = None;
+                        }
+                        Err(e) => log::error("proxy", format!("Failed to stop instance: {}", e)),
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Starts the instance, returning the address to forward to and the SSM tunnel behind it, if any
+async fn start(
+    aws_config: &SdkConfig,
+    config: &ProxyConfig,
+) -> Result<(SocketAddr, Option<Tunnel>)> {
+    let instance = run_action(aws_config, config, Action::Start).await?;
+    if config.ssm {
+        let tunnel = Tunnel::open(aws_config, &config.instance_id, config.port)?;
+        return Ok((tunnel.address, Some(tunnel)));
+    }
+    let address = instance
+        .address()
+        .ok_or_else(|| eyre!("The instance has no IP address"))?;
+
+    Ok((SocketAddr::new(address, config.port), None))
+}
+
+async fn run_action(
+    aws_config: &SdkConfig,
+    config: &ProxyConfig,
+    action: Action,
+) -> Result<crate::aws::Instance> {
+    let instance_config = Config {
+        wait_for_ssm: config.ssm && action == Action::Start,
+        ..Config::new(action, config.instance_id.clone())
+    };
+    timeout(
+        Duration::from_secs(config.timeout),
+        work(aws_config, &instance_config, &instance_config.action),
+    )
+    .await
+    .map_err(|_| eyre!("Timed out waiting for the instance"))?
+}
+
+/// Copies data both ways, retrying the connection while the service on the instance comes up
+async fn forward(mut socket: TcpStream, address: SocketAddr, activity: Activity) {
+    let mut upstream = None;
+    for _ in 0..30 {
+        match TcpStream::connect(address).await {
+            Ok(stream) => {
+                upstream = Some(stream);
+                break;
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(2)).await,
+        }
+    }
+
+    match upstream {
+        Some(mut upstream) => {
+            if let Err(e) = tokio::io::copy_bidirectional(&mut socket, &mut upstream).await {
+                log::warn("proxy", format!("Connection to {} failed: {}", address, e));
+            }
+        }
+        None => log::warn("proxy", format!("Failed to connect to {}", address)),
+    }
+
+    activity.active.fetch_sub(1, Ordering::SeqCst);
+    activity.touch();
+}
@@ -0,0 +1,111 @@
+use crate::clients;
+use crate::config::{self, Action, Config};
+use crate::error::Hint;
+use crate::names;
+use crate::work;
+use aws_config::SdkConfig;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use serde_json::json;
+use tokio::process::Command;
+use tokio::time::{timeout, Duration};
+
+/// SSM document forwarding a session to the SSH port of the instance
+const SSH_DOCUMENT: &str = "AWS-StartSSHSession";
+
+#[derive(Debug)]
+pub struct ProxyCommandConfig {
+    /// Instance id, or Name tag, as given by ssh as `%h`
+    pub host: String,
+    pub port: u16,
+    pub timeout: u64,
+}
+
+/// Starts the instance if needed, then bridges stdin and stdout to its SSH port through an SSM
+/// session, for use as the ProxyCommand of ssh
+///
+/// The session is run by the Session Manager plugin, as the AWS CLI does.
+pub async fn run(aws_config: &SdkConfig, config: ProxyCommandConfig) -> Result<()> {
+    let instance_id = if config::parse_instance(&config.host).is_ok() {
+        config.host.clone()
+    } else {
+        names::resolve(aws_config, &config.host)
+            .await?
+            .ok_or_else(|| eyre!("Several instances are named {}", config.host))?
+    };
+
+    let instance_config = Config {
+        wait_for_ssm: true,
+        stdout_reserved: true,
+        ..Config::new(Action::Start, instance_id.clone())
+    };
+    timeout(
+        Duration::from_secs(config.timeout),
+        work(aws_config, &instance_config, &instance_config.action),
+    )
+    .await
+    .map_err(|_| eyre!("Timed out waiting for the instance"))??;
+
+    let status = session_command(
+        aws_config,
+        &instance_id,
+        SSH_DOCUMENT,
+        json!({"portNumber": [config.port.to_string()]}),
+    )
+    .await?
+    .status()
+    .await
+    .wrap_err("Failed to run session-manager-plugin, which must be installed")?;
+    if !status.success() {
+        return Err(eyre!("The SSM session ended with {}", status));
+    }
+
+    Ok(())
+}
+
+/// Starts an SSM session and returns the Session Manager plugin command running it
+pub async fn session_command(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    document: &str,
+    parameters: serde_json::Value,
+) -> Result<Command> {
+    let mut request = clients::ssm(aws_config)
+        .start_session()
+        .target(instance_id)
+        .document_name(document);
+    for (name, values) in parameters.as_object().into_iter().flatten() {
+        let values = values
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|value| value.as_str().map(String::from))
+            .collect();
+        request = request.parameters(name, values);
+    }
+    let response = request.send().await.hint("ssm:StartSession")?;
+    let region = aws_config
+        .region()
+        .ok_or_else(|| eyre!("No region configured"))?
+        .to_string();
+    let session = json!({
+        "SessionId": response.session_id(),
+        "TokenValue": response.token_value(),
+        "StreamUrl": response.stream_url(),
+    });
+    let request = json!({
+        "Target": instance_id,
+        "DocumentName": document,
+        "Parameters": parameters,
+    });
+
+    let mut command = Command::new("session-manager-plugin");
+    command
+        .arg(session.to_string())
+        .arg(&region)
+        .arg("StartSession")
+        .arg("")
+        .arg(request.to_string())
+        .arg(format!("https://ssm.{}.amazonaws.com", region));
+    Ok(command)
+}
@@ -0,0 +1,20 @@
+use color_eyre::Result;
+use jmespath::Variable;
+
+/// Checks the JMESPath expression, which is compiled again when applied
+pub fn parse(value: &str) -> Result<String, String> {
+    jmespath::compile(value)
+        .map(|_| value.to_string())
+        .map_err(|e| e.to_string())
+}
+
+/// Prints the result of the expression on the JSON, strings raw for the shell to capture
+pub fn print(query: &str, json: &serde_json::Value) -> Result<()> {
+    let expression = jmespath::compile(query)?;
+    let result = expression.search(Variable::from_serializable(json)?)?;
+    match result.as_string() {
+        Some(string) => println!("{}", string),
+        None => println!("{}", serde_json::to_string_pretty(&*result)?),
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+use aws_sdk_ec2::error::{ProvideErrorMetadata, SdkError};
+use std::future::Future;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// How long the rate takes to recover from a throttled call
+const RECOVERY: Duration = Duration::from_secs(60);
+/// Lowest share of the configured rate throttled calls bring the rate down to
+const MIN_SHARE: f64 = 0.1;
+
+/// Token bucket, refilled at a rate which is halved when AWS throttles a call anyway, as other
+/// tools of the account use the same limits
+struct Bucket {
+    /// Calls per second given on the command line
+    configured: f64,
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = (now - self.refilled).as_secs_f64();
+        self.refilled = now;
+        self.rate =
+            (self.rate + self.configured * elapsed / RECOVERY.as_secs_f64()).min(self.configured);
+        // A burst of at most a second's worth of calls
+        self.tokens = (self.tokens + self.rate * elapsed).min(self.rate.max(1.0));
+    }
+
+    /// Takes a token, or tells how long until there is one
+    fn take(&mut self) -> Option<Duration> {
+        self.refill();
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate))
+        }
+    }
+}
+
+static BUCKET: OnceLock<Mutex<Bucket>> = OnceLock::new();
+
+/// Limits the calls describing, starting and stopping instances to this many per second from now
+/// on, all regions together
+pub fn init(rate: Option<f64>) {
+    if let Some(rate) = rate {
+        let _ = BUCKET.set(Mutex::new(Bucket {
+            configured: rate,
+            rate,
+            tokens: rate.max(1.0),
+            refilled: Instant::now(),
+        }));
+    }
+}
+
+/// Sends the request once the rate allows it, slowing down the next ones if it was throttled
+pub async fn call<T, E, R>(
+    request: impl Future<Output = Result<T, SdkError<E, R>>>,
+) -> Result<T, SdkError<E, R>>
+where
+    E: ProvideErrorMetadata,
+{
+    let Some(bucket) = BUCKET.get() else {
+        return request.await;
+    };
+
+    loop {
+        let wait = bucket.lock().unwrap().take();
+        match wait {
+            Some(wait) => sleep(wait).await,
+            None => break,
+        }
+    }
+    let result = request.await;
+    if let Err(error) = &result {
+        if matches!(
+            error.code(),
+            Some("RequestLimitExceeded" | "Throttling" | "ThrottlingException")
+        ) {
+            let mut bucket = bucket.lock().unwrap();
+            bucket.rate = (bucket.rate / 2.0).max(bucket.configured * MIN_SHARE);
+        }
+    }
+    result
+}
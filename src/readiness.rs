@@ -0,0 +1,227 @@
+use crate::aws::{AwsEc2Client, AwsSsmClient};
+use crate::clients;
+use crate::log;
+use crate::telemetry;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio::time::{sleep, timeout, Duration};
+
+/// How often the probes are tried again
+const PROBE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Future of a check, boxed as traits can't have async methods returning `dyn` futures
+pub type CheckFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Condition an instance must meet to be ready, waited for until it does
+///
+/// Checks wait indefinitely, the caller bounding the whole wait with its timeout.
+pub trait ReadinessCheck: Send + Sync {
+    /// Phase of the messages and spans about the check
+    fn name(&self) -> &str;
+
+    /// Resolves once the instance meets the condition, or fails once it no longer can
+    fn wait<'a>(&'a self, instance_id: &'a str) -> CheckFuture<'a>;
+}
+
+/// How the checks are run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Order {
+    /// One after the other, in the order given
+    Sequential,
+    /// All at once, the first failure failing the wait
+    Parallel,
+}
+
+/// Waits for the instance to pass all the checks
+pub async fn wait_for_ready(
+    checks: &[Arc<dyn ReadinessCheck>],
+    instance_id: &str,
+    order: Order,
+) -> Result<()> {
+    match order {
+        Order::Sequential => {
+            for check in checks {
+                run_check(check.as_ref(), instance_id).await?;
+            }
+        }
+        Order::Parallel => {
+            let mut tasks = JoinSet::new();
+            for check in checks {
+                let check = check.clone();
+                let instance_id = instance_id.to_string();
+                tasks.spawn(async move {
+                    let future = run_check(check.as_ref(), &instance_id);
+                    log::with_instance(&instance_id, future).await
+                });
+            }
+            while let Some(joined) = tasks.join_next().await {
+                joined??;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_check(check: &dyn ReadinessCheck, instance_id: &str) -> Result<()> {
+    telemetry::span(check.name(), instance_id, check.wait(instance_id)).await
+}
+
+/// The instance reaching a state, while something else may still be acting on it
+pub struct Ec2State {
+    pub client: aws_sdk_ec2::client::Client,
+    pub target: InstanceStateName,
+}
+
+impl ReadinessCheck for Ec2State {
+    fn name(&self) -> &str {
+        "state"
+    }
+
+    fn wait<'a>(&'a self, instance_id: &'a str) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let aws_ec2_client = AwsEc2Client::new(
+                self.client.clone(),
+                instance_id,
+                self.target.clone(),
+                Duration::from_secs(10),
+            );
+            // Any state but a terminal one can still lead to the target
+            let mut wait_interval = tokio::time::interval(Duration::from_secs(10));
+            loop {
+                wait_interval.tick().await;
+                let instance = aws_ec2_client.get_instance().await?;
+                match instance.state() {
+                    state if *state == self.target => return Ok(()),
+                    InstanceStateName::ShuttingDown | InstanceStateName::Terminated => {
+                        return Err(eyre!("The instance is {}", instance.state().as_str()))
+                    }
+                    _ => {}
+                }
+            }
+        })
+    }
+}
+
+/// The SSM agent of the instance being connected
+pub struct SsmConnected {
+    pub client: aws_sdk_ssm::client::Client,
+}
+
+impl SsmConnected {
+    pub fn new(aws_config: &SdkConfig) -> Self {
+        Self {
+            client: clients::ssm(aws_config),
+        }
+    }
+}
+
+impl ReadinessCheck for SsmConnected {
+    fn name(&self) -> &str {
+        "ssm"
+    }
+
+    fn wait<'a>(&'a self, instance_id: &'a str) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let aws_ssm_client = AwsSsmClient {
+                client: self.client.clone(),
+                instance_id: instance_id.to_string(),
+                wait: Duration::from_secs(10),
+            };
+            aws_ssm_client.wait_for_connection().await
+        })
+    }
+}
+
+/// A TCP port of the instance accepting connections
+pub struct TcpProbe {
+    pub address: SocketAddr,
+}
+
+impl ReadinessCheck for TcpProbe {
+    fn name(&self) -> &str {
+        "tcp"
+    }
+
+    fn wait<'a>(&'a self, _instance_id: &'a str) -> CheckFuture<'a> {
+        Box::pin(async move {
+            log::info(
+                "tcp",
+                format!("Waiting for {} to accept connections...", self.address),
+            );
+            loop {
+                if let Ok(Ok(_)) = timeout(PROBE_INTERVAL, TcpStream::connect(self.address)).await {
+                    log::info("tcp", format!("{} accepts connections", self.address));
+                    return Ok(());
+                }
+                sleep(PROBE_INTERVAL).await;
+            }
+        })
+    }
+}
+
+/// A URL served by the instance answering with a success status
+pub struct HttpProbe {
+    pub url: String,
+}
+
+impl ReadinessCheck for HttpProbe {
+    fn name(&self) -> &str {
+        "http"
+    }
+
+    fn wait<'a>(&'a self, _instance_id: &'a str) -> CheckFuture<'a> {
+        Box::pin(async move {
+            let client = reqwest::Client::builder().timeout(PROBE_INTERVAL).build()?;
+            log::info("http", format!("Waiting for {} to answer...", self.url));
+            loop {
+                match client.get(&self.url).send().await {
+                    Ok(response) if response.status().is_success() => {
+                        log::info(
+                            "http",
+                            format!("{} answered {}", self.url, response.status()),
+                        );
+                        return Ok(());
+                    }
+                    _ => sleep(PROBE_INTERVAL).await,
+                }
+            }
+        })
+    }
+}
+
+/// Any other condition, given as a function
+pub struct Custom {
+    name: String,
+    check: Box<dyn Fn(&str) -> CheckFuture<'static> + Send + Sync>,
+}
+
+impl Custom {
+    pub fn new(
+        name: &str,
+        check: impl Fn(&str) -> CheckFuture<'static> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            check: Box::new(check),
+        }
+    }
+}
+
+impl ReadinessCheck for Custom {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn wait<'a>(&'a self, instance_id: &'a str) -> CheckFuture<'a> {
+        (self.check)(instance_id)
+    }
+}
@@ -0,0 +1,133 @@
+use crate::aws::AwsEc2InstancesClient;
+use crate::clients;
+use crate::log;
+use aws_config::{Region, SdkConfig};
+use aws_sdk_ec2::types::Filter;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use tokio::task::JoinSet;
+
+/// Regions to run an operation in at once
+#[derive(Debug, Clone)]
+pub enum Regions {
+    /// All the regions enabled in the account
+    All,
+    Listed(Vec<String>),
+}
+
+pub fn parse(value: &str) -> Result<Regions, String> {
+    if value == "all" {
+        return Ok(Regions::All);
+    }
+    let regions: Vec<String> = value
+        .split(',')
+        .map(str::trim)
+        .filter(|region| !region.is_empty())
+        .map(str::to_string)
+        .collect();
+    if regions.is_empty() {
+        return Err("Expected regions separated by commas, or all".to_string());
+    }
+    Ok(Regions::Listed(regions))
+}
+
+/// Returns a configuration for each region, or the given one when there are no regions
+pub async fn configs(aws_config: &SdkConfig, regions: Option<&Regions>) -> Result<Vec<SdkConfig>> {
+    let regions = match regions {
+        None => return Ok(vec![aws_config.clone()]),
+        Some(Regions::Listed(regions)) => regions.clone(),
+        Some(Regions::All) => {
+            AwsEc2InstancesClient::new(clients::ec2(aws_config))
+                .enabled_regions()
+                .await?
+        }
+    };
+
+    Ok(regions
+        .into_iter()
+        .map(|region| aws_config.to_builder().region(Region::new(region)).build())
+        .collect())
+}
+
+/// Runs the operation in each region at once, returning the results by region in their order
+pub async fn fan_out<T, F, Fut>(
+    aws_config: &SdkConfig,
+    regions: Option<&Regions>,
+    operation: F,
+) -> Result<Vec<(String, Result<T>)>>
+where
+    T: Send + 'static,
+    F: Fn(SdkConfig) -> Fut,
+    Fut: std::future::Future<Output = Result<T>> + Send + 'static,
+{
+    let mut tasks = JoinSet::new();
+    for (index, aws_config) in configs(aws_config, regions).await?.into_iter().enumerate() {
+        let region = aws_config
+            .region()
+            .map(|region| region.to_string())
+            .unwrap_or_default();
+        let future = operation(aws_config);
+        tasks.spawn(async move { (index, region, future.await) });
+    }
+
+    let mut results = vec![];
+    while let Some(joined) = tasks.join_next().await {
+        results.push(joined?);
+    }
+    results.sort_by_key(|(index, _, _)| *index);
+    Ok(results
+        .into_iter()
+        .map(|(_, region, result)| (region, result))
+        .collect())
+}
+
+/// Returns the configuration of the region the instances are in, looking in the other enabled
+/// regions for those the given one doesn't have
+pub async fn locate(aws_config: &SdkConfig, instance_ids: &[String]) -> Result<SdkConfig> {
+    let filter = Filter::builder()
+        .name("instance-id")
+        .set_values(Some(instance_ids.to_vec()))
+        .build();
+    let client = AwsEc2InstancesClient::new(clients::ec2(aws_config));
+    let found = client.describe_instances(vec![filter.clone()]).await?;
+    if found.len() == instance_ids.len() {
+        return Ok(aws_config.clone());
+    }
+
+    let default_region = aws_config.region().map(|region| region.to_string());
+    let results = fan_out(aws_config, Some(&Regions::All), |aws_config| {
+        let filter = filter.clone();
+        async move {
+            let client = AwsEc2InstancesClient::new(clients::ec2(&aws_config));
+            let found = client.describe_instances(vec![filter]).await?;
+            Ok((aws_config, found.len()))
+        }
+    })
+    .await?;
+
+    let mut located = None;
+    for (region, result) in results {
+        if Some(&region) == default_region.as_ref() {
+            continue;
+        }
+        match result {
+            Ok((_, 0)) => {}
+            Ok((region_config, count)) => {
+                if count < instance_ids.len() || located.is_some() || !found.is_empty() {
+                    return Err(eyre!(
+                        "The instances are in several regions, act on each one"
+                    ));
+                }
+                log::info(
+                    "search",
+                    format!("{} found in {}", instance_ids.join(", "), region),
+                );
+                located = Some(region_config);
+            }
+            // Regions this account can't look in can't have its instances
+            Err(e) => log::warn("search", format!("Failed to look in {}: {}", region, e)),
+        }
+    }
+
+    located.ok_or_else(|| eyre!("Instance not found in any enabled region"))
+}
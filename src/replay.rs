@@ -0,0 +1,126 @@
+use crate::log;
+use aws_credential_types::Credentials;
+use aws_smithy_runtime::client::http::test_util::dvr::{RecordingClient, ReplayingClient};
+use aws_smithy_runtime_api::client::http::SharedHttpClient;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+/// Headers of the requests which give away the credentials
+const SECRET_HEADERS: [&str; 2] = ["authorization", "x-amz-security-token"];
+
+/// Client recording the exchanges with AWS, and the fixture they are saved to
+static RECORDING: OnceLock<(RecordingClient, PathBuf)> = OnceLock::new();
+
+/// HTTP client of the SDK recording the exchanges with AWS to the file, or replaying those of the
+/// file instead of calling AWS
+///
+/// The fixtures are the JSON files of the smithy runtime, so that tests can also replay them with
+/// `ReplayingClient` and check the requests the program made.
+pub fn http_client(
+    record: Option<&Path>,
+    replay: Option<&Path>,
+) -> Result<Option<SharedHttpClient>> {
+    match (record, replay) {
+        (Some(path), _) => {
+            let client = RecordingClient::https();
+            let _ = RECORDING.set((client.clone(), path.to_path_buf()));
+            log::info(
+                "replay",
+                format!("Recording the AWS exchanges to {}", path.display()),
+            );
+            Ok(Some(SharedHttpClient::new(client)))
+        }
+        (None, Some(path)) => {
+            let client = ReplayingClient::from_file(path)
+                .map_err(|e| eyre!("Failed to read {}: {}", path.display(), e))?;
+            log::info(
+                "replay",
+                format!("Replaying the AWS exchanges of {}", path.display()),
+            );
+            Ok(Some(SharedHttpClient::new(client)))
+        }
+        (None, None) => Ok(None),
+    }
+}
+
+/// Credentials signing the replayed requests, so that no real ones are needed
+pub fn credentials() -> Credentials {
+    Credentials::new("AKIDREPLAY", "replay", None, None, "replay")
+}
+
+/// Writes the exchanges recorded so far to the fixture, when recording
+///
+/// The signatures and session tokens are left out, so that the fixtures can be committed.
+pub fn save() {
+    let Some((client, path)) = RECORDING.get() else {
+        return;
+    };
+    let saved = serde_json::to_value(client.network_traffic())
+        .map(|mut traffic| {
+            scrub(&mut traffic);
+            traffic
+        })
+        .and_then(|traffic| serde_json::to_string_pretty(&traffic))
+        .map_err(|e| e.to_string())
+        .and_then(|traffic| std::fs::write(path, traffic).map_err(|e| e.to_string()));
+    if let Err(e) = saved {
+        log::warn(
+            "replay",
+            format!("Failed to save the recording to {}: {}", path.display(), e),
+        );
+    }
+}
+
+/// Removes the headers carrying credentials from the requests, wherever they are
+fn scrub(value: &mut Value) {
+    match value {
+        Value::Object(object) => {
+            if let Some(Value::Object(headers)) = object.get_mut("headers") {
+                headers.retain(|name, _| !SECRET_HEADERS.contains(&name.to_lowercase().as_str()));
+            }
+            object.values_mut().for_each(scrub);
+        }
+        Value::Array(array) => array.iter_mut().for_each(scrub),
+        _ => {}
+    }
+}
+
+/// Saves the recording when dropped, as the program returns, failing or not
+pub struct SaveOnDrop;
+
+impl Drop for SaveOnDrop {
+    fn drop(&mut self) {
+        save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::aws::AwsEc2Client;
+    use crate::fixtures;
+    use aws_sdk_ec2::types::InstanceStateName;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn replays_starting_an_instance() {
+        let _exclusive = fixtures::EXCLUSIVE.lock().await;
+        let (client, aws_config) = fixtures::replay("start");
+        let ec2 = AwsEc2Client::new(
+            aws_sdk_ec2::client::Client::new(&aws_config),
+            fixtures::INSTANCE_ID,
+            InstanceStateName::Running,
+            Duration::from_millis(10),
+        );
+
+        let state = ec2.start_instance().await.unwrap();
+        assert_eq!(state, InstanceStateName::Pending);
+        let instance = ec2.wait_for_state(Some(&state)).await.unwrap();
+        assert_eq!(*instance.state(), InstanceStateName::Running);
+        assert_eq!(instance.name(), Some("replay"));
+
+        fixtures::validate(&client).await;
+    }
+}
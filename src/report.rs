@@ -0,0 +1,152 @@
+use crate::aws::Instance;
+use crate::config::Action;
+use crate::log;
+use aws_smithy_runtime_api::box_error::BoxError;
+use aws_smithy_runtime_api::client::interceptors::{
+    context::BeforeDeserializationInterceptorContextRef, Intercept,
+};
+use aws_smithy_runtime_api::client::orchestrator::Metadata;
+use aws_smithy_runtime_api::client::runtime_components::RuntimeComponents;
+use aws_smithy_types::config_bag::ConfigBag;
+use aws_types::request_id::RequestId;
+use chrono::{DateTime, SecondsFormat, Utc};
+use color_eyre::Result;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// Everything known of the run so far
+struct Report {
+    path: PathBuf,
+    started: DateTime<Utc>,
+    action: Option<String>,
+    targets: Vec<String>,
+    instances: Vec<Value>,
+    requests: Vec<Value>,
+    error: Option<String>,
+}
+
+impl Report {
+    /// Rewrites the whole file, so that it is complete even if the process exits right after
+    fn write(&self) {
+        let report = json!({
+            "started": self.started.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "updated": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "action": self.action,
+            "targets": self.targets,
+            "succeeded": self.error.is_none()
+                && self.instances.iter().all(|instance| instance["succeeded"] == true),
+            "error": self.error,
+            "instances": self.instances,
+            "requests": self.requests,
+        });
+        let result = serde_json::to_string_pretty(&report)
+            .map_err(std::io::Error::from)
+            .and_then(|contents| std::fs::write(&self.path, contents));
+        if let Err(e) = result {
+            eprintln!("Failed to write the report {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+static REPORT: OnceLock<Mutex<Report>> = OnceLock::new();
+
+/// Writes a JSON report of the run to the file from now on, updated as it goes
+pub fn init(path: Option<PathBuf>) {
+    if let Some(path) = path {
+        let _ = REPORT.set(Mutex::new(Report {
+            path,
+            started: Utc::now(),
+            action: None,
+            targets: vec![],
+            instances: vec![],
+            requests: vec![],
+            error: None,
+        }));
+    }
+}
+
+fn update(change: impl FnOnce(&mut Report)) {
+    if let Some(report) = REPORT.get() {
+        let mut report = report.lock().unwrap();
+        change(&mut report);
+        report.write();
+    }
+}
+
+/// Records the instances the action is about to be run on
+pub fn targets(action: &Action, instance_ids: &[String]) {
+    update(|report| {
+        report.action = Some(action.to_string());
+        report.targets.extend(instance_ids.iter().cloned());
+    });
+}
+
+/// Records the outcome of the action on an instance, along with how long each phase took
+pub fn outcome(
+    action: &Action,
+    instance_id: &str,
+    result: &Result<Instance>,
+    duration: Duration,
+    timings: &[(String, Duration)],
+) {
+    update(|report| {
+        let instance = result.as_ref().ok();
+        let phases: Vec<Value> = timings
+            .iter()
+            .map(|(phase, duration)| json!({"phase": phase, "seconds": duration.as_secs_f64()}))
+            .collect();
+        report.instances.push(json!({
+            "instance_id": instance_id,
+            "name": instance.and_then(Instance::name),
+            "action": action.to_string(),
+            "succeeded": result.is_ok(),
+            "state": instance.map(|instance| instance.state().as_str()),
+            "public_ipv4": instance.and_then(Instance::ipv4_address_public),
+            "private_ipv4": instance.and_then(Instance::ipv4_address_private),
+            "ipv6": instance.and_then(Instance::ipv6_address),
+            "duration_seconds": duration.as_secs_f64(),
+            "phases": phases,
+            "error": result.as_ref().err().map(ToString::to_string),
+        }));
+    });
+}
+
+/// Records the error which ended the run, if any
+pub fn finish<T>(result: &Result<T>) {
+    update(|report| report.error = result.as_ref().err().map(ToString::to_string));
+}
+
+/// Records the id of each AWS request of the clients it is added to, along with the operation
+/// and instance it was made for, so that AWS support can trace them
+#[derive(Debug)]
+pub struct RequestIds;
+
+impl Intercept for RequestIds {
+    fn name(&self) -> &'static str {
+        "RequestIds"
+    }
+
+    fn read_after_transmit(
+        &self,
+        context: &BeforeDeserializationInterceptorContextRef<'_>,
+        _runtime_components: &RuntimeComponents,
+        cfg: &mut ConfigBag,
+    ) -> Result<(), BoxError> {
+        if REPORT.get().is_none() {
+            return Ok(());
+        }
+        let metadata = cfg.load::<Metadata>();
+        let request = json!({
+            "at": Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true),
+            "instance_id": log::instance_id(),
+            "service": metadata.map(Metadata::service),
+            "operation": metadata.map(Metadata::name),
+            "status": context.response().status().as_u16(),
+            "request_id": context.response().headers().request_id(),
+        });
+        update(|report| report.requests.push(request));
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use crate::aws::Instance;
+use crate::color;
+use crate::config::Action;
+use crate::log::{self, Level};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::fmt;
+use std::time::Duration;
+
+/// Exit code when the action failed on some of the instances, but succeeded on the others
+pub const PARTIAL_FAILURE_EXIT_CODE: i32 = 4;
+
+/// Failures of some of the instances of a run, the others having succeeded
+#[derive(Debug)]
+pub struct PartialFailure {
+    action: Action,
+    /// Instance ids and their errors
+    failed: Vec<(String, String)>,
+    total: usize,
+}
+
+impl fmt::Display for PartialFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Failed to {} {} of {} instances: {}",
+            self.action,
+            self.failed.len(),
+            self.total,
+            errors(&self.failed)
+        )
+    }
+}
+
+impl std::error::Error for PartialFailure {}
+
+fn errors(failed: &[(String, String)]) -> String {
+    failed
+        .iter()
+        .map(|(instance_id, error)| format!("{}: {}", instance_id, error))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Outcome of the action on one of the instances of a run
+pub struct Outcome {
+    pub instance_id: String,
+    pub action: Action,
+    pub result: Result<Instance>,
+    pub duration: Duration,
+}
+
+impl Outcome {
+    pub fn name(&self) -> Option<&str> {
+        self.result.as_ref().ok().and_then(Instance::name)
+    }
+
+    pub fn state(&self) -> &str {
+        match &self.result {
+            Ok(instance) => instance.state().as_str(),
+            Err(_) => "failed",
+        }
+    }
+
+    pub fn ip(&self, ip: fn(&Instance) -> Option<&str>) -> Option<&str> {
+        self.result.as_ref().ok().and_then(ip)
+    }
+
+    pub fn error(&self) -> Option<String> {
+        self.result.as_ref().err().map(ToString::to_string)
+    }
+}
+
+/// Fails with the errors of all the failed instances, as a `PartialFailure` when some succeeded
+pub fn check(action: &Action, outcomes: &[Outcome]) -> Result<()> {
+    let failed: Vec<(String, String)> = outcomes
+        .iter()
+        .filter_map(|outcome| Some((outcome.instance_id.clone(), outcome.error()?)))
+        .collect();
+    if failed.is_empty() {
+        Ok(())
+    } else if failed.len() == outcomes.len() {
+        Err(eyre!(
+            "Failed to {} all {} instances: {}",
+            action,
+            failed.len(),
+            errors(&failed)
+        ))
+    } else {
+        Err(PartialFailure {
+            action: action.clone(),
+            failed,
+            total: outcomes.len(),
+        }
+        .into())
+    }
+}
+
+/// Shows how the action went on each of the instances, once all are done, as a table or as the
+/// fields of a JSON message
+///
+/// The table goes to stderr when stdout is reserved for the result.
+pub fn print(outcomes: &[Outcome], stdout_reserved: bool) {
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| outcome.result.is_err())
+        .count();
+    let message = format!(
+        "{} instances: {} succeeded, {} failed",
+        outcomes.len(),
+        outcomes.len() - failed,
+        failed
+    );
+
+    if log::is_json() {
+        let results: Vec<serde_json::Value> = outcomes
+            .iter()
+            .map(|outcome| {
+                serde_json::json!({
+                    "instance_id": outcome.instance_id,
+                    "name": outcome.name(),
+                    "action": outcome.action.to_string(),
+                    "succeeded": outcome.result.is_ok(),
+                    "state": outcome.state(),
+                    "duration_seconds": outcome.duration.as_secs_f64(),
+                    "public_ipv4": outcome.ip(Instance::ipv4_address_public),
+                    "private_ipv4": outcome.ip(Instance::ipv4_address_private),
+                    "ipv6": outcome.ip(Instance::ipv6_address),
+                    "error": outcome.error(),
+                })
+            })
+            .collect();
+        log::event(
+            Level::Info,
+            "results",
+            message,
+            serde_json::json!({ "results": results }),
+        );
+        return;
+    }
+
+    let mut rows = vec![[
+        "INSTANCE".to_string(),
+        "NAME".to_string(),
+        "ACTION".to_string(),
+        "RESULT".to_string(),
+        "DURATION".to_string(),
+        "PUBLIC IP".to_string(),
+        "PRIVATE IP".to_string(),
+        "ERROR".to_string(),
+    ]];
+    rows.extend(outcomes.iter().map(|outcome| {
+        [
+            outcome.instance_id.clone(),
+            outcome.name().unwrap_or("-").to_string(),
+            outcome.action.to_string(),
+            outcome.state().to_string(),
+            format!("{:.1}s", outcome.duration.as_secs_f64()),
+            outcome
+                .ip(Instance::ipv4_address_public)
+                .unwrap_or("-")
+                .to_string(),
+            outcome
+                .ip(Instance::ipv4_address_private)
+                .unwrap_or("-")
+                .to_string(),
+            outcome.error().unwrap_or_default(),
+        ]
+    }));
+
+    let mut widths = [0; 8];
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let write = |line: &str| {
+        if stdout_reserved {
+            eprintln!("{}", line)
+        } else {
+            println!("{}", line)
+        }
+    };
+    write("");
+    for (index, row) in rows.iter().enumerate() {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .enumerate()
+            .map(|(column, (cell, width))| {
+                // Padded apart, as the color codes don't take any room
+                let padding = " ".repeat(width - cell.len());
+                match column {
+                    3 if index > 0 => color::state(cell) + &padding,
+                    _ => cell.clone() + &padding,
+                }
+            })
+            .collect();
+        write(cells.join("  ").trim_end());
+    }
+    log::info("results", message);
+}
@@ -0,0 +1,30 @@
+use crate::clients;
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use color_eyre::Result;
+
+/// Disables or enables the Route53 health check, a disabled health check being considered healthy
+pub async fn set_health_check(
+    aws_config: &SdkConfig,
+    health_check_id: &str,
+    disabled: bool,
+) -> Result<()> {
+    clients::route53(aws_config)
+        .update_health_check()
+        .health_check_id(health_check_id)
+        .disabled(disabled)
+        .send()
+        .await
+        .hint("route53:UpdateHealthCheck")?;
+    log::info(
+        "health-check",
+        format!(
+            "{} health check {}",
+            if disabled { "Disabled" } else { "Enabled" },
+            health_check_id
+        ),
+    );
+
+    Ok(())
+}
@@ -0,0 +1,116 @@
+use crate::aws::{self, AwsSchedulerClient, SCHEDULE_PREFIX};
+use crate::clients;
+use crate::config::{Action, Config, ScheduleCommand, ScheduleConfig};
+use crate::log;
+use aws_config::SdkConfig;
+use aws_sdk_scheduler::types::ActionAfterCompletion;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::time::Duration;
+
+pub async fn run(aws_config: &SdkConfig, command: ScheduleCommand) -> Result<()> {
+    let client = AwsSchedulerClient::new(clients::scheduler(aws_config));
+
+    match command {
+        ScheduleCommand::Create(config) => create(&client, config).await,
+        ScheduleCommand::List => list(&client).await,
+        ScheduleCommand::Delete(name) => {
+            client.delete_schedule(&name).await?;
+            println!("Deleted schedule {}", name);
+            Ok(())
+        }
+    }
+}
+
+async fn create(client: &AwsSchedulerClient, config: ScheduleConfig) -> Result<()> {
+    let name = match config.name {
+        Some(name) if name.starts_with(SCHEDULE_PREFIX) => name,
+        Some(name) => format!("{}{}", SCHEDULE_PREFIX, name),
+        None => format!(
+            "{}{}-{}",
+            SCHEDULE_PREFIX, config.action, config.instance_id
+        ),
+    };
+    let target = match config.lambda_arn {
+        Some(function_arn) => aws::lambda_target(
+            &function_arn,
+            &config.action,
+            &config.instance_id,
+            &config.role_arn,
+        )?,
+        None => aws::ec2_target(&config.action, &config.instance_id, &config.role_arn)?,
+    };
+
+    client
+        .create_schedule(
+            &name,
+            &config.cron,
+            &config.timezone,
+            target,
+            ActionAfterCompletion::None,
+        )
+        .await?;
+
+    println!("Created schedule {}", name);
+    Ok(())
+}
+
+async fn list(client: &AwsSchedulerClient) -> Result<()> {
+    let schedules = client.list_schedules().await?;
+    if schedules.is_empty() {
+        println!("No schedules found");
+    }
+
+    for schedule in schedules {
+        println!(
+            "{}\t{}\t{} ({})",
+            schedule.name, schedule.state, schedule.expression, schedule.timezone
+        );
+    }
+
+    Ok(())
+}
+
+/// Schedules a one-time stop of the instance, so the program doesn't have to stay around
+pub async fn schedule_stop(
+    aws_config: &SdkConfig,
+    config: &Config,
+    stop_after: Duration,
+) -> Result<()> {
+    let stop_at = chrono::Utc::now() + chrono::Duration::from_std(stop_after)?;
+    schedule_once(aws_config, config, &Action::Stop, stop_at).await
+}
+
+/// Schedules the action on the instance once, at the given time
+pub async fn schedule_once(
+    aws_config: &SdkConfig,
+    config: &Config,
+    action: &Action,
+    at: DateTime<Utc>,
+) -> Result<()> {
+    let role_arn = config
+        .scheduler_role_arn
+        .as_deref()
+        .ok_or_else(|| eyre!("Missing scheduler role ARN"))?;
+
+    let aws_scheduler_client = AwsSchedulerClient::new(clients::scheduler(aws_config));
+    let schedule_name = aws_scheduler_client
+        .schedule_once(action, &config.instance_id, at, role_arn)
+        .await?;
+
+    log::info(
+        "schedule",
+        format!(
+            "Instance will be {} at {} by schedule {}",
+            match action {
+                Action::Start => "started",
+                Action::Stop => "stopped",
+            },
+            at.format("%Y-%m-%d %H:%M:%S UTC"),
+            schedule_name
+        ),
+    );
+
+    Ok(())
+}
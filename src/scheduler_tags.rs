@@ -0,0 +1,396 @@
+use crate::aws::{AwsEc2InstancesClient, Instance};
+use crate::clients;
+use crate::config::Action;
+use crate::ctl::{self, Command, Request};
+use crate::health;
+use crate::log;
+use crate::notify;
+use crate::protection::{self, TimeWindow};
+use crate::settings::Settings;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::{Filter, InstanceStateName};
+use chrono::{DateTime, SecondsFormat, Utc};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+#[derive(Debug)]
+pub struct SchedulerTagsConfig {
+    /// Tag naming the schedule of each instance, `Schedule` as for AWS Instance Scheduler
+    pub tag_key: String,
+    /// Only print what would be done
+    pub dry_run: bool,
+    /// Keep running, applying the schedules at this interval
+    pub every: Option<Duration>,
+    /// Unix socket `ctl` commands are accepted on while running
+    pub control_socket: Option<PathBuf>,
+}
+
+/// Schedule applied by the daemon
+#[derive(Default)]
+struct Job {
+    paused: bool,
+    last_run: Option<DateTime<Utc>>,
+    /// Summary of the last run, or why it failed
+    last_result: Option<String>,
+}
+
+type Schedules = HashMap<String, Vec<TimeWindow>>;
+
+/// Starts and stops the instances tagged with a schedule, so that they run during its periods
+///
+/// Schedules are defined in the `tag_schedules` of the configuration file, by name. With
+/// `every`, they are applied again at that interval until interrupted, reloaded from the file
+/// when it changes.
+pub async fn run(
+    aws_config: &SdkConfig,
+    settings: &Settings,
+    config_file: Option<&Path>,
+    config: SchedulerTagsConfig,
+) -> Result<()> {
+    let schedules = parse_schedules(settings)?;
+
+    match config.every {
+        None => {
+            let all = schedules.keys().cloned().collect();
+            apply(aws_config, &schedules, &config, &all).await?;
+            Ok(())
+        }
+        Some(every) => daemon(aws_config, schedules, config_file, &config, every).await,
+    }
+}
+
+fn parse_schedules(settings: &Settings) -> Result<Schedules> {
+    settings
+        .tag_schedules
+        .iter()
+        .map(|(name, windows)| {
+            let windows = windows
+                .iter()
+                .map(TimeWindow::parse)
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| eyre!("Schedule {}: {}", name, e))?;
+            Ok((name.clone(), windows))
+        })
+        .collect()
+}
+
+/// Applies the schedules every interval, answering the commands of the control socket and
+/// reloading the schedules when the configuration file changes meanwhile
+///
+/// Runs are never interrupted by the reloads, which happen between them.
+async fn daemon(
+    aws_config: &SdkConfig,
+    mut schedules: Schedules,
+    config_file: Option<&Path>,
+    config: &SchedulerTagsConfig,
+    every: Duration,
+) -> Result<()> {
+    let mut jobs: BTreeMap<String, Job> = schedules
+        .keys()
+        .map(|name| (name.clone(), Job::default()))
+        .collect();
+    let config_file = Settings::path(config_file);
+    // The watcher stops when dropped
+    let (_watcher, mut changes) = match &config_file {
+        Some(path) => {
+            let (watcher, changes) = Settings::watch(path)?;
+            (Some(watcher), Some(changes))
+        }
+        None => (None, None),
+    };
+    let mut commands = match &config.control_socket {
+        Some(path) => Some(ctl::listen(path)?),
+        None => None,
+    };
+    log::info(
+        "schedule",
+        format!(
+            "Applying the schedules every {}",
+            humantime::format_duration(every)
+        ),
+    );
+
+    let mut interval = tokio::time::interval(every);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                health::alive(every);
+                let active: HashSet<String> = jobs
+                    .iter()
+                    .filter(|(_, job)| !job.paused)
+                    .map(|(name, _)| name.clone())
+                    .collect();
+                let result = apply(aws_config, &schedules, config, &active).await;
+                if let Err(e) = &result {
+                    log::error("schedule", format!("Failed to apply the schedules: {}", e));
+                }
+                for name in &active {
+                    record(&mut jobs, name, &result);
+                }
+            }
+            Some(request) = next_request(&mut commands) => {
+                let command = request.command;
+                let answer = answer(aws_config, &schedules, config, &mut jobs, command).await;
+                let _ = request.reply.send(answer);
+            }
+            Some(()) = next_change(&mut changes) => {
+                // Editors write the file in several steps
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                if let Some(changes) = &mut changes {
+                    while changes.try_recv().is_ok() {}
+                }
+                let Some(path) = &config_file else {
+                    continue;
+                };
+                let reloaded = Settings::load(Some(path.as_path()))
+                    .and_then(|settings| parse_schedules(&settings));
+                match reloaded {
+                    Ok(reloaded) => {
+                        log_changes(&schedules, &reloaded);
+                        jobs.retain(|name, _| reloaded.contains_key(name));
+                        for name in reloaded.keys() {
+                            jobs.entry(name.clone()).or_default();
+                        }
+                        schedules = reloaded;
+                    }
+                    Err(e) => log::error(
+                        "schedule",
+                        format!("Failed to reload, keeping the previous schedules: {}", e),
+                    ),
+                }
+            }
+        }
+    }
+}
+
+async fn next_request(commands: &mut Option<mpsc::Receiver<Request>>) -> Option<Request> {
+    match commands {
+        Some(commands) => commands.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_change(changes: &mut Option<mpsc::UnboundedReceiver<()>>) -> Option<()> {
+    match changes {
+        Some(changes) => changes.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Logs the schedules added, removed and changed in the configuration file
+fn log_changes(previous: &Schedules, reloaded: &Schedules) {
+    fn describe(windows: &[TimeWindow]) -> String {
+        windows
+            .iter()
+            .map(TimeWindow::description)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    let mut changes = vec![];
+    for (name, windows) in reloaded.iter().collect::<BTreeMap<_, _>>() {
+        match previous.get(name) {
+            None => changes.push(format!("added {}: {}", name, describe(windows))),
+            Some(before) if describe(before) != describe(windows) => changes.push(format!(
+                "changed {}: {} -> {}",
+                name,
+                describe(before),
+                describe(windows)
+            )),
+            Some(_) => {}
+        }
+    }
+    for name in previous.keys().collect::<BTreeSet<_>>() {
+        if !reloaded.contains_key(name) {
+            changes.push(format!("removed {}", name));
+        }
+    }
+
+    if changes.is_empty() {
+        log::info(
+            "schedule",
+            "Configuration file changed, but not its schedules",
+        );
+    } else {
+        log::info(
+            "schedule",
+            format!("Reloaded the schedules: {}", changes.join("; ")),
+        );
+    }
+}
+
+fn record(jobs: &mut BTreeMap<String, Job>, name: &str, result: &Result<String>) {
+    if let Some(job) = jobs.get_mut(name) {
+        job.last_run = Some(Utc::now());
+        job.last_result = Some(match result {
+            Ok(summary) => summary.clone(),
+            Err(e) => format!("failed: {}", e),
+        });
+    }
+}
+
+async fn answer(
+    aws_config: &SdkConfig,
+    schedules: &Schedules,
+    config: &SchedulerTagsConfig,
+    jobs: &mut BTreeMap<String, Job>,
+    command: Command,
+) -> Result<String, String> {
+    let unknown = |name: &str| format!("unknown job {}, see status", name);
+    match command {
+        Command::Status => Ok(jobs
+            .iter()
+            .map(|(name, job)| {
+                format!(
+                    "{}\t{}\t{}\n",
+                    name,
+                    if job.paused { "paused" } else { "active" },
+                    match (&job.last_run, &job.last_result) {
+                        (Some(at), Some(result)) => format!(
+                            "last run {}: {}",
+                            at.to_rfc3339_opts(SecondsFormat::Secs, true),
+                            result
+                        ),
+                        _ => "never run".to_string(),
+                    }
+                )
+            })
+            .collect()),
+        Command::RunNow(name) => {
+            if !jobs.contains_key(&name) {
+                return Err(unknown(&name));
+            }
+            log::info("schedule", format!("Running {} now, as asked", name));
+            let result = apply(
+                aws_config,
+                schedules,
+                config,
+                &HashSet::from([name.clone()]),
+            )
+            .await;
+            record(jobs, &name, &result);
+            result
+                .map(|summary| format!("{}\n", summary))
+                .map_err(|e| e.to_string())
+        }
+        Command::Pause(name) => set_paused(jobs, &name, true).ok_or_else(|| unknown(&name)),
+        Command::Resume(name) => set_paused(jobs, &name, false).ok_or_else(|| unknown(&name)),
+    }
+}
+
+fn set_paused(jobs: &mut BTreeMap<String, Job>, name: &str, paused: bool) -> Option<String> {
+    jobs.get_mut(name)?.paused = paused;
+    let state = if paused { "Paused" } else { "Resumed" };
+    log::info("schedule", format!("{} {}, as asked", state, name));
+    Some(format!("{} {}\n", state, name))
+}
+
+/// Starts and stops the instances of the given schedules, returning a summary of what was done
+async fn apply(
+    aws_config: &SdkConfig,
+    schedules: &Schedules,
+    config: &SchedulerTagsConfig,
+    jobs: &HashSet<String>,
+) -> Result<String> {
+    let client = AwsEc2InstancesClient::new(clients::ec2(aws_config));
+    let filters = vec![
+        Filter::builder()
+            .name("tag-key")
+            .values(&config.tag_key)
+            .build(),
+        Filter::builder()
+            .name("instance-state-name")
+            .values("running")
+            .values("stopped")
+            .build(),
+    ];
+    // Sorted, so the plan is stable
+    let mut instances = BTreeMap::new();
+    for instance in client.describe_instances(filters).await? {
+        instances.insert(instance.instance_id().to_string(), instance);
+    }
+
+    let mut to_start = vec![];
+    let mut to_stop = vec![];
+    for (instance_id, instance) in &instances {
+        let Some(schedule) = instance.tag(&config.tag_key) else {
+            continue;
+        };
+        if !jobs.contains(schedule) && schedules.contains_key(schedule) {
+            continue;
+        }
+        let Some(windows) = schedules.get(schedule) else {
+            log::warn(
+                "schedule",
+                format!(
+                    "{} has unknown schedule {}, skipping",
+                    instance_id, schedule
+                ),
+            );
+            continue;
+        };
+        let should_run = windows.iter().any(TimeWindow::is_now);
+        match (should_run, instance.state()) {
+            (true, InstanceStateName::Stopped) => to_start.push(instance),
+            (false, InstanceStateName::Running) => to_stop.push(instance),
+            _ => {}
+        }
+    }
+    let to_start = protection::filter("schedule", &Action::Start, to_start);
+    let to_stop = protection::filter("schedule", &Action::Stop, to_stop);
+
+    if to_start.is_empty() && to_stop.is_empty() {
+        println!("Nothing to do");
+        return Ok("nothing to do".to_string());
+    }
+
+    println!("Plan:");
+    for instance_id in &to_start {
+        println!("\tstart {}", instance_id);
+    }
+    for instance_id in &to_stop {
+        println!("\tstop {}", instance_id);
+    }
+
+    if config.dry_run {
+        return Ok(format!(
+            "would start {} and stop {} instances",
+            to_start.len(),
+            to_stop.len()
+        ));
+    }
+
+    change_state(&client, &Action::Start, &to_start, &mut instances).await?;
+    change_state(&client, &Action::Stop, &to_stop, &mut instances).await?;
+    let summary = format!(
+        "Started {} and stopped {} instances",
+        to_start.len(),
+        to_stop.len()
+    );
+    println!("{}", summary);
+
+    Ok(summary)
+}
+
+/// Requests the change of state of the instances, sending the outcome for each to the notifiers
+async fn change_state(
+    client: &AwsEc2InstancesClient,
+    action: &Action,
+    instance_ids: &[String],
+    instances: &mut BTreeMap<String, Instance>,
+) -> Result<()> {
+    let result = client.change_state(action, instance_ids).await;
+    for instance_id in instance_ids {
+        let outcome = match (&result, instances.remove(instance_id)) {
+            (Ok(()), Some(instance)) => Ok(instance),
+            (Ok(()), None) => continue,
+            (Err(e), _) => Err(eyre!("{}", e)),
+        };
+        notify::send(action, instance_id, &outcome).await;
+    }
+    result
+}
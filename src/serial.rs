@@ -0,0 +1,106 @@
+use crate::aws::Instance;
+use crate::clients;
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use std::path::PathBuf;
+use tokio::process::Command;
+
+/// What to do with the serial console once the key has been pushed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SerialConsole {
+    /// Print the ssh command, to run within a minute
+    Print,
+    /// Run ssh, connected to the terminal
+    Exec,
+}
+
+/// Pushes an ephemeral key for the serial console of the instance, then prints or runs the ssh
+/// command connecting to it
+///
+/// Serial console access is enabled for the account and region first if needed.
+pub async fn connect(
+    aws_config: &SdkConfig,
+    instance: &Instance,
+    serial_console: SerialConsole,
+) -> Result<()> {
+    let ec2_client = clients::ec2(aws_config);
+    let enabled = ec2_client
+        .get_serial_console_access_status()
+        .send()
+        .await
+        .hint("ec2:GetSerialConsoleAccessStatus")?
+        .serial_console_access_enabled()
+        .unwrap_or_default();
+    if !enabled {
+        log::info("serial", "Enabling serial console access for the region");
+        ec2_client
+            .enable_serial_console_access()
+            .send()
+            .await
+            .hint("ec2:EnableSerialConsoleAccess")?;
+    }
+
+    let key_file = generate_key(instance.instance_id()).await?;
+    let public_key = std::fs::read_to_string(key_file.with_extension("pub"))?;
+    clients::ec2instanceconnect(aws_config)
+        .send_serial_console_ssh_public_key()
+        .instance_id(instance.instance_id())
+        .serial_port(0)
+        .ssh_public_key(public_key.trim())
+        .send()
+        .await
+        .hint("ec2-instance-connect:SendSerialConsoleSSHPublicKey")?;
+
+    let region = aws_config
+        .region()
+        .ok_or_else(|| eyre!("No region configured"))?;
+    let destination = format!(
+        "{}.port0@serial-console.ec2-instance-connect.{}.aws",
+        instance.instance_id(),
+        region
+    );
+    match serial_console {
+        SerialConsole::Print => {
+            println!("Serial console, within a minute:");
+            println!("\tssh -i {} {}", key_file.display(), destination);
+        }
+        SerialConsole::Exec => {
+            let status = Command::new("ssh")
+                .arg("-i")
+                .arg(&key_file)
+                .arg(&destination)
+                .status()
+                .await
+                .wrap_err("Failed to run ssh")?;
+            if !status.success() {
+                return Err(eyre!("ssh exited with {}", status));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Generates a key pair for the instance in the temporary directory, returning the private key
+async fn generate_key(instance_id: &str) -> Result<PathBuf> {
+    let key_file = std::env::temp_dir().join(format!("aws-start-stop-serial-{}", instance_id));
+    for path in [key_file.clone(), key_file.with_extension("pub")] {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let status = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+        .arg(&key_file)
+        .status()
+        .await
+        .wrap_err("Failed to run ssh-keygen")?;
+    if !status.success() {
+        return Err(eyre!("ssh-keygen exited with {}", status));
+    }
+
+    Ok(key_file)
+}
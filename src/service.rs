@@ -0,0 +1,164 @@
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::ffi::OsString;
+use std::process::Command;
+use std::sync::mpsc;
+use std::sync::OnceLock;
+use std::time::Duration;
+use windows_service::service::{
+    ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+    ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+pub const DEFAULT_NAME: &str = "aws-start-stop";
+
+/// Manages this program as a Windows service, running a long-lived mode such as `proxy`
+#[derive(Debug)]
+pub enum ServiceCommand {
+    /// Registers the service, starting with Windows and running the arguments
+    Install {
+        name: String,
+        args: Vec<String>,
+    },
+    Uninstall {
+        name: String,
+    },
+    /// Entry point of the service, called by the service control manager
+    Run {
+        name: String,
+        args: Vec<String>,
+    },
+}
+
+pub fn run(command: ServiceCommand) -> Result<()> {
+    match command {
+        ServiceCommand::Install { name, args } => install(&name, args),
+        ServiceCommand::Uninstall { name } => uninstall(&name),
+        ServiceCommand::Run { name, args } => {
+            SERVICE
+                .set((name.clone(), args))
+                .map_err(|_| eyre!("The service is already running"))?;
+            service_dispatcher::start(name, ffi_service_main)?;
+            Ok(())
+        }
+    }
+}
+
+fn install(name: &str, args: Vec<String>) -> Result<()> {
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )?;
+
+    let mut launch_arguments: Vec<OsString> = vec![
+        "service".into(),
+        "run".into(),
+        "--name".into(),
+        name.into(),
+        "--".into(),
+    ];
+    launch_arguments.extend(args.into_iter().map(OsString::from));
+    let info = ServiceInfo {
+        name: name.into(),
+        display_name: name.into(),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: std::env::current_exe()?,
+        launch_arguments,
+        dependencies: vec![],
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager.create_service(&info, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description("Starts and stops AWS EC2 instances")?;
+
+    println!("Installed service {}", name);
+    Ok(())
+}
+
+fn uninstall(name: &str) -> Result<()> {
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(
+        name,
+        ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+    )?;
+
+    if service.query_status()?.current_state != ServiceState::Stopped {
+        service.stop()?;
+    }
+    service.delete()?;
+
+    println!("Uninstalled service {}", name);
+    Ok(())
+}
+
+/// Name and arguments of the service being run, as the dispatcher only passes the arguments
+/// given when starting it by hand
+static SERVICE: OnceLock<(String, Vec<String>)> = OnceLock::new();
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_arguments: Vec<OsString>) {
+    // Nothing can be reported from here, the service control manager logs the service stopping
+    let _ = run_service();
+}
+
+/// Runs the arguments in a child process until it exits or the service is stopped
+fn run_service() -> Result<()> {
+    let (name, args) = SERVICE
+        .get()
+        .ok_or_else(|| eyre!("The service isn't configured"))?;
+
+    let (stop_sender, stop_receiver) = mpsc::channel();
+    let status_handle = service_control_handler::register(name, move |control| match control {
+        ServiceControl::Stop | ServiceControl::Shutdown => {
+            let _ = stop_sender.send(());
+            ServiceControlHandlerResult::NoError
+        }
+        ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+        _ => ServiceControlHandlerResult::NotImplemented,
+    })?;
+    let set_status = |state: ServiceState, exit_code: u32| {
+        status_handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted: match state {
+                ServiceState::Running => {
+                    ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+                }
+                _ => ServiceControlAccept::empty(),
+            },
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        })
+    };
+
+    let mut child = match Command::new(std::env::current_exe()?).args(args).spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            set_status(ServiceState::Stopped, 1)?;
+            return Err(e.into());
+        }
+    };
+    set_status(ServiceState::Running, 0)?;
+
+    let exit_code = loop {
+        if stop_receiver.recv_timeout(Duration::from_secs(1)).is_ok() {
+            child.kill()?;
+            child.wait()?;
+            break 0;
+        }
+        if let Some(status) = child.try_wait()? {
+            break status.code().unwrap_or(1) as u32;
+        }
+    };
+    set_status(ServiceState::Stopped, exit_code)?;
+
+    Ok(())
+}
@@ -0,0 +1,231 @@
+use ::notify as fs_notify;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use fs_notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// Settings read from the configuration file
+#[derive(Debug, Default, Deserialize)]
+pub struct Settings {
+    /// Role to assume for each account alias
+    #[serde(default)]
+    pub accounts: HashMap<String, String>,
+    /// Stages of each group, in the order they are acted on
+    #[serde(default)]
+    pub groups: HashMap<String, Vec<Stage>>,
+    /// Instance ids, or tags as `key=value`, of instances which must not be stopped
+    #[serde(default)]
+    pub protected: Vec<String>,
+    /// Tags as `key=value` instances must have to be acted on
+    #[serde(default)]
+    pub require_tags: Vec<String>,
+    /// Time windows during which instances must not be stopped
+    #[serde(default)]
+    pub no_stop_windows: Vec<Window>,
+    /// How long instances must have been running before they can be stopped, e.g. `15m`
+    pub min_runtime: Option<String>,
+    /// Minimum runtime of the instances with these ids, or tags as `key=value`
+    #[serde(default)]
+    pub min_runtimes: HashMap<String, String>,
+    /// Where the state shared between runs is kept, `s3://bucket/key` or a path
+    pub state: Option<String>,
+    /// Channels the outcome of each action is sent to
+    #[serde(default)]
+    pub notifications: Vec<NotifierSettings>,
+    /// Periods during which the instances tagged with each schedule name run, for
+    /// `from-scheduler-tags`
+    #[serde(default)]
+    pub tag_schedules: HashMap<String, Vec<Window>>,
+    /// Values of the arguments by name, e.g. `timeout = 300`, used when not on the command line
+    #[serde(default)]
+    pub defaults: HashMap<String, toml::Value>,
+    /// Instances referred to by a name on the command line
+    #[serde(default)]
+    pub aliases: HashMap<String, Alias>,
+    /// Who may use the APIs of `serve`, and on which instances
+    pub api_auth: Option<ApiAuthSettings>,
+    /// Certificates the gRPC API of `serve` is served with, requiring clients to have one
+    pub api_tls: Option<ApiTlsSettings>,
+}
+
+/// Name standing for an instance, with the arguments always used with it
+#[derive(Debug, Deserialize)]
+pub struct Alias {
+    /// Instance id, or any reference accepted on the command line, e.g. `dev/i-0123456789abcdef0`
+    pub instance: String,
+    /// Region of the instance, when not the configured one
+    pub region: Option<String>,
+    /// Values of the arguments by name, taking precedence over the `defaults` of the file
+    #[serde(default)]
+    pub defaults: HashMap<String, toml::Value>,
+}
+
+/// Days and hours of the week, e.g. `Mon-Fri` from `08:00` to `19:00` in `Europe/Paris`
+#[derive(Debug, Deserialize)]
+pub struct Window {
+    pub days: String,
+    pub from: String,
+    pub to: String,
+    pub timezone: String,
+}
+
+/// Channel notified of the outcome of the actions
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NotifierSettings {
+    /// URL the outcome is posted to as JSON
+    Webhook {
+        url: String,
+    },
+    /// Incoming webhook of a Slack channel
+    Slack {
+        webhook_url: String,
+    },
+    Sns {
+        topic_arn: String,
+    },
+    /// Sent with SES, from a verified address
+    Email {
+        from: String,
+        to: Vec<String>,
+    },
+    Desktop,
+    /// Topic of an ntfy server, e.g. `https://ntfy.sh/my-instances`, the summary being pushed to
+    /// the phones subscribed to it
+    Ntfy {
+        url: String,
+    },
+    /// Events v2 integration, alerted when an action fails and resolved once it succeeds again
+    PagerDuty {
+        routing_key: String,
+    },
+    /// `aws-start-stop-<name>` executable on the `PATH`, given the outcome as JSON
+    Plugin {
+        name: String,
+    },
+}
+
+/// Tokens accepted by the APIs of `serve`, as `Authorization: Bearer` headers
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiAuthSettings {
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+    /// Secret HS256 JSON Web Tokens are signed with, their claims giving what they allow
+    pub jwt_secret: Option<String>,
+}
+
+/// PEM files of the server's certificate and key, and of the CA client certificates are checked
+/// against
+#[derive(Debug, Deserialize)]
+pub struct ApiTlsSettings {
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    pub client_ca: PathBuf,
+}
+
+/// Static token, allowed to act on some instances only, or all of them if none is given
+#[derive(Debug, Clone, Deserialize)]
+pub struct ApiToken {
+    /// Who the token was given to, shown in the messages
+    pub name: String,
+    pub token: String,
+    #[serde(default)]
+    pub instances: Vec<String>,
+    /// `start`, `stop` or `status`, all of them if none is given
+    #[serde(default)]
+    pub actions: Vec<String>,
+}
+
+/// Instances of a group acted on at the same time
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum Stage {
+    Instance(String),
+    Instances(Vec<String>),
+}
+
+impl Stage {
+    pub fn instance_ids(&self) -> Vec<String> {
+        match self {
+            Self::Instance(instance_id) => vec![instance_id.clone()],
+            Self::Instances(instance_ids) => instance_ids.clone(),
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the given file, or the default one if it exists
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = Self::path(path) else {
+            return Ok(Self::default());
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .wrap_err_with(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&contents).wrap_err_with(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// The given file, or the default one if it exists
+    pub fn path(path: Option<&Path>) -> Option<PathBuf> {
+        match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => default_path().filter(|path| path.exists()),
+        }
+    }
+
+    /// Sends a message each time the file changes, until the returned watcher is dropped
+    ///
+    /// Its directory is watched, as editors often replace the file rather than write to it.
+    pub fn watch(path: &Path) -> Result<(RecommendedWatcher, mpsc::UnboundedReceiver<()>)> {
+        let path = std::fs::canonicalize(path)
+            .wrap_err_with(|| format!("Failed to watch {}", path.display()))?;
+        let directory = path.parent().unwrap_or(&path).to_path_buf();
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let mut watcher =
+            fs_notify::recommended_watcher(move |event: fs_notify::Result<Event>| {
+                if let Ok(event) = event {
+                    if event
+                        .paths
+                        .iter()
+                        .any(|changed| changed.file_name() == path.file_name())
+                    {
+                        let _ = sender.send(());
+                    }
+                }
+            })?;
+        watcher.watch(&directory, RecursiveMode::NonRecursive)?;
+        Ok((watcher, receiver))
+    }
+
+    pub fn account_role(&self, account: &str) -> Result<&str> {
+        self.accounts
+            .get(account)
+            .map(String::as_str)
+            .ok_or_else(|| eyre!("Account {} is not in the configuration file", account))
+    }
+
+    /// Finds a role of the account among those of the account aliases
+    pub fn account_id_role(&self, account_id: &str) -> Option<&str> {
+        self.accounts
+            .values()
+            .map(String::as_str)
+            .find(|role_arn| role_arn.split(':').nth(4) == Some(account_id))
+    }
+
+    pub fn group(&self, group: &str) -> Result<Vec<Vec<String>>> {
+        let stages = self
+            .groups
+            .get(group)
+            .ok_or_else(|| eyre!("Group {} is not in the configuration file", group))?;
+
+        Ok(stages.iter().map(Stage::instance_ids).collect())
+    }
+}
+
+/// `aws-start-stop/config.toml` in the user's configuration directory
+pub fn default_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("aws-start-stop").join("config.toml"))
+}
@@ -0,0 +1,156 @@
+use crate::bot::{self, BotCommand, BotInstance};
+use crate::log;
+use aws_config::SdkConfig;
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::post;
+use axum::{Json, Router};
+use color_eyre::Result;
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// How old a request may be before it is considered replayed, as recommended by Slack
+const MAX_REQUEST_AGE: i64 = 5 * 60;
+
+#[derive(Debug)]
+pub struct SlackConfig {
+    pub listen: SocketAddr,
+    /// Secret Slack signs its requests with, from the settings of the app
+    pub signing_secret: String,
+    pub instances: Vec<BotInstance>,
+    pub timeout: u64,
+}
+
+/// Fields of a slash command request used here
+#[derive(Deserialize)]
+struct SlashCommand {
+    text: String,
+    user_name: String,
+    response_url: String,
+}
+
+struct SlackState {
+    aws_config: SdkConfig,
+    config: SlackConfig,
+    client: reqwest::Client,
+}
+
+/// Serves the slash command until interrupted
+pub async fn run(aws_config: &SdkConfig, config: SlackConfig) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(config.listen).await?;
+    log::info(
+        "slack",
+        format!("Serving the slash command on {}", config.listen),
+    );
+
+    let state = Arc::new(SlackState {
+        aws_config: aws_config.clone(),
+        config,
+        client: reqwest::Client::new(),
+    });
+    let app = Router::new()
+        .route("/", post(slash_command))
+        .with_state(state);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    Ok(())
+}
+
+/// Acknowledges the command right away, as Slack expects an answer within 3 seconds, and posts
+/// the outcome to its response URL
+async fn slash_command(
+    State(state): State<Arc<SlackState>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<serde_json::Value>, StatusCode> {
+    if !signed(&state.config.signing_secret, &headers, &body) {
+        log::warn("slack", "Rejected a request with an invalid signature");
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let request: SlashCommand =
+        serde_urlencoded::from_bytes(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+
+    let mut words = request.text.split_whitespace();
+    let command = match words.next() {
+        Some("start") => BotCommand::Start,
+        Some("stop") => BotCommand::Stop,
+        Some("status") => BotCommand::Status,
+        _ => return Ok(ephemeral("Usage: start|stop|status [instance]".to_string())),
+    };
+    let instance_id = match bot::select_instance(words.next(), &state.config.instances) {
+        Ok(instance_id) => instance_id,
+        Err(e) => return Ok(ephemeral(e.to_string())),
+    };
+
+    log::info(
+        "slack",
+        format!(
+            "{} asked for {:?} of {}",
+            request.user_name, command, instance_id
+        ),
+    );
+    let acknowledgement = format!("Working on {}...", instance_id);
+    tokio::spawn(async move {
+        let reply = match bot::execute(
+            &state.aws_config,
+            &instance_id,
+            command,
+            state.config.timeout,
+        )
+        .await
+        {
+            Ok(reply) => reply,
+            Err(e) => format!("Failed: {}", e),
+        };
+        let body = serde_json::json!({"response_type": "in_channel", "text": reply});
+        let posted = state
+            .client
+            .post(&request.response_url)
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(e) = posted {
+            log::warn("slack", format!("Failed to post the outcome: {}", e));
+        }
+    });
+
+    Ok(ephemeral(acknowledgement))
+}
+
+/// Reply only shown to the user who sent the command
+fn ephemeral(text: String) -> Json<serde_json::Value> {
+    Json(serde_json::json!({"response_type": "ephemeral", "text": text}))
+}
+
+/// Checks the signature Slack computes over the timestamp and body of its requests
+fn signed(signing_secret: &str, headers: &HeaderMap, body: &[u8]) -> bool {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let (Some(timestamp), Some(signature)) = (
+        header("x-slack-request-timestamp"),
+        header("x-slack-signature"),
+    ) else {
+        return false;
+    };
+    let recent = timestamp.parse::<i64>().map_or(false, |timestamp| {
+        (chrono::Utc::now().timestamp() - timestamp).abs() <= MAX_REQUEST_AGE
+    });
+    let Some(Ok(signature)) = signature.strip_prefix("v0=").map(hex::decode) else {
+        return false;
+    };
+
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(signing_secret.as_bytes()) else {
+        return false;
+    };
+    mac.update(format!("v0:{}:", timestamp).as_bytes());
+    mac.update(body);
+    recent && mac.verify_slice(&signature).is_ok()
+}
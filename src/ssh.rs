@@ -0,0 +1,167 @@
+use crate::aws::Instance;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Where and how to write an ssh_config `Host` block for the instance
+#[derive(Debug, Clone)]
+pub struct SshConfig {
+    pub path: PathBuf,
+    /// Host alias, defaults to the file name
+    pub host: Option<String>,
+    pub user: Option<String>,
+    pub identity_file: Option<PathBuf>,
+}
+
+impl SshConfig {
+    fn host(&self) -> Result<String> {
+        match &self.host {
+            Some(host) => Ok(host.clone()),
+            None => self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| eyre!("Pass --ssh-host to name the host")),
+        }
+    }
+}
+
+/// Line cloud-init prints to the console after the instance's host keys
+pub const HOST_KEYS_END: &str = "-----END SSH HOST KEY KEYS-----";
+const HOST_KEYS_BEGIN: &str = "-----BEGIN SSH HOST KEY KEYS-----";
+
+/// Extracts the public host keys cloud-init prints to the console, as `type key` pairs
+pub fn parse_host_keys(console_output: &str) -> Vec<String> {
+    console_output
+        .lines()
+        .skip_while(|line| !line.contains(HOST_KEYS_BEGIN))
+        .skip(1)
+        .take_while(|line| !line.contains(HOST_KEYS_END))
+        .filter_map(|line| {
+            // Drop the comment and anything the console may have prefixed the line with
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let index = fields
+                .iter()
+                .position(|field| field.starts_with("ssh-") || field.starts_with("ecdsa-"))?;
+            let key = fields.get(index + 1)?;
+            Some(format!("{} {}", fields[index], key))
+        })
+        .collect()
+}
+
+/// Adds the host keys to a known_hosts file for every address of the instance
+///
+/// Previous entries for these addresses are removed, as they are often reused by other instances.
+pub fn update_known_hosts(path: &Path, instance: &Instance, host_keys: &[String]) -> Result<()> {
+    let names: Vec<&str> = [
+        instance.public_dns_name(),
+        instance.ipv4_address_public(),
+        instance.private_dns_name(),
+        instance.ipv4_address_private(),
+        instance.ipv6_address(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect();
+    if names.is_empty() {
+        return Err(eyre!("The instance has no address"));
+    }
+
+    let mut contents = String::new();
+    for line in read_existing(path)?.lines() {
+        let hosts = line.split_whitespace().next().unwrap_or_default();
+        if !hosts.split(',').any(|host| names.contains(&host)) {
+            contents.push_str(line);
+            contents.push('\n');
+        }
+    }
+    for key in host_keys {
+        contents.push_str(&format!("{} {}\n", names.join(","), key));
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)?;
+
+    Ok(())
+}
+
+/// Writes or updates the `Host` block pointing to the instance's current address
+///
+/// The rest of the file is kept as is, so it can be a shared config.
+pub fn write_config(config: &SshConfig, instance: &Instance) -> Result<()> {
+    let host = config.host()?;
+    // The public DNS name only resolves to the IPv4 address
+    let address = instance
+        .address()
+        .ok_or_else(|| eyre!("The instance has no address"))?;
+    let hostname = match (address, instance.public_dns_name()) {
+        (IpAddr::V4(_), Some(name)) => name.to_string(),
+        _ => address.to_string(),
+    };
+
+    let mut block = format!("Host {}\n    HostName {}\n", host, hostname);
+    if address.is_ipv6() {
+        block.push_str("    AddressFamily inet6\n");
+    }
+    if let Some(user) = &config.user {
+        block.push_str(&format!("    User {}\n", user));
+    }
+    if let Some(identity_file) = &config.identity_file {
+        block.push_str(&format!("    IdentityFile {}\n", identity_file.display()));
+    }
+
+    let existing = read_existing(&config.path)?;
+    let contents = replace_host_block(&existing, &host, &block);
+
+    if let Some(parent) = config.path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&config.path, contents)?;
+
+    Ok(())
+}
+
+fn read_existing(path: &Path) -> Result<String> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Replaces the block for `host`, which runs until the next `Host` or `Match` line,
+/// or appends `block` if there is none
+fn replace_host_block(existing: &str, host: &str, block: &str) -> String {
+    let mut output = String::new();
+    let mut in_block = false;
+    let mut replaced = false;
+
+    for line in existing.lines() {
+        let keyword = line.split_whitespace().next().unwrap_or_default();
+        if keyword.eq_ignore_ascii_case("host") || keyword.eq_ignore_ascii_case("match") {
+            in_block =
+                keyword.eq_ignore_ascii_case("host") && line.split_whitespace().skip(1).eq([host]);
+            if in_block {
+                output.push_str(block);
+                replaced = true;
+                continue;
+            }
+        }
+        if !in_block {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+
+    if !replaced {
+        if !output.is_empty() && !output.ends_with("\n\n") {
+            output.push('\n');
+        }
+        output.push_str(block);
+    }
+
+    output
+}
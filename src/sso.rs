@@ -0,0 +1,159 @@
+use crate::credentials;
+use aws_config::SdkConfig;
+use aws_sdk_ssooidc::config::Region;
+use chrono::{SecondsFormat, Utc};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use sha1::{Digest, Sha1};
+use std::path::PathBuf;
+use tokio::time::{sleep, Duration};
+
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// IAM Identity Center settings of the selected profile
+pub struct SsoProfile {
+    start_url: String,
+    region: String,
+    /// Name of the `sso-session` section, if the profile uses one instead of legacy settings
+    session_name: Option<String>,
+}
+
+impl SsoProfile {
+    /// Reads the selected profile, returning `None` if it doesn't use IAM Identity Center
+    pub async fn load() -> Result<Option<Self>> {
+        let profiles = credentials::load_profiles().await?;
+        let Some(profile) = profiles.get_profile(profiles.selected_profile()) else {
+            return Ok(None);
+        };
+
+        if let Some(session_name) = profile.get("sso_session") {
+            let session = profiles
+                .sso_session(session_name)
+                .ok_or_else(|| eyre!("SSO session {} not found", session_name))?;
+            let start_url = session
+                .get("sso_start_url")
+                .ok_or_else(|| eyre!("SSO session {} has no sso_start_url", session_name))?;
+            let region = session
+                .get("sso_region")
+                .ok_or_else(|| eyre!("SSO session {} has no sso_region", session_name))?;
+            return Ok(Some(Self {
+                start_url: start_url.to_string(),
+                region: region.to_string(),
+                session_name: Some(session_name.to_string()),
+            }));
+        }
+
+        match (profile.get("sso_start_url"), profile.get("sso_region")) {
+            (Some(start_url), Some(region)) => Ok(Some(Self {
+                start_url: start_url.to_string(),
+                region: region.to_string(),
+                session_name: None,
+            })),
+            _ => Ok(None),
+        }
+    }
+
+    /// Path of the token cache file the SDK reads for this profile
+    fn cache_path(&self) -> Result<PathBuf> {
+        let identifier = self.session_name.as_deref().unwrap_or(&self.start_url);
+        let hash = Sha1::digest(identifier.as_bytes());
+        let home = dirs::home_dir().ok_or_else(|| eyre!("Failed to find the home directory"))?;
+
+        Ok(home
+            .join(".aws")
+            .join("sso")
+            .join("cache")
+            .join(format!("{:x}.json", hash)))
+    }
+}
+
+/// Runs the OIDC device authorization flow and caches the resulting token where the SDK expects it
+pub async fn login(aws_config: &SdkConfig, profile: &SsoProfile) -> Result<()> {
+    let client = aws_sdk_ssooidc::client::Client::from_conf(
+        aws_sdk_ssooidc::config::Builder::from(aws_config)
+            .region(Region::new(profile.region.clone()))
+            .build(),
+    );
+
+    let registration = client
+        .register_client()
+        .client_name("aws-start-stop")
+        .client_type("public")
+        .send()
+        .await?;
+    let client_id = registration
+        .client_id()
+        .ok_or_else(|| eyre!("SSO client registration returned no client id"))?;
+    let client_secret = registration
+        .client_secret()
+        .ok_or_else(|| eyre!("SSO client registration returned no client secret"))?;
+
+    let authorization = client
+        .start_device_authorization()
+        .client_id(client_id)
+        .client_secret(client_secret)
+        .start_url(&profile.start_url)
+        .send()
+        .await?;
+    let device_code = authorization
+        .device_code()
+        .ok_or_else(|| eyre!("SSO device authorization returned no device code"))?;
+
+    println!(
+        "Log in to AWS by opening {} and confirming the code {}",
+        authorization
+            .verification_uri_complete()
+            .unwrap_or_default(),
+        authorization.user_code().unwrap_or_default()
+    );
+
+    let mut interval = Duration::from_secs(authorization.interval().max(1) as u64);
+    let token = loop {
+        sleep(interval).await;
+        let result = client
+            .create_token()
+            .client_id(client_id)
+            .client_secret(client_secret)
+            .grant_type(DEVICE_CODE_GRANT_TYPE)
+            .device_code(device_code)
+            .send()
+            .await;
+
+        match result {
+            Ok(token) => break token,
+            Err(err) => match err.as_service_error() {
+                Some(err) if err.is_authorization_pending_exception() => {}
+                Some(err) if err.is_slow_down_exception() => interval += Duration::from_secs(5),
+                _ => return Err(err.into()),
+            },
+        }
+    };
+
+    let access_token = token
+        .access_token()
+        .ok_or_else(|| eyre!("SSO token creation returned no access token"))?;
+    let expires_at = Utc::now() + chrono::Duration::seconds(token.expires_in().into());
+    let registration_expires_at =
+        chrono::DateTime::from_timestamp(registration.client_secret_expires_at(), 0)
+            .ok_or_else(|| eyre!("SSO client registration returned an invalid expiration"))?;
+
+    let cached_token = serde_json::json!({
+        "startUrl": profile.start_url,
+        "region": profile.region,
+        "accessToken": access_token,
+        "expiresAt": expires_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        "clientId": client_id,
+        "clientSecret": client_secret,
+        "registrationExpiresAt": registration_expires_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+        "refreshToken": token.refresh_token(),
+    });
+
+    let path = profile.cache_path()?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(&path, serde_json::to_vec_pretty(&cached_token)?)?;
+
+    println!("Logged in to AWS");
+    Ok(())
+}
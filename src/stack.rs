@@ -0,0 +1,148 @@
+use crate::clients;
+use crate::color;
+use crate::config::{self, Action, Config};
+use crate::error::Hint;
+use crate::log;
+use aws_config::SdkConfig;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::io::IsTerminal;
+use tokio::task::JoinSet;
+use tokio::time::{Duration, Instant};
+
+/// Prefix of instances read from CloudFormation, e.g. `cfn:my-stack:WebServerInstanceId`
+pub const PREFIX: &str = "cfn:";
+
+/// Reads the id of the instance from an output of the stack, or else the physical id of one of
+/// its resources, given as `STACK:NAME`
+pub async fn resolve(aws_config: &SdkConfig, reference: &str) -> Result<String> {
+    // The stack may be given as an ARN, which contains colons
+    let (stack, name) = reference
+        .rsplit_once(':')
+        .ok_or_else(|| eyre!("{} must be STACK:OUTPUT or STACK:RESOURCE", reference))?;
+    let client = clients::cloudformation(aws_config);
+
+    let output = client
+        .describe_stacks()
+        .stack_name(stack)
+        .send()
+        .await
+        .hint("cloudformation:DescribeStacks")?
+        .stacks()
+        .first()
+        .ok_or_else(|| eyre!("Stack {} not found", stack))?
+        .outputs()
+        .iter()
+        .find(|output| output.output_key() == Some(name))
+        .and_then(|output| output.output_value())
+        .map(str::to_string);
+    let value = match output {
+        Some(value) => value,
+        None => client
+            .describe_stack_resource()
+            .stack_name(stack)
+            .logical_resource_id(name)
+            .send()
+            .await
+            .hint("cloudformation:DescribeStackResource")?
+            .stack_resource_detail()
+            .and_then(|resource| resource.physical_resource_id())
+            .ok_or_else(|| eyre!("Stack {} has no output or resource {}", stack, name))?
+            .to_string(),
+    };
+    if value.starts_with('@') || value.contains('/') {
+        return Err(eyre!(
+            "{} of stack {} must be the id of an instance",
+            name,
+            stack
+        ));
+    }
+
+    config::parse_instance(&value).map_err(|e| eyre!("{} of stack {}: {}", name, stack, e))
+}
+
+/// Applies the action to every instance of the stack at once, then prints how each went
+pub async fn run(aws_config: &SdkConfig, config: Config) -> Result<()> {
+    let stack = config
+        .stack
+        .as_deref()
+        .ok_or_else(|| eyre!("Missing stack"))?;
+    let instance_ids = instances(aws_config, stack).await?;
+    if instance_ids.is_empty() {
+        println!("Stack {} has no instances", stack);
+        return Ok(());
+    }
+    if config.action == Action::Stop && !config.yes && std::io::stdin().is_terminal() {
+        crate::confirm_stop(aws_config, &instance_ids).await?;
+    }
+
+    let mut tasks = JoinSet::new();
+    for instance_id in instance_ids {
+        let aws_config = aws_config.clone();
+        let config = config.for_instance(&instance_id);
+        tasks.spawn(async move {
+            let started = Instant::now();
+            let future =
+                crate::work_with_retries(&aws_config, &config, &config.action, config.timeout);
+            let result = log::with_instance(&instance_id, future).await;
+            (instance_id, result, started.elapsed())
+        });
+    }
+
+    let mut failed = 0;
+    while let Some(joined) = tasks.join_next().await {
+        let (instance_id, result, elapsed) = joined?;
+        let elapsed = humantime::format_duration(Duration::from_secs(elapsed.as_secs()));
+        match result {
+            Ok(instance) => println!(
+                "{}\t{}\t{}",
+                instance_id,
+                color::state(instance.state().as_str()),
+                elapsed
+            ),
+            Err(e) => {
+                failed += 1;
+                println!("{}\tfailed\t{}\t{}", instance_id, elapsed, e)
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(eyre!(
+            "Failed to {} {} instances of {}",
+            config.action,
+            failed,
+            stack
+        ));
+    }
+
+    Ok(())
+}
+
+/// Ids of the `AWS::EC2::Instance` resources of the stack
+async fn instances(aws_config: &SdkConfig, stack: &str) -> Result<Vec<String>> {
+    let mut resources = clients::cloudformation(aws_config)
+        .list_stack_resources()
+        .stack_name(stack)
+        .into_paginator()
+        .items()
+        .send();
+
+    let mut instance_ids = vec![];
+    while let Some(resource) = resources
+        .next()
+        .await
+        .transpose()
+        .hint("cloudformation:ListStackResources")?
+    {
+        if resource.resource_type() != Some("AWS::EC2::Instance") {
+            continue;
+        }
+        // Resources not created yet have no id
+        if let Some(instance_id) = resource.physical_resource_id().filter(|id| !id.is_empty()) {
+            instance_ids.push(instance_id.to_string());
+        }
+    }
+
+    Ok(instance_ids)
+}
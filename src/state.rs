@@ -0,0 +1,295 @@
+use crate::clients;
+use crate::config::Action;
+use crate::error::Hint;
+use aws_config::SdkConfig;
+use aws_sdk_s3::error::ProvideErrorMetadata;
+use aws_sdk_s3::primitives::ByteStream;
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Times a conflicting update by someone else is retried
+const ATTEMPTS: usize = 5;
+/// Durations kept for each instance and action
+const DURATIONS: usize = 5;
+
+/// What the program remembers between runs
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct State {
+    /// Last action on each group
+    #[serde(default)]
+    pub groups: BTreeMap<String, LastAction>,
+    /// Seconds the last starts and stops of each instance took, the latest last
+    #[serde(default)]
+    pub durations: BTreeMap<String, Durations>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Durations {
+    #[serde(default)]
+    pub start: Vec<f64>,
+    #[serde(default)]
+    pub stop: Vec<f64>,
+}
+
+impl Durations {
+    fn of(&mut self, action: &Action) -> &mut Vec<f64> {
+        match action {
+            Action::Start => &mut self.start,
+            Action::Stop => &mut self.stop,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct LastAction {
+    pub action: String,
+    pub at: DateTime<Utc>,
+    pub by: String,
+    pub succeeded: bool,
+}
+
+/// Where the state is kept
+///
+/// Saving only succeeds if the state wasn't saved by someone else since it was loaded.
+pub trait StateStore {
+    /// Returns the saved contents, if any, and their version
+    async fn load(&self) -> Result<Option<(Vec<u8>, String)>>;
+    /// Saves the contents if the version is still the given one, returning whether it was
+    async fn save(&self, contents: Vec<u8>, version: Option<&str>) -> Result<bool>;
+}
+
+/// Local file, for a single operator
+pub struct FileStore {
+    path: PathBuf,
+}
+
+impl StateStore for FileStore {
+    async fn load(&self) -> Result<Option<(Vec<u8>, String)>> {
+        match std::fs::read(&self.path) {
+            Ok(contents) => Ok(Some((contents, String::new()))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).wrap_err_with(|| format!("Failed to read {}", self.path.display())),
+        }
+    }
+
+    async fn save(&self, contents: Vec<u8>, _version: Option<&str>) -> Result<bool> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(&self.path, contents)
+            .wrap_err_with(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(true)
+    }
+}
+
+/// S3 object shared by operators and CI agents, updated with conditional writes
+pub struct S3Store {
+    client: aws_sdk_s3::client::Client,
+    bucket: String,
+    key: String,
+}
+
+impl StateStore for S3Store {
+    async fn load(&self) -> Result<Option<(Vec<u8>, String)>> {
+        let response = match self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .send()
+            .await
+        {
+            Err(e) if e.as_service_error().map_or(false, |e| e.is_no_such_key()) => {
+                return Ok(None)
+            }
+            response => response.hint("s3:GetObject")?,
+        };
+        let etag = response.e_tag().unwrap_or_default().to_string();
+        let contents = response.body.collect().await?.into_bytes().to_vec();
+
+        Ok(Some((contents, etag)))
+    }
+
+    async fn save(&self, contents: Vec<u8>, version: Option<&str>) -> Result<bool> {
+        let request = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&self.key)
+            .content_type("application/json")
+            .body(ByteStream::from(contents));
+        let request = match version {
+            Some(etag) => request.if_match(etag),
+            None => request.if_none_match("*"),
+        };
+        match request.send().await {
+            Err(e)
+                if matches!(
+                    e.code(),
+                    Some("PreconditionFailed" | "ConditionalRequestConflict")
+                ) =>
+            {
+                Ok(false)
+            }
+            result => result
+                .hint("s3:PutObject")
+                .map(|_| true)
+                .map_err(Into::into),
+        }
+    }
+}
+
+/// Applies the change to the state, starting over if someone else saved it meanwhile
+async fn update(store: &impl StateStore, change: impl Fn(&mut State)) -> Result<()> {
+    for _ in 0..ATTEMPTS {
+        let (mut state, version) = match store.load().await? {
+            Some((contents, version)) => (serde_json::from_slice(&contents)?, Some(version)),
+            None => (State::default(), None),
+        };
+        change(&mut state);
+        if store
+            .save(serde_json::to_vec_pretty(&state)?, version.as_deref())
+            .await?
+        {
+            return Ok(());
+        }
+    }
+
+    Err(eyre!("The state kept changing, giving up on saving it"))
+}
+
+/// Where the state is kept: `s3://bucket/key`, a path, or the default file
+pub enum Store {
+    File(FileStore),
+    S3(S3Store),
+}
+
+impl Store {
+    pub fn new(aws_config: &SdkConfig, location: Option<&str>) -> Result<Self> {
+        match location {
+            Some(location) => match location.strip_prefix("s3://") {
+                Some(object) => {
+                    let (bucket, key) = object
+                        .split_once('/')
+                        .filter(|(bucket, key)| !bucket.is_empty() && !key.is_empty())
+                        .ok_or_else(|| eyre!("{} isn't s3://bucket/key", location))?;
+                    Ok(Self::S3(S3Store {
+                        client: clients::s3(aws_config),
+                        bucket: bucket.to_string(),
+                        key: key.to_string(),
+                    }))
+                }
+                None => Ok(Self::File(FileStore {
+                    path: PathBuf::from(location),
+                })),
+            },
+            None => {
+                let path = dirs::data_dir()
+                    .ok_or_else(|| eyre!("No data directory to keep the state in"))?
+                    .join("aws-start-stop")
+                    .join("state.json");
+                Ok(Self::File(FileStore { path }))
+            }
+        }
+    }
+
+    async fn load(&self) -> Result<State> {
+        let contents = match self {
+            Self::File(store) => store.load().await?,
+            Self::S3(store) => store.load().await?,
+        };
+        match contents {
+            Some((contents, _)) => Ok(serde_json::from_slice(&contents)?),
+            None => Ok(State::default()),
+        }
+    }
+
+    async fn update(&self, change: impl Fn(&mut State)) -> Result<()> {
+        match self {
+            Self::File(store) => update(store, change).await,
+            Self::S3(store) => update(store, change).await,
+        }
+    }
+
+    /// How long the last times the action took on the instance
+    pub async fn durations(&self, instance_id: &str, action: &Action) -> Result<Vec<Duration>> {
+        let mut state = self.load().await?;
+        let durations = match state.durations.get_mut(instance_id) {
+            Some(durations) => durations.of(action),
+            None => return Ok(vec![]),
+        };
+        Ok(durations
+            .iter()
+            .filter_map(|secs| Duration::try_from_secs_f64(*secs).ok())
+            .collect())
+    }
+
+    pub async fn record_duration(
+        &self,
+        instance_id: &str,
+        action: &Action,
+        duration: Duration,
+    ) -> Result<()> {
+        self.update(|state| {
+            let durations = state
+                .durations
+                .entry(instance_id.to_string())
+                .or_default()
+                .of(action);
+            durations.push(duration.as_secs_f64());
+            let excess = durations.len().saturating_sub(DURATIONS);
+            durations.drain(..excess);
+        })
+        .await
+    }
+
+    /// Last action on the group, by anyone sharing the state
+    pub async fn last_group_action(&self, group: &str) -> Result<Option<LastAction>> {
+        Ok(self.load().await?.groups.remove(group))
+    }
+
+    pub async fn record_group_action(
+        &self,
+        group: &str,
+        action: &Action,
+        by: &str,
+        succeeded: bool,
+    ) -> Result<()> {
+        self.update(|state| {
+            state.groups.insert(
+                group.to_string(),
+                LastAction {
+                    action: action.to_string(),
+                    at: Utc::now(),
+                    by: by.to_string(),
+                    succeeded,
+                },
+            );
+        })
+        .await
+    }
+}
+
+static STORE: OnceLock<Store> = OnceLock::new();
+
+/// Keeps the state at the location of the configuration file, or in the default file
+pub fn init(aws_config: &SdkConfig, location: Option<&str>) -> Result<()> {
+    // Without a data directory, there's nowhere to keep the state unless told where
+    if location.is_none() && dirs::data_dir().is_none() {
+        return Ok(());
+    }
+    STORE
+        .set(Store::new(aws_config, location)?)
+        .map_err(|_| eyre!("The state is already configured"))
+}
+
+/// Where the state is kept, if this run keeps any
+pub fn store() -> Option<&'static Store> {
+    STORE.get()
+}
@@ -0,0 +1,197 @@
+use crate::aws::{self, AwsEc2InstancesClient, Instance};
+use crate::clients;
+use crate::color;
+use crate::config::{Action, Config};
+use crate::log;
+use crate::regions::{self, Regions};
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::Filter;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::io::{BufRead, IsTerminal, Write};
+use tokio::task::JoinSet;
+use tokio::time::{timeout, Duration, Instant};
+
+#[derive(Debug)]
+pub struct StopAllConfig {
+    /// Only stop instances with all these tags
+    pub tags: Vec<(String, String)>,
+    pub regions: Option<Regions>,
+    /// Only stop instances launched at least this long ago
+    pub older_than: Option<Duration>,
+    /// Don't ask for confirmation
+    pub yes: bool,
+    pub timeout: u64,
+}
+
+/// Stops every running instance with the tags in the regions at once, then prints how each went
+pub async fn run(aws_config: &SdkConfig, config: StopAllConfig) -> Result<()> {
+    let mut filters: Vec<Filter> = config
+        .tags
+        .iter()
+        .map(|(key, value)| aws::tag_filter(key, value))
+        .collect();
+    filters.push(aws::actionable_filter(&Action::Stop));
+
+    let mut found = find(aws_config, config.regions.as_ref(), filters).await?;
+    if let Some(older_than) = config.older_than {
+        // Without a launch time, the uptime is unknown so the instance is left alone
+        found
+            .retain(|(_, _, instance)| uptime(instance).is_some_and(|uptime| uptime >= older_than));
+    }
+    if found.is_empty() {
+        println!("No running instance matches");
+        return Ok(());
+    }
+
+    for (region, _, instance) in &found {
+        println!(
+            "{}\t{}\t{}\trunning for {}",
+            region,
+            instance.instance_id(),
+            instance.name().unwrap_or("-"),
+            uptime(instance).map_or("unknown".to_string(), |uptime| {
+                humantime::format_duration(Duration::from_secs(uptime.as_secs() / 60 * 60))
+                    .to_string()
+            })
+        );
+    }
+
+    if !config.yes {
+        confirm(&found)?;
+    }
+
+    stop(found, config.timeout).await
+}
+
+/// Instances matching the filters in each of the regions, along with the region and its
+/// configuration
+pub async fn find(
+    aws_config: &SdkConfig,
+    regions: Option<&Regions>,
+    filters: Vec<Filter>,
+) -> Result<Vec<(String, SdkConfig, Instance)>> {
+    let results = regions::fan_out(aws_config, regions, |aws_config| {
+        let filters = filters.clone();
+        async move {
+            let client = AwsEc2InstancesClient::new(clients::ec2(&aws_config));
+            let instances = client.describe_instances(filters).await?;
+            Ok((aws_config, instances))
+        }
+    })
+    .await?;
+
+    let mut found = vec![];
+    for (region, result) in results {
+        match result {
+            Ok((region_config, instances)) => found.extend(
+                instances
+                    .into_iter()
+                    .map(|instance| (region.clone(), region_config.clone(), instance)),
+            ),
+            // Better to stop what can be than nothing at all
+            Err(e) => log::warn(
+                "stop-all",
+                format!("Failed to list the instances of {}: {}", region, e),
+            ),
+        }
+    }
+
+    Ok(found)
+}
+
+/// Stops the instances at once, then prints how each went
+pub async fn stop(found: Vec<(String, SdkConfig, Instance)>, timeout_secs: u64) -> Result<()> {
+    let mut tasks = JoinSet::new();
+    for (region, region_config, instance) in found {
+        let instance_id = instance.instance_id().to_string();
+        let name = instance.name().unwrap_or("-").to_string();
+        let config = Config {
+            yes: true,
+            timeout: timeout_secs,
+            ..Config::new(Action::Stop, instance_id.clone())
+        };
+        tasks.spawn(async move {
+            let started = Instant::now();
+            let future = timeout(
+                Duration::from_secs(config.timeout),
+                crate::work_with_hooks(&region_config, &config, &Action::Stop),
+            );
+            let result = match log::with_instance(&instance_id, future).await {
+                Ok(result) => result,
+                Err(_) => Err(eyre!("Timed out")),
+            };
+            (region, instance_id, name, result, started.elapsed())
+        });
+    }
+
+    let mut failed = 0;
+    while let Some(joined) = tasks.join_next().await {
+        let (region, instance_id, name, result, elapsed) = joined?;
+        let elapsed = humantime::format_duration(Duration::from_secs(elapsed.as_secs()));
+        match result {
+            Ok(instance) => println!(
+                "{}\t{}\t{}\t{}\t{}",
+                region,
+                instance_id,
+                name,
+                color::state(instance.state().as_str()),
+                elapsed
+            ),
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "{}\t{}\t{}\tfailed\t{}\t{}",
+                    region, instance_id, name, elapsed, e
+                )
+            }
+        }
+    }
+
+    if failed > 0 {
+        return Err(eyre!("Failed to stop {} instances", failed));
+    }
+
+    Ok(())
+}
+
+/// How long the instance has been running, as far as its launch time tells
+pub fn uptime(instance: &Instance) -> Option<Duration> {
+    instance.launch_time()?.elapsed().ok()
+}
+
+/// Shows how many instances would be stopped in each region, and asks for that number to be
+/// typed back, so that a selector matching more than expected doesn't go unnoticed
+pub fn confirm(found: &[(String, SdkConfig, Instance)]) -> Result<()> {
+    if !std::io::stdin().is_terminal() {
+        return Err(eyre!(
+            "{} instances match, use --yes to stop them without confirming",
+            found.len()
+        ));
+    }
+
+    let mut regions: Vec<(&str, usize)> = vec![];
+    for (region, _, _) in found {
+        match regions.iter_mut().find(|(name, _)| name == region) {
+            Some((_, count)) => *count += 1,
+            None => regions.push((region, 1)),
+        }
+    }
+    for (region, count) in &regions {
+        println!("{}\t{} running", region, count);
+    }
+
+    print!(
+        "Type {} to stop these {} instances: ",
+        found.len(),
+        found.len()
+    );
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer)?;
+    if answer.trim() != found.len().to_string() {
+        return Err(eyre!("Not stopping"));
+    }
+
+    Ok(())
+}
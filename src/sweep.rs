@@ -0,0 +1,117 @@
+use crate::aws::{self, Instance};
+use crate::config::Action;
+use crate::log;
+use crate::pricing;
+use crate::protection;
+use crate::regions::Regions;
+use crate::stop_all;
+use aws_config::SdkConfig;
+use color_eyre::Result;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct SweepConfig {
+    /// Leave the instances with any of these tags running
+    pub exclude_tags: Vec<(String, String)>,
+    pub regions: Option<Regions>,
+    /// Don't ask for confirmation
+    pub yes: bool,
+    pub timeout: u64,
+}
+
+/// Stops every running instance of the regions but the protected and excluded ones, once their
+/// list and what they cost an hour were shown
+pub async fn run(aws_config: &SdkConfig, config: SweepConfig) -> Result<()> {
+    let found = stop_all::find(
+        aws_config,
+        config.regions.as_ref(),
+        vec![aws::actionable_filter(&Action::Stop)],
+    )
+    .await?;
+
+    let mut to_stop = vec![];
+    for (region, region_config, instance) in found {
+        match excluded(&config, &instance) {
+            Some(reason) => println!(
+                "Skipping {} ({}), {}",
+                instance.instance_id(),
+                instance.name().unwrap_or("-"),
+                reason
+            ),
+            None => to_stop.push((region, region_config, instance)),
+        }
+    }
+    if to_stop.is_empty() {
+        println!("No running instance to stop");
+        return Ok(());
+    }
+
+    let mut prices: HashMap<(String, String, bool), Option<f64>> = HashMap::new();
+    let mut total = 0.0;
+    let mut unknown = 0;
+    for (region, _, instance) in &to_stop {
+        let key = (
+            region.clone(),
+            instance.instance_type().to_string(),
+            instance.is_windows(),
+        );
+        let price = match prices.get(&key) {
+            Some(price) => *price,
+            None => {
+                // The list is still worth showing without the prices
+                let price = pricing::hourly_price(aws_config, &key.0, &key.1, key.2)
+                    .await
+                    .unwrap_or_else(|e| {
+                        log::warn("pricing", format!("Failed to get the price: {}", e));
+                        None
+                    });
+                prices.insert(key, price);
+                price
+            }
+        };
+        match price {
+            Some(price) => total += price,
+            None => unknown += 1,
+        }
+        println!(
+            "{}\t{}\t{}\t{}\trunning for {}\t{}",
+            region,
+            instance.instance_id(),
+            instance.name().unwrap_or("-"),
+            instance.instance_type(),
+            stop_all::uptime(instance).map_or("unknown".to_string(), |uptime| {
+                humantime::format_duration(Duration::from_secs(uptime.as_secs() / 60 * 60))
+                    .to_string()
+            }),
+            price.map_or("$?/h".to_string(), |price| format!("${:.3}/h", price))
+        );
+    }
+    println!(
+        "About ${:.2} an hour at on-demand prices{}",
+        total,
+        if unknown > 0 {
+            format!(", without {} instances of unknown price", unknown)
+        } else {
+            String::new()
+        }
+    );
+
+    if !config.yes {
+        stop_all::confirm(&to_stop)?;
+    }
+
+    stop_all::stop(to_stop, config.timeout).await
+}
+
+/// Why the instance is left running, if it is
+fn excluded(config: &SweepConfig, instance: &Instance) -> Option<String> {
+    if let Err(e) = protection::check_action(&Action::Stop, instance) {
+        return Some(e.to_string());
+    }
+    config
+        .exclude_tags
+        .iter()
+        .find(|(key, value)| instance.tag(key) == Some(value.as_str()))
+        .map(|(key, value)| format!("excluded by its tag {}={}", key, value))
+}
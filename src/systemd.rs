@@ -0,0 +1,99 @@
+use crate::proxy::ProxyConfig;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::path::{Path, PathBuf};
+
+/// First file descriptor passed by systemd
+#[cfg(unix)]
+const LISTEN_FDS_START: std::os::unix::io::RawFd = 3;
+
+/// Returns the socket passed by systemd socket activation, if this program was started that way
+pub fn activated_listener() -> Result<Option<std::net::TcpListener>> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::FromRawFd;
+
+        let for_us = std::env::var("LISTEN_PID")
+            .ok()
+            .and_then(|pid| pid.parse::<u32>().ok())
+            == Some(std::process::id());
+        let count = std::env::var("LISTEN_FDS")
+            .ok()
+            .and_then(|count| count.parse::<i32>().ok())
+            .unwrap_or(0);
+        if !for_us || count < 1 {
+            return Ok(None);
+        }
+        // Child processes, like hooks, must not think the sockets are theirs
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        // Safety: systemd passes the sockets, open, from this descriptor on
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START) };
+        listener.set_nonblocking(true)?;
+        Ok(Some(listener))
+    }
+
+    #[cfg(not(unix))]
+    Ok(None)
+}
+
+/// Writes a socket unit listening on the proxy's address and the service unit it activates
+///
+/// Returns the paths of the unit files.
+pub fn write_units(directory: &Path, config: &ProxyConfig) -> Result<Vec<PathBuf>> {
+    let listen = config
+        .listen
+        .ok_or_else(|| eyre!("--listen is needed to generate the socket unit"))?;
+    let name = format!("aws-start-stop-{}", config.instance_id);
+    let executable = std::env::current_exe()?;
+
+    let mut exec_start = format!(
+        "{} proxy --port {} --timeout {}",
+        executable.display(),
+        config.port,
+        config.timeout
+    );
+    if let Some(idle_timeout) = config.idle_timeout {
+        exec_start.push_str(&format!(
+            " --idle-timeout {}",
+            humantime::format_duration(idle_timeout)
+        ));
+    }
+    if config.ssm {
+        exec_start.push_str(" --ssm");
+    }
+    exec_start.push_str(&format!(" {}", config.instance_id));
+
+    let socket = format!(
+        "[Unit]\n\
+        Description=Start {instance_id} on connection\n\
+        \n\
+        [Socket]\n\
+        ListenStream={listen}\n\
+        \n\
+        [Install]\n\
+        WantedBy=sockets.target\n",
+        instance_id = config.instance_id,
+        listen = listen,
+    );
+    let service = format!(
+        "[Unit]\n\
+        Description=Start {instance_id} and forward connections to it\n\
+        Requires={name}.socket\n\
+        \n\
+        [Service]\n\
+        ExecStart={exec_start}\n",
+        instance_id = config.instance_id,
+        name = name,
+        exec_start = exec_start,
+    );
+
+    let socket_path = directory.join(format!("{}.socket", name));
+    let service_path = directory.join(format!("{}.service", name));
+    std::fs::write(&socket_path, socket)?;
+    std::fs::write(&service_path, service)?;
+
+    Ok(vec![socket_path, service_path])
+}
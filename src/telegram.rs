@@ -0,0 +1,180 @@
+use crate::bot::{self, BotCommand, BotInstance};
+use crate::log;
+use aws_config::SdkConfig;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::sync::Arc;
+use tokio::time::{sleep, Duration};
+
+/// How long Telegram holds a request for updates when there are none
+const POLL_TIMEOUT: u64 = 30;
+
+#[derive(Debug)]
+pub struct TelegramConfig {
+    pub token: String,
+    /// Chats allowed to send commands, others are ignored
+    pub chat_ids: Vec<i64>,
+    pub instances: Vec<BotInstance>,
+    pub timeout: u64,
+}
+
+#[derive(Deserialize)]
+struct Reply<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Update {
+    update_id: i64,
+    message: Option<Message>,
+}
+
+#[derive(Deserialize)]
+struct Message {
+    chat: Chat,
+    text: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Chat {
+    id: i64,
+}
+
+struct Bot {
+    client: reqwest::Client,
+    token: String,
+}
+
+impl Bot {
+    async fn call<T: DeserializeOwned>(&self, method: &str, body: serde_json::Value) -> Result<T> {
+        // The token is part of the URL, which must not end up in the logs
+        let reply: Reply<T> = self
+            .client
+            .post(format!(
+                "https://api.telegram.org/bot{}/{}",
+                self.token, method
+            ))
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(reqwest::Error::without_url)?
+            .json()
+            .await
+            .map_err(reqwest::Error::without_url)?;
+        match (reply.ok, reply.result) {
+            (true, Some(result)) => Ok(result),
+            _ => Err(eyre!(
+                "Telegram refused {}: {}",
+                method,
+                reply.description.unwrap_or_default()
+            )),
+        }
+    }
+
+    async fn send(&self, chat_id: i64, text: String) {
+        let body = serde_json::json!({"chat_id": chat_id, "text": text});
+        if let Err(e) = self.call::<serde_json::Value>("sendMessage", body).await {
+            log::warn("telegram", format!("Failed to reply to {}: {}", chat_id, e));
+        }
+    }
+}
+
+/// Answers the commands sent to the bot by the allowed chats, until interrupted
+pub async fn run(aws_config: &SdkConfig, config: TelegramConfig) -> Result<()> {
+    let bot = Arc::new(Bot {
+        client: reqwest::Client::builder()
+            .timeout(Duration::from_secs(POLL_TIMEOUT * 2))
+            .build()?,
+        token: config.token.clone(),
+    });
+    let config = Arc::new(config);
+
+    log::info("telegram", "Waiting for commands");
+    let mut offset = 0;
+    loop {
+        let body = serde_json::json!({
+            "offset": offset,
+            "timeout": POLL_TIMEOUT,
+            "allowed_updates": ["message"],
+        });
+        let updates: Vec<Update> = match bot.call("getUpdates", body).await {
+            Ok(updates) => updates,
+            Err(e) => {
+                log::warn("telegram", format!("Failed to get updates: {}", e));
+                sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        for update in updates {
+            offset = update.update_id + 1;
+            let Some(Message {
+                chat,
+                text: Some(text),
+            }) = update.message
+            else {
+                continue;
+            };
+            if !config.chat_ids.contains(&chat.id) {
+                log::warn(
+                    "telegram",
+                    format!("Ignoring command from chat {}", chat.id),
+                );
+                continue;
+            }
+
+            // Starting an instance takes a while, other commands are answered meanwhile
+            let aws_config = aws_config.clone();
+            let bot = bot.clone();
+            let config = config.clone();
+            tokio::spawn(async move {
+                let reply = match answer(&aws_config, &config, &bot, chat.id, &text).await {
+                    Ok(reply) => reply,
+                    Err(e) => format!("Failed: {}", e),
+                };
+                bot.send(chat.id, reply).await;
+            });
+        }
+    }
+}
+
+/// Runs a command, returning the reply
+async fn answer(
+    aws_config: &SdkConfig,
+    config: &TelegramConfig,
+    bot: &Bot,
+    chat_id: i64,
+    text: &str,
+) -> Result<String> {
+    let mut words = text.split_whitespace();
+    // In groups, commands are suffixed with the name of the bot
+    let command = match words.next().and_then(|command| command.split('@').next()) {
+        Some("/start_instance") => BotCommand::Start,
+        Some("/stop_instance") => BotCommand::Stop,
+        Some("/status") => BotCommand::Status,
+        _ => {
+            return Err(eyre!(
+                "Unknown command, use /start_instance, /stop_instance or /status"
+            ))
+        }
+    };
+    let instance_id = bot::select_instance(words.next(), &config.instances)?;
+
+    if !matches!(command, BotCommand::Status) {
+        log::info(
+            "telegram",
+            format!(
+                "Chat {} asked for {:?} of {}",
+                chat_id, command, instance_id
+            ),
+        );
+        bot.send(chat_id, format!("Working on {}...", instance_id))
+            .await;
+    }
+    bot::execute(aws_config, &instance_id, command, config.timeout).await
+}
@@ -0,0 +1,80 @@
+use color_eyre::Result;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "otel")]
+const TRACER: &str = "aws-start-stop";
+
+/// How long each phase took, with the instance it was for, in the order they completed
+static TIMINGS: Mutex<Vec<(String, String, Duration)>> = Mutex::new(Vec::new());
+
+/// Installs the OTLP exporter if an endpoint is configured
+///
+/// Spans are only exported when built with the `otel` feature and `OTEL_EXPORTER_OTLP_ENDPOINT`
+/// is set, the exporter reading its other settings from the standard `OTEL_*` variables.
+pub fn init() -> Result<()> {
+    #[cfg(feature = "otel")]
+    if std::env::var_os("OTEL_EXPORTER_OTLP_ENDPOINT").is_some() {
+        opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+            .install_batch(opentelemetry_sdk::runtime::TokioCurrentThread)?;
+    }
+
+    Ok(())
+}
+
+/// Exports the spans not sent yet, to be called before exiting
+pub fn shutdown() {
+    #[cfg(feature = "otel")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// Runs `future` within a span named after the phase, nested in the current one, recording
+/// how long it took for the summary
+pub async fn span<F: Future>(phase: &str, instance_id: &str, future: F) -> F::Output {
+    let started = Instant::now();
+    let output = traced(phase, instance_id, future).await;
+    TIMINGS.lock().unwrap().push((
+        instance_id.to_string(),
+        phase.to_string(),
+        started.elapsed(),
+    ));
+    output
+}
+
+/// Removes and returns how long the phases of the instance took
+pub fn take_timings(instance_id: &str) -> Vec<(String, Duration)> {
+    let mut timings = TIMINGS.lock().unwrap();
+    let (taken, kept) = timings.drain(..).partition(|(id, _, _)| id == instance_id);
+    *timings = kept;
+    taken
+        .into_iter()
+        .map(|(_, phase, duration)| (phase, duration))
+        .collect()
+}
+
+async fn traced<F: Future>(phase: &str, instance_id: &str, future: F) -> F::Output {
+    #[cfg(feature = "otel")]
+    {
+        use opentelemetry::trace::{FutureExt, TraceContextExt, Tracer};
+        use opentelemetry::{global, Context, KeyValue};
+
+        let tracer = global::tracer(TRACER);
+        let span = tracer
+            .span_builder(phase.to_string())
+            .with_attributes(vec![KeyValue::new(
+                "aws.ec2.instance_id",
+                instance_id.to_string(),
+            )])
+            .start(&tracer);
+        future.with_context(Context::current_with_span(span)).await
+    }
+
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = (phase, instance_id);
+        future.await
+    }
+}
@@ -0,0 +1,126 @@
+use crate::aws::Instance;
+use chrono::{DateTime, Utc};
+
+/// Variables of the templates, besides `tag:KEY`
+const FIELDS: [&str; 20] = [
+    "instance_id",
+    "name",
+    "state",
+    "public_ip",
+    "private_ip",
+    "ipv6",
+    "public_dns",
+    "private_dns",
+    "security_groups",
+    "key_name",
+    "availability_zone",
+    "instance_type",
+    "launch_time",
+    "launched_by",
+    "windows",
+    "hibernation",
+    "root_volume_id",
+    "lifecycle",
+    "capacity_reservation_id",
+    "tenancy",
+];
+
+#[derive(Debug, Clone)]
+enum Part {
+    Text(String),
+    Field(String),
+}
+
+/// Text with `{field}` replaced by the values of the instance, `{{` and `}}` being literal braces
+#[derive(Debug, Clone)]
+pub struct Template(Vec<Part>);
+
+pub fn parse(value: &str) -> Result<Template, String> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = value.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut field = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => field.push(c),
+                        None => return Err("Unmatched {, use {{ for a literal brace".to_string()),
+                    }
+                }
+                if !FIELDS.contains(&field.as_str()) && !field.starts_with("tag:") {
+                    return Err(format!(
+                        "Unknown field {{{}}}, expected tag:KEY or one of {}",
+                        field,
+                        FIELDS.join(", ")
+                    ));
+                }
+                parts.push(Part::Text(std::mem::take(&mut text)));
+                parts.push(Part::Field(field));
+            }
+            '}' => return Err("Unmatched }, use }} for a literal brace".to_string()),
+            c => text.push(c),
+        }
+    }
+    parts.push(Part::Text(text));
+
+    Ok(Template(parts))
+}
+
+impl Template {
+    /// Fills the template, missing values being empty
+    pub fn render(&self, instance: &Instance) -> String {
+        self.0
+            .iter()
+            .map(|part| match part {
+                Part::Text(text) => text.clone(),
+                Part::Field(field) => field_value(instance, field).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+fn field_value(instance: &Instance, field: &str) -> Option<String> {
+    if let Some(key) = field.strip_prefix("tag:") {
+        return instance.tag(key).map(str::to_string);
+    }
+
+    let value = match field {
+        "instance_id" => instance.instance_id(),
+        "name" => instance.name()?,
+        "state" => instance.state().as_str(),
+        "public_ip" => instance.ipv4_address_public()?,
+        "private_ip" => instance.ipv4_address_private()?,
+        "ipv6" => instance.ipv6_address()?,
+        "public_dns" => instance.public_dns_name()?,
+        "private_dns" => instance.private_dns_name()?,
+        "security_groups" => return Some(instance.security_groups().join(", ")),
+        "key_name" => instance.key_name()?,
+        "availability_zone" => instance.availability_zone()?,
+        "instance_type" => instance.instance_type(),
+        "launch_time" => {
+            return instance
+                .launch_time()
+                .map(|time| DateTime::<Utc>::from(time).to_rfc3339())
+        }
+        "launched_by" => instance.launched_by()?,
+        "windows" => return Some(instance.is_windows().to_string()),
+        "hibernation" => return Some(instance.hibernation_configured().to_string()),
+        "root_volume_id" => instance.root_volume_id()?,
+        "lifecycle" => instance.lifecycle(),
+        "capacity_reservation_id" => instance.capacity_reservation_id()?,
+        "tenancy" => instance.tenancy(),
+        _ => return None,
+    };
+    Some(value.to_string())
+}
@@ -0,0 +1,63 @@
+use crate::aws::Instance;
+use crate::config::{self, Action};
+use clap::ValueEnum;
+use color_eyre::eyre::{eyre, WrapErr};
+use color_eyre::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Query of Terraform's external data source, whose values are all strings
+#[derive(Debug, Deserialize)]
+struct Query {
+    instance_id: String,
+    action: String,
+}
+
+/// Reads the instance and action to act on from the query Terraform writes to stdin
+pub fn read_query() -> Result<(Action, String)> {
+    let query: Query = serde_json::from_reader(std::io::stdin().lock())
+        .wrap_err("Failed to read the query of the external data source from stdin")?;
+    let action = Action::from_str(&query.action, true)
+        .map_err(|_| eyre!("{} isn't start or stop", query.action))?;
+    if query.instance_id.contains('/') {
+        return Err(eyre!(
+            "{} must be an instance id or @group",
+            query.instance_id
+        ));
+    }
+    config::parse_instance(&query.instance_id).map_err(|e| eyre!(e))?;
+
+    Ok((action, query.instance_id))
+}
+
+/// Prints the instance as the flat map of strings Terraform's external data source expects
+pub fn print_result(instance: &Instance) -> Result<()> {
+    let result: BTreeMap<&str, &str> = [
+        ("instance_id", instance.instance_id()),
+        ("instance_state", instance.state().as_str()),
+        (
+            "public_ip",
+            instance.ipv4_address_public().unwrap_or_default(),
+        ),
+        (
+            "private_ip",
+            instance.ipv4_address_private().unwrap_or_default(),
+        ),
+        ("ipv6", instance.ipv6_address().unwrap_or_default()),
+        ("public_dns", instance.public_dns_name().unwrap_or_default()),
+        (
+            "private_dns",
+            instance.private_dns_name().unwrap_or_default(),
+        ),
+        (
+            "availability_zone",
+            instance.availability_zone().unwrap_or_default(),
+        ),
+        ("instance_type", instance.instance_type()),
+    ]
+    .into_iter()
+    .collect();
+    println!("{}", serde_json::to_string(&result)?);
+
+    Ok(())
+}
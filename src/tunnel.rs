@@ -0,0 +1,106 @@
+use crate::log;
+use crate::proxy_command::session_command;
+use aws_config::SdkConfig;
+use color_eyre::Result;
+use serde_json::json;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Duration, Instant};
+
+/// SSM document forwarding a local port to a port of the instance
+const PORT_FORWARDING_DOCUMENT: &str = "AWS-StartPortForwardingSession";
+/// First delay before re-establishing a dropped session, doubled on each failure
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A session lasting this long is considered healthy, and resets the backoff
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// SSM port forwarding session, re-established whenever it drops
+pub struct Tunnel {
+    /// Local address the session forwards from
+    pub address: SocketAddr,
+    supervisor: JoinHandle<()>,
+}
+
+impl Tunnel {
+    /// Forwards a free local port to `port` on the instance, until dropped
+    pub fn open(aws_config: &SdkConfig, instance_id: &str, port: u16) -> Result<Self> {
+        // Let the system pick a free port, for the plugin to listen on
+        let local_port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+            .local_addr()?
+            .port();
+        let supervisor = tokio::spawn(supervise(
+            aws_config.clone(),
+            instance_id.to_string(),
+            port,
+            local_port,
+        ));
+
+        Ok(Self {
+            address: SocketAddr::from((Ipv4Addr::LOCALHOST, local_port)),
+            supervisor,
+        })
+    }
+}
+
+impl Drop for Tunnel {
+    fn drop(&mut self) {
+        // Killing the plugin is left to kill_on_drop
+        self.supervisor.abort();
+    }
+}
+
+/// Runs the session, restarting it with bounded backoff when it ends
+async fn supervise(aws_config: SdkConfig, instance_id: String, port: u16, local_port: u16) {
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        let started = Instant::now();
+        match session(&aws_config, &instance_id, port, local_port).await {
+            Ok(()) => log::warn("tunnel", format!("SSM session to {} ended", instance_id)),
+            Err(e) => log::warn(
+                "tunnel",
+                format!("SSM session to {} failed: {}", instance_id, e),
+            ),
+        }
+
+        if started.elapsed() >= STABLE_AFTER {
+            backoff = MIN_BACKOFF;
+        }
+        log::info(
+            "tunnel",
+            format!("Reconnecting in {}", humantime::format_duration(backoff)),
+        );
+        sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn session(
+    aws_config: &SdkConfig,
+    instance_id: &str,
+    port: u16,
+    local_port: u16,
+) -> Result<()> {
+    let parameters = json!({
+        "portNumber": [port.to_string()],
+        "localPortNumber": [local_port.to_string()],
+    });
+    let status = session_command(
+        aws_config,
+        instance_id,
+        PORT_FORWARDING_DOCUMENT,
+        parameters,
+    )
+    .await?
+    .stdin(std::process::Stdio::null())
+    .stdout(std::process::Stdio::null())
+    .kill_on_drop(true)
+    .status()
+    .await?;
+    log::info(
+        "tunnel",
+        format!("Session manager plugin exited with {}", status),
+    );
+
+    Ok(())
+}
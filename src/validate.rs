@@ -0,0 +1,245 @@
+use crate::aws::AwsEc2Client;
+use crate::clients;
+use crate::config;
+use crate::credentials;
+use crate::plugins;
+use crate::protection::TimeWindow;
+use crate::settings::{self, NotifierSettings, Settings};
+use crate::state::Store;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use regex::Regex;
+use std::path::Path;
+use tokio::time::Duration;
+
+#[derive(Debug)]
+pub struct ValidateConfig {
+    /// Also check the credentials, roles and instances against AWS, read-only
+    pub aws: bool,
+}
+
+/// Checks the configuration file, printing each problem found
+///
+/// Protected instances, required tags and no-stop windows have already been parsed when this
+/// runs, as they are for every command.
+pub async fn run(
+    aws_config: &SdkConfig,
+    settings: &Settings,
+    path: Option<&Path>,
+    config: ValidateConfig,
+) -> Result<()> {
+    match path.map(Path::to_path_buf).or_else(settings::default_path) {
+        Some(path) if path.exists() => println!("Checking {}", path.display()),
+        _ => println!("No configuration file, checking the defaults"),
+    }
+
+    let mut problems = vec![];
+    check_groups(settings, &mut problems);
+    check_aliases(settings, &mut problems);
+    check_tag_schedules(settings, &mut problems);
+    check_accounts(settings, &mut problems);
+    check_notifications(settings, &mut problems);
+    if let Err(e) = Store::new(aws_config, settings.state.as_deref()) {
+        problems.push(format!("state: {}", e));
+    }
+    check_profile(&mut problems).await;
+    if config.aws {
+        check_aws(aws_config, settings, &mut problems).await;
+    }
+
+    for problem in &problems {
+        println!("  {}", problem);
+    }
+    if !problems.is_empty() {
+        return Err(eyre!(
+            "Found {} problems in the configuration",
+            problems.len()
+        ));
+    }
+    println!("Configuration is valid");
+
+    Ok(())
+}
+
+fn check_groups(settings: &Settings, problems: &mut Vec<String>) {
+    let mut groups: Vec<_> = settings.groups.iter().collect();
+    groups.sort_by_key(|(name, _)| name.as_str());
+    for (name, stages) in groups {
+        if stages.is_empty() {
+            problems.push(format!("group {}: no instances", name));
+        }
+        for instance_id in stages.iter().flat_map(|stage| stage.instance_ids()) {
+            if instance_id.starts_with('@') {
+                problems.push(format!(
+                    "group {}: groups can't contain other groups ({})",
+                    name, instance_id
+                ));
+            } else if let Err(e) = config::parse_instance(&instance_id) {
+                problems.push(format!("group {}: {}", name, e));
+            } else if let Some((account, _)) = instance_id.split_once('/') {
+                if !settings.accounts.contains_key(account) {
+                    problems.push(format!(
+                        "group {}: account {} of {} isn't in accounts",
+                        name, account, instance_id
+                    ));
+                }
+            }
+        }
+    }
+}
+
+fn check_aliases(settings: &Settings, problems: &mut Vec<String>) {
+    let mut aliases: Vec<_> = settings.aliases.iter().collect();
+    aliases.sort_by_key(|(name, _)| name.as_str());
+    for (name, alias) in aliases {
+        if settings.aliases.contains_key(&alias.instance) {
+            problems.push(format!(
+                "alias {}: aliases can't refer to other aliases ({})",
+                name, alias.instance
+            ));
+        } else if let Err(e) = config::parse_instance(&alias.instance) {
+            problems.push(format!("alias {}: {}", name, e));
+        } else if let Some((account, _)) = alias.instance.split_once('/') {
+            if !alias.instance.starts_with("arn:") && !settings.accounts.contains_key(account) {
+                problems.push(format!(
+                    "alias {}: account {} of {} isn't in accounts",
+                    name, account, alias.instance
+                ));
+            }
+        }
+    }
+}
+
+fn check_tag_schedules(settings: &Settings, problems: &mut Vec<String>) {
+    let mut schedules: Vec<_> = settings.tag_schedules.iter().collect();
+    schedules.sort_by_key(|(name, _)| name.as_str());
+    for (name, windows) in schedules {
+        for window in windows {
+            if let Err(e) = TimeWindow::parse(window) {
+                problems.push(format!("tag schedule {}: {}", name, e));
+            }
+        }
+    }
+}
+
+fn check_accounts(settings: &Settings, problems: &mut Vec<String>) {
+    let role_arn = Regex::new(r"^arn:aws[a-z-]*:iam::\d{12}:role/.+$").unwrap();
+    for (account, arn) in &settings.accounts {
+        if !role_arn.is_match(arn) {
+            problems.push(format!("account {}: {} isn't a role ARN", account, arn));
+        }
+    }
+}
+
+fn check_notifications(settings: &Settings, problems: &mut Vec<String>) {
+    for notifier in &settings.notifications {
+        match notifier {
+            NotifierSettings::Webhook { url }
+            | NotifierSettings::Slack { webhook_url: url }
+            | NotifierSettings::Ntfy { url } => {
+                if let Err(e) = reqwest::Url::parse(url) {
+                    problems.push(format!("notifications: {} isn't a URL: {}", url, e));
+                }
+            }
+            NotifierSettings::Sns { topic_arn } if !topic_arn.starts_with("arn:") => {
+                problems.push(format!("notifications: {} isn't a topic ARN", topic_arn));
+            }
+            NotifierSettings::Email { to, .. } if to.is_empty() => {
+                problems.push("notifications: email without recipients".to_string());
+            }
+            NotifierSettings::PagerDuty { routing_key } if routing_key.is_empty() => {
+                problems.push("notifications: pagerduty without a routing key".to_string());
+            }
+            NotifierSettings::Plugin { name } if !plugins::discover().contains_key(name) => {
+                problems.push(format!(
+                    "notifications: no aws-start-stop-{} plugin on the PATH",
+                    name
+                ));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Checks that the AWS profile in use, and its source profile, are defined
+async fn check_profile(problems: &mut Vec<String>) {
+    let profiles = match credentials::load_profiles().await {
+        Ok(profiles) => profiles,
+        Err(e) => {
+            problems.push(format!("profiles: {}", e));
+            return;
+        }
+    };
+    let selected = profiles.selected_profile();
+    let Some(profile) = profiles.get_profile(selected) else {
+        // Without any profile, the credentials come from the environment or the instance role
+        if selected != "default" {
+            problems.push(format!("profile {} isn't defined", selected));
+        }
+        return;
+    };
+    if let Some(source) = profile.get("source_profile") {
+        if profiles.get_profile(source).is_none() {
+            problems.push(format!(
+                "profile {}: source profile {} isn't defined",
+                selected, source
+            ));
+        }
+    }
+}
+
+/// Checks the credentials, that the roles of the accounts can be assumed and that the
+/// instances of the groups exist, without changing anything
+async fn check_aws(aws_config: &SdkConfig, settings: &Settings, problems: &mut Vec<String>) {
+    match credentials::caller(aws_config).await {
+        Ok(caller) => println!("Authenticated as {}", caller),
+        Err(e) => {
+            problems.push(format!("credentials: {}", e));
+            return;
+        }
+    }
+
+    let mut accounts: Vec<_> = settings.accounts.iter().collect();
+    accounts.sort();
+    for (account, role_arn) in accounts {
+        if let Err(e) = credentials::assume_role(aws_config.clone(), role_arn).await {
+            problems.push(format!("account {}: {}", account, e));
+        }
+    }
+
+    let mut instances: Vec<String> = settings
+        .groups
+        .values()
+        .flatten()
+        .flat_map(|stage| stage.instance_ids())
+        .filter(|instance_id| config::parse_instance(instance_id).is_ok())
+        .collect();
+    instances.sort();
+    instances.dedup();
+    for instance in instances {
+        let (aws_config, instance_id) = match instance.split_once('/') {
+            Some((account, instance_id)) => {
+                let Ok(role_arn) = settings.account_role(account) else {
+                    continue;
+                };
+                match credentials::assume_role(aws_config.clone(), role_arn).await {
+                    Ok(aws_config) => (aws_config, instance_id),
+                    Err(_) => continue,
+                }
+            }
+            None => (aws_config.clone(), instance.as_str()),
+        };
+        // The target state is only used when waiting
+        let aws_ec2_client = AwsEc2Client::new(
+            clients::ec2(&aws_config),
+            instance_id,
+            InstanceStateName::Running,
+            Duration::from_secs(10),
+        );
+        if let Err(e) = aws_ec2_client.get_instance().await {
+            problems.push(format!("instance {}: {}", instance, e));
+        }
+    }
+}
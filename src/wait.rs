@@ -0,0 +1,84 @@
+use crate::clients;
+use crate::log;
+use crate::readiness::{self, Ec2State, Order, ReadinessCheck, SsmConnected};
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use clap::PossibleValue;
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use std::fmt;
+use std::sync::Arc;
+use tokio::time::{timeout, Duration};
+
+/// Condition to wait for
+#[derive(Debug, PartialEq, Clone)]
+pub enum Until {
+    Running,
+    Stopped,
+    SsmConnected,
+}
+
+impl clap::ValueEnum for Until {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Self::Running, Self::Stopped, Self::SsmConnected]
+    }
+
+    fn to_possible_value<'a>(&self) -> Option<PossibleValue<'a>> {
+        match self {
+            Self::Running => Some(PossibleValue::new("running")),
+            Self::Stopped => Some(PossibleValue::new("stopped")),
+            Self::SsmConnected => Some(PossibleValue::new("ssm-connected")),
+        }
+    }
+}
+
+impl fmt::Display for Until {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Running => write!(f, "running"),
+            Self::Stopped => write!(f, "stopped"),
+            Self::SsmConnected => write!(f, "ssm-connected"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct WaitConfig {
+    pub instance_id: String,
+    pub until: Until,
+    pub timeout: u64,
+}
+
+/// Waits for the instance to reach the condition, without acting on it
+pub async fn run(aws_config: &SdkConfig, config: WaitConfig) -> Result<()> {
+    log::info(
+        "wait",
+        format!("Waiting for the instance to be {}...", config.until),
+    );
+    timeout(
+        Duration::from_secs(config.timeout),
+        wait(aws_config, &config),
+    )
+    .await
+    .map_err(|_| eyre!("Timed out waiting for the instance to be {}", config.until))??;
+    log::info("wait", format!("Instance is {}", config.until));
+
+    Ok(())
+}
+
+async fn wait(aws_config: &SdkConfig, config: &WaitConfig) -> Result<()> {
+    // Something else is expected to act on the instance, so the state check tolerates any state
+    // but a terminal one
+    let mut checks: Vec<Arc<dyn ReadinessCheck>> = vec![Arc::new(Ec2State {
+        client: clients::ec2(aws_config),
+        target: match config.until {
+            Until::Stopped => InstanceStateName::Stopped,
+            Until::Running | Until::SsmConnected => InstanceStateName::Running,
+        },
+    })];
+    if config.until == Until::SsmConnected {
+        checks.push(Arc::new(SsmConnected::new(aws_config)));
+    }
+
+    readiness::wait_for_ready(&checks, &config.instance_id, Order::Sequential).await
+}
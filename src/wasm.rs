@@ -0,0 +1,132 @@
+use crate::log;
+use crate::readiness::{CheckFuture, ReadinessCheck};
+use color_eyre::eyre::eyre;
+use color_eyre::Result;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::time::{sleep, Duration};
+use wasmtime::{Caller, Config, Engine, Linker, Module, Store};
+
+/// Instructions a call may run, so that a looping module can't hang the action
+const FUEL: u64 = 1_000_000_000;
+/// What `ready` returns while the instance isn't ready yet
+const NOT_READY: i32 = 1;
+/// How often `ready` is called again
+const READINESS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Calls the function the module exports with the payload as JSON, returning what it returns
+///
+/// Modules export their `memory`, `alloc(len) -> ptr`, which the payload is written to, and
+/// `hook(ptr, len) -> i32` or `ready(ptr, len) -> i32`. They can only import `env.log(ptr, len)`,
+/// to log a message, so they can't reach the files or network of the machine.
+pub async fn call(path: &Path, export: &'static str, payload: &Value) -> Result<i32> {
+    let path = path.to_path_buf();
+    let payload = payload.to_string();
+    // Running the module blocks
+    tokio::task::spawn_blocking(move || run(&path, export, &payload)).await?
+}
+
+fn run(path: &Path, export: &str, payload: &str) -> Result<i32> {
+    let wasm_error = |e: wasmtime::Error| eyre!("WASM plugin {}: {:#}", path.display(), e);
+
+    let mut config = Config::new();
+    config.consume_fuel(true);
+    let engine = Engine::new(&config).map_err(wasm_error)?;
+    let module = Module::from_file(&engine, path).map_err(wasm_error)?;
+
+    let mut linker = Linker::new(&engine);
+    linker
+        .func_wrap(
+            "env",
+            "log",
+            |mut caller: Caller<'_, String>, ptr: i32, len: i32| {
+                let Some(memory) = caller
+                    .get_export("memory")
+                    .and_then(|export| export.into_memory())
+                else {
+                    return;
+                };
+                let mut message = vec![0; len.max(0) as usize];
+                if memory.read(&caller, ptr as usize, &mut message).is_ok() {
+                    log::info(caller.data(), String::from_utf8_lossy(&message));
+                }
+            },
+        )
+        .map_err(wasm_error)?;
+
+    let phase = format!(
+        "wasm-{}",
+        path.file_stem().unwrap_or_default().to_string_lossy()
+    );
+    let mut store = Store::new(&engine, phase);
+    store.set_fuel(FUEL).map_err(wasm_error)?;
+    let instance = linker
+        .instantiate(&mut store, &module)
+        .map_err(wasm_error)?;
+    let memory = instance
+        .get_memory(&mut store, "memory")
+        .ok_or_else(|| eyre!("WASM plugin {} exports no memory", path.display()))?;
+    let alloc = instance
+        .get_typed_func::<i32, i32>(&mut store, "alloc")
+        .map_err(wasm_error)?;
+    let function = instance
+        .get_typed_func::<(i32, i32), i32>(&mut store, export)
+        .map_err(wasm_error)?;
+
+    let len = i32::try_from(payload.len())?;
+    let ptr = alloc.call(&mut store, len).map_err(wasm_error)?;
+    memory
+        .write(&mut store, ptr as usize, payload.as_bytes())
+        .map_err(|e| eyre!("WASM plugin {}: {}", path.display(), e))?;
+    function.call(&mut store, (ptr, len)).map_err(wasm_error)
+}
+
+/// Calls the `hook` of the module, failing unless it returns 0
+pub async fn hook(path: &Path, payload: &Value) -> Result<()> {
+    match call(path, "hook", payload).await? {
+        0 => Ok(()),
+        code => Err(eyre!(
+            "WASM hook {} failed, returning {}",
+            path.display(),
+            code
+        )),
+    }
+}
+
+/// Module whose `ready` is called until it returns 0, or anything but `NOT_READY` when the
+/// instance won't be ready
+pub struct Readiness {
+    pub path: PathBuf,
+    /// Sent to the module on each call
+    pub payload: Value,
+}
+
+impl ReadinessCheck for Readiness {
+    fn name(&self) -> &str {
+        "wasm"
+    }
+
+    fn wait<'a>(&'a self, _instance_id: &'a str) -> CheckFuture<'a> {
+        Box::pin(async move {
+            log::info(
+                "wasm",
+                format!("Waiting for {} to report ready...", self.path.display()),
+            );
+            loop {
+                match call(&self.path, "ready", &self.payload).await? {
+                    0 => break,
+                    NOT_READY => sleep(READINESS_INTERVAL).await,
+                    code => {
+                        return Err(eyre!(
+                            "{} reported the instance won't be ready, returning {}",
+                            self.path.display(),
+                            code
+                        ))
+                    }
+                }
+            }
+            log::info("wasm", format!("{} reported ready", self.path.display()));
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,294 @@
+use crate::auth::{ApiAuth, Grant};
+use crate::aws::AwsEc2Client;
+use crate::clients;
+use crate::config::{Action, Config};
+use crate::log;
+use crate::progress;
+use crate::work;
+use aws_config::SdkConfig;
+use aws_sdk_ec2::types::InstanceStateName;
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::Html;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use color_eyre::Result;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::time::{timeout, Duration};
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{IntoParams, Modify, OpenApi, ToSchema};
+
+const INDEX: &str = include_str!("web.html");
+
+#[derive(Debug)]
+pub struct WebConfig {
+    pub listen: SocketAddr,
+    /// Token the page must send with its requests, unless it sends one of the `api_auth` ones
+    pub token: String,
+    /// Instances listed on the page, the only ones it can act on
+    pub instance_ids: Vec<String>,
+}
+
+/// Browsers can't set headers on event streams, so their token is passed in the query
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct TokenQuery {
+    /// Token given with `--web-token`, when it can't be sent in the `Authorization` header
+    token: Option<String>,
+}
+
+/// An instance listed on the page
+#[derive(Serialize, ToSchema)]
+struct InstanceSummary {
+    instance_id: String,
+    name: Option<String>,
+    /// EC2 state of the instance, or `unknown` if it couldn't be described
+    state: String,
+    public_ipv4: Option<String>,
+    private_ipv4: Option<String>,
+    /// Why the instance couldn't be described
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// A state the instance moved to, sent as a `state` event
+#[derive(Serialize, ToSchema)]
+struct StateChange {
+    instance_id: String,
+    state: String,
+}
+
+/// Description of the API the page uses, generated from the handlers, for clients to be
+/// generated against
+#[derive(OpenApi)]
+#[openapi(
+    paths(instances, act, events),
+    components(schemas(InstanceSummary, StateChange)),
+    modifiers(&BearerToken),
+    security(("token" = []))
+)]
+struct ApiDoc;
+
+/// Adds the token the requests are authorized with to the description
+struct BearerToken;
+
+impl Modify for BearerToken {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme(
+                "token",
+                SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+            );
+        }
+    }
+}
+
+struct WebState {
+    aws_config: SdkConfig,
+    config: WebConfig,
+    auth: ApiAuth,
+    timeout: u64,
+}
+
+/// Serves the page listing the instances, with buttons to start and stop them, until interrupted
+pub async fn run(
+    aws_config: &SdkConfig,
+    config: WebConfig,
+    auth: ApiAuth,
+    timeout_secs: u64,
+) -> Result<()> {
+    let listener = tokio::net::TcpListener::bind(config.listen).await?;
+    log::info("serve", format!("Serving the web UI on {}", config.listen));
+
+    let state = Arc::new(WebState {
+        aws_config: aws_config.clone(),
+        config,
+        auth,
+        timeout: timeout_secs,
+    });
+    let app = Router::new()
+        .route("/", get(|| async { Html(INDEX) }))
+        .route("/openapi.json", get(|| async { Json(ApiDoc::openapi()) }))
+        .route("/api/instances", get(instances))
+        .route("/api/instances/:instance_id/:action", post(act))
+        .route("/instances/:instance_id/events", get(events))
+        .with_state(state);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await?;
+
+    Ok(())
+}
+
+fn authorized(
+    state: &WebState,
+    headers: &HeaderMap,
+    query_token: Option<&str>,
+) -> Result<Grant, StatusCode> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .or(query_token);
+    state.auth.authorize(token).ok_or(StatusCode::UNAUTHORIZED)
+}
+
+/// Lists the instances, with their current state
+#[utoipa::path(
+    get,
+    path = "/api/instances",
+    responses(
+        (status = 200, description = "The instances", body = [InstanceSummary]),
+        (status = 401, description = "Missing or wrong token"),
+    )
+)]
+async fn instances(
+    State(state): State<Arc<WebState>>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<InstanceSummary>>, StatusCode> {
+    let grant = authorized(&state, &headers, None)?;
+
+    let mut instances = vec![];
+    for instance_id in &state.config.instance_ids {
+        if !grant.allows(instance_id, "status") {
+            continue;
+        }
+        // The target state is only used when waiting
+        let aws_ec2_client = AwsEc2Client::new(
+            clients::ec2(&state.aws_config),
+            instance_id,
+            InstanceStateName::Running,
+            Duration::from_secs(10),
+        );
+        instances.push(match aws_ec2_client.get_instance().await {
+            Ok(instance) => InstanceSummary {
+                instance_id: instance_id.clone(),
+                name: instance.name().map(str::to_string),
+                state: instance.state().as_str().to_string(),
+                public_ipv4: instance.ipv4_address_public().map(str::to_string),
+                private_ipv4: instance.ipv4_address_private().map(str::to_string),
+                error: None,
+            },
+            Err(e) => InstanceSummary {
+                instance_id: instance_id.clone(),
+                name: None,
+                state: "unknown".to_string(),
+                public_ipv4: None,
+                private_ipv4: None,
+                error: Some(e.to_string()),
+            },
+        });
+    }
+
+    Ok(Json(instances))
+}
+
+/// Starts acting on the instance, the page follows the progress through its state
+#[utoipa::path(
+    post,
+    path = "/api/instances/{instance_id}/{action}",
+    params(
+        ("instance_id" = String, Path, description = "One of the instances listed"),
+        ("action" = String, Path, description = "`start` or `stop`"),
+    ),
+    responses(
+        (status = 202, description = "The action was started"),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 403, description = "The token may not take the action on the instance"),
+        (status = 404, description = "Instance not listed, or unknown action"),
+    )
+)]
+async fn act(
+    State(state): State<Arc<WebState>>,
+    Path((instance_id, action)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<StatusCode, StatusCode> {
+    let grant = authorized(&state, &headers, None)?;
+    if !state.config.instance_ids.contains(&instance_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let action = match action.as_str() {
+        "start" => Action::Start,
+        "stop" => Action::Stop,
+        _ => return Err(StatusCode::NOT_FOUND),
+    };
+    if !grant.allows(&instance_id, &action.to_string()) {
+        log::warn(
+            "serve",
+            format!("{} may not {} {}", grant.name, action, instance_id),
+        );
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    log::info(
+        "serve",
+        format!("{} asked to {} {}", grant.name, action, instance_id),
+    );
+    tokio::spawn(async move {
+        let mut config = Config::new(action.clone(), instance_id.clone());
+        config.timeout = state.timeout;
+        let future = timeout(
+            Duration::from_secs(state.timeout),
+            work(&state.aws_config, &config, &action),
+        );
+        match log::with_instance(&instance_id, future).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => log::error(
+                "serve",
+                format!("Failed to {} {}: {}", action, instance_id, e),
+            ),
+            Err(_) => log::error(
+                "serve",
+                format!("Timed out waiting for {} to {}", instance_id, action),
+            ),
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Streams the states the instance moves to while an action waits for it, as server-sent events
+#[utoipa::path(
+    get,
+    path = "/instances/{instance_id}/events",
+    params(
+        ("instance_id" = String, Path, description = "One of the instances listed"),
+        TokenQuery,
+    ),
+    responses(
+        (status = 200, description = "`state` events", content_type = "text/event-stream",
+            body = StateChange),
+        (status = 401, description = "Missing or wrong token"),
+        (status = 404, description = "Instance not listed"),
+    )
+)]
+async fn events(
+    State(state): State<Arc<WebState>>,
+    Path(instance_id): Path<String>,
+    Query(query): Query<TokenQuery>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, StatusCode> {
+    let grant = authorized(&state, &headers, query.token.as_deref())?;
+    if !state.config.instance_ids.contains(&instance_id) || !grant.allows(&instance_id, "status") {
+        return Err(StatusCode::NOT_FOUND);
+    }
+
+    // Changes missed by a slow client are skipped, the next one gives the current state
+    let changes = BroadcastStream::new(progress::subscribe()).filter_map(move |change| {
+        let change = change
+            .ok()
+            .filter(|change| change.instance_id == instance_id)?;
+        Some(Event::default().event("state").json_data(StateChange {
+            instance_id: change.instance_id,
+            state: change.state.as_str().to_string(),
+        }))
+    });
+    Ok(Sse::new(changes).keep_alive(KeepAlive::default()))
+}
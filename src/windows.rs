@@ -0,0 +1,37 @@
+use color_eyre::Result;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
+use std::net::SocketAddr;
+use std::path::Path;
+
+/// Decrypts the administrator password returned by GetPasswordData
+///
+/// The password is encrypted with the public part of the instance's key pair.
+/// The key file can be either PKCS#1 (`BEGIN RSA PRIVATE KEY`) or PKCS#8 (`BEGIN PRIVATE KEY`).
+pub fn decrypt_password(key_file: &Path, password_data: &str) -> Result<String> {
+    let pem = std::fs::read_to_string(key_file)?;
+    let key = match RsaPrivateKey::from_pkcs1_pem(&pem) {
+        Ok(key) => key,
+        Err(_) => RsaPrivateKey::from_pkcs8_pem(&pem)?,
+    };
+
+    let encrypted = aws_smithy_types::base64::decode(password_data.trim())?;
+    let password = key.decrypt(Pkcs1v15Encrypt, &encrypted)?;
+
+    Ok(String::from_utf8(password)?)
+}
+
+/// Port Remote Desktop listens on by default
+pub const RDP_PORT: u16 = 3389;
+
+/// Default name of the administrator account on Windows AMIs
+pub const ADMINISTRATOR: &str = "Administrator";
+
+/// Builds a Remote Desktop connection file for the given address, IPv6 ones being bracketed
+pub fn rdp_file_contents(address: &SocketAddr) -> String {
+    format!(
+        "full address:s:{}\r\nusername:s:{}\r\nprompt for credentials:i:1\r\n",
+        address, ADMINISTRATOR
+    )
+}